@@ -0,0 +1,22 @@
+#!/bin/sh
+# Replays every script in this directory against the file of the same name, failing if any of
+# their checks do. A script with a config of the same name (`.exl`) is replayed with that config.
+set -e
+cd "$(dirname "$0")"
+cargo build --quiet
+status=0
+for script in *.replay; do
+    config="${script%.replay}.exl"
+    for input in "${script%.replay}".*; do
+        case "$input" in
+            *.replay | *.exl) continue ;;
+        esac
+        if [ -e "$config" ]; then
+            ../target/debug/exalted --replay "$script" --size 800x600 --config "$config" "$input" ||
+                status=1
+        else
+            ../target/debug/exalted --replay "$script" --size 800x600 "$input" || status=1
+        fi
+    done
+done
+exit $status
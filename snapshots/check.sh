@@ -0,0 +1,18 @@
+#!/bin/sh
+# Renders every file in this directory headlessly and compares it against the PNG of the same
+# name. Pass --update to rewrite the PNGs instead. The file tree is rooted in tree/, so that what
+# it shows doesn't depend on whatever else is lying around in here.
+set -e
+cd "$(dirname "$0")"
+cargo build --quiet
+cd tree
+status=0
+for input in ../*; do
+    [ -f "$input" ] || continue
+    case "$input" in
+        *.png | *.sh) continue ;;
+    esac
+    ../../target/debug/exalted --snapshot "${input%.*}.png" --size 800x600 "$@" "$input" ||
+        status=1
+done
+exit $status
@@ -0,0 +1,5 @@
+/// Greets whoever is named on the command line.
+fn main() {
+    let name = std::env::args().nth(1).unwrap_or_else(|| "wörld".to_owned());
+    println!("Hello, {name}!");
+}
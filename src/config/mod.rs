@@ -0,0 +1,641 @@
+mod panels;
+mod plugins;
+mod syntax;
+mod theme;
+
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use cosmic_text::Color as CTColor;
+
+use crate::layout::keymap::{Bindings, Command, GlobalCommand, KeyPress, Named, NavCommand};
+use crate::layout::modes::{Hook, MajorMode, MinorMode};
+use crate::Waker;
+pub use panels::PanelSizes;
+pub use plugins::{Callback, Context, Effect, Outcome, Plugins};
+use syntax::{Error, Key, Value, ValueKind};
+pub use theme::Theme;
+
+/// How often the config file is checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Everything that can be configured, with a default for anything the config leaves out.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Config {
+    pub editor: EditorConfig,
+    pub nav_bar: NavBarConfig,
+    pub status_bar: StatusBarConfig,
+    pub theme: Arc<Theme>,
+    pub keys: KeysConfig,
+    /// Settings for particular major and minor modes, in the order they're given.
+    pub modes: Vec<ModeConfig>,
+    /// Loaded from the `plugins` directory next to the config rather than from the config itself.
+    pub plugins: Arc<Plugins>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct EditorConfig {
+    pub font_size: f32,
+    pub line_height: f32,
+}
+
+impl Default for EditorConfig {
+    fn default() -> Self {
+        Self {
+            font_size: 32.0,
+            line_height: 48.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct NavBarConfig {
+    pub width: f32,
+    pub font_size: f32,
+    pub line_height: f32,
+}
+
+impl Default for NavBarConfig {
+    fn default() -> Self {
+        Self {
+            width: 200.0,
+            font_size: 18.0,
+            line_height: 28.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct StatusBarConfig {
+    pub height: f32,
+    pub font_size: f32,
+}
+
+impl Default for StatusBarConfig {
+    fn default() -> Self {
+        Self {
+            height: 36.0,
+            font_size: 20.0,
+        }
+    }
+}
+
+/// Keys to bind commands to instead of their defaults, for each keymap.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct KeysConfig {
+    /// Keys that work wherever the focus is, before any pane sees them.
+    pub global: Bindings<GlobalCommand>,
+    pub normal: Bindings<Command>,
+    pub insert: Bindings<Command>,
+    pub select: Bindings<Command>,
+    pub nav_bar: Bindings<NavCommand>,
+}
+
+/// What the config says about a major or minor mode, which applies to buffers while it's on.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ModeConfig {
+    pub name: &'static str,
+    pub indent: Option<String>,
+    /// More minor modes for a major mode to turn on, after its own.
+    pub minor_modes: Vec<&'static MinorMode>,
+    pub keys: ModeKeys,
+    /// Commands to run at each hook, after the mode's own.
+    pub hooks: Vec<(Hook, Vec<Command>)>,
+}
+
+/// Keys a mode binds in the editor's own modes.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ModeKeys {
+    pub normal: Bindings<Command>,
+    pub insert: Bindings<Command>,
+    pub select: Bindings<Command>,
+}
+
+/// A set in the config that's decoded into a struct, one setting at a time.
+trait Section: Default {
+    /// The settings it has, for listing when one that doesn't exist is set.
+    const SETTINGS: &'static [&'static str];
+
+    fn set(&mut self, key: &Key, value: &Value) -> Result<(), Error>;
+
+    fn decode(value: &Value) -> Result<Self, Error> {
+        let mut section = Self::default();
+        for (key, value) in set(value)? {
+            section.set(key, value)?;
+        }
+        Ok(section)
+    }
+}
+
+impl Section for Config {
+    const SETTINGS: &'static [&'static str] =
+        &["editor", "nav_bar", "status_bar", "theme", "keys", "modes"];
+
+    fn set(&mut self, key: &Key, value: &Value) -> Result<(), Error> {
+        match key.name.as_str() {
+            "editor" => self.editor = Section::decode(value)?,
+            "nav_bar" => self.nav_bar = Section::decode(value)?,
+            "status_bar" => self.status_bar = Section::decode(value)?,
+            // Looked up by `Config::load`, which knows where to find theme files
+            "theme" => {
+                string(value)?;
+            }
+            "keys" => self.keys = Section::decode(value)?,
+            "modes" => self.modes = modes(value)?,
+            _ => return Err(unknown::<Self>(key)),
+        }
+        Ok(())
+    }
+}
+
+impl Section for EditorConfig {
+    const SETTINGS: &'static [&'static str] = &["font_size", "line_height"];
+
+    fn set(&mut self, key: &Key, value: &Value) -> Result<(), Error> {
+        match key.name.as_str() {
+            "font_size" => self.font_size = size(value)?,
+            "line_height" => self.line_height = size(value)?,
+            _ => return Err(unknown::<Self>(key)),
+        }
+        Ok(())
+    }
+}
+
+impl Section for NavBarConfig {
+    const SETTINGS: &'static [&'static str] = &["width", "font_size", "line_height"];
+
+    fn set(&mut self, key: &Key, value: &Value) -> Result<(), Error> {
+        match key.name.as_str() {
+            "width" => self.width = size(value)?,
+            "font_size" => self.font_size = size(value)?,
+            "line_height" => self.line_height = size(value)?,
+            _ => return Err(unknown::<Self>(key)),
+        }
+        Ok(())
+    }
+}
+
+impl Section for StatusBarConfig {
+    const SETTINGS: &'static [&'static str] = &["height", "font_size"];
+
+    fn set(&mut self, key: &Key, value: &Value) -> Result<(), Error> {
+        match key.name.as_str() {
+            "height" => self.height = size(value)?,
+            "font_size" => self.font_size = size(value)?,
+            _ => return Err(unknown::<Self>(key)),
+        }
+        Ok(())
+    }
+}
+
+impl Section for KeysConfig {
+    const SETTINGS: &'static [&'static str] = &["global", "normal", "insert", "select", "nav_bar"];
+
+    fn set(&mut self, key: &Key, value: &Value) -> Result<(), Error> {
+        match key.name.as_str() {
+            "global" => self.global = bindings(value)?,
+            "normal" => self.normal = bindings(value)?,
+            "insert" => self.insert = bindings(value)?,
+            "select" => self.select = bindings(value)?,
+            "nav_bar" => self.nav_bar = bindings(value)?,
+            _ => return Err(unknown::<Self>(key)),
+        }
+        Ok(())
+    }
+}
+
+impl Section for ModeConfig {
+    const SETTINGS: &'static [&'static str] = &["indent", "minor_modes", "keys", "hooks"];
+
+    fn set(&mut self, key: &Key, value: &Value) -> Result<(), Error> {
+        match key.name.as_str() {
+            "indent" => self.indent = Some(string(value)?.to_owned()),
+            "minor_modes" => {
+                self.minor_modes = list(value, |value| {
+                    let name = string(value)?;
+                    MinorMode::named(name).ok_or_else(|| {
+                        let message = format!(
+                            "there's no minor mode called `{name}`, only {}",
+                            names(MinorMode::all().iter().map(|mode| mode.name))
+                        );
+                        Error::new(value.span.clone(), message)
+                    })
+                })?
+            }
+            "keys" => self.keys = Section::decode(value)?,
+            "hooks" => {
+                self.hooks = set(value)?
+                    .iter()
+                    .map(|(key, value)| {
+                        let hook = Hook::ALL.iter().find(|hook| hook.name() == key.name);
+                        let hook = *hook.ok_or_else(|| {
+                            let message = format!(
+                                "there's no hook called `{}`, only {}",
+                                key.name,
+                                names(Hook::ALL.iter().map(|hook| hook.name()))
+                            );
+                            Error::new(key.span.clone(), message)
+                        })?;
+                        Ok((hook, list(value, command)?))
+                    })
+                    .collect::<Result<_, _>>()?
+            }
+            _ => return Err(unknown::<Self>(key)),
+        }
+        Ok(())
+    }
+}
+
+impl Section for ModeKeys {
+    const SETTINGS: &'static [&'static str] = &["normal", "insert", "select"];
+
+    fn set(&mut self, key: &Key, value: &Value) -> Result<(), Error> {
+        match key.name.as_str() {
+            "normal" => self.normal = bindings(value)?,
+            "insert" => self.insert = bindings(value)?,
+            "select" => self.select = bindings(value)?,
+            _ => return Err(unknown::<Self>(key)),
+        }
+        Ok(())
+    }
+}
+
+/// Modes by name, each with its own settings. Only a major mode can turn on minor modes.
+fn modes(value: &Value) -> Result<Vec<ModeConfig>, Error> {
+    set(value)?
+        .iter()
+        .map(|(key, value)| {
+            let major = MajorMode::named(&key.name).map(|mode| mode.name);
+            let minor = MinorMode::named(&key.name).map(|mode| mode.name);
+            let Some(name) = major.or(minor) else {
+                let modes = MajorMode::all().iter().map(|mode| mode.name);
+                let modes = modes.chain(MinorMode::all().iter().map(|mode| mode.name));
+                let message = format!(
+                    "there's no mode called `{}`, only {}",
+                    key.name,
+                    names(modes)
+                );
+                return Err(Error::new(key.span.clone(), message));
+            };
+            let mode = ModeConfig {
+                name,
+                ..Section::decode(value)?
+            };
+            if major.is_none() && !mode.minor_modes.is_empty() {
+                let (key, _) = set(value)?
+                    .iter()
+                    .find(|(key, _)| key.name == "minor_modes")
+                    .unwrap();
+                let message = "only major modes turn on minor modes";
+                return Err(Error::new(key.span.clone(), message));
+            }
+            Ok(mode)
+        })
+        .collect()
+}
+
+fn mismatch(expected: &str, value: &Value) -> Error {
+    let found = value.kind.describe();
+    Error::new(
+        value.span.clone(),
+        format!("expected {expected}, found {found}"),
+    )
+}
+
+fn unknown<T: Section>(key: &Key) -> Error {
+    Error::new(
+        key.span.clone(),
+        format!(
+            "there's no setting called `{}`, only {}",
+            key.name,
+            names(T::SETTINGS.iter().copied())
+        ),
+    )
+}
+
+/// Names to list in an error, quoted like they're written in the config.
+fn names<'a>(names: impl Iterator<Item = &'a str>) -> String {
+    let names: Vec<_> = names.map(|name| format!("`{name}`")).collect();
+    names.join(", ")
+}
+
+fn string(value: &Value) -> Result<&str, Error> {
+    match &value.kind {
+        ValueKind::String(string) => Ok(string),
+        _ => Err(mismatch("a string", value)),
+    }
+}
+
+fn boolean(value: &Value) -> Result<bool, Error> {
+    match value.kind {
+        ValueKind::Bool(boolean) => Ok(boolean),
+        _ => Err(mismatch("`true` or `false`", value)),
+    }
+}
+
+/// Either one of what `item` decodes or a list of them.
+fn list<T>(value: &Value, item: impl Fn(&Value) -> Result<T, Error>) -> Result<Vec<T>, Error> {
+    match &value.kind {
+        ValueKind::List(items) => items.iter().map(item).collect(),
+        _ => Ok(vec![item(value)?]),
+    }
+}
+
+fn set(value: &Value) -> Result<&[(Key, Value)], Error> {
+    match &value.kind {
+        ValueKind::Set(bindings) => Ok(bindings),
+        _ => Err(mismatch("a set", value)),
+    }
+}
+
+/// Commands bound to the keys that run them, as either one sequence of keys or a list of them,
+/// where an empty list leaves the command without any.
+fn bindings<C: Named>(value: &Value) -> Result<Bindings<C>, Error> {
+    set(value)?
+        .iter()
+        .map(|(key, value)| {
+            let command = C::from_name(&key.name).ok_or_else(|| {
+                Error::new(
+                    key.span.clone(),
+                    format!("there's no command called `{}` here", key.name),
+                )
+            })?;
+            Ok((command, list(value, keys)?))
+        })
+        .collect()
+}
+
+/// The name of an editor command.
+fn command(value: &Value) -> Result<Command, Error> {
+    let name = string(value)?;
+    Command::from_name(name).ok_or_else(|| {
+        Error::new(
+            value.span.clone(),
+            format!("there's no command called `{name}`"),
+        )
+    })
+}
+
+/// A sequence of keys like `"C-s"` or `"space f"`.
+fn keys(value: &Value) -> Result<Vec<KeyPress>, Error> {
+    let description = match &value.kind {
+        ValueKind::String(description) => description,
+        _ => return Err(mismatch("keys like \"C-s\" or \"g g\"", value)),
+    };
+    KeyPress::parse_sequence(description).ok_or_else(|| {
+        Error::new(
+            value.span.clone(),
+            format!("`{description}` isn't keys like `C-s` or `g g`"),
+        )
+    })
+}
+
+/// A number bigger than zero, like a size in pixels.
+fn size(value: &Value) -> Result<f32, Error> {
+    let size = match value.kind {
+        ValueKind::Int(int) => int as f32,
+        ValueKind::Float(float) => float as f32,
+        _ => return Err(mismatch("a number", value)),
+    };
+    match size > 0.0 {
+        true => Ok(size),
+        false => Err(Error::new(value.span.clone(), "this has to be more than 0")),
+    }
+}
+
+fn colour(value: &Value) -> Result<CTColor, Error> {
+    match value.kind {
+        ValueKind::Colour(colour) => Ok(colour),
+        _ => Err(mismatch("a colour like #rrggbb", value)),
+    }
+}
+
+/// Why a config couldn't be loaded.
+#[derive(Debug)]
+pub enum LoadError {
+    Io(PathBuf, io::Error),
+    Invalid {
+        path: PathBuf,
+        source: String,
+        error: Error,
+    },
+}
+
+impl LoadError {
+    /// The error along with the line it's on and a marker under where it is, for printing to a
+    /// terminal.
+    pub fn report(&self) -> String {
+        let Self::Invalid { source, error, .. } = self else {
+            return self.to_string();
+        };
+        let (line, column) = syntax::line_column(source, error.span.start);
+        let text = source.lines().nth(line - 1).unwrap_or("");
+        let (end_line, end_column) = syntax::line_column(source, error.span.end);
+        let width = match end_line == line {
+            true => end_column.saturating_sub(column).max(1),
+            false => text.chars().count().saturating_sub(column - 1).max(1),
+        };
+        let gutter = " ".repeat(line.to_string().len());
+        let marker = format!("{}{}", " ".repeat(column - 1), "^".repeat(width));
+        format!("{self}\n{gutter} |\n{line} | {text}\n{gutter} | {marker}")
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(path, err) => write!(f, "Could not read {}: {err}", path.display()),
+            Self::Invalid {
+                path,
+                source,
+                error,
+            } => {
+                let (line, column) = syntax::line_column(source, error.span.start);
+                write!(f, "{}:{line}:{column}: {}", path.display(), error.message)
+            }
+        }
+    }
+}
+
+impl Config {
+    /// Reads the config at `path`, which is all defaults if there's no file there. A theme it names
+    /// is looked for in the `themes` directory next to it before the built-in ones, and the
+    /// plugins in the `plugins` directory next to it are loaded either way.
+    pub fn load(path: &Path) -> Result<Self, LoadError> {
+        let plugins = Arc::new(Plugins::load(&plugins_dir(path))?);
+        let source = match fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return Ok(Self {
+                    plugins,
+                    ..Self::default()
+                })
+            }
+            Err(err) => return Err(LoadError::Io(path.to_owned(), err)),
+        };
+        let config = syntax::parse(&source).and_then(|value| {
+            let config: Self = Section::decode(&value)?;
+            let theme = set(&value)?.iter().find(|(key, _)| key.name == "theme");
+            Ok((config, theme.map(|(_, value)| value.clone())))
+        });
+        let (mut config, theme) = match config {
+            Ok(config) => config,
+            Err(error) => {
+                return Err(LoadError::Invalid {
+                    path: path.to_owned(),
+                    source,
+                    error,
+                })
+            }
+        };
+        if let Some(value) = theme {
+            let name = string(&value).unwrap();
+            let dir = themes_dir(path);
+            let Some(theme) = Theme::load(name, &dir)? else {
+                let message = format!(
+                    "there's no theme called `{name}` in {}, and the built-in ones are {}",
+                    dir.display(),
+                    names(Theme::built_in_names())
+                );
+                return Err(LoadError::Invalid {
+                    path: path.to_owned(),
+                    source,
+                    error: Error::new(value.span, message),
+                });
+            };
+            config.theme = Arc::new(theme);
+        }
+        config.plugins = plugins;
+        Ok(config)
+    }
+
+    /// Where the config is read from unless another file is given: `exalted/config.exl` in the
+    /// XDG config directory.
+    pub fn default_path() -> Option<PathBuf> {
+        let config = match env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+        };
+        Some(config.join("exalted").join("config.exl"))
+    }
+
+    /// Loads the config at `path` again each time it or one of the themes or plugins next to it
+    /// changes, waking the event loop to pick it up.
+    pub fn watch(path: PathBuf, waker: Waker) -> Receiver<Result<Self, LoadError>> {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let mut last = last_change(&path);
+            loop {
+                thread::sleep(POLL_INTERVAL);
+                let now = last_change(&path);
+                if now == last {
+                    continue;
+                }
+                last = now;
+                // Nobody's listening once the app is exiting
+                if sender.send(Self::load(&path)).is_err() {
+                    return;
+                }
+                waker.wake();
+            }
+        });
+        receiver
+    }
+}
+
+/// Where the themes that the config at `path` can name are, besides the built-in ones.
+fn themes_dir(path: &Path) -> PathBuf {
+    path.parent().unwrap_or(Path::new("")).join("themes")
+}
+
+/// Where the plugins for the config at `path` are.
+fn plugins_dir(path: &Path) -> PathBuf {
+    path.parent().unwrap_or(Path::new("")).join("plugins")
+}
+
+/// When the config at `path` or any of the themes or plugins next to it last changed.
+fn last_change(path: &Path) -> Option<SystemTime> {
+    let modified = |path: &Path| -> Option<SystemTime> {
+        fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    };
+    let files = [themes_dir(path), plugins_dir(path)]
+        .into_iter()
+        .flat_map(fs::read_dir)
+        .flatten()
+        .filter_map(|entry| modified(&entry.ok()?.path()));
+    modified(path).into_iter().chain(files).max()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(source: &str) -> Error {
+        let value = syntax::parse(source).unwrap();
+        Config::decode(&value).unwrap_err()
+    }
+
+    #[test]
+    fn unknown_section() {
+        assert_eq!(
+            error("editr.font_size = 12;"),
+            Error::new(
+                0..5,
+                "there's no setting called `editr`, only `editor`, `nav_bar`, `status_bar`, \
+                 `theme`, `keys`, `modes`"
+            )
+        );
+    }
+
+    #[test]
+    fn unknown_key() {
+        assert_eq!(
+            error("nav_bar = { width = 100; size = 12; };"),
+            Error::new(
+                25..29,
+                "there's no setting called `size`, only `width`, `font_size`, `line_height`"
+            )
+        );
+    }
+
+    #[test]
+    fn bad_values() {
+        assert_eq!(
+            error("editor.font_size = \"big\";"),
+            Error::new(19..24, "expected a number, found a string")
+        );
+        assert_eq!(
+            error("status_bar.height = 0;"),
+            Error::new(20..21, "this has to be more than 0")
+        );
+        assert_eq!(
+            error("editor = 12;"),
+            Error::new(9..11, "expected a set, found an integer")
+        );
+    }
+
+    #[test]
+    fn report_on_last_line_without_newline() {
+        let source = "editor.font_size = 12;\nnav_bar.width = \"wide\"";
+        let error = syntax::parse(source).unwrap_err();
+        let error = LoadError::Invalid {
+            path: PathBuf::from("config.exl"),
+            source: source.to_owned(),
+            error,
+        };
+        assert_eq!(
+            error.report(),
+            "config.exl:2:23: the config ends too soon\n  \
+             |\n2 | nav_bar.width = \"wide\"\n  |                       ^"
+        );
+    }
+}
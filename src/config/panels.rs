@@ -0,0 +1,176 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::syntax::{self, Error, Key, Value};
+use super::{boolean, size, unknown, LoadError, Section};
+
+/// The sizes the panels around the editor were dragged to, which take the place of the config's
+/// until they're dragged again. They're kept from one session to the next in a file of their own,
+/// written like a config.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PanelSizes {
+    pub nav_bar: NavBarSize,
+    pub status_bar: StatusBarSize,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NavBarSize {
+    pub width: Option<f32>,
+    /// Hidden entirely, keeping the width to open it back up at.
+    pub collapsed: bool,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StatusBarSize {
+    pub height: Option<f32>,
+}
+
+impl Section for PanelSizes {
+    const SETTINGS: &'static [&'static str] = &["nav_bar", "status_bar"];
+
+    fn set(&mut self, key: &Key, value: &Value) -> Result<(), Error> {
+        match key.name.as_str() {
+            "nav_bar" => self.nav_bar = Section::decode(value)?,
+            "status_bar" => self.status_bar = Section::decode(value)?,
+            _ => return Err(unknown::<Self>(key)),
+        }
+        Ok(())
+    }
+}
+
+impl Section for NavBarSize {
+    const SETTINGS: &'static [&'static str] = &["width", "collapsed"];
+
+    fn set(&mut self, key: &Key, value: &Value) -> Result<(), Error> {
+        match key.name.as_str() {
+            "width" => self.width = Some(size(value)?),
+            "collapsed" => self.collapsed = boolean(value)?,
+            _ => return Err(unknown::<Self>(key)),
+        }
+        Ok(())
+    }
+}
+
+impl Section for StatusBarSize {
+    const SETTINGS: &'static [&'static str] = &["height"];
+
+    fn set(&mut self, key: &Key, value: &Value) -> Result<(), Error> {
+        match key.name.as_str() {
+            "height" => self.height = Some(size(value)?),
+            _ => return Err(unknown::<Self>(key)),
+        }
+        Ok(())
+    }
+}
+
+impl PanelSizes {
+    /// Where the sizes are kept: `exalted/panels.exl` in the XDG state directory.
+    pub fn default_path() -> Option<PathBuf> {
+        let state = match env::var_os("XDG_STATE_HOME").filter(|dir| !dir.is_empty()) {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(env::var_os("HOME")?).join(".local/state"),
+        };
+        Some(state.join("exalted").join("panels.exl"))
+    }
+
+    /// Reads the sizes at `path`, which are all left to the config if it doesn't exist yet.
+    pub fn load(path: &Path) -> Result<Self, LoadError> {
+        let source = match fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(LoadError::Io(path.to_owned(), err)),
+        };
+        syntax::parse(&source)
+            .and_then(|value| Section::decode(&value))
+            .map_err(|error| LoadError::Invalid {
+                path: path.to_owned(),
+                source,
+                error,
+            })
+    }
+
+    /// Writes the sizes to `path`, making the directory it's in if need be.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut source = String::from("// The sizes the panels were last dragged to\n");
+        source += &format!("nav_bar.collapsed = {};\n", self.nav_bar.collapsed);
+        if let Some(width) = self.nav_bar.width {
+            source += &format!("nav_bar.width = {width};\n");
+        }
+        if let Some(height) = self.status_bar.height {
+            source += &format!("status_bar.height = {height};\n");
+        }
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process;
+
+    use super::*;
+
+    /// A path for `name` in a directory of its own that doesn't exist yet.
+    fn path(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("exalted-panels-{}-{name}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir.join("state").join("panels.exl")
+    }
+
+    #[test]
+    fn round_trip() {
+        let path = path("round_trip");
+        let sizes = PanelSizes {
+            nav_bar: NavBarSize {
+                width: Some(212.5),
+                collapsed: true,
+            },
+            status_bar: StatusBarSize { height: Some(40.0) },
+        };
+        sizes.save(&path).unwrap();
+        assert_eq!(PanelSizes::load(&path).unwrap(), sizes);
+
+        // Saving again replaces what was there, leaving out sizes that were never dragged
+        let sizes = PanelSizes {
+            nav_bar: NavBarSize {
+                width: Some(180.0),
+                collapsed: false,
+            },
+            status_bar: StatusBarSize::default(),
+        };
+        sizes.save(&path).unwrap();
+        assert_eq!(PanelSizes::load(&path).unwrap(), sizes);
+        fs::remove_dir_all(path.parent().unwrap().parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn missing_file() {
+        let path = path("missing_file");
+        assert_eq!(PanelSizes::load(&path).unwrap(), PanelSizes::default());
+    }
+
+    #[test]
+    fn corrupt_file() {
+        let path = path("corrupt_file");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        for source in [
+            "nav_bar.width = ",
+            "nav_bar.width = \"wide\";",
+            "nav_bar.height = 3;",
+        ] {
+            fs::write(&path, source).unwrap();
+            match PanelSizes::load(&path) {
+                Err(LoadError::Invalid { source: read, .. }) => assert_eq!(read, source),
+                result => panic!("{source:?} loaded as {result:?}"),
+            }
+        }
+        // Not even text
+        fs::write(&path, b"nav_bar.width = \xff;").unwrap();
+        assert!(matches!(PanelSizes::load(&path), Err(LoadError::Io(..))));
+        fs::remove_dir_all(path.parent().unwrap().parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,661 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rhai::module_resolvers::DummyModuleResolver;
+use rhai::{
+    Array, CallFnOptions, Dynamic, Engine, EvalAltResult, NativeCallContext, Position, Scope, AST,
+};
+
+use crate::document::selection::{Range as SelectionRange, Selection};
+use crate::layout::keymap::{Command, KeyPress, Mode, Named};
+use crate::layout::modes::Hook;
+
+use super::syntax::Error;
+use super::LoadError;
+
+/// How many operations a plugin gets each time it runs before it's stopped, so that one stuck in
+/// a loop can't hang the editor.
+const MAX_OPERATIONS: u64 = 10_000_000;
+const MAX_CALL_LEVELS: usize = 64;
+/// How big a string, array or object map a plugin may make, so that one can't run the editor out
+/// of memory well within its operations. Strings leave room for the text of large buffers.
+const MAX_STRING_SIZE: usize = 256 << 20;
+const MAX_ARRAY_SIZE: usize = 1 << 20;
+const MAX_MAP_SIZE: usize = 1 << 16;
+/// How long a plugin gets each time it runs. Checking the sizes above makes changing an array or
+/// map take as long as it is big, so a plugin can take far longer than its operations suggest.
+const MAX_TIME: Duration = Duration::from_secs(2);
+
+/// Scripts in the `plugins` directory next to the config, which add commands, bind keys and run at
+/// hooks. They're written in Rhai, which can't touch files or the network, and can only reach the
+/// editor through the functions registered here.
+pub struct Plugins {
+    engine: Engine,
+    scripts: Vec<Script>,
+    commands: Vec<PluginCommand>,
+    keys: Vec<(Mode, Command, Vec<Vec<KeyPress>>)>,
+    hooks: Vec<(Hook, Callback)>,
+    /// Shared with the functions registered with `engine`, which have no other way to reach it.
+    state: Arc<Mutex<State>>,
+}
+
+struct Script {
+    path: PathBuf,
+    source: String,
+    ast: AST,
+}
+
+/// A function in one of the scripts, for the editor to call back.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Callback {
+    script: usize,
+    function: String,
+}
+
+/// A command a plugin added, which can be run from the palette or bound to keys like any other.
+pub struct PluginCommand {
+    pub name: String,
+    pub description: String,
+    pub callback: Callback,
+}
+
+/// What a plugin can see of the buffer it's run in.
+pub struct Context {
+    pub text: String,
+    pub path: Option<String>,
+    pub selection: Selection,
+    pub mode: &'static str,
+    pub major_mode: &'static str,
+    pub minor_modes: Vec<&'static str>,
+}
+
+/// What a plugin did to the buffer it was run in, and what it asked the editor to do afterwards.
+pub struct Outcome {
+    /// Each time it replaced the selections, one replacement per selection. The ranges are where
+    /// the selections were after the replacements before.
+    pub edits: Vec<Vec<(Range<usize>, String)>>,
+    pub selection: Selection,
+    pub effects: Vec<Effect>,
+}
+
+pub enum Effect {
+    /// Runs a command by name, which may be one a plugin added.
+    Run(String),
+    Message(String),
+    Error(String),
+    /// Asks for a line of input and calls back with it.
+    Prompt {
+        label: String,
+        callback: Callback,
+    },
+    SetMajorMode(String),
+    ToggleMinorMode(String),
+}
+
+#[derive(Default)]
+struct State {
+    /// The script being loaded or run.
+    script: usize,
+    /// The functions each script defines, with how many parameters each one takes.
+    functions: Vec<Vec<(String, usize)>>,
+    /// Only set while the scripts are being loaded, which is the only time they can add commands,
+    /// bind keys and hook in.
+    loading: Option<Registry>,
+    /// Only set while a command or hook is running, which is the only time there's a buffer.
+    running: Option<Running>,
+    /// When the script being loaded or run started.
+    started: Option<Instant>,
+}
+
+#[derive(Default)]
+struct Registry {
+    commands: Vec<PluginCommand>,
+    keys: Vec<(Mode, Command, Vec<Vec<KeyPress>>)>,
+    hooks: Vec<(Hook, Callback)>,
+}
+
+impl Registry {
+    /// The built-in command called `name`, or else one added so far.
+    fn command(&self, name: &str) -> Result<Command, String> {
+        let added = || {
+            let index = self
+                .commands
+                .iter()
+                .position(|command| command.name == name)?;
+            Some(Command::Plugin(index))
+        };
+        Command::from_name(name).or_else(added).ok_or_else(|| {
+            format!(
+                "there's no command called `{name}`, and plugins have to add commands before \
+                 binding keys to them"
+            )
+        })
+    }
+}
+
+struct Running {
+    context: Context,
+    outcome: Outcome,
+}
+
+impl Running {
+    /// Replaces each selection with the text at the same index, selecting it if `select` and
+    /// leaving a cursor after it otherwise.
+    fn replace(&mut self, texts: Vec<String>, select: bool) {
+        let selection = &self.outcome.selection;
+        let mut edits = Vec::with_capacity(texts.len());
+        let mut ranges = Vec::with_capacity(texts.len());
+        // How far the replacements so far have moved everything after them
+        let mut shift = 0;
+        for (range, text) in selection.ranges().iter().zip(texts) {
+            let bytes = range.bytes();
+            let start = bytes.start.checked_add_signed(shift).unwrap();
+            let end = start + text.len();
+            self.context
+                .text
+                .replace_range(start..start + bytes.len(), &text);
+            shift += text.len() as isize - bytes.len() as isize;
+            ranges.push(match select {
+                true => SelectionRange::new(start, end),
+                false => SelectionRange::point(end),
+            });
+            edits.push((bytes, text));
+        }
+        let primary = selection.primary_index();
+        self.outcome.selection = Selection::new(ranges, primary);
+        self.outcome.edits.push(edits);
+    }
+}
+
+impl Plugins {
+    /// Loads every `.rhai` file in `dir`, in order of their names, running each one to let it add
+    /// its commands, keys and hooks. There are no plugins if there's no such directory.
+    pub fn load(dir: &Path) -> Result<Self, LoadError> {
+        let mut this = Self::default();
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(this),
+            Err(err) => return Err(LoadError::Io(dir.to_owned(), err)),
+        };
+        let mut paths: Vec<_> = entries
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|extension| extension == "rhai")
+            })
+            .collect();
+        paths.sort();
+
+        this.state.lock().unwrap().loading = Some(Registry::default());
+        for (index, path) in paths.into_iter().enumerate() {
+            let source = match fs::read_to_string(&path) {
+                Ok(source) => source,
+                Err(err) => return Err(LoadError::Io(path, err)),
+            };
+            let invalid = |source: String, position: Position, message: String| {
+                let span = span(&source, position);
+                LoadError::Invalid {
+                    path: path.clone(),
+                    source,
+                    error: Error::new(span, message),
+                }
+            };
+            let ast = match this.engine.compile(&source) {
+                Ok(ast) => ast,
+                Err(err) => return Err(invalid(source, err.position(), err.0.to_string())),
+            };
+            {
+                let mut state = this.state.lock().unwrap();
+                state.script = index;
+                state.started = Some(Instant::now());
+                let functions = ast.iter_functions();
+                (state.functions).push(
+                    functions
+                        .map(|function| (function.name.to_owned(), function.params.len()))
+                        .collect(),
+                );
+            }
+            if let Err(err) = this.engine.run_ast(&ast) {
+                let (position, message) = describe(*err);
+                return Err(invalid(source, position, message));
+            }
+            this.scripts.push(Script { path, source, ast });
+        }
+        let registry = this.state.lock().unwrap().loading.take().unwrap();
+        this.commands = registry.commands;
+        this.keys = registry.keys;
+        this.hooks = registry.hooks;
+        Ok(this)
+    }
+
+    pub fn commands(&self) -> &[PluginCommand] {
+        &self.commands
+    }
+
+    /// The built-in command called `name`, or else the one a plugin added by that name.
+    pub fn command(&self, name: &str) -> Option<Command> {
+        Command::from_name(name).or_else(|| {
+            let index = self
+                .commands
+                .iter()
+                .position(|command| command.name == name)?;
+            Some(Command::Plugin(index))
+        })
+    }
+
+    /// The keys the plugins bound, in the order they bound them.
+    pub fn keys(&self) -> &[(Mode, Command, Vec<Vec<KeyPress>>)] {
+        &self.keys
+    }
+
+    /// What the plugins hooked in to run at `hook`, in order.
+    pub fn hooks(&self, hook: Hook) -> Vec<Callback> {
+        (self.hooks.iter())
+            .filter(|(bound, _)| *bound == hook)
+            .map(|(_, callback)| callback.clone())
+            .collect()
+    }
+
+    /// Calls back into a plugin for the buffer `context` describes, passing `input` if it's
+    /// answering a prompt. A plugin that fails does nothing to the buffer, and the error says
+    /// which script and where.
+    pub fn call(
+        &self,
+        callback: &Callback,
+        input: Option<String>,
+        context: Context,
+    ) -> Result<Outcome, String> {
+        let script = &self.scripts[callback.script];
+        let outcome = Outcome {
+            edits: Vec::new(),
+            selection: context.selection.clone(),
+            effects: Vec::new(),
+        };
+        {
+            let mut state = self.state.lock().unwrap();
+            state.script = callback.script;
+            state.running = Some(Running { context, outcome });
+            state.started = Some(Instant::now());
+        }
+        let options = CallFnOptions::new().eval_ast(false);
+        let args: Vec<Dynamic> = input.into_iter().map(Dynamic::from).collect();
+        let result = (self.engine).call_fn_with_options::<Dynamic>(
+            options,
+            &mut Scope::new(),
+            &script.ast,
+            &callback.function,
+            args,
+        );
+        let running = self.state.lock().unwrap().running.take().unwrap();
+        match result {
+            Ok(_) => Ok(running.outcome),
+            Err(err) => {
+                let (position, message) = describe(*err);
+                let name = script
+                    .path
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy();
+                Err(match (position.line(), position.position()) {
+                    (Some(line), Some(column)) => format!("{name}:{line}:{column}: {message}"),
+                    (Some(line), None) => format!("{name}:{line}: {message}"),
+                    _ => format!("{name}: {message}"),
+                })
+            }
+        }
+    }
+}
+
+impl Default for Plugins {
+    fn default() -> Self {
+        let state = Arc::new(Mutex::new(State::default()));
+        let mut engine = Engine::new();
+        // Scripts can't load other files or run code they make up
+        engine.set_module_resolver(DummyModuleResolver::new());
+        engine.disable_symbol("eval");
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.set_max_call_levels(MAX_CALL_LEVELS);
+        engine.set_max_string_size(MAX_STRING_SIZE);
+        engine.set_max_array_size(MAX_ARRAY_SIZE);
+        engine.set_max_map_size(MAX_MAP_SIZE);
+        let shared = state.clone();
+        engine.on_progress(move |operations| {
+            if operations % 256 != 0 {
+                return None;
+            }
+            let started = shared.lock().unwrap().started?;
+            (started.elapsed() > MAX_TIME).then(|| Dynamic::from("the plugin took too long"))
+        });
+        let shared = state.clone();
+        engine.on_print(move |text| match &mut shared.lock().unwrap().running {
+            Some(running) => (running.outcome.effects).push(Effect::Message(text.to_owned())),
+            None => eprintln!("{text}"),
+        });
+        register_loading(&mut engine, &state);
+        register_running(&mut engine, &state);
+        Self {
+            engine,
+            scripts: Vec::new(),
+            commands: Vec::new(),
+            keys: Vec::new(),
+            hooks: Vec::new(),
+            state,
+        }
+    }
+}
+
+impl fmt::Debug for Plugins {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let paths = self.scripts.iter().map(|script| &script.path);
+        f.debug_list().entries(paths).finish()
+    }
+}
+
+impl PartialEq for Plugins {
+    fn eq(&self, other: &Self) -> bool {
+        let scripts = |plugins: &Self| {
+            (plugins.scripts.iter())
+                .map(|script| (script.path.clone(), script.source.clone()))
+                .collect::<Vec<_>>()
+        };
+        scripts(self) == scripts(other)
+    }
+}
+
+type Fallible<T> = Result<T, Box<EvalAltResult>>;
+
+fn fail(context: &NativeCallContext, message: String) -> Box<EvalAltResult> {
+    EvalAltResult::ErrorRuntime(message.into(), context.call_position()).into()
+}
+
+/// Runs `f` on what the scripts have added so far, failing unless they're being loaded.
+fn loading<T>(
+    state: &Mutex<State>,
+    context: &NativeCallContext,
+    f: impl FnOnce(&mut Registry, usize, &[(String, usize)]) -> Result<T, String>,
+) -> Fallible<T> {
+    let mut state = state.lock().unwrap();
+    let state = &mut *state;
+    let Some(registry) = &mut state.loading else {
+        let name = context.fn_name();
+        return Err(fail(
+            context,
+            format!("{name} only works while loading plugins"),
+        ));
+    };
+    let functions = &state.functions[state.script];
+    f(registry, state.script, functions).map_err(|message| fail(context, message))
+}
+
+/// Runs `f` on the buffer the running command or hook was run in, failing if there isn't one.
+fn running<T>(
+    state: &Mutex<State>,
+    context: &NativeCallContext,
+    f: impl FnOnce(&mut Running, usize, &[(String, usize)]) -> Result<T, String>,
+) -> Fallible<T> {
+    let mut state = state.lock().unwrap();
+    let state = &mut *state;
+    let Some(running) = &mut state.running else {
+        let name = context.fn_name();
+        let message = format!("{name} only works in a command, a hook or a prompt's callback");
+        return Err(fail(context, message));
+    };
+    let functions = &state.functions[state.script];
+    f(running, state.script, functions).map_err(|message| fail(context, message))
+}
+
+/// A callback to `function` in `script`, as long as it takes `params` parameters.
+fn callback(
+    script: usize,
+    functions: &[(String, usize)],
+    function: &str,
+    params: usize,
+) -> Result<Callback, String> {
+    match functions
+        .iter()
+        .any(|(name, count)| name == function && *count == params)
+    {
+        true => Ok(Callback {
+            script,
+            function: function.to_owned(),
+        }),
+        false => Err(format!(
+            "there's no function called `{function}` taking {params} parameters in this script"
+        )),
+    }
+}
+
+/// The functions scripts use while they're loaded, to add themselves to the editor.
+fn register_loading(engine: &mut Engine, state: &Arc<Mutex<State>>) {
+    // command(name, description, function)
+    let shared = state.clone();
+    engine.register_fn(
+        "command",
+        move |context: NativeCallContext, name: &str, description: &str, function: &str| {
+            loading(&shared, &context, |registry, script, functions| {
+                if registry.command(name).is_ok() {
+                    return Err(format!("there's already a command called `{name}`"));
+                }
+                registry.commands.push(PluginCommand {
+                    name: name.to_owned(),
+                    description: description.to_owned(),
+                    callback: callback(script, functions, function, 0)?,
+                });
+                Ok(())
+            })
+        },
+    );
+
+    // bind(mode, keys, command), which adds to the keys the command already has
+    let shared = state.clone();
+    engine.register_fn(
+        "bind",
+        move |context: NativeCallContext, mode: &str, keys: &str, name: &str| {
+            loading(&shared, &context, |registry, _, _| {
+                let Some(mode) = [Mode::Normal, Mode::Insert, Mode::Select]
+                    .into_iter()
+                    .find(|known| known.name() == mode)
+                else {
+                    return Err(format!(
+                        "there's no mode called `{mode}`, only normal, insert and select"
+                    ));
+                };
+                let Some(keys) = KeyPress::parse_sequence(keys) else {
+                    return Err(format!(
+                        "`{keys}` isn't a key sequence like \"C-x\" or \"g g\""
+                    ));
+                };
+                let command = registry.command(name)?;
+                let bound = (registry.keys.iter_mut())
+                    .find(|(bound_mode, bound, _)| *bound_mode == mode && *bound == command);
+                match bound {
+                    Some((_, _, sequences)) => sequences.push(keys),
+                    None => registry.keys.push((mode, command, vec![keys])),
+                }
+                Ok(())
+            })
+        },
+    );
+
+    // on(hook, function)
+    let shared = state.clone();
+    engine.register_fn(
+        "on",
+        move |context: NativeCallContext, hook: &str, function: &str| {
+            loading(&shared, &context, |registry, script, functions| {
+                let Some(&hook) = Hook::ALL.iter().find(|known| known.name() == hook) else {
+                    let names: Vec<_> = Hook::ALL.iter().map(|hook| hook.name()).collect();
+                    return Err(format!(
+                        "there's no hook called `{hook}`, only {}",
+                        names.join(", ")
+                    ));
+                };
+                let callback = callback(script, functions, function, 0)?;
+                registry.hooks.push((hook, callback));
+                Ok(())
+            })
+        },
+    );
+}
+
+/// The functions scripts use while they're run, to see and change the buffer they're run in and
+/// ask the editor for things.
+fn register_running(engine: &mut Engine, state: &Arc<Mutex<State>>) {
+    let shared = state.clone();
+    engine.register_fn("text", move |context: NativeCallContext| {
+        running(&shared, &context, |running, _, _| {
+            Ok(running.context.text.clone())
+        })
+    });
+
+    let shared = state.clone();
+    engine.register_fn("path", move |context: NativeCallContext| {
+        running(&shared, &context, |running, _, _| {
+            Ok(running
+                .context
+                .path
+                .clone()
+                .map_or(Dynamic::UNIT, Dynamic::from))
+        })
+    });
+
+    // The text of each selection, in order
+    let shared = state.clone();
+    engine.register_fn("selections", move |context: NativeCallContext| {
+        running(&shared, &context, |running, _, _| {
+            let text = &running.context.text;
+            let ranges = running.outcome.selection.ranges();
+            Ok(ranges
+                .iter()
+                .map(|range| Dynamic::from(text[range.bytes()].to_owned()))
+                .collect::<Array>())
+        })
+    });
+
+    let shared = state.clone();
+    engine.register_fn("mode", move |context: NativeCallContext| {
+        running(&shared, &context, |running, _, _| {
+            Ok(running.context.mode.to_owned())
+        })
+    });
+
+    let shared = state.clone();
+    engine.register_fn("major_mode", move |context: NativeCallContext| {
+        running(&shared, &context, |running, _, _| {
+            Ok(running.context.major_mode.to_owned())
+        })
+    });
+
+    let shared = state.clone();
+    engine.register_fn("minor_modes", move |context: NativeCallContext| {
+        running(&shared, &context, |running, _, _| {
+            let modes = running.context.minor_modes.iter();
+            Ok(modes
+                .map(|&mode| Dynamic::from(mode.to_owned()))
+                .collect::<Array>())
+        })
+    });
+
+    // insert(text) replaces every selection with `text`, leaving a cursor after each
+    let shared = state.clone();
+    engine.register_fn("insert", move |context: NativeCallContext, text: &str| {
+        running(&shared, &context, |running, _, _| {
+            let count = running.outcome.selection.ranges().len();
+            running.replace(vec![text.to_owned(); count], false);
+            Ok(())
+        })
+    });
+
+    // replace_selections(texts) replaces each selection with the text at the same index,
+    // selecting it
+    let shared = state.clone();
+    engine.register_fn(
+        "replace_selections",
+        move |context: NativeCallContext, texts: Array| {
+            running(&shared, &context, |running, _, _| {
+                let count = running.outcome.selection.ranges().len();
+                if texts.len() != count {
+                    return Err(format!(
+                        "there are {count} selections, but {} replacements",
+                        texts.len()
+                    ));
+                }
+                let texts = texts.into_iter().map(|text| text.to_string()).collect();
+                running.replace(texts, true);
+                Ok(())
+            })
+        },
+    );
+
+    // Each of these asks the editor to do something with a string once the script is done
+    let effects = [
+        ("run", Effect::Run as fn(String) -> Effect),
+        ("message", Effect::Message),
+        ("error", Effect::Error),
+        ("set_major_mode", Effect::SetMajorMode),
+        ("toggle_minor_mode", Effect::ToggleMinorMode),
+    ];
+    for (name, effect) in effects {
+        let shared = state.clone();
+        engine.register_fn(name, move |context: NativeCallContext, text: &str| {
+            running(&shared, &context, |running, _, _| {
+                running.outcome.effects.push(effect(text.to_owned()));
+                Ok(())
+            })
+        });
+    }
+
+    // prompt(label, function) asks for a line of input and calls `function` with it
+    let shared = state.clone();
+    engine.register_fn(
+        "prompt",
+        move |context: NativeCallContext, label: &str, function: &str| {
+            running(&shared, &context, |running, script, functions| {
+                let callback = callback(script, functions, function, 1)?;
+                running.outcome.effects.push(Effect::Prompt {
+                    label: label.to_owned(),
+                    callback,
+                });
+                Ok(())
+            })
+        },
+    );
+}
+
+/// Where an error was and what it says, from the innermost of any function calls it came through.
+fn describe(mut error: EvalAltResult) -> (Position, String) {
+    while let EvalAltResult::ErrorInFunctionCall(_, _, inner, _) = error {
+        error = *inner;
+    }
+    let position = error.take_position();
+    let message = match error {
+        EvalAltResult::ErrorRuntime(value, _) | EvalAltResult::ErrorTerminated(value, _) => {
+            value.to_string()
+        }
+        error => error.to_string(),
+    };
+    (position, message)
+}
+
+/// The bytes of `source` at `position`, which counts lines and columns from 1.
+fn span(source: &str, position: Position) -> Range<usize> {
+    let Some(line) = position.line() else {
+        return 0..0;
+    };
+    let line_start = match line {
+        0 | 1 => 0,
+        line => (source.match_indices('\n'))
+            .nth(line - 2)
+            .map_or(source.len(), |(i, _)| i + 1),
+    };
+    let column = position.position().unwrap_or(1).saturating_sub(1);
+    let start = (source[line_start..].char_indices())
+        .nth(column)
+        .map_or(source.len(), |(i, _)| line_start + i);
+    start..start
+}
@@ -0,0 +1,481 @@
+use std::fmt;
+use std::ops::Range;
+
+use cosmic_text::Color as CTColor;
+
+/// Where something is in the config's source, in bytes.
+pub type Span = Range<usize>;
+
+/// Something wrong with the config, and where.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Error {
+    pub span: Span,
+    pub message: String,
+}
+
+impl Error {
+    pub fn new(span: Span, message: impl Into<String>) -> Self {
+        Self {
+            span,
+            message: message.into(),
+        }
+    }
+}
+
+/// A value written in the config, along with where it was written.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Value {
+    pub kind: ValueKind,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ValueKind {
+    Int(i64),
+    Float(f64),
+    String(String),
+    Bool(bool),
+    Colour(CTColor),
+    List(Vec<Value>),
+    /// The names and values bound in `{ ... }`, or at the top level of the file, in the order
+    /// they were first bound.
+    Set(Vec<(Key, Value)>),
+}
+
+impl ValueKind {
+    /// What this is called in errors about it not being what was expected.
+    pub fn describe(&self) -> &'static str {
+        match self {
+            Self::Int(_) => "an integer",
+            Self::Float(_) => "a number",
+            Self::String(_) => "a string",
+            Self::Bool(_) => "a boolean",
+            Self::Colour(_) => "a colour",
+            Self::List(_) => "a list",
+            Self::Set(_) => "a set",
+        }
+    }
+}
+
+/// A name bound in a set.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Key {
+    pub name: String,
+    pub span: Span,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Ident(String),
+    Int(i64),
+    Float(f64),
+    String(String),
+    Colour(CTColor),
+    Equals,
+    Semicolon,
+    Dot,
+    OpenBrace,
+    CloseBrace,
+    OpenBracket,
+    CloseBracket,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ident(name) => write!(f, "`{name}`"),
+            Self::Int(_) | Self::Float(_) => f.write_str("a number"),
+            Self::String(_) => f.write_str("a string"),
+            Self::Colour(_) => f.write_str("a colour"),
+            Self::Equals => f.write_str("`=`"),
+            Self::Semicolon => f.write_str("`;`"),
+            Self::Dot => f.write_str("`.`"),
+            Self::OpenBrace => f.write_str("`{`"),
+            Self::CloseBrace => f.write_str("`}`"),
+            Self::OpenBracket => f.write_str("`[`"),
+            Self::CloseBracket => f.write_str("`]`"),
+        }
+    }
+}
+
+/// Splits `source` into tokens, skipping whitespace and `//` comments.
+fn lex(source: &str) -> Result<Vec<(Token, Span)>, Error> {
+    let mut tokens = Vec::new();
+    let mut chars = source.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        let take_while = |chars: &mut std::iter::Peekable<std::str::CharIndices>,
+                          pred: fn(char) -> bool| {
+            while chars.next_if(|&(_, c)| pred(c)).is_some() {}
+            chars.peek().map_or(source.len(), |&(i, _)| i)
+        };
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '/' if chars.next_if(|&(_, c)| c == '/').is_some() => {
+                take_while(&mut chars, |c| c != '\n');
+                continue;
+            }
+            '=' => Token::Equals,
+            ';' => Token::Semicolon,
+            '.' => Token::Dot,
+            '{' => Token::OpenBrace,
+            '}' => Token::CloseBrace,
+            '[' => Token::OpenBracket,
+            ']' => Token::CloseBracket,
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let end = take_while(&mut chars, |c| c.is_ascii_alphanumeric() || c == '_');
+                tokens.push((Token::Ident(source[start..end].to_owned()), start..end));
+                continue;
+            }
+            c if c.is_ascii_digit() || c == '-' => {
+                let end = take_while(&mut chars, |c| {
+                    c.is_ascii_alphanumeric() || c == '.' || c == '_'
+                });
+                let text = &source[start..end];
+                let number = text.replace('_', "");
+                // Rust would also take things like `inf` and `1e3`, which aren't numbers here
+                let plain = number
+                    .chars()
+                    .all(|c| c.is_ascii_digit() || c == '.' || c == '-');
+                let token = match (number.parse(), number.parse()) {
+                    (Ok(int), _) => Token::Int(int),
+                    (_, Ok(float)) if plain => Token::Float(float),
+                    _ => return Err(Error::new(start..end, format!("`{text}` isn't a number"))),
+                };
+                tokens.push((token, start..end));
+                continue;
+            }
+            '#' => {
+                let end = take_while(&mut chars, |c| c.is_ascii_alphanumeric());
+                let colour = parse_colour(&source[start + 1..end]).ok_or_else(|| {
+                    Error::new(
+                        start..end,
+                        "colours are written like #rgb, #rrggbb or #rrggbbaa in hex",
+                    )
+                })?;
+                tokens.push((Token::Colour(colour), start..end));
+                continue;
+            }
+            '"' => {
+                let mut text = String::new();
+                let end = loop {
+                    match chars.next() {
+                        Some((i, '"')) => break i + 1,
+                        Some((i, '\\')) => match chars.next() {
+                            Some((_, 'n')) => text.push('\n'),
+                            Some((_, 't')) => text.push('\t'),
+                            Some((_, c @ ('"' | '\\'))) => text.push(c),
+                            Some((j, c)) => {
+                                return Err(Error::new(
+                                    i..j + c.len_utf8(),
+                                    format!("unknown escape `\\{c}`"),
+                                ))
+                            }
+                            None => {
+                                return Err(Error::new(start..source.len(), "unterminated string"))
+                            }
+                        },
+                        Some((_, c)) => text.push(c),
+                        None => return Err(Error::new(start..source.len(), "unterminated string")),
+                    }
+                };
+                tokens.push((Token::String(text), start..end));
+                continue;
+            }
+            c => {
+                let span = start..start + c.len_utf8();
+                return Err(Error::new(span, format!("unexpected `{c}`")));
+            }
+        };
+        tokens.push((token, start..start + c.len_utf8()));
+    }
+    Ok(tokens)
+}
+
+fn parse_colour(hex: &str) -> Option<CTColor> {
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let digit = |i: usize| u8::from_str_radix(&hex[i..i + 1], 16).unwrap();
+    let byte = |i: usize| u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).unwrap();
+    match hex.len() {
+        3 => Some(CTColor::rgb(digit(0) * 17, digit(1) * 17, digit(2) * 17)),
+        6 => Some(CTColor::rgb(byte(0), byte(1), byte(2))),
+        8 => Some(CTColor::rgba(byte(0), byte(1), byte(2), byte(3))),
+        _ => None,
+    }
+}
+
+/// Parses a whole config: a set of bindings like `name = value;`, without the braces around
+/// them. A binding's name can be a path into nested sets like `editor.font_size`, and sets bound
+/// to the same name are merged.
+pub fn parse(source: &str) -> Result<Value, Error> {
+    let tokens = lex(source)?;
+    let mut parser = Parser {
+        tokens: &tokens,
+        pos: 0,
+        end: source.len(),
+    };
+    let bindings = parser.bindings(None)?;
+    Ok(Value {
+        kind: ValueKind::Set(bindings),
+        span: 0..source.len(),
+    })
+}
+
+struct Parser<'a> {
+    tokens: &'a [(Token, Span)],
+    pos: usize,
+    /// The end of the source, for errors about it ending too soon.
+    end: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&(Token, Span)> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Result<(Token, Span), Error> {
+        let token = self.peek().cloned();
+        self.pos += 1;
+        token.ok_or_else(|| Error::new(self.end..self.end, "the config ends too soon"))
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<Span, Error> {
+        match self.next()? {
+            (token, span) if token == expected => Ok(span),
+            (token, span) => Err(Error::new(
+                span,
+                format!("expected {expected}, found {token}"),
+            )),
+        }
+    }
+
+    /// Bindings up to `close`, or the end of the source if there isn't one.
+    fn bindings(&mut self, close: Option<Token>) -> Result<Vec<(Key, Value)>, Error> {
+        let mut bindings = Vec::new();
+        loop {
+            match (self.peek(), &close) {
+                (None, None) => return Ok(bindings),
+                (Some((token, _)), Some(close)) if token == close => {
+                    self.pos += 1;
+                    return Ok(bindings);
+                }
+                _ => (),
+            }
+            let mut path = vec![self.key()?];
+            while let Some((Token::Dot, _)) = self.peek() {
+                self.pos += 1;
+                path.push(self.key()?);
+            }
+            self.expect(Token::Equals)?;
+            let value = self.value()?;
+            self.expect(Token::Semicolon)?;
+            bind(&mut bindings, &path, value)?;
+        }
+    }
+
+    fn key(&mut self) -> Result<Key, Error> {
+        match self.next()? {
+            (Token::Ident(name), span) => Ok(Key { name, span }),
+            (token, span) => Err(Error::new(span, format!("expected a name, found {token}"))),
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, Error> {
+        let (token, span) = self.next()?;
+        let kind = match token {
+            Token::Int(int) => ValueKind::Int(int),
+            Token::Float(float) => ValueKind::Float(float),
+            Token::String(text) => ValueKind::String(text),
+            Token::Colour(colour) => ValueKind::Colour(colour),
+            Token::Ident(name) if name == "true" => ValueKind::Bool(true),
+            Token::Ident(name) if name == "false" => ValueKind::Bool(false),
+            Token::OpenBrace => {
+                let bindings = self.bindings(Some(Token::CloseBrace))?;
+                let end = self.tokens[self.pos - 1].1.end;
+                return Ok(Value {
+                    kind: ValueKind::Set(bindings),
+                    span: span.start..end,
+                });
+            }
+            Token::OpenBracket => {
+                let mut items = Vec::new();
+                loop {
+                    if let Some((Token::CloseBracket, close)) = self.peek() {
+                        let end = close.end;
+                        self.pos += 1;
+                        return Ok(Value {
+                            kind: ValueKind::List(items),
+                            span: span.start..end,
+                        });
+                    }
+                    items.push(self.value()?);
+                }
+            }
+            token => return Err(Error::new(span, format!("expected a value, found {token}"))),
+        };
+        Ok(Value { kind, span })
+    }
+}
+
+/// Binds `value` to `path` in `bindings`, making sets along the way for the path to go through
+/// and merging it into a set already bound there.
+fn bind(bindings: &mut Vec<(Key, Value)>, path: &[Key], value: Value) -> Result<(), Error> {
+    let (key, rest) = path.split_first().unwrap();
+    let existing = bindings
+        .iter_mut()
+        .find(|(bound, _)| bound.name == key.name);
+    let Some((_, existing)) = existing else {
+        let value = match rest.is_empty() {
+            true => value,
+            false => {
+                let mut nested = Vec::new();
+                bind(&mut nested, rest, value)?;
+                let span = key.span.start..path.last().unwrap().span.end;
+                Value {
+                    kind: ValueKind::Set(nested),
+                    span,
+                }
+            }
+        };
+        bindings.push((key.clone(), value));
+        return Ok(());
+    };
+    let ValueKind::Set(nested) = &mut existing.kind else {
+        return Err(Error::new(
+            key.span.clone(),
+            format!(
+                "`{}` is already set to {}",
+                key.name,
+                existing.kind.describe()
+            ),
+        ));
+    };
+    if !rest.is_empty() {
+        return bind(nested, rest, value);
+    }
+    let ValueKind::Set(merging) = value.kind else {
+        return Err(Error::new(
+            key.span.clone(),
+            format!("`{}` is already set", key.name),
+        ));
+    };
+    for (key, value) in merging {
+        bind(nested, std::slice::from_ref(&key), value)?;
+    }
+    Ok(())
+}
+
+/// The line and column of `pos` in `source`, both counted from 1 and the column in characters.
+pub fn line_column(source: &str, pos: usize) -> (usize, usize) {
+    let before = &source[..pos.min(source.len())];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let line = before.matches('\n').count() + 1;
+    (line, before[line_start..].chars().count() + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(source: &str) -> Error {
+        parse(source).unwrap_err()
+    }
+
+    #[test]
+    fn unterminated_string() {
+        assert_eq!(
+            error("theme = \"dark;\n"),
+            Error::new(8..15, "unterminated string")
+        );
+        assert_eq!(
+            error("theme = \"dark\\"),
+            Error::new(8..14, "unterminated string")
+        );
+    }
+
+    #[test]
+    fn unknown_escape() {
+        assert_eq!(
+            error("theme = \"da\\rk\";"),
+            Error::new(11..13, "unknown escape `\\r`")
+        );
+    }
+
+    #[test]
+    fn bad_tokens() {
+        assert_eq!(
+            error("editor.font_size = 1e3;"),
+            Error::new(19..22, "`1e3` isn't a number")
+        );
+        assert_eq!(
+            error("theme.background = #12345;"),
+            Error::new(
+                19..25,
+                "colours are written like #rgb, #rrggbb or #rrggbbaa in hex"
+            )
+        );
+        assert_eq!(error("editor @ 1;"), Error::new(7..8, "unexpected `@`"));
+    }
+
+    #[test]
+    fn missing_equals() {
+        assert_eq!(
+            error("editor.font_size 12;"),
+            Error::new(17..19, "expected `=`, found a number")
+        );
+    }
+
+    #[test]
+    fn missing_semicolon() {
+        assert_eq!(
+            error("editor.font_size = 12\nnav_bar.width = 100;"),
+            Error::new(22..29, "expected `;`, found `nav_bar`")
+        );
+    }
+
+    #[test]
+    fn missing_close_brace() {
+        assert_eq!(
+            error("editor = {\n    font_size = 12;\n"),
+            Error::new(31..31, "the config ends too soon")
+        );
+        assert_eq!(
+            error("editor = { font_size = 12; ];"),
+            Error::new(27..28, "expected a name, found `]`")
+        );
+    }
+
+    #[test]
+    fn last_line_without_newline() {
+        let source = "editor.font_size = 12;\nnav_bar.width = 100";
+        assert_eq!(
+            error(source),
+            Error::new(42..42, "the config ends too soon")
+        );
+        assert_eq!(line_column(source, 42), (2, 20));
+    }
+
+    #[test]
+    fn rebinding() {
+        assert_eq!(
+            error("editor = 1;\neditor.font_size = 12;"),
+            Error::new(12..18, "`editor` is already set to an integer")
+        );
+        assert_eq!(
+            error("editor.font_size = 1;\neditor.font_size = 2;"),
+            Error::new(29..38, "`font_size` is already set to an integer")
+        );
+    }
+
+    #[test]
+    fn spans() {
+        let value = parse("editor = { font_size = 12; };\n").unwrap();
+        let ValueKind::Set(bindings) = value.kind else {
+            panic!("expected a set");
+        };
+        let (key, editor) = &bindings[0];
+        assert_eq!(key.span, 0..6);
+        assert_eq!(editor.span, 9..28);
+    }
+}
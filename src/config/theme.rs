@@ -0,0 +1,396 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use cosmic_text::Color as CTColor;
+
+use super::syntax::{self, Error, Key, Value, ValueKind};
+use super::{boolean, colour, mismatch, set, unknown, LoadError, Section};
+
+/// The themes that come with the editor, by name, in the order switching themes goes through them.
+/// `dark` is the default, and the only one not written as a theme file.
+const BUILT_IN: &[(&str, &str)] = &[
+    ("dark", ""),
+    ("light", include_str!("themes/light.exl")),
+    ("solarized-dark", include_str!("themes/solarized-dark.exl")),
+    (
+        "solarized-light",
+        include_str!("themes/solarized-light.exl"),
+    ),
+];
+
+/// How text with a syntax highlighting scope like `keyword` or `function.macro` is drawn.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SyntaxStyle {
+    pub colour: CTColor,
+    pub bold: bool,
+    pub italic: bool,
+}
+
+impl SyntaxStyle {
+    const fn plain(colour: CTColor) -> Self {
+        Self {
+            colour,
+            bold: false,
+            italic: false,
+        }
+    }
+}
+
+/// The syntax styles of the dark theme.
+const DARK_SYNTAX: &[(&str, SyntaxStyle)] = &[
+    ("attribute", SyntaxStyle::plain(CTColor::rgb(229, 192, 123))),
+    (
+        "comment",
+        SyntaxStyle {
+            italic: true,
+            ..SyntaxStyle::plain(CTColor::rgb(127, 132, 142))
+        },
+    ),
+    ("constant", SyntaxStyle::plain(CTColor::rgb(209, 154, 102))),
+    (
+        "constructor",
+        SyntaxStyle::plain(CTColor::rgb(229, 192, 123)),
+    ),
+    ("escape", SyntaxStyle::plain(CTColor::rgb(86, 182, 194))),
+    ("function", SyntaxStyle::plain(CTColor::rgb(97, 175, 239))),
+    (
+        "function.macro",
+        SyntaxStyle::plain(CTColor::rgb(86, 182, 194)),
+    ),
+    (
+        "keyword",
+        SyntaxStyle {
+            bold: true,
+            ..SyntaxStyle::plain(CTColor::rgb(198, 120, 221))
+        },
+    ),
+    (
+        "label",
+        SyntaxStyle {
+            italic: true,
+            ..SyntaxStyle::plain(CTColor::rgb(224, 108, 117))
+        },
+    ),
+    ("operator", SyntaxStyle::plain(CTColor::rgb(86, 182, 194))),
+    ("property", SyntaxStyle::plain(CTColor::rgb(224, 108, 117))),
+    (
+        "punctuation",
+        SyntaxStyle::plain(CTColor::rgb(171, 178, 191)),
+    ),
+    ("string", SyntaxStyle::plain(CTColor::rgb(152, 195, 121))),
+    ("type", SyntaxStyle::plain(CTColor::rgb(229, 192, 123))),
+    (
+        "variable.builtin",
+        SyntaxStyle {
+            italic: true,
+            ..SyntaxStyle::plain(CTColor::rgb(224, 108, 117))
+        },
+    ),
+    (
+        "variable.parameter",
+        SyntaxStyle::plain(CTColor::rgb(224, 108, 117)),
+    ),
+];
+
+/// The colours everything is drawn in: one for each part of the UI, and a style for each syntax
+/// highlighting scope.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Theme {
+    /// What the theme was loaded as.
+    pub name: String,
+
+    /// Behind the text being edited.
+    pub background: CTColor,
+    pub foreground: CTColor,
+    /// Text that's less important than what's around it, like key bindings next to commands.
+    pub dimmed: CTColor,
+    /// The parts of a list item matching what's being searched for.
+    pub matched: CTColor,
+
+    pub selection: CTColor,
+    /// Selections other than the primary one.
+    pub secondary_selection: CTColor,
+    pub selected_text: CTColor,
+    pub cursor: CTColor,
+    pub secondary_cursor: CTColor,
+    /// Behind text matching a search.
+    pub search_match: CTColor,
+    pub scrollbar: CTColor,
+
+    /// Behind the tabs of the open buffers.
+    pub tab_bar: CTColor,
+    pub tab: CTColor,
+    pub current_tab: CTColor,
+    /// Between the views of the editor.
+    pub divider: CTColor,
+    /// Behind messages and prompts.
+    pub message: CTColor,
+    /// Behind the nav bar.
+    pub panel: CTColor,
+    /// Behind the selected item of a list, like a file in the nav bar or a completion.
+    pub current_item: CTColor,
+    /// Behind popups like completions, the command palette and the file picker.
+    pub popup: CTColor,
+    pub popup_border: CTColor,
+
+    /// Behind the status bar.
+    pub status: CTColor,
+    /// Behind a part of the status bar that can be clicked while the mouse is over it.
+    pub status_hovered: CTColor,
+    pub normal_mode: CTColor,
+    pub insert_mode: CTColor,
+    pub select_mode: CTColor,
+
+    pub directory: CTColor,
+    pub error: CTColor,
+    pub warning: CTColor,
+    pub info: CTColor,
+    pub hint: CTColor,
+    /// Lines that a replacement adds.
+    pub added: CTColor,
+    /// Lines that a replacement removes.
+    pub removed: CTColor,
+
+    /// Styles by syntax highlighting scope.
+    pub syntax: Vec<(String, SyntaxStyle)>,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            name: "dark".to_owned(),
+            background: CTColor::rgb(24, 24, 24),
+            foreground: CTColor::rgb(200, 200, 200),
+            dimmed: CTColor::rgb(140, 140, 140),
+            matched: CTColor::rgb(229, 192, 123),
+            selection: CTColor::rgba(128, 63, 16, 100),
+            secondary_selection: CTColor::rgba(128, 63, 16, 50),
+            selected_text: CTColor::rgb(0, 128, 196),
+            cursor: CTColor::rgba(255, 255, 255, 128),
+            secondary_cursor: CTColor::rgba(160, 160, 160, 96),
+            search_match: CTColor::rgba(229, 192, 123, 60),
+            scrollbar: CTColor::rgba(255, 255, 255, 64),
+            tab_bar: CTColor::rgb(36, 36, 36),
+            tab: CTColor::rgb(150, 150, 150),
+            current_tab: CTColor::rgb(220, 220, 220),
+            divider: CTColor::rgb(64, 64, 64),
+            message: CTColor::rgb(40, 40, 40),
+            panel: CTColor::rgb(48, 48, 48),
+            current_item: CTColor::rgb(62, 68, 81),
+            popup: CTColor::rgb(40, 44, 52),
+            popup_border: CTColor::rgb(80, 86, 98),
+            status: CTColor::rgb(64, 64, 64),
+            status_hovered: CTColor::rgb(88, 88, 88),
+            normal_mode: CTColor::rgb(70, 110, 170),
+            insert_mode: CTColor::rgb(80, 140, 80),
+            select_mode: CTColor::rgb(170, 120, 60),
+            directory: CTColor::rgb(97, 175, 239),
+            error: CTColor::rgb(240, 96, 96),
+            warning: CTColor::rgb(229, 192, 123),
+            info: CTColor::rgb(97, 175, 239),
+            hint: CTColor::rgb(127, 132, 142),
+            added: CTColor::rgb(152, 195, 121),
+            removed: CTColor::rgb(240, 96, 96),
+            syntax: DARK_SYNTAX
+                .iter()
+                .map(|&(scope, style)| (scope.to_owned(), style))
+                .collect(),
+        }
+    }
+}
+
+impl Section for Theme {
+    const SETTINGS: &'static [&'static str] = &[
+        "background",
+        "foreground",
+        "dimmed",
+        "matched",
+        "selection",
+        "secondary_selection",
+        "selected_text",
+        "cursor",
+        "secondary_cursor",
+        "search_match",
+        "scrollbar",
+        "tab_bar",
+        "tab",
+        "current_tab",
+        "divider",
+        "message",
+        "panel",
+        "current_item",
+        "popup",
+        "popup_border",
+        "status",
+        "status_hovered",
+        "normal_mode",
+        "insert_mode",
+        "select_mode",
+        "directory",
+        "error",
+        "warning",
+        "info",
+        "hint",
+        "added",
+        "removed",
+        "syntax",
+    ];
+
+    fn set(&mut self, key: &Key, value: &Value) -> Result<(), Error> {
+        let field = match key.name.as_str() {
+            "syntax" => return set_scopes(&mut self.syntax, None, value),
+            "background" => &mut self.background,
+            "foreground" => &mut self.foreground,
+            "dimmed" => &mut self.dimmed,
+            "matched" => &mut self.matched,
+            "selection" => &mut self.selection,
+            "secondary_selection" => &mut self.secondary_selection,
+            "selected_text" => &mut self.selected_text,
+            "cursor" => &mut self.cursor,
+            "secondary_cursor" => &mut self.secondary_cursor,
+            "search_match" => &mut self.search_match,
+            "scrollbar" => &mut self.scrollbar,
+            "tab_bar" => &mut self.tab_bar,
+            "tab" => &mut self.tab,
+            "current_tab" => &mut self.current_tab,
+            "divider" => &mut self.divider,
+            "message" => &mut self.message,
+            "panel" => &mut self.panel,
+            "current_item" => &mut self.current_item,
+            "popup" => &mut self.popup,
+            "popup_border" => &mut self.popup_border,
+            "status" => &mut self.status,
+            "status_hovered" => &mut self.status_hovered,
+            "normal_mode" => &mut self.normal_mode,
+            "insert_mode" => &mut self.insert_mode,
+            "select_mode" => &mut self.select_mode,
+            "directory" => &mut self.directory,
+            "error" => &mut self.error,
+            "warning" => &mut self.warning,
+            "info" => &mut self.info,
+            "hint" => &mut self.hint,
+            "added" => &mut self.added,
+            "removed" => &mut self.removed,
+            _ => return Err(unknown::<Self>(key)),
+        };
+        *field = colour(value)?;
+        Ok(())
+    }
+}
+
+/// Sets the styles of the scopes in `value` under `parent`, each of which is given either a
+/// colour or a set. A set has the style's `colour`, and whether it's `bold` or `italic`, along
+/// with any scopes nested in it, like `method` in `function = { colour = #..; method = #..; };`.
+fn set_scopes(
+    styles: &mut Vec<(String, SyntaxStyle)>,
+    parent: Option<&str>,
+    value: &Value,
+) -> Result<(), Error> {
+    for (key, value) in set(value)? {
+        let scope = match parent {
+            Some(parent) => format!("{parent}.{}", key.name),
+            None => key.name.clone(),
+        };
+        let style = match &value.kind {
+            &ValueKind::Colour(colour) => SyntaxStyle::plain(colour),
+            ValueKind::Set(bindings) => {
+                let (mut colour, mut bold, mut italic) = (None, false, false);
+                let mut styled = false;
+                for (key, value) in bindings {
+                    match key.name.as_str() {
+                        "colour" => colour = Some(super::colour(value)?),
+                        "bold" => bold = boolean(value)?,
+                        "italic" => italic = boolean(value)?,
+                        _ => continue,
+                    }
+                    styled = true;
+                }
+                let nested: Vec<_> = bindings
+                    .iter()
+                    .filter(|(key, _)| !matches!(key.name.as_str(), "colour" | "bold" | "italic"))
+                    .cloned()
+                    .collect();
+                if !nested.is_empty() {
+                    let nested = Value {
+                        kind: ValueKind::Set(nested),
+                        span: value.span.clone(),
+                    };
+                    set_scopes(styles, Some(&scope), &nested)?;
+                }
+                if !styled {
+                    continue;
+                }
+                let Some(colour) = colour else {
+                    return Err(Error::new(
+                        key.span.clone(),
+                        format!("`{scope}` needs a `colour` to go with being bold or italic"),
+                    ));
+                };
+                SyntaxStyle {
+                    colour,
+                    bold,
+                    italic,
+                }
+            }
+            _ => return Err(mismatch("a colour or a set", value)),
+        };
+        match styles.iter_mut().find(|(name, _)| *name == scope) {
+            Some((_, existing)) => *existing = style,
+            None => styles.push((scope, style)),
+        }
+    }
+    Ok(())
+}
+
+impl Theme {
+    /// The names of the built-in themes.
+    pub fn built_in_names() -> impl Iterator<Item = &'static str> {
+        BUILT_IN.iter().map(|&(name, _)| name)
+    }
+
+    /// The built-in theme called `name`, if there is one.
+    pub fn built_in(name: &str) -> Option<Self> {
+        let &(name, source) = BUILT_IN.iter().find(|&&(built_in, _)| built_in == name)?;
+        let value = syntax::parse(source).expect("built-in themes are valid");
+        let mut theme: Self = Section::decode(&value).expect("built-in themes are valid");
+        theme.name = name.to_owned();
+        Some(theme)
+    }
+
+    /// The theme called `name`, which is `name.exl` in `dir` if there's a file there, and
+    /// otherwise the built-in one by that name, if there is one. Anything a theme file leaves out
+    /// is the same as in the dark theme.
+    pub fn load(name: &str, dir: &Path) -> Result<Option<Self>, LoadError> {
+        let path = dir.join(format!("{name}.exl"));
+        let source = match fs::read_to_string(&path) {
+            Ok(source) => source,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Self::built_in(name)),
+            Err(err) => return Err(LoadError::Io(path, err)),
+        };
+        let theme = syntax::parse(&source).and_then(|value| Section::decode(&value));
+        match theme {
+            Ok(theme) => Ok(Some(Self {
+                name: name.to_owned(),
+                ..theme
+            })),
+            Err(error) => Err(LoadError::Invalid {
+                path,
+                source,
+                error,
+            }),
+        }
+    }
+
+    /// How text in the syntax highlighting scope `scope` is drawn. A scope such as
+    /// `function.method` with no style of its own falls back to `function`.
+    pub fn syntax_style(&self, scope: &str) -> Option<SyntaxStyle> {
+        let mut name = scope;
+        loop {
+            if let Some(&(_, style)) = self.syntax.iter().find(|(scope, _)| scope == name) {
+                return Some(style);
+            }
+            name = name.rsplit_once('.')?.0;
+        }
+    }
+}
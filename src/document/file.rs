@@ -0,0 +1,213 @@
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use chardetng::EncodingDetector;
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
+
+/// The line terminator a document is written back with. Text is always held in memory with
+/// bare `\n` terminators and converted on load/save.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+}
+
+impl LineEnding {
+    /// Picks whichever terminator occurs most often, preferring `\n` on a tie (or no lines).
+    pub fn detect(text: &str) -> Self {
+        let crlf = text.matches("\r\n").count();
+        let lf = text.matches('\n').count() - crlf;
+        if crlf > lf {
+            Self::CrLf
+        } else {
+            Self::Lf
+        }
+    }
+
+    /// Whether writing `text` back with these line endings changes any of the ones it has, which
+    /// happens when a file mostly ending its lines in `\r\n` has some ending in `\n` alone.
+    pub fn normalises(self, text: &str) -> bool {
+        match self {
+            Self::Lf => false,
+            Self::CrLf => text.matches('\n').count() > text.matches("\r\n").count(),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Lf => "\n",
+            Self::CrLf => "\r\n",
+        }
+    }
+}
+
+impl fmt::Display for LineEnding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Lf => "LF",
+            Self::CrLf => "CRLF",
+        })
+    }
+}
+
+/// The encoding a document was read with, including whether it started with a byte order mark,
+/// so that it can be written back byte-for-byte the same way.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TextEncoding {
+    encoding: &'static Encoding,
+    bom: bool,
+}
+
+impl Default for TextEncoding {
+    fn default() -> Self {
+        Self {
+            encoding: UTF_8,
+            bom: false,
+        }
+    }
+}
+
+impl TextEncoding {
+    /// Decodes `bytes`, trusting a BOM if there is one, then UTF-8 if the bytes are valid UTF-8,
+    /// and only then falling back to guessing a legacy encoding.
+    pub fn decode(bytes: &[u8]) -> (Self, String) {
+        if let Some((encoding, bom_len)) = Encoding::for_bom(bytes) {
+            let (text, _) = encoding.decode_without_bom_handling(&bytes[bom_len..]);
+            return (
+                Self {
+                    encoding,
+                    bom: true,
+                },
+                text.into_owned(),
+            );
+        }
+
+        if let Ok(text) = std::str::from_utf8(bytes) {
+            return (Self::default(), text.to_owned());
+        }
+
+        let mut detector = EncodingDetector::new();
+        detector.feed(bytes, true);
+        let encoding = detector.guess(None, false);
+        let (text, _) = encoding.decode_without_bom_handling(bytes);
+        (
+            Self {
+                encoding,
+                bom: false,
+            },
+            text.into_owned(),
+        )
+    }
+
+    pub fn encode(&self, text: &str) -> Result<Vec<u8>, SaveError> {
+        // encoding_rs only decodes UTF-16, so the encoder has to be written by hand
+        if self.encoding == UTF_16LE || self.encoding == UTF_16BE {
+            let to_bytes = if self.encoding == UTF_16LE {
+                u16::to_le_bytes
+            } else {
+                u16::to_be_bytes
+            };
+            let mut bytes = Vec::with_capacity(text.len() * 2 + 2);
+            if self.bom {
+                bytes.extend_from_slice(&to_bytes(0xFEFF));
+            }
+            for unit in text.encode_utf16() {
+                bytes.extend_from_slice(&to_bytes(unit));
+            }
+            return Ok(bytes);
+        }
+
+        let mut bytes = Vec::with_capacity(text.len() + 3);
+        if self.bom && self.encoding == UTF_8 {
+            bytes.extend_from_slice(b"\xEF\xBB\xBF");
+        }
+        let (encoded, _, unmappable) = self.encoding.encode(text);
+        if unmappable {
+            // encoding_rs would have substituted HTML character references, which is never what
+            // we want to silently write into a source file
+            return Err(SaveError::Unencodable(self.encoding.name()));
+        }
+        bytes.extend_from_slice(&encoded);
+        Ok(bytes)
+    }
+}
+
+impl fmt::Display for TextEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.encoding.name())?;
+        if self.bom {
+            f.write_str(" BOM")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum SaveError {
+    NoPath,
+    Unencodable(&'static str),
+    Io(PathBuf, io::Error),
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoPath => write!(f, "the buffer is not associated with a file"),
+            Self::Unencodable(encoding) => write!(
+                f,
+                "the buffer contains characters that cannot be represented in {encoding}"
+            ),
+            Self::Io(path, err) => write!(f, "could not write {}: {err}", path.display()),
+        }
+    }
+}
+
+impl std::error::Error for SaveError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(_, err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Writes `bytes` to a temporary file next to `path` and renames it over the top, so that a
+/// failed or interrupted write never leaves a truncated file behind.
+pub fn write_atomic(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    // Write through symlinks rather than replacing them with a regular file
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_owned());
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    let mut temp_name = OsString::from(".");
+    temp_name.push(file_name);
+    temp_name.push(format!(".{}.tmp", process::id()));
+    let temp_path = dir.join(temp_name);
+
+    let result = (|| {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp_path)?;
+        file.write_all(bytes)?;
+        file.sync_all()?;
+        if let Ok(metadata) = fs::metadata(&path) {
+            fs::set_permissions(&temp_path, metadata.permissions())?;
+        }
+        fs::rename(&temp_path, &path)
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
@@ -0,0 +1,345 @@
+use std::mem;
+
+use super::selection::Selection;
+
+/// One replacement as it was applied, keeping the text it removed so it can be reverted.
+#[derive(Clone, Debug)]
+pub struct Edit {
+    pub start: usize,
+    pub deleted: String,
+    pub inserted: String,
+}
+
+impl Edit {
+    fn inverse(&self) -> Self {
+        Self {
+            start: self.start,
+            deleted: self.inserted.clone(),
+            inserted: self.deleted.clone(),
+        }
+    }
+}
+
+/// What caused a group of edits, which decides whether it can be merged into the one before.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EditKind {
+    /// Typing a character. Runs of these undo as one step.
+    Insert,
+    /// Deleting a single character with Backspace or Delete. Runs of these undo as one step.
+    Delete,
+    /// Anything else (pastes, word deletions, ...), which always undoes on its own.
+    Other,
+}
+
+/// A node in the undo tree: the edits that lead to it from its parent, and the selections on
+/// either side of them.
+struct Revision {
+    parent: usize,
+    /// The child that redo moves to, i.e. the branch most recently created or undone out of.
+    last_child: Option<usize>,
+    edits: Vec<Edit>,
+    kind: EditKind,
+    before: Selection,
+    after: Selection,
+}
+
+/// Undo tree for a document. Undoing and then editing starts a new branch rather than discarding
+/// the undone revisions, and redo follows whichever branch was visited last.
+pub struct History {
+    revisions: Vec<Revision>,
+    current: usize,
+    /// Edits applied since the last commit, which become (or join) a revision together.
+    pending: Vec<Edit>,
+    /// Whether the current revision may still absorb further edits of the same kind.
+    open: bool,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            revisions: vec![Revision {
+                parent: 0,
+                last_child: None,
+                edits: Vec::new(),
+                kind: EditKind::Other,
+                before: Selection::point(0),
+                after: Selection::point(0),
+            }],
+            current: 0,
+            pending: Vec::new(),
+            open: false,
+        }
+    }
+}
+
+impl History {
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    pub fn record(&mut self, edit: Edit) {
+        self.pending.push(edit);
+    }
+
+    /// Groups the edits recorded since the last commit into an undo step. Edits of the same
+    /// coalescing kind that carry on from where the current revision left the cursor are merged
+    /// into it instead.
+    pub fn commit(&mut self, kind: EditKind, before: Selection, after: Selection) {
+        if self.pending.is_empty() {
+            return;
+        }
+        let edits = mem::take(&mut self.pending);
+
+        let current = &mut self.revisions[self.current];
+        if self.open && kind != EditKind::Other && current.kind == kind && current.after == before {
+            current.edits.extend(edits);
+            current.after = after;
+            return;
+        }
+
+        let revision = self.revisions.len();
+        self.revisions.push(Revision {
+            parent: self.current,
+            last_child: None,
+            edits,
+            kind,
+            before,
+            after,
+        });
+        self.revisions[self.current].last_child = Some(revision);
+        self.current = revision;
+        self.open = true;
+    }
+
+    /// Stops the current revision from absorbing any more edits, e.g. because it was saved.
+    pub fn seal(&mut self) {
+        self.open = false;
+    }
+
+    /// Steps back to the parent revision, returning the edits that revert the current one (in
+    /// the order they must be applied) and the selections to restore.
+    pub fn undo(&mut self) -> Option<(Vec<Edit>, Selection)> {
+        if self.current == 0 {
+            return None;
+        }
+        self.open = false;
+
+        let revision = &self.revisions[self.current];
+        let edits = revision.edits.iter().rev().map(Edit::inverse).collect();
+        let selection = revision.before.clone();
+        let parent = revision.parent;
+        self.revisions[parent].last_child = Some(self.current);
+        self.current = parent;
+        Some((edits, selection))
+    }
+
+    /// Steps forward along the most recently visited branch, returning the edits to re-apply and
+    /// the selections to restore.
+    pub fn redo(&mut self) -> Option<(Vec<Edit>, Selection)> {
+        let child = self.revisions[self.current].last_child?;
+        self.open = false;
+
+        let revision = &self.revisions[child];
+        self.current = child;
+        Some((revision.edits.clone(), revision.after.clone()))
+    }
+
+    /// Moves to the revision made just before (or after) the current one, whichever branch it is
+    /// on, returning the edits that get there and the selections to restore. This is how a branch
+    /// left behind by undoing and then editing is reached again.
+    pub fn travel(&mut self, forward: bool) -> Option<(Vec<Edit>, Selection)> {
+        let target = if forward {
+            Some(self.current + 1).filter(|&target| target < self.revisions.len())?
+        } else {
+            self.current.checked_sub(1)?
+        };
+        self.open = false;
+
+        // Undo up to the revision both branches share, then redo down to the target. Parents
+        // always come before their children, so the later of the two is the one to step up from
+        let mut edits = Vec::new();
+        let mut selection = None;
+        let (mut up, mut down) = (self.current, target);
+        let mut path = Vec::new();
+        while up != down {
+            if up > down {
+                let revision = &self.revisions[up];
+                edits.extend(revision.edits.iter().rev().map(Edit::inverse));
+                selection = Some(revision.before.clone());
+                up = revision.parent;
+            } else {
+                path.push(down);
+                down = self.revisions[down].parent;
+            }
+        }
+        for &revision in path.iter().rev() {
+            let parent = self.revisions[revision].parent;
+            self.revisions[parent].last_child = Some(revision);
+            edits.extend(self.revisions[revision].edits.iter().cloned());
+            selection = Some(self.revisions[revision].after.clone());
+        }
+        self.current = target;
+        Some((edits, selection?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Edit, EditKind, History};
+    use crate::document::selection::Selection;
+
+    /// Records inserting `text` at `start` as one commit of `kind`, with the cursor moving from
+    /// before the text to after it.
+    fn insert(history: &mut History, kind: EditKind, start: usize, text: &str) {
+        history.record(Edit {
+            start,
+            deleted: String::new(),
+            inserted: text.to_owned(),
+        });
+        let end = start + text.len();
+        history.commit(kind, Selection::point(start), Selection::point(end));
+    }
+
+    fn type_text(history: &mut History, start: usize, text: &str) {
+        for (i, c) in text.char_indices() {
+            insert(history, EditKind::Insert, start + i, &c.to_string());
+        }
+    }
+
+    /// The text each undo step puts back, most recent first, undoing everything.
+    fn undo_all(history: &mut History) -> Vec<String> {
+        let mut steps = Vec::new();
+        while let Some((edits, _)) = history.undo() {
+            steps.push(edits.iter().map(|edit| edit.deleted.as_str()).collect());
+        }
+        steps
+    }
+
+    #[test]
+    fn typing_coalesces() {
+        let mut history = History::default();
+        type_text(&mut history, 0, "hello");
+        let (edits, selection) = history.undo().unwrap();
+        assert_eq!(edits.len(), 5);
+        // Undone in reverse, putting the cursor back where the typing began
+        assert_eq!(edits[0].start, 4);
+        assert_eq!(selection, Selection::point(0));
+        assert!(history.undo().is_none());
+    }
+
+    #[test]
+    fn typing_elsewhere_starts_a_new_step() {
+        let mut history = History::default();
+        type_text(&mut history, 0, "ab");
+        type_text(&mut history, 10, "cd");
+        assert_eq!(undo_all(&mut history), ["dc", "ba"]);
+    }
+
+    #[test]
+    fn pastes_and_word_deletions_stay_separate() {
+        let mut history = History::default();
+        type_text(&mut history, 0, "a");
+        insert(&mut history, EditKind::Other, 1, "pasted");
+        insert(&mut history, EditKind::Other, 7, "again");
+        type_text(&mut history, 12, "b");
+        assert_eq!(undo_all(&mut history), ["b", "again", "pasted", "a"]);
+
+        let mut history = History::default();
+        type_text(&mut history, 0, "ab");
+        history.record(Edit {
+            start: 0,
+            deleted: "ab".to_owned(),
+            inserted: String::new(),
+        });
+        history.commit(EditKind::Other, Selection::point(2), Selection::point(0));
+        let (edits, _) = history.undo().unwrap();
+        assert_eq!(edits[0].inserted, "ab");
+        assert_eq!(undo_all(&mut history), ["ba"]);
+    }
+
+    #[test]
+    fn typing_and_deleting_stay_separate() {
+        let mut history = History::default();
+        type_text(&mut history, 0, "ab");
+        history.record(Edit {
+            start: 1,
+            deleted: "b".to_owned(),
+            inserted: String::new(),
+        });
+        history.commit(EditKind::Delete, Selection::point(2), Selection::point(1));
+        assert_eq!(undo_all(&mut history), ["", "ba"]);
+    }
+
+    #[test]
+    fn editing_after_undo_keeps_the_old_branch() {
+        let mut history = History::default();
+        type_text(&mut history, 0, "ab");
+        insert(&mut history, EditKind::Other, 2, "old");
+        history.undo().unwrap();
+        insert(&mut history, EditKind::Other, 2, "new");
+
+        // Redo follows the newest branch
+        assert!(history.redo().is_none());
+        history.undo().unwrap();
+        let (edits, _) = history.redo().unwrap();
+        assert_eq!(edits[0].inserted, "new");
+
+        // Going back in time reaches the old branch, by way of the revision both share
+        let (edits, selection) = history.travel(false).unwrap();
+        let edits: Vec<_> = edits
+            .iter()
+            .map(|edit| (edit.deleted.as_str(), edit.inserted.as_str()))
+            .collect();
+        assert_eq!(edits, [("new", ""), ("", "old")]);
+        assert_eq!(selection, Selection::point(5));
+
+        // After which undo and redo follow the old branch
+        let (edits, _) = history.undo().unwrap();
+        assert_eq!(edits[0].deleted, "old");
+        let (edits, _) = history.redo().unwrap();
+        assert_eq!(edits[0].inserted, "old");
+
+        // And going forward in time comes back to the new one
+        let (edits, _) = history.travel(true).unwrap();
+        assert_eq!(edits.len(), 2);
+        assert_eq!(edits[1].inserted, "new");
+        assert!(history.travel(true).is_none());
+    }
+
+    #[test]
+    fn travelling_along_one_branch_is_undo_and_redo() {
+        let mut history = History::default();
+        type_text(&mut history, 0, "ab");
+        insert(&mut history, EditKind::Other, 2, "c");
+        let (edits, selection) = history.travel(false).unwrap();
+        assert_eq!(edits[0].deleted, "c");
+        assert_eq!(selection, Selection::point(2));
+        let (edits, selection) = history.travel(false).unwrap();
+        assert_eq!(edits.len(), 2);
+        assert_eq!(selection, Selection::point(0));
+        assert!(history.travel(false).is_none());
+        let (edits, selection) = history.travel(true).unwrap();
+        assert_eq!(edits[1].inserted, "b");
+        assert_eq!(selection, Selection::point(2));
+    }
+
+    #[test]
+    fn undone_steps_do_not_absorb_typing() {
+        let mut history = History::default();
+        type_text(&mut history, 0, "ab");
+        history.undo().unwrap();
+        history.redo().unwrap();
+        type_text(&mut history, 2, "c");
+        assert_eq!(undo_all(&mut history), ["c", "ba"]);
+    }
+
+    #[test]
+    fn no_merging_across_a_save() {
+        let mut history = History::default();
+        type_text(&mut history, 0, "ab");
+        // As `Document::save` does
+        history.seal();
+        type_text(&mut history, 2, "cd");
+        assert_eq!(undo_all(&mut history), ["dc", "ba"]);
+    }
+}
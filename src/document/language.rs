@@ -0,0 +1,46 @@
+use std::path::Path;
+
+use tree_sitter::Language as Grammar;
+
+/// What we know about a language: which files are in it, how to parse and highlight it, and
+/// which language server understands it.
+pub struct Language {
+    /// The identifier language servers know the language by.
+    pub name: &'static str,
+    pub extensions: &'static [&'static str],
+    pub grammar: fn() -> Grammar,
+    pub highlights: &'static str,
+    /// The command that starts the language server, with its arguments.
+    pub language_server: &'static [&'static str],
+    /// Files that mark the root of a project, which the language server is started in.
+    pub roots: &'static [&'static str],
+}
+
+const LANGUAGES: &[Language] = &[Language {
+    name: "rust",
+    extensions: &["rs"],
+    grammar: || tree_sitter_rust::LANGUAGE.into(),
+    highlights: tree_sitter_rust::HIGHLIGHTS_QUERY,
+    language_server: &["rust-analyzer"],
+    roots: &["Cargo.toml"],
+}];
+
+impl Language {
+    /// The language for `path`'s extension, if there is one.
+    pub fn for_path(path: &Path) -> Option<&'static Self> {
+        let extension = path.extension()?.to_str()?;
+        LANGUAGES
+            .iter()
+            .find(|language| language.extensions.contains(&extension))
+    }
+
+    /// The outermost directory above `path` holding one of the language's root files, or the
+    /// directory `path` is in if there isn't one.
+    pub fn project_root<'a>(&self, path: &'a Path) -> Option<&'a Path> {
+        let dir = path.parent()?;
+        dir.ancestors()
+            .filter(|dir| self.roots.iter().any(|root| dir.join(root).exists()))
+            .last()
+            .or(Some(dir))
+    }
+}
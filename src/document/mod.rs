@@ -0,0 +1,518 @@
+mod file;
+mod history;
+mod language;
+pub mod movement;
+pub mod selection;
+mod syntax;
+
+use std::collections::VecDeque;
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use crop::{Rope, RopeSlice};
+
+pub use file::{LineEnding, SaveError, TextEncoding};
+pub use history::EditKind;
+pub use language::Language;
+
+use history::{Edit, History};
+
+/// How many of the latest replacements are kept for views to catch up with. A view further behind
+/// than that lays the whole document out again instead.
+const MAX_REPLACEMENTS: usize = 1 << 14;
+use selection::Selection;
+use syntax::Syntax;
+
+/// An open file: the text itself, held in a rope with `\n` line endings, plus everything needed
+/// to write it back the way it was read.
+pub struct Document {
+    path: Option<PathBuf>,
+    encoding: TextEncoding,
+    line_ending: LineEnding,
+    /// Set when the file had lines that don't end in `line_ending`, until they're written back
+    /// with it.
+    mixed_line_endings: bool,
+    text: Rope,
+    history: History,
+    /// The revision that was last read from or written to disk.
+    saved_revision: usize,
+    language: Option<&'static Language>,
+    /// Only there for languages we have a grammar for.
+    syntax: Option<Syntax>,
+    /// Every replacement since `take_changes` was last called.
+    changes: Vec<TextChange>,
+    /// Counts every replacement ever made, see `version`.
+    version: usize,
+    /// The latest replacements, the last of which brought the document to `version`.
+    replacements: VecDeque<Replacement>,
+}
+
+/// Where a single replacement landed, in both byte offsets and lines, so that views of the
+/// document can patch their own state instead of rebuilding it.
+#[derive(Clone, Copy, Debug)]
+pub struct Change {
+    pub new_end: usize,
+    pub start_line: usize,
+    pub old_end_line: usize,
+    pub new_end_line: usize,
+}
+
+/// A replacement as it was applied, kept so that views of the document other than the one that
+/// made it can move their selections past it and patch their lines to match.
+#[derive(Clone, Debug)]
+pub struct Replacement {
+    pub start: usize,
+    /// How many bytes were replaced.
+    pub deleted: usize,
+    /// How many bytes replaced them.
+    pub inserted: usize,
+    pub change: Change,
+    /// Where the highlighting changed beyond the replaced text when the document was next
+    /// reparsed, in the text as it was right after the replacement.
+    pub rehighlighted: Vec<Range<usize>>,
+}
+
+impl Replacement {
+    /// Where the byte at `pos` is after the replacement. Text after the replaced bytes moves
+    /// along with them, and a position inside them goes to where they started.
+    pub fn map(&self, pos: usize) -> usize {
+        if pos <= self.start {
+            pos
+        } else if pos >= self.start + self.deleted {
+            pos + self.inserted - self.deleted
+        } else {
+            self.start
+        }
+    }
+}
+
+/// A replacement as it was applied, positioned in the text from just before it, so that
+/// language servers can be told about edits without being sent the whole text.
+#[derive(Clone, Debug)]
+pub struct TextChange {
+    pub start: LineColumn,
+    pub old_end: LineColumn,
+    pub text: String,
+}
+
+/// A position as a line and a column. Language servers count columns in either bytes or UTF-16
+/// code units, so both are kept.
+#[derive(Clone, Copy, Debug)]
+pub struct LineColumn {
+    pub line: usize,
+    pub byte: usize,
+    pub utf16: usize,
+}
+
+impl Default for Document {
+    fn default() -> Self {
+        Self {
+            path: None,
+            encoding: TextEncoding::default(),
+            line_ending: LineEnding::Lf,
+            mixed_line_endings: false,
+            text: Rope::new(),
+            history: History::default(),
+            saved_revision: 0,
+            language: None,
+            syntax: None,
+            changes: Vec::new(),
+            version: 0,
+            replacements: VecDeque::new(),
+        }
+    }
+}
+
+impl Document {
+    /// Reads the file at `path`, normalising its line endings to `\n`. A file that doesn't exist
+    /// yet opens as an empty document that will be created on the first save.
+    pub fn open(path: PathBuf) -> io::Result<Self> {
+        let bytes = match fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                let language = Language::for_path(&path);
+                return Ok(Self {
+                    syntax: language.and_then(|language| Syntax::new(language, &Rope::new())),
+                    language,
+                    path: Some(path),
+                    ..Default::default()
+                });
+            }
+            Err(err) => return Err(err),
+        };
+
+        let (encoding, text) = TextEncoding::decode(&bytes);
+        let line_ending = LineEnding::detect(&text);
+        let mixed_line_endings = line_ending.normalises(&text);
+        let text = match line_ending {
+            LineEnding::Lf => text,
+            LineEnding::CrLf => text.replace("\r\n", "\n"),
+        };
+        let text = Rope::from(text);
+        let language = Language::for_path(&path);
+        Ok(Self {
+            syntax: language.and_then(|language| Syntax::new(language, &text)),
+            language,
+            path: Some(path),
+            encoding,
+            line_ending,
+            mixed_line_endings,
+            text,
+            ..Default::default()
+        })
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Changes where the document is saved to, like after the file was moved.
+    pub fn set_path(&mut self, path: PathBuf) {
+        self.path = Some(path);
+    }
+
+    pub fn language(&self) -> Option<&'static Language> {
+        self.language
+    }
+
+    pub fn encoding(&self) -> TextEncoding {
+        self.encoding
+    }
+
+    pub fn line_ending(&self) -> LineEnding {
+        self.line_ending
+    }
+
+    /// Whether saving will change the endings of some of the lines the file was read with, to
+    /// `line_ending`.
+    pub fn has_mixed_line_endings(&self) -> bool {
+        self.mixed_line_endings
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.history.current() != self.saved_revision
+    }
+
+    pub fn text(&self) -> &Rope {
+        &self.text
+    }
+
+    /// The number of lines, counting the empty line after a trailing `\n` (which `Rope::line_len`
+    /// doesn't), since the cursor can be placed on it.
+    pub fn len_lines(&self) -> usize {
+        self.text.line_of_byte(self.text.byte_len()) + 1
+    }
+
+    /// The text of a line, without its terminator.
+    pub fn line(&self, line: usize) -> RopeSlice<'_> {
+        if line < self.text.line_len() {
+            self.text.line(line)
+        } else {
+            self.text.byte_slice(self.text.byte_len()..)
+        }
+    }
+
+    pub fn byte_of_line(&self, line: usize) -> usize {
+        self.text.byte_of_line(line)
+    }
+
+    pub fn line_of_byte(&self, byte: usize) -> usize {
+        self.text.line_of_byte(byte)
+    }
+
+    pub fn line_column(&self, byte: usize) -> LineColumn {
+        let line = self.text.line_of_byte(byte);
+        let line_start = self.text.byte_of_line(line);
+        LineColumn {
+            line,
+            byte: byte - line_start,
+            utf16: self
+                .text
+                .byte_slice(line_start..byte)
+                .chars()
+                .map(char::len_utf16)
+                .sum(),
+        }
+    }
+
+    /// The byte offset of a column on `line`, counted in UTF-16 code units if `utf16` and bytes
+    /// otherwise. Columns past the end of the line (or lines past the end of the document)
+    /// are clamped to it.
+    pub fn byte_of_column(&self, line: usize, column: usize, utf16: bool) -> usize {
+        if line >= self.len_lines() {
+            return self.text.byte_len();
+        }
+        let line_start = self.text.byte_of_line(line);
+        let line_text = self.line(line);
+        if !utf16 {
+            let mut byte = column.min(line_text.byte_len());
+            while !line_text.is_char_boundary(byte) {
+                byte -= 1;
+            }
+            return line_start + byte;
+        }
+
+        let mut units = 0;
+        let mut byte = 0;
+        for c in line_text.chars() {
+            if units >= column {
+                break;
+            }
+            units += c.len_utf16();
+            byte += c.len_utf8();
+        }
+        line_start + byte
+    }
+
+    /// The start of the grapheme before `byte`, or 0 at the start of the document.
+    pub fn prev_grapheme_boundary(&self, byte: usize) -> usize {
+        let mut byte = byte;
+        while byte > 0 {
+            byte -= 1;
+            if self.text.is_grapheme_boundary(byte) {
+                break;
+            }
+        }
+        byte
+    }
+
+    /// The end of the grapheme after `byte`, or the length of the document at its end.
+    pub fn next_grapheme_boundary(&self, byte: usize) -> usize {
+        let mut byte = byte;
+        while byte < self.text.byte_len() {
+            byte += 1;
+            if self.text.is_grapheme_boundary(byte) {
+                break;
+            }
+        }
+        byte
+    }
+
+    /// Replaces `range` with `text`, which must already use `\n` line endings. The edit becomes
+    /// undoable once it is committed. Replacing nothing with nothing isn't an edit at all, so it
+    /// leaves the history and the dirty marker alone.
+    pub fn replace(&mut self, range: Range<usize>, text: &str) -> Change {
+        if range.is_empty() && text.is_empty() {
+            let line = self.text.line_of_byte(range.start);
+            return Change {
+                new_end: range.start,
+                start_line: line,
+                old_end_line: line,
+                new_end_line: line,
+            };
+        }
+        let edit = Edit {
+            start: range.start,
+            deleted: self.text.byte_slice(range).to_string(),
+            inserted: text.to_owned(),
+        };
+        let change = self.apply(&edit);
+        self.history.record(edit);
+        change
+    }
+
+    /// Makes the edits since the last commit a single undo step (or merges them into the previous
+    /// one, see `History::commit`), along with the selections before and after them.
+    pub fn commit(&mut self, kind: EditKind, before: Selection, after: Selection) {
+        self.history.commit(kind, before, after);
+    }
+
+    /// Reverts the last undo step, returning what changed in the order it changed and the
+    /// selections from before the step.
+    pub fn undo(&mut self) -> Option<(Vec<Change>, Selection)> {
+        let (edits, selection) = self.history.undo()?;
+        Some((
+            edits.iter().map(|edit| self.apply(edit)).collect(),
+            selection,
+        ))
+    }
+
+    /// Re-applies the last undone step, returning what changed in the order it changed and the
+    /// selections from after the step.
+    pub fn redo(&mut self) -> Option<(Vec<Change>, Selection)> {
+        let (edits, selection) = self.history.redo()?;
+        Some((
+            edits.iter().map(|edit| self.apply(edit)).collect(),
+            selection,
+        ))
+    }
+
+    /// Moves to the revision made just before (or after) the current one, whichever branch of
+    /// the undo tree it is on, returning what changed and the selections to restore.
+    pub fn travel(&mut self, forward: bool) -> Option<(Vec<Change>, Selection)> {
+        let (edits, selection) = self.history.travel(forward)?;
+        Some((
+            edits.iter().map(|edit| self.apply(edit)).collect(),
+            selection,
+        ))
+    }
+
+    /// Brings the syntax tree up to date after edits, returning the byte ranges whose
+    /// highlighting may have changed beyond the edited text itself.
+    pub fn reparse(&mut self) -> Vec<Range<usize>> {
+        let ranges = match &mut self.syntax {
+            Some(syntax) => syntax.reparse(&self.text),
+            None => Vec::new(),
+        };
+        if let Some(replacement) = self.replacements.back_mut() {
+            replacement.rehighlighted.extend(ranges.iter().cloned());
+        }
+        ranges
+    }
+
+    /// The highlight captures overlapping `range`, as byte ranges and capture names. This is
+    /// only accurate once the document has been reparsed after an edit, but close enough before
+    /// that for text the edit didn't touch.
+    pub fn highlights(&self, range: Range<usize>) -> Vec<(Range<usize>, &str)> {
+        match &self.syntax {
+            Some(syntax) => syntax.highlights(&self.text, range),
+            None => Vec::new(),
+        }
+    }
+
+    /// Goes up with every change to the text, so that a view of the document can tell whether it
+    /// was changed from somewhere else since the view last looked.
+    pub fn version(&self) -> usize {
+        self.version
+    }
+
+    /// The replacements made since the document was at `version`, in the order they were made, or
+    /// nothing if they're too far back to have been kept.
+    pub fn replacements_since(&self, version: usize) -> Option<impl Iterator<Item = &Replacement>> {
+        let count = self.version.checked_sub(version)?;
+        let kept = self.replacements.len();
+        (count <= kept).then(|| self.replacements.range(kept - count..))
+    }
+
+    /// Takes the replacements made since this was last called, in the order they were made.
+    pub fn take_changes(&mut self) -> Vec<TextChange> {
+        std::mem::take(&mut self.changes)
+    }
+
+    fn apply(&mut self, edit: &Edit) -> Change {
+        let range = edit.start..edit.start + edit.deleted.len();
+        self.version += 1;
+        self.changes.push(TextChange {
+            start: self.line_column(range.start),
+            old_end: self.line_column(range.end),
+            text: edit.inserted.clone(),
+        });
+        let start_line = self.text.line_of_byte(range.start);
+        let old_end_line = self.text.line_of_byte(range.end);
+        let old_end_position = syntax::point(&self.text, range.end);
+        self.text.replace(range.clone(), &edit.inserted);
+        let new_end = edit.start + edit.inserted.len();
+        if let Some(syntax) = &mut self.syntax {
+            syntax.edit(&syntax::input_edit(
+                range.start,
+                range.end,
+                old_end_position,
+                new_end,
+                &self.text,
+            ));
+        }
+
+        let change = Change {
+            new_end,
+            start_line,
+            old_end_line,
+            new_end_line: self.text.line_of_byte(new_end),
+        };
+        if self.replacements.len() == MAX_REPLACEMENTS {
+            self.replacements.pop_front();
+        }
+        self.replacements.push_back(Replacement {
+            start: edit.start,
+            deleted: edit.deleted.len(),
+            inserted: edit.inserted.len(),
+            change,
+            rehighlighted: Vec::new(),
+        });
+        change
+    }
+
+    /// Writes the text back to disk using the encoding and line endings the file was read with.
+    pub fn save(&mut self) -> Result<(), SaveError> {
+        let path = self.path.as_deref().ok_or(SaveError::NoPath)?;
+        let text = self.text.to_string();
+        let bytes = match self.line_ending {
+            LineEnding::Lf => self.encoding.encode(&text)?,
+            line_ending => self
+                .encoding
+                .encode(&text.replace('\n', line_ending.as_str()))?,
+        };
+        file::write_atomic(path, &bytes).map_err(|err| SaveError::Io(path.to_owned(), err))?;
+        self.mixed_line_endings = false;
+        // Typing after a save shouldn't fold into the revision that was saved
+        self.history.seal();
+        self.saved_revision = self.history.current();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use super::{Document, LineEnding, MAX_REPLACEMENTS};
+
+    #[test]
+    fn positions_move_with_the_text_around_them() {
+        let mut document = Document::default();
+        document.replace(0..0, "one two three");
+        let version = document.version();
+        document.replace(4..7, "2");
+        document.replace(0..0, "zero ");
+        let moved = |pos: usize| {
+            (document.replacements_since(version).unwrap())
+                .fold(pos, |pos, replacement| replacement.map(pos))
+        };
+        assert_eq!(document.text().to_string(), "zero one 2 three");
+        // Before, inside and after "two"
+        assert_eq!(moved(0), 0);
+        assert_eq!(moved(4), 9);
+        assert_eq!(moved(5), 9);
+        assert_eq!(moved(8), 11);
+    }
+
+    #[test]
+    fn only_the_latest_replacements_are_kept() {
+        let mut document = Document::default();
+        for _ in 0..MAX_REPLACEMENTS + 1 {
+            document.replace(0..0, "a");
+        }
+        assert!(document.replacements_since(0).is_none());
+        assert_eq!(
+            document.replacements_since(1).unwrap().count(),
+            MAX_REPLACEMENTS
+        );
+        assert_eq!(
+            document
+                .replacements_since(document.version())
+                .unwrap()
+                .count(),
+            0
+        );
+    }
+
+    #[test]
+    fn saving_makes_mixed_line_endings_the_same() {
+        let path = env::temp_dir().join(format!("exalted-line-endings-{}.txt", process::id()));
+        fs::write(&path, "one\r\ntwo\nthree\r\n").unwrap();
+        let mut document = Document::open(path.clone()).unwrap();
+        assert_eq!(document.line_ending(), LineEnding::CrLf);
+        assert!(document.has_mixed_line_endings());
+        assert_eq!(document.text().to_string(), "one\ntwo\nthree\n");
+
+        document.save().unwrap();
+        assert!(!document.has_mixed_line_endings());
+        assert_eq!(fs::read(&path).unwrap(), b"one\r\ntwo\r\nthree\r\n");
+        // Mostly `\n`, where the odd `\r` is kept as part of the line and written back as it was
+        fs::write(&path, "one\r\ntwo\nthree\n").unwrap();
+        let document = Document::open(path.clone()).unwrap();
+        assert!(!document.has_mixed_line_endings());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,150 @@
+//! Motions over document text. These only compute byte offsets; what to do with them (move the
+//! cursor, extend the selection, ...) is up to the caller.
+
+use super::Document;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum CharClass {
+    Whitespace,
+    Newline,
+    Word,
+    Punctuation,
+}
+
+fn class(c: char) -> CharClass {
+    if c == '\n' {
+        CharClass::Newline
+    } else if c.is_whitespace() {
+        CharClass::Whitespace
+    } else if c.is_alphanumeric() || c == '_' {
+        CharClass::Word
+    } else {
+        CharClass::Punctuation
+    }
+}
+
+/// The start of the next word after `pos`, skipping the rest of the current word and any
+/// whitespace after it (like `w` in Kakoune and Helix).
+pub fn next_word_start(document: &Document, pos: usize) -> usize {
+    let text = document.text();
+    let mut chars = text.byte_slice(pos..).chars().peekable();
+    let mut pos = pos;
+    while let Some(c) = chars.next_if(|&c| class(c) == CharClass::Newline) {
+        pos += c.len_utf8();
+    }
+    if let Some(&first) = chars.peek() {
+        let first = class(first);
+        while let Some(c) = chars.next_if(|&c| class(c) == first) {
+            pos += c.len_utf8();
+        }
+    }
+    while let Some(c) = chars.next_if(|&c| class(c) == CharClass::Whitespace) {
+        pos += c.len_utf8();
+    }
+    pos
+}
+
+/// The end of the word at or after `pos`, skipping any whitespace before it (like `e`).
+pub fn next_word_end(document: &Document, pos: usize) -> usize {
+    let text = document.text();
+    let mut chars = text.byte_slice(pos..).chars().peekable();
+    let mut pos = pos;
+    while let Some(c) =
+        chars.next_if(|&c| matches!(class(c), CharClass::Whitespace | CharClass::Newline))
+    {
+        pos += c.len_utf8();
+    }
+    if let Some(&first) = chars.peek() {
+        let first = class(first);
+        while let Some(c) = chars.next_if(|&c| class(c) == first) {
+            pos += c.len_utf8();
+        }
+    }
+    pos
+}
+
+/// The start of the word at or before `pos`, skipping any whitespace before it (like `b`).
+pub fn prev_word_start(document: &Document, pos: usize) -> usize {
+    let text = document.text();
+    let mut chars = text.byte_slice(..pos).chars().rev().peekable();
+    let mut pos = pos;
+    while let Some(c) =
+        chars.next_if(|&c| matches!(class(c), CharClass::Whitespace | CharClass::Newline))
+    {
+        pos -= c.len_utf8();
+    }
+    if let Some(&first) = chars.peek() {
+        let first = class(first);
+        while let Some(c) = chars.next_if(|&c| class(c) == first) {
+            pos -= c.len_utf8();
+        }
+    }
+    pos
+}
+
+/// The column of `pos` in characters, which is what vertical motions try to preserve.
+pub fn column(document: &Document, pos: usize) -> usize {
+    let line_start = document.byte_of_line(document.line_of_byte(pos));
+    document.text().byte_slice(line_start..pos).chars().count()
+}
+
+/// The position `lines` lines above (negative) or below `pos`, as close to `column` as that
+/// line's length allows.
+pub fn vertical(document: &Document, pos: usize, lines: isize, column: usize) -> usize {
+    let line = document.line_of_byte(pos);
+    let line = line
+        .saturating_add_signed(lines)
+        .min(document.len_lines() - 1);
+    let line_start = document.byte_of_line(line);
+    let offset: usize = document
+        .line(line)
+        .chars()
+        .take(column)
+        .map(char::len_utf8)
+        .sum();
+    line_start + offset
+}
+
+/// The start of the line containing `pos`.
+pub fn line_start(document: &Document, pos: usize) -> usize {
+    document.byte_of_line(document.line_of_byte(pos))
+}
+
+/// The first non-blank character of the line containing `pos`.
+pub fn line_first_non_blank(document: &Document, pos: usize) -> usize {
+    let line = document.line_of_byte(pos);
+    let indent: usize = document
+        .line(line)
+        .chars()
+        .take_while(|&c| class(c) == CharClass::Whitespace)
+        .map(char::len_utf8)
+        .sum();
+    document.byte_of_line(line) + indent
+}
+
+/// The end of the line containing `pos`, before its `\n`.
+pub fn line_end(document: &Document, pos: usize) -> usize {
+    let line = document.line_of_byte(pos);
+    document.byte_of_line(line) + document.line(line).byte_len()
+}
+
+/// The start of the line after the one containing `pos`, or the end of the document if that is
+/// the last line.
+pub fn next_line_start(document: &Document, pos: usize) -> usize {
+    let line = document.line_of_byte(pos);
+    if line + 1 < document.len_lines() {
+        document.byte_of_line(line + 1)
+    } else {
+        document.text().byte_len()
+    }
+}
+
+/// The start of the first line.
+pub fn first_line_start(_document: &Document, _pos: usize) -> usize {
+    0
+}
+
+/// The start of the last line.
+pub fn last_line_start(document: &Document, _pos: usize) -> usize {
+    document.byte_of_line(document.len_lines() - 1)
+}
@@ -0,0 +1,214 @@
+/// A selected span of a document in byte offsets. The head is where the cursor is and what
+/// motions move; the anchor stays put. Either may come first, and they are equal when nothing is
+/// selected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Range {
+    pub anchor: usize,
+    pub head: usize,
+}
+
+impl Range {
+    pub fn new(anchor: usize, head: usize) -> Self {
+        Self { anchor, head }
+    }
+
+    pub fn point(offset: usize) -> Self {
+        Self::new(offset, offset)
+    }
+
+    pub fn start(&self) -> usize {
+        self.anchor.min(self.head)
+    }
+
+    pub fn end(&self) -> usize {
+        self.anchor.max(self.head)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.anchor == self.head
+    }
+
+    /// Whether the head comes after the anchor.
+    pub fn is_forward(&self) -> bool {
+        self.head > self.anchor
+    }
+
+    /// The selected bytes, for slicing the document.
+    pub fn bytes(&self) -> std::ops::Range<usize> {
+        self.start()..self.end()
+    }
+
+    /// Grows the range to cover `other` too, keeping its own direction.
+    fn merge(self, other: Range) -> Self {
+        let (start, end) = (self.start().min(other.start()), self.end().max(other.end()));
+        if self.is_forward() || (self.is_empty() && other.is_forward()) {
+            Self::new(start, end)
+        } else {
+            Self::new(end, start)
+        }
+    }
+}
+
+/// Every selection in a view of a document, sorted by position with overlapping ones merged. One
+/// of them is the primary selection, which the view follows and which commands that only make
+/// sense once (like hover or go-to-line) act on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Selection {
+    ranges: Vec<Range>,
+    primary: usize,
+}
+
+impl Selection {
+    /// Builds a selection from ranges in any order, the one at index `primary` being the primary
+    /// one. Ranges that overlap, or cursors in the same place, are merged.
+    pub fn new(ranges: Vec<Range>, primary: usize) -> Self {
+        assert!(primary < ranges.len(), "Selection needs a primary range");
+        let mut ranges: Vec<_> = ranges
+            .into_iter()
+            .enumerate()
+            .map(|(i, range)| (range, i == primary))
+            .collect();
+        ranges.sort_by_key(|(range, _)| (range.start(), range.end()));
+
+        let mut merged: Vec<(Range, bool)> = Vec::with_capacity(ranges.len());
+        for (range, is_primary) in ranges {
+            match merged.last_mut() {
+                Some((last, last_is_primary))
+                    if range.start() < last.end() || range.start() == last.start() =>
+                {
+                    *last = last.merge(range);
+                    *last_is_primary |= is_primary;
+                }
+                _ => merged.push((range, is_primary)),
+            }
+        }
+
+        let primary = merged
+            .iter()
+            .position(|&(_, is_primary)| is_primary)
+            .expect("The primary range survives merging");
+        Self {
+            ranges: merged.into_iter().map(|(range, _)| range).collect(),
+            primary,
+        }
+    }
+
+    pub fn single(range: Range) -> Self {
+        Self {
+            ranges: vec![range],
+            primary: 0,
+        }
+    }
+
+    pub fn point(offset: usize) -> Self {
+        Self::single(Range::point(offset))
+    }
+
+    pub fn ranges(&self) -> &[Range] {
+        &self.ranges
+    }
+
+    pub fn primary(&self) -> Range {
+        self.ranges[self.primary]
+    }
+
+    pub fn primary_index(&self) -> usize {
+        self.primary
+    }
+
+    /// Transforms every range, keeping the same one primary.
+    pub fn map(&self, f: impl FnMut(Range) -> Range) -> Self {
+        Self::new(self.ranges.iter().copied().map(f).collect(), self.primary)
+    }
+
+    /// Adds `range` as the new primary selection.
+    pub fn push(&self, range: Range) -> Self {
+        let mut ranges = self.ranges.clone();
+        ranges.push(range);
+        let primary = ranges.len() - 1;
+        Self::new(ranges, primary)
+    }
+
+    /// Drops every selection but the primary one.
+    pub fn keep_primary(&self) -> Self {
+        Self::single(self.primary())
+    }
+
+    /// Makes the next (or previous) selection the primary one, wrapping around.
+    pub fn rotate(&self, forward: bool) -> Self {
+        let len = self.ranges.len();
+        Self {
+            ranges: self.ranges.clone(),
+            primary: if forward {
+                (self.primary + 1) % len
+            } else {
+                (self.primary + len - 1) % len
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Range, Selection};
+
+    #[test]
+    fn ranges_are_sorted() {
+        let selection = Selection::new(vec![Range::new(8, 10), Range::new(0, 2)], 0);
+        assert_eq!(selection.ranges(), [Range::new(0, 2), Range::new(8, 10)]);
+        assert_eq!(selection.primary(), Range::new(8, 10));
+    }
+
+    #[test]
+    fn overlapping_ranges_merge() {
+        let selection = Selection::new(vec![Range::new(0, 5), Range::new(3, 8)], 0);
+        assert_eq!(selection.ranges(), [Range::new(0, 8)]);
+        // One range covering another
+        let selection = Selection::new(vec![Range::new(2, 4), Range::new(0, 8)], 0);
+        assert_eq!(selection.ranges(), [Range::new(0, 8)]);
+    }
+
+    #[test]
+    fn merging_keeps_the_direction_of_the_first_range() {
+        let selection = Selection::new(vec![Range::new(5, 0), Range::new(3, 8)], 0);
+        assert_eq!(selection.ranges(), [Range::new(8, 0)]);
+        // A cursor takes on the direction of what it merges with
+        let selection = Selection::new(vec![Range::point(3), Range::new(3, 8)], 0);
+        assert_eq!(selection.ranges(), [Range::new(3, 8)]);
+    }
+
+    #[test]
+    fn cursors_in_the_same_place_merge() {
+        let selection = Selection::new(vec![Range::point(4), Range::point(4)], 1);
+        assert_eq!(selection.ranges(), [Range::point(4)]);
+        assert_eq!(selection.primary_index(), 0);
+    }
+
+    #[test]
+    fn adjacent_ranges_stay_apart() {
+        let ranges = vec![Range::new(0, 3), Range::new(3, 6), Range::point(6)];
+        let selection = Selection::new(ranges.clone(), 2);
+        assert_eq!(selection.ranges(), ranges);
+        assert_eq!(selection.primary(), Range::point(6));
+    }
+
+    #[test]
+    fn primary_survives_merging() {
+        let ranges = vec![Range::new(0, 2), Range::new(6, 9), Range::new(1, 4)];
+        let selection = Selection::new(ranges, 2);
+        assert_eq!(selection.ranges(), [Range::new(0, 4), Range::new(6, 9)]);
+        assert_eq!(selection.primary(), Range::new(0, 4));
+        // Pushing a range that swallows the others makes the merged range primary
+        let selection = selection.push(Range::new(3, 7));
+        assert_eq!(selection.ranges(), [Range::new(0, 9)]);
+        assert_eq!(selection.primary_index(), 0);
+    }
+
+    #[test]
+    fn rotating_wraps_around() {
+        let selection = Selection::new(vec![Range::point(0), Range::point(2)], 1);
+        assert_eq!(selection.rotate(true).primary(), Range::point(0));
+        assert_eq!(selection.rotate(false).primary(), Range::point(0));
+        assert_eq!(selection.rotate(true).rotate(true), selection);
+    }
+}
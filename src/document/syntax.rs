@@ -0,0 +1,142 @@
+use std::ops::Range;
+
+use crop::Rope;
+use tree_sitter::{InputEdit, Node, Parser, Point, Query, QueryCursor, StreamingIterator, Tree};
+
+use super::language::Language;
+
+/// The syntax tree of a document, kept up to date by editing it alongside the text and
+/// reparsing incrementally.
+pub struct Syntax {
+    parser: Parser,
+    tree: Tree,
+    highlights: Query,
+    /// Whether the tree has been edited since it was last parsed.
+    stale: bool,
+}
+
+impl Syntax {
+    /// Parses `text` as `language`.
+    pub fn new(language: &Language, text: &Rope) -> Option<Self> {
+        let grammar = (language.grammar)();
+        let mut parser = Parser::new();
+        parser
+            .set_language(&grammar)
+            .expect("Grammar should be compatible with the Tree-sitter library");
+        let highlights =
+            Query::new(&grammar, language.highlights).expect("Bundled queries should be valid");
+        let tree = parse(&mut parser, text, None)?;
+        Some(Self {
+            parser,
+            tree,
+            highlights,
+            stale: false,
+        })
+    }
+
+    /// Tells the tree about an edit to the text, so that the next reparse can reuse the parts
+    /// it didn't touch.
+    pub fn edit(&mut self, edit: &InputEdit) {
+        self.tree.edit(edit);
+        self.stale = true;
+    }
+
+    /// Reparses after edits, returning the byte ranges whose syntax changed as a result (beyond
+    /// the edited text itself).
+    pub fn reparse(&mut self, text: &Rope) -> Vec<Range<usize>> {
+        if !self.stale {
+            return Vec::new();
+        }
+        self.stale = false;
+        let Some(tree) = parse(&mut self.parser, text, Some(&self.tree)) else {
+            return Vec::new();
+        };
+        let changed = self
+            .tree
+            .changed_ranges(&tree)
+            .map(|range| range.start_byte..range.end_byte)
+            .collect();
+        self.tree = tree;
+        changed
+    }
+
+    /// The highlight captures overlapping `range`, in order, as byte ranges and capture names
+    /// such as `keyword` or `function.method`. Where several patterns capture the same node,
+    /// only the first one counts.
+    pub fn highlights(&self, text: &Rope, range: Range<usize>) -> Vec<(Range<usize>, &str)> {
+        let mut cursor = QueryCursor::new();
+        cursor.set_byte_range(range);
+        let mut captures =
+            cursor.captures(&self.highlights, self.tree.root_node(), |node: Node| {
+                text.byte_slice(node.byte_range())
+                    .chunks()
+                    .map(str::as_bytes)
+            });
+
+        let names = self.highlights.capture_names();
+        let mut highlights: Vec<(Range<usize>, &str)> = Vec::new();
+        while let Some((found, index)) = captures.next() {
+            let capture = found.captures()[*index];
+            let range = capture.node.byte_range();
+            if highlights.last().is_some_and(|(last, _)| *last == range) {
+                continue;
+            }
+            highlights.push((range, names[capture.index as usize]));
+        }
+        highlights
+    }
+}
+
+/// Builds the edit Tree-sitter needs from byte offsets, `old_end` being from before the edit and
+/// `text` the text after it.
+pub fn input_edit(
+    start: usize,
+    old_end: usize,
+    old_end_position: Point,
+    new_end: usize,
+    text: &Rope,
+) -> InputEdit {
+    InputEdit {
+        start_byte: start,
+        old_end_byte: old_end,
+        new_end_byte: new_end,
+        start_position: point(text, start),
+        old_end_position,
+        new_end_position: point(text, new_end),
+    }
+}
+
+/// The row and byte column of `byte`.
+pub fn point(text: &Rope, byte: usize) -> Point {
+    let row = text.line_of_byte(byte);
+    Point::new(row, byte - text.byte_of_line(row))
+}
+
+fn parse(parser: &mut Parser, text: &Rope, old_tree: Option<&Tree>) -> Option<Tree> {
+    // Tree-sitter asks for text at arbitrary offsets, but mostly reads forwards, so walk the
+    // chunks from wherever the last read left off
+    let mut chunks = text.chunks();
+    let mut chunk = "";
+    let mut chunk_start = 0;
+    parser.parse_with_options(
+        &mut |byte, _| {
+            if byte < chunk_start {
+                chunks = text.chunks();
+                chunk = "";
+                chunk_start = 0;
+            }
+            while byte >= chunk_start + chunk.len() {
+                match chunks.next() {
+                    Some(next) => {
+                        chunk_start += chunk.len();
+                        chunk = next;
+                    }
+                    None => return &[][..],
+                }
+            }
+            &chunk.as_bytes()[byte - chunk_start..]
+        },
+        old_tree,
+        None,
+    )
+}
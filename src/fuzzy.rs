@@ -0,0 +1,45 @@
+use nucleo_matcher::pattern::{CaseMatching, Normalization, Pattern};
+use nucleo_matcher::{Config, Matcher, Utf32Str};
+
+/// One of the items passed to `filter` that matched, with where it matched.
+pub struct Match<T> {
+    pub item: T,
+    /// How well it matched, higher being better.
+    pub score: u32,
+    /// The indices of the characters in the item's text that matched, in order.
+    pub indices: Vec<u32>,
+}
+
+/// Narrows `items` down to the ones whose text fuzzily matches `query`, best match first, with
+/// ties left in the order they came in. `paths` makes path separators count as word boundaries.
+pub fn filter<T, S: AsRef<str>>(
+    query: &str,
+    items: impl IntoIterator<Item = (T, S)>,
+    paths: bool,
+) -> Vec<Match<T>> {
+    let config = if paths {
+        Config::DEFAULT.match_paths()
+    } else {
+        Config::DEFAULT
+    };
+    let mut matcher = Matcher::new(config);
+    let pattern = Pattern::parse(query, CaseMatching::Smart, Normalization::Smart);
+    let mut buf = Vec::new();
+    let mut matches: Vec<_> = items
+        .into_iter()
+        .filter_map(|(item, text)| {
+            let mut indices = Vec::new();
+            let haystack = Utf32Str::new(text.as_ref(), &mut buf);
+            let score = pattern.indices(haystack, &mut matcher, &mut indices)?;
+            indices.sort_unstable();
+            indices.dedup();
+            Some(Match {
+                item,
+                score,
+                indices,
+            })
+        })
+        .collect();
+    matches.sort_by_key(|found| std::cmp::Reverse(found.score));
+    matches
+}
@@ -0,0 +1,150 @@
+//! Rendering the layout without a window, so that what it draws can be checked against PNG
+//! snapshots on machines without a display.
+
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use image::{ImageError, RgbaImage};
+use tiny_skia::{Paint, Pixmap, Rect};
+
+use crate::layout::{Interactive, RootLayout};
+
+/// How far a channel may be off before a pixel counts as different, to allow for small
+/// differences in anti-aliasing between machines.
+const CHANNEL_TOLERANCE: u8 = 2;
+
+/// The size of the stand-in for a window.
+#[derive(Clone, Copy)]
+pub struct Viewport {
+    pub width: u32,
+    pub height: u32,
+    pub scale_factor: f64,
+}
+
+impl Default for Viewport {
+    fn default() -> Self {
+        Self {
+            width: 800,
+            height: 600,
+            scale_factor: 1.0,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(PathBuf, io::Error),
+    Image(PathBuf, ImageError),
+    Size {
+        expected: (u32, u32),
+        actual: (u32, u32),
+    },
+    /// Some pixels differ, and what was rendered instead was written to `actual`.
+    Mismatch {
+        differing: usize,
+        actual: PathBuf,
+    },
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(path, err) => write!(f, "could not use {}: {err}", path.display()),
+            Self::Image(path, err) => write!(f, "could not use {}: {err}", path.display()),
+            Self::Size { expected, actual } => write!(
+                f,
+                "the snapshot is {}x{} but {}x{} was rendered",
+                expected.0, expected.1, actual.0, actual.1
+            ),
+            Self::Mismatch { differing, actual } => write!(
+                f,
+                "{differing} pixels differ from the snapshot, see {}",
+                actual.display()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(_, err) => Some(err),
+            Self::Image(_, err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Renders `layout` the same way the window would, into an image of its own.
+pub fn render(layout: &mut RootLayout, viewport: Viewport) -> RgbaImage {
+    let Viewport {
+        width,
+        height,
+        scale_factor,
+    } = viewport;
+    let mut pixmap = Pixmap::new(width, height).expect("Viewports can't be empty");
+    layout.render(
+        &mut pixmap.as_mut(),
+        &mut Paint::default(),
+        scale_factor,
+        Rect::from_xywh(0.0, 0.0, width as f32, height as f32).unwrap(),
+    );
+
+    // The renderer draws with red and blue swapped for softbuffer, whose pixels are 0RGB words
+    let mut data = pixmap.take();
+    for pixel in data.chunks_exact_mut(4) {
+        pixel.swap(0, 2);
+        pixel[3] = 255;
+    }
+    RgbaImage::from_raw(width, height, data).unwrap()
+}
+
+/// Checks a rendered image against the snapshot at `path`, or writes it there if there isn't one
+/// yet (or `update` says to replace it). Returns whether it was written.
+pub fn check(image: &RgbaImage, path: &Path, update: bool) -> Result<bool, SnapshotError> {
+    if update || !path.exists() {
+        save(image, path)?;
+        return Ok(true);
+    }
+
+    let expected = image::open(path)
+        .map_err(|err| SnapshotError::Image(path.to_owned(), err))?
+        .into_rgba8();
+    if expected.dimensions() != image.dimensions() {
+        return Err(SnapshotError::Size {
+            expected: expected.dimensions(),
+            actual: image.dimensions(),
+        });
+    }
+
+    let differing = expected
+        .pixels()
+        .zip(image.pixels())
+        .filter(|(expected, actual)| {
+            expected
+                .0
+                .iter()
+                .zip(actual.0)
+                .any(|(&expected, actual)| expected.abs_diff(actual) > CHANNEL_TOLERANCE)
+        })
+        .count();
+    if differing == 0 {
+        return Ok(false);
+    }
+    // Not next to the snapshot, where it would show up in the file tree of the next one rendered
+    let dir = env::temp_dir().join("exalted-snapshots");
+    fs::create_dir_all(&dir).map_err(|err| SnapshotError::Io(dir.clone(), err))?;
+    let name = path.file_stem().unwrap_or_default().to_string_lossy();
+    let actual = dir.join(format!("{name}.actual.png"));
+    save(image, &actual)?;
+    Err(SnapshotError::Mismatch { differing, actual })
+}
+
+fn save(image: &RgbaImage, path: &Path) -> Result<(), SnapshotError> {
+    image
+        .save(path)
+        .map_err(|err| SnapshotError::Image(path.to_owned(), err))
+}
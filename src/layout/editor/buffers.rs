@@ -0,0 +1,618 @@
+use std::cell::RefCell;
+use std::ops::Range;
+use std::path::{self, Path, PathBuf};
+use std::rc::Rc;
+
+use cosmic_text::{Buffer, Edit, Metrics, Scroll, Shaping};
+use lsp_types::Diagnostic;
+use regex::Regex;
+use tiny_skia::{Paint, PixmapMut, PixmapPaint, Rect, Transform};
+
+use crate::document::selection::{Range as SelectionRange, Selection};
+use crate::document::{Document, EditKind, Replacement};
+use crate::pattern::{self, Pattern};
+
+use super::keymap::Mode;
+use super::lsp::LanguageServer;
+use super::modes::{ActiveModes, BufferModes, Hook, MajorMode, MinorMode};
+use super::render::EditorRenderer;
+use super::{Editor, Request};
+
+/// A document open in the editor, along with everything else that belongs to it rather than to
+/// how it's being looked at.
+pub struct OpenBuffer {
+    pub document: Document,
+    pub language_server: Option<LanguageServer>,
+    pub modes: BufferModes,
+    /// As last published by the language server, positioned in the text as the server saw it.
+    pub diagnostics: Vec<Diagnostic>,
+    /// Where the selection and scroll position were when the buffer was last switched away from,
+    /// to pick up from when switching back.
+    selection: Selection,
+    scroll: Scroll,
+}
+
+pub type SharedBuffer = Rc<RefCell<OpenBuffer>>;
+
+/// Every open buffer, in the order their tabs are shown, shared by every view so that they can
+/// show the same ones.
+pub type BufferList = Rc<RefCell<Vec<SharedBuffer>>>;
+
+impl OpenBuffer {
+    pub fn new(document: Document, language_server: Option<LanguageServer>) -> SharedBuffer {
+        Rc::new(RefCell::new(Self {
+            modes: BufferModes::detect(&document),
+            document,
+            language_server,
+            diagnostics: Vec::new(),
+            selection: Selection::point(0),
+            scroll: Scroll::default(),
+        }))
+    }
+
+    /// What the tab strip and status bar call the buffer.
+    pub fn name(&self) -> String {
+        match self.document.path().and_then(|path| path.file_name()) {
+            Some(name) => name.to_string_lossy().into_owned(),
+            None => "[scratch]".to_owned(),
+        }
+    }
+
+    /// A buffer that was never saved or typed in, which opening a file can replace.
+    fn is_untouched(&self) -> bool {
+        self.document.path().is_none() && self.document.text().byte_len() == 0
+    }
+}
+
+impl Editor<'_> {
+    /// The index of the buffer for the file at `path`, if it's open.
+    fn find_buffer(&self, path: &Path) -> Option<usize> {
+        let absolute = path::absolute(path).ok();
+        self.buffers.borrow().iter().position(|buffer| {
+            let buffer = buffer.borrow();
+            let open = buffer
+                .document
+                .path()
+                .and_then(|path| path::absolute(path).ok());
+            open.is_some() && open == absolute
+        })
+    }
+
+    /// Switches to the buffer for the file at `path`, opening it first if it isn't already.
+    pub(super) fn open(&mut self, path: PathBuf) {
+        if let Some(index) = self.find_buffer(&path) {
+            return self.switch_buffer(index);
+        }
+
+        let document = match Document::open(path.clone()) {
+            Ok(document) => document,
+            Err(err) => {
+                return self.show_error(&format!("Could not open {}: {err}", path.display()))
+            }
+        };
+        let started = LanguageServer::start(&document, &self.servers, self.waker.clone());
+        let (language_server, error) = match started {
+            Ok(server) => (server, None),
+            Err(err) => (None, Some(err)),
+        };
+        let buffer = OpenBuffer::new(document, language_server);
+        let index = self.buffer_index();
+        if self.buffer.borrow().is_untouched() {
+            self.buffers.borrow_mut()[index] = buffer.clone();
+            self.buffer = buffer;
+            self.show_buffer();
+        } else {
+            self.buffers.borrow_mut().insert(index + 1, buffer);
+            self.switch_buffer(index + 1);
+        }
+        self.warn_of_mixed_line_endings();
+        if let Some(error) = error {
+            self.show_error(&error);
+        }
+        self.run_hooks(Hook::Open);
+    }
+
+    /// Warns that saving the current buffer will change the endings of some of its lines.
+    fn warn_of_mixed_line_endings(&mut self) {
+        let document = self.document();
+        if !document.has_mixed_line_endings() {
+            return;
+        }
+        let message = format!(
+            "Mixed line endings will all be {} once saved",
+            document.line_ending()
+        );
+        drop(document);
+        self.show_message(&message, self.theme.warning);
+    }
+
+    /// Opens the file at `path` like `open`, selecting `columns` of `line`. Both are clamped to
+    /// the text, which might have changed since they were worked out.
+    pub(super) fn open_at(&mut self, path: PathBuf, line: usize, columns: Range<usize>) {
+        self.open(path.clone());
+        if self.find_buffer(&path) != Some(self.buffer_index()) {
+            // Opening it failed, which has already been shown
+            return;
+        }
+        let range = {
+            let document = self.document();
+            let line = line.min(document.len_lines() - 1);
+            let start = document.byte_of_line(line);
+            let len = document.line(line).byte_len();
+            let end = document.text().byte_len();
+            let clamp = |column: usize| match start + column.min(len) {
+                pos if pos == end => end,
+                pos => document.prev_grapheme_boundary(document.next_grapheme_boundary(pos)),
+            };
+            SelectionRange::new(clamp(columns.start), clamp(columns.end))
+        };
+        self.set_selection(Selection::single(range));
+    }
+
+    /// Opens the project search, handing it the text of every buffer with unsaved changes to
+    /// search instead of what's on disk.
+    pub(super) fn search_project(&self) {
+        let unsaved = (self.buffers.borrow().iter())
+            .filter_map(|buffer| {
+                let document = &buffer.borrow().document;
+                let path = path::absolute(document.path()?).ok()?;
+                document
+                    .is_dirty()
+                    .then(|| (path, document.text().to_string()))
+            })
+            .collect();
+        self.request(Request::SearchProject(unsaved));
+    }
+
+    /// Replaces every match of `pattern` in each of `files`. Files open in a buffer are changed
+    /// there, as one undo step each, and left to be saved; the rest are changed on disk.
+    pub(super) fn replace_in_files(
+        &mut self,
+        pattern: &Pattern,
+        replacement: &str,
+        files: &[PathBuf],
+    ) {
+        let regex = match pattern.build() {
+            Ok(regex) => regex,
+            Err(err) => return self.show_error(&format!("Invalid regex: {err}")),
+        };
+        let (mut count, mut changed, mut errors) = (0, 0, Vec::new());
+        // Files saved with line endings that weren't all the same before
+        let mut normalised = Vec::new();
+        for path in files {
+            let replaced = match self.find_buffer(path) {
+                Some(index) => Ok((
+                    self.replace_in_buffer(index, pattern, &regex, replacement),
+                    false,
+                )),
+                None => replace_in_file(path, pattern, &regex, replacement),
+            };
+            match replaced {
+                Ok((0, _)) => (),
+                Ok((replaced, mixed)) => {
+                    count += replaced;
+                    changed += 1;
+                    if mixed {
+                        normalised.push(path.display().to_string());
+                    }
+                }
+                Err(err) => errors.push(format!("{}: {err}", path.display())),
+            }
+        }
+        if !errors.is_empty() {
+            return self.show_error(&format!("Could not replace in {}", errors.join(", ")));
+        }
+        let matches = if count == 1 { "match" } else { "matches" };
+        let files = if changed == 1 { "file" } else { "files" };
+        let mut message = format!("Replaced {count} {matches} in {changed} {files}");
+        if normalised.is_empty() {
+            return self.show_message(&message, self.theme.foreground);
+        }
+        message += &format!(
+            ", making the line endings of {} all the same",
+            normalised.join(", ")
+        );
+        self.show_message(&message, self.theme.warning);
+    }
+
+    /// Replaces every match in the buffer at `index` as one undo step, selecting the
+    /// replacements, and returns how many there were.
+    fn replace_in_buffer(
+        &mut self,
+        index: usize,
+        pattern: &Pattern,
+        regex: &Regex,
+        replacement: &str,
+    ) -> usize {
+        let buffer = self.buffers.borrow()[index].clone();
+        let edits = {
+            let text = buffer.borrow().document.text().to_string();
+            let matches = pattern::find_all(regex, &text);
+            pattern.replacements(regex, &text, &matches, replacement)
+        };
+        let count = edits.len();
+        if count == 0 {
+            return 0;
+        }
+        let select = |spans: Vec<Range<usize>>| {
+            let ranges = (spans.into_iter())
+                .map(|span| SelectionRange::new(span.start, span.end))
+                .collect();
+            Selection::new(ranges, 0)
+        };
+        if Rc::ptr_eq(&buffer, &self.buffer) {
+            self.edit(EditKind::Other, |this| {
+                let spans = this.replace_each(edits);
+                this.set_selection(select(spans));
+            });
+            return count;
+        }
+
+        // Views showing it catch up with the edits when they're next drawn
+        let mut buffer = buffer.borrow_mut();
+        let mut spans = Vec::with_capacity(count);
+        // How far the replacements so far have moved everything after them
+        let mut shift = 0;
+        for (range, text) in edits {
+            let start = range.start.checked_add_signed(shift).unwrap();
+            let change = buffer.document.replace(start..start + range.len(), &text);
+            shift += text.len() as isize - range.len() as isize;
+            spans.push(start..change.new_end);
+        }
+        buffer.document.reparse();
+        buffer.sync_language_server();
+        let before = buffer.selection.clone();
+        buffer.selection = select(spans);
+        let after = buffer.selection.clone();
+        buffer.document.commit(EditKind::Other, before, after);
+        count
+    }
+
+    /// The index of the current buffer in the list.
+    fn buffer_index(&self) -> usize {
+        self.buffers
+            .borrow()
+            .iter()
+            .position(|buffer| Rc::ptr_eq(buffer, &self.buffer))
+            .expect("The current buffer is always in the list")
+    }
+
+    pub(super) fn switch_buffer(&mut self, index: usize) {
+        let buffer = self.buffers.borrow()[index].clone();
+        if Rc::ptr_eq(&buffer, &self.buffer) {
+            return;
+        }
+        self.remember_position();
+        self.buffer = buffer;
+        self.show_buffer();
+    }
+
+    /// Switches to the buffer `offset` places along from the current one, wrapping around.
+    pub(super) fn cycle_buffer(&mut self, offset: isize) {
+        let len = self.buffers.borrow().len() as isize;
+        let index = (self.buffer_index() as isize + offset).rem_euclid(len);
+        self.switch_buffer(index as usize);
+    }
+
+    /// Closes the current buffer in every view, unless it has unsaved changes and `discard` isn't
+    /// set. Closing the last one leaves an empty scratch buffer.
+    pub(super) fn close_buffer(&mut self, discard: bool) {
+        if self.document().is_dirty() && !discard {
+            let name = self.buffer.borrow().name();
+            self.close_warned = true;
+            return self.show_error(&format!(
+                "{name} has unsaved changes, close it again to throw them away"
+            ));
+        }
+        let index = self.buffer_index();
+        let mut buffers = self.buffers.borrow_mut();
+        buffers.remove(index);
+        if buffers.is_empty() {
+            buffers.push(OpenBuffer::new(Document::default(), None));
+        }
+        self.buffer = buffers[index.min(buffers.len() - 1)].clone();
+        drop(buffers);
+        self.show_buffer();
+    }
+
+    pub(super) fn remember_position(&mut self) {
+        let scroll = self.editor.with_buffer(|buffer| buffer.scroll());
+        let mut buffer = self.buffer.borrow_mut();
+        buffer.selection = self.selection.clone();
+        buffer.scroll = scroll;
+    }
+
+    /// Lays out the current buffer's text and goes back to where it was last left.
+    pub(super) fn show_buffer(&mut self) {
+        self.mode = Mode::Normal;
+        self.count = None;
+        self.preferred_columns.clear();
+        self.prompt = None;
+        self.hover = None;
+        self.completion = None;
+        if let Some(search) = &mut self.search {
+            search.close();
+        }
+        self.apply_modes();
+        self.sync_all_lines();
+        let (selection, scroll) = {
+            let buffer = self.buffer.borrow();
+            (buffer.selection.clone(), buffer.scroll)
+        };
+        self.set_selection(selection);
+        self.editor
+            .with_buffer_mut(|buffer| buffer.set_scroll(scroll));
+    }
+
+    /// Picks up whatever was done to the buffer through other views since this one last looked:
+    /// closing it, which leaves this view showing the first buffer instead, or editing it.
+    pub(super) fn catch_up(&mut self) {
+        let open = (self.buffers.borrow().iter()).any(|buffer| Rc::ptr_eq(buffer, &self.buffer));
+        if !open {
+            self.buffer = self.buffers.borrow()[0].clone();
+            return self.show_buffer();
+        }
+        // Another view might have changed the buffer's modes
+        if self.buffer.borrow().modes != self.modes.buffer {
+            self.apply_modes();
+        }
+        if self.document().version() == self.version {
+            return;
+        }
+        let replacements: Option<Vec<Replacement>> = self
+            .document()
+            .replacements_since(self.version)
+            .map(|replacements| replacements.cloned().collect());
+        let Some(replacements) = replacements else {
+            return self.catch_up_from_scratch();
+        };
+        let moved = |pos: usize, replacements: &[Replacement]| {
+            (replacements.iter()).fold(pos, |pos, replacement| replacement.map(pos))
+        };
+
+        let changes: Vec<_> = replacements
+            .iter()
+            .map(|replacement| replacement.change)
+            .collect();
+        self.sync_changes(&changes);
+        // The replaced lines were highlighted as they were built, but reparsing can change the
+        // highlighting of others too, positioned in the text as it was at the time
+        let rehighlighted = (replacements.iter().enumerate())
+            .flat_map(|(i, replacement)| {
+                let after = &replacements[i + 1..];
+                (replacement.rehighlighted.iter())
+                    .map(move |range| moved(range.start, after)..moved(range.end, after))
+            })
+            .collect();
+        self.rehighlight(rehighlighted);
+        let selection = self.selection.map(|range| {
+            SelectionRange::new(
+                moved(range.anchor, &replacements),
+                moved(range.head, &replacements),
+            )
+        });
+        self.set_selection(selection);
+    }
+
+    /// Lays out the whole document again when it's been edited too much since this view last
+    /// looked to know where. The best the selections can do then is stay in bounds.
+    fn catch_up_from_scratch(&mut self) {
+        self.sync_all_lines();
+        let selection = {
+            let document = &self.document();
+            let len = document.text().byte_len();
+            let clamp = |pos: usize| match pos.min(len) {
+                pos if pos == len => len,
+                pos => document.prev_grapheme_boundary(document.next_grapheme_boundary(pos)),
+            };
+            self.selection
+                .map(|range| SelectionRange::new(clamp(range.anchor), clamp(range.head)))
+        };
+        self.set_selection(selection);
+    }
+
+    /// Works out the keymap, settings and hooks of the current buffer's modes again.
+    pub(super) fn apply_modes(&mut self) {
+        self.modes = ActiveModes::new(&self.buffer.borrow().modes, &self.config);
+        self.pending.clear();
+    }
+
+    /// Runs the commands the current buffer's modes have for `hook`, then what the plugins
+    /// hooked in there.
+    pub(super) fn run_hooks(&mut self, hook: Hook) {
+        for command in self.modes.hooks(hook) {
+            self.execute(command, 1);
+        }
+        self.run_plugin_hooks(hook);
+    }
+
+    /// Switches the current buffer to the major mode called `name`.
+    pub(super) fn set_major_mode(&mut self, name: &str) {
+        let Some(major) = MajorMode::named(name.trim()) else {
+            return self.show_error(&format!("There's no major mode called {name}"));
+        };
+        self.buffer.borrow_mut().modes.major = major;
+        self.apply_modes();
+        self.run_hooks(Hook::ModeChange);
+    }
+
+    /// Turns the minor mode called `name` on in the current buffer if it's off, or off if it's on.
+    pub(super) fn toggle_minor_mode(&mut self, name: &str) {
+        let Some(minor) = MinorMode::named(name.trim()) else {
+            return self.show_error(&format!("There's no minor mode called {name}"));
+        };
+        self.buffer.borrow_mut().modes.toggle(minor);
+        self.apply_modes();
+        let state = if self.modes.minor.contains(&minor) {
+            "on"
+        } else {
+            "off"
+        };
+        let message = format!("{} is {state}: {}", minor.name, minor.description);
+        self.show_message(&message, self.theme.foreground);
+        self.run_hooks(Hook::ModeChange);
+    }
+
+    /// The height of the tab strip along the top, scaled for the display.
+    pub(super) fn tabs_height(&self) -> f32 {
+        self.editor_metrics().line_height * 0.75
+    }
+
+    /// The buffer whose tab is at `x`, as of when the tabs were last drawn.
+    pub(super) fn tab_at(&self, x: f32) -> Option<usize> {
+        self.tab_areas.iter().position(|area| area.contains(&x))
+    }
+
+    /// Draws a tab for every open buffer along the top of the editor. When they don't all fit,
+    /// the ones at the start give way until the current one does, and the rest are counted at the
+    /// end of the strip.
+    pub(super) fn render_tabs<'draw>(
+        &mut self,
+        pixmap: &mut PixmapMut<'draw>,
+        paint: &mut Paint<'draw>,
+        rect: Rect,
+    ) {
+        let height = self.tabs_height();
+        let metrics = self.editor_metrics();
+        let metrics = Metrics::new(metrics.font_size * 0.5, height);
+        let padding = metrics.font_size;
+        let tab_bar = self.theme.tab_bar;
+        paint.set_color_rgba8(tab_bar.b(), tab_bar.g(), tab_bar.r(), tab_bar.a());
+        pixmap.fill_rect(
+            Rect::from_xywh(rect.x(), rect.y(), rect.width(), height).unwrap(),
+            paint,
+            Transform::identity(),
+            None,
+        );
+
+        let mut fonts = self.fonts.borrow_mut();
+        let fonts = &mut *fonts;
+        let attrs = &self.attrs;
+        let mut label = |label: &str| {
+            let mut text = Buffer::new(&mut fonts.font_system, metrics);
+            text.set_text(label, attrs, Shaping::Advanced, None);
+            text.set_size(None, Some(height));
+            text.shape_until_scroll(&mut fonts.font_system, false);
+            let width =
+                text.layout_runs().map(|run| run.line_w).fold(0.0, f32::max) + 2.0 * padding;
+            (text, width)
+        };
+
+        let buffers = self.buffers.borrow();
+        let current = buffers
+            .iter()
+            .position(|buffer| Rc::ptr_eq(buffer, &self.buffer))
+            .unwrap_or(0);
+        let tabs: Vec<_> = buffers
+            .iter()
+            .map(|buffer| {
+                let buffer = buffer.borrow();
+                let mut name = buffer.name();
+                if buffer.document.is_dirty() {
+                    name.push_str(" [+]");
+                }
+                label(&name)
+            })
+            .collect();
+        let total: f32 = tabs.iter().map(|(_, width)| width).sum();
+
+        // Room is kept for the count of hidden tabs whenever there are any, sized for them all
+        // being hidden so that how many fit doesn't depend on it
+        let (first, last, room) = if total <= rect.width() {
+            (0, tabs.len(), rect.width())
+        } else {
+            let room = rect.width() - label(&format!("+{}", tabs.len())).1;
+            let mut first = 0;
+            let mut width: f32 = tabs[..=current].iter().map(|(_, width)| width).sum();
+            while first < current && width > room {
+                width -= tabs[first].1;
+                first += 1;
+            }
+            let mut last = current + 1;
+            while last < tabs.len() && width + tabs[last].1 <= room {
+                width += tabs[last].1;
+                last += 1;
+            }
+            (first, last, room)
+        };
+        let hidden = tabs.len() - (last - first);
+        let marker = (hidden > 0).then(|| label(&format!("+{hidden}")));
+
+        self.tab_areas.clear();
+        self.tab_areas.resize(tabs.len(), 0.0..0.0);
+        let mut x = 0.0;
+        for (i, (text, width)) in tabs.iter().enumerate().take(last).skip(first) {
+            // A current tab too wide for the strip by itself is cut off rather than drawn over
+            // whatever is next to the editor
+            let width = width.min(room - x);
+            if i == current {
+                let background = self.theme.background;
+                paint.set_color_rgba8(
+                    background.b(),
+                    background.g(),
+                    background.r(),
+                    background.a(),
+                );
+                if let Some(area) = Rect::from_xywh(rect.x() + x, rect.y(), width, height) {
+                    pixmap.fill_rect(area, paint, Transform::identity(), None);
+                }
+            }
+            let mut renderer = EditorRenderer {
+                swash_cache: &mut fonts.swash_cache,
+                font_system: &mut fonts.font_system,
+                pixmap,
+                paint,
+                pixmap_paint: &PixmapPaint::default(),
+                transform: Transform::from_translate(rect.x() + x + padding, rect.y()),
+            };
+            let colour = if i == current {
+                self.theme.current_tab
+            } else {
+                self.theme.tab
+            };
+            renderer.draw_buffer(text, colour);
+            self.tab_areas[i] = x..x + width;
+            x += width;
+        }
+
+        if let Some((text, width)) = marker {
+            let mut renderer = EditorRenderer {
+                swash_cache: &mut fonts.swash_cache,
+                font_system: &mut fonts.font_system,
+                pixmap,
+                paint,
+                pixmap_paint: &PixmapPaint::default(),
+                transform: Transform::from_translate(
+                    rect.x() + rect.width() - width + padding,
+                    rect.y(),
+                ),
+            };
+            renderer.draw_buffer(&text, self.theme.tab);
+        }
+    }
+}
+
+/// Replaces every match of `pattern` in the file at `path` on disk, keeping its encoding and line
+/// endings, and returns how many there were and whether saving made its mixed line endings all
+/// the same.
+fn replace_in_file(
+    path: &Path,
+    pattern: &Pattern,
+    regex: &Regex,
+    replacement: &str,
+) -> Result<(usize, bool), String> {
+    let mut document = Document::open(path.to_owned()).map_err(|err| err.to_string())?;
+    let mixed = document.has_mixed_line_endings();
+    let text = document.text().to_string();
+    let matches = pattern::find_all(regex, &text);
+    let edits = pattern.replacements(regex, &text, &matches, replacement);
+    if edits.is_empty() {
+        return Ok((0, mixed));
+    }
+    // Backwards, so that each replacement leaves the matches before it where they were
+    for (range, text) in edits.iter().rev() {
+        document.replace(range.clone(), text);
+    }
+    document.save().map_err(|err| err.to_string())?;
+    Ok((edits.len(), mixed))
+}
@@ -0,0 +1,588 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::{self, PathBuf};
+use std::rc::{Rc, Weak};
+
+use cosmic_text::{Buffer, Edit, Shaping};
+use lsp_types::{
+    CompletionItem, CompletionTextEdit, DiagnosticSeverity, HoverContents, MarkedString,
+    MessageType, Url,
+};
+use tiny_skia::{Paint, PixmapMut, PixmapPaint, Rect, Transform};
+use winit::keyboard::{Key, ModifiersState, NamedKey, SmolStr};
+
+use crate::document::{Document, EditKind};
+use crate::lsp::{self, Client, Event};
+use crate::Waker;
+
+use super::buffers::OpenBuffer;
+use super::keymap::Mode;
+use super::render::{EditorRenderer, Underline};
+use super::{cursor_at, Editor};
+
+/// How many completions are shown at once.
+const COMPLETION_ROWS: usize = 10;
+
+/// The language server for a buffer's document, along with what it knows the document as. The
+/// server itself is shared with every other buffer of the same language in the same project.
+pub struct LanguageServer {
+    client: Rc<RefCell<Client>>,
+    uri: Url,
+    /// Goes up with every change sent, as the protocol requires.
+    version: i32,
+}
+
+/// The language server for each language and project root, shared by every view like the buffers
+/// are.
+pub type LanguageServers = Rc<RefCell<HashMap<(&'static str, PathBuf), Server>>>;
+
+pub enum Server {
+    /// Only the buffers using it keep it running, so it's shut down once the last of them is
+    /// closed.
+    Running(Weak<RefCell<Client>>),
+    /// It couldn't be started, which isn't tried again, so that every file opened doesn't
+    /// complain about it.
+    Failed,
+}
+
+/// Text shown in a box by the primary cursor, like hover information.
+pub struct Popup {
+    buffer: Buffer,
+}
+
+/// Completions offered at the primary cursor, narrowed down as more of the word is typed.
+pub struct CompletionMenu {
+    items: Vec<CompletionItem>,
+    /// Where the word being completed starts.
+    start: usize,
+    /// Indices into `items` of the ones matching what has been typed so far.
+    matches: Vec<usize>,
+    /// Index into `matches`.
+    selected: usize,
+    buffer: Buffer,
+}
+
+impl LanguageServer {
+    /// Opens the document with the language server for its language and project, starting the
+    /// server if it isn't running yet. A server that can't be started is only complained about
+    /// the first time. Without an event loop to wake, like when rendering headlessly, nothing
+    /// would ever hear back from a server, so none is started.
+    pub fn start(
+        document: &Document,
+        servers: &LanguageServers,
+        waker: Waker,
+    ) -> Result<Option<Self>, String> {
+        if waker.is_none() {
+            return Ok(None);
+        }
+        let found = || {
+            let (language, path) = document.language().zip(document.path())?;
+            let path = path::absolute(path).ok()?;
+            let root = language.project_root(&path)?.to_owned();
+            Some((language, root, lsp::file_url(&path)?))
+        };
+        let Some((language, root, uri)) = found() else {
+            return Ok(None);
+        };
+        let key = (language.name, root);
+        let running = match servers.borrow().get(&key) {
+            Some(Server::Running(client)) => client.upgrade(),
+            Some(Server::Failed) => return Ok(None),
+            None => None,
+        };
+        let client = match running {
+            Some(client) => client,
+            None => {
+                let command = language.language_server;
+                let started = Client::start(command, &key.1, waker);
+                let mut servers = servers.borrow_mut();
+                servers.retain(|_, server| match server {
+                    Server::Running(client) => client.strong_count() > 0,
+                    Server::Failed => true,
+                });
+                let client = match started {
+                    Ok(client) => Rc::new(RefCell::new(client)),
+                    Err(err) => {
+                        servers.insert(key, Server::Failed);
+                        return Err(format!("Could not start {}: {err}", command[0]));
+                    }
+                };
+                servers.insert(key, Server::Running(Rc::downgrade(&client)));
+                client
+            }
+        };
+        let text = document.text().to_string();
+        (client.borrow_mut()).did_open(uri.clone(), language.name, 0, text);
+        Ok(Some(Self {
+            client,
+            uri,
+            version: 0,
+        }))
+    }
+}
+
+impl Drop for LanguageServer {
+    /// Tells the server the document was closed, which shuts the server down as well if no other
+    /// buffer is using it.
+    fn drop(&mut self) {
+        self.client.borrow_mut().did_close(self.uri.clone());
+    }
+}
+
+impl OpenBuffer {
+    /// Tells the language server about edits since the last call.
+    pub fn sync_language_server(&mut self) {
+        let OpenBuffer {
+            document,
+            language_server,
+            ..
+        } = self;
+        let changes = document.take_changes();
+        let Some(server) = language_server else {
+            return;
+        };
+        if !changes.is_empty() {
+            server.version += 1;
+            (server.client.borrow_mut()).did_change(
+                server.uri.clone(),
+                server.version,
+                &changes,
+                document,
+            );
+        }
+    }
+}
+
+impl Editor<'_> {
+    pub(super) fn sync_language_server(&mut self) {
+        self.buffer.borrow_mut().sync_language_server();
+    }
+
+    pub(super) fn notify_saved(&mut self) {
+        if let Some(server) = &mut self.buffer.borrow_mut().language_server {
+            server.client.borrow_mut().did_save(server.uri.clone());
+        }
+    }
+
+    /// Handles whatever the running language servers have sent, returning whether anything
+    /// changed. Only the current buffer's server gets to show anything.
+    pub(super) fn poll_language_servers(&mut self) -> bool {
+        let mut redraw = false;
+        let clients: Vec<_> = (self.servers.borrow().values())
+            .filter_map(|server| match server {
+                Server::Running(client) => client.upgrade(),
+                Server::Failed => None,
+            })
+            .collect();
+        for client in clients {
+            let uses_client = |buffer: &OpenBuffer| {
+                (buffer.language_server.as_ref())
+                    .is_some_and(|server| Rc::ptr_eq(&server.client, &client))
+            };
+            let current = uses_client(&self.buffer.borrow());
+            let events = client.borrow_mut().poll();
+            redraw |= !events.is_empty();
+            for event in events {
+                match event {
+                    Event::Diagnostics(params) => {
+                        for buffer in self.buffers.borrow().iter() {
+                            let mut buffer = buffer.borrow_mut();
+                            let open = (buffer.language_server.as_ref())
+                                .is_some_and(|server| server.uri == params.uri);
+                            if open && uses_client(&buffer) {
+                                buffer.diagnostics = params.diagnostics.clone();
+                            }
+                        }
+                    }
+                    Event::Hover(hover) if current => {
+                        self.show_hover(hover.map(|hover| hover_text(hover.contents)))
+                    }
+                    Event::Completion(items) if current => self.show_completions(items),
+                    Event::Message(MessageType::ERROR, text) => self.show_error(&text),
+                    Event::Message(_, text) => self.show_message(&text, self.theme.foreground),
+                    Event::Exited(reason) => {
+                        self.show_error(&format!("Language server stopped: {reason}"));
+                        for buffer in self.buffers.borrow().iter() {
+                            let mut buffer = buffer.borrow_mut();
+                            if uses_client(&buffer) {
+                                buffer.language_server = None;
+                                buffer.diagnostics.clear();
+                            }
+                        }
+                    }
+                    Event::Hover(_) | Event::Completion(_) => (),
+                }
+            }
+        }
+        redraw
+    }
+
+    /// The diagnostics as underlines, an empty range covering the grapheme after it.
+    pub(super) fn diagnostic_underlines(&self) -> Vec<Underline> {
+        let buffer = self.buffer.borrow();
+        let Some(server) = &buffer.language_server else {
+            return Vec::new();
+        };
+        let document = &buffer.document;
+        let client = server.client.borrow();
+        buffer
+            .diagnostics
+            .iter()
+            .map(|diagnostic| {
+                let start = client.offset(document, diagnostic.range.start);
+                let end = client.offset(document, diagnostic.range.end);
+                let end = if end <= start {
+                    document.next_grapheme_boundary(start)
+                } else {
+                    end
+                };
+                Underline {
+                    start: cursor_at(document, start),
+                    end: cursor_at(document, end),
+                    colour: match diagnostic.severity {
+                        Some(DiagnosticSeverity::ERROR) => self.theme.error,
+                        Some(DiagnosticSeverity::WARNING) => self.theme.warning,
+                        Some(DiagnosticSeverity::INFORMATION) => self.theme.info,
+                        _ => self.theme.hint,
+                    },
+                }
+            })
+            .collect()
+    }
+
+    /// Asks the language server about what's under the primary cursor. Its answer is shown
+    /// along with the messages of any diagnostics there.
+    pub(super) fn hover(&mut self) {
+        let head = self.selection.primary().head;
+        let mut buffer = self.buffer.borrow_mut();
+        let OpenBuffer {
+            document,
+            language_server,
+            ..
+        } = &mut *buffer;
+        let Some(server) = language_server else {
+            drop(buffer);
+            return self.show_error("No language server for this document");
+        };
+        (server.client.borrow_mut()).hover(server.uri.clone(), document, head);
+    }
+
+    fn show_hover(&mut self, text: Option<String>) {
+        let head = self.selection.primary().head;
+        let buffer = self.buffer.borrow();
+        let mut sections: Vec<String> = buffer
+            .language_server
+            .as_ref()
+            .map(|server| {
+                let client = server.client.borrow();
+                buffer
+                    .diagnostics
+                    .iter()
+                    .filter(|diagnostic| {
+                        let start = client.offset(&buffer.document, diagnostic.range.start);
+                        let end = client.offset(&buffer.document, diagnostic.range.end);
+                        start <= head && head <= end
+                    })
+                    .map(|diagnostic| diagnostic.message.clone())
+                    .collect()
+            })
+            .unwrap_or_default();
+        drop(buffer);
+        sections.extend(text.filter(|text| !text.trim().is_empty()));
+        if sections.is_empty() {
+            return self.show_message("No hover information", self.theme.foreground);
+        }
+
+        let mut buffer = Buffer::new(
+            &mut self.fonts.borrow_mut().font_system,
+            self.editor_metrics(),
+        );
+        buffer.set_text(&sections.join("\n\n"), &self.attrs, Shaping::Advanced, None);
+        self.hover = Some(Popup { buffer });
+    }
+
+    /// Asks the language server for completions at the primary cursor.
+    pub(super) fn complete(&mut self) {
+        let head = self.selection.primary().head;
+        let mut buffer = self.buffer.borrow_mut();
+        let OpenBuffer {
+            document,
+            language_server,
+            ..
+        } = &mut *buffer;
+        if let Some(server) = language_server {
+            (server.client.borrow_mut()).completion(server.uri.clone(), document, head);
+        }
+    }
+
+    /// Asks for completions if `text`, which was just typed, ends in one of the characters the
+    /// language server wants them after.
+    pub(super) fn complete_after(&mut self, text: &str) {
+        let triggered = self
+            .buffer
+            .borrow()
+            .language_server
+            .as_ref()
+            .is_some_and(|server| {
+                (server.client.borrow())
+                    .completion_triggers()
+                    .iter()
+                    .any(|trigger| text.ends_with(trigger.as_str()))
+            });
+        if triggered {
+            self.complete();
+        }
+    }
+
+    fn show_completions(&mut self, items: Vec<CompletionItem>) {
+        if self.mode != Mode::Insert || items.is_empty() {
+            return;
+        }
+        let head = self.selection.primary().head;
+        let start = word_start(&self.document(), head);
+        let buffer = Buffer::new(
+            &mut self.fonts.borrow_mut().font_system,
+            self.editor_metrics(),
+        );
+        self.completion = Some(CompletionMenu {
+            items,
+            start,
+            matches: Vec::new(),
+            selected: 0,
+            buffer,
+        });
+        self.filter_completions();
+    }
+
+    /// Narrows the completions down to what has been typed since they were asked for, closing
+    /// them if the cursor has left the word or nothing matches.
+    pub(super) fn filter_completions(&mut self) {
+        let Some(mut menu) = self.completion.take() else {
+            return;
+        };
+        let head = self.selection.primary().head;
+        if self.mode != Mode::Insert
+            || head < menu.start
+            || word_start(&self.document(), head) != menu.start
+        {
+            return;
+        }
+        let typed = self
+            .document()
+            .text()
+            .byte_slice(menu.start..head)
+            .to_string()
+            .to_lowercase();
+        menu.matches = (0..menu.items.len())
+            .filter(|&i| {
+                let item = &menu.items[i];
+                item.filter_text
+                    .as_deref()
+                    .unwrap_or(&item.label)
+                    .to_lowercase()
+                    .starts_with(&typed)
+            })
+            .collect();
+        if menu.matches.is_empty() {
+            return;
+        }
+        menu.selected = menu.selected.min(menu.matches.len() - 1);
+        self.completion = Some(menu);
+        self.layout_completions();
+    }
+
+    /// Refills the completion menu's buffer with the rows around the selected one.
+    fn layout_completions(&mut self) {
+        let Some(menu) = &mut self.completion else {
+            return;
+        };
+        let first = menu.selected.saturating_sub(COMPLETION_ROWS - 1);
+        let rows: Vec<_> = menu.matches[first..]
+            .iter()
+            .take(COMPLETION_ROWS)
+            .map(|&i| menu.items[i].label.as_str())
+            .collect();
+        menu.buffer
+            .set_text(&rows.join("\n"), &self.attrs, Shaping::Advanced, None);
+    }
+
+    /// Handles a key press while completions are showing, returning whether it was used.
+    pub(super) fn handle_completion_key(
+        &mut self,
+        key: &Key<SmolStr>,
+        modifiers: ModifiersState,
+    ) -> bool {
+        let Some(menu) = &mut self.completion else {
+            return false;
+        };
+        let ctrl = modifiers.control_key();
+        let len = menu.matches.len();
+        match key {
+            Key::Named(NamedKey::ArrowDown) => menu.selected = (menu.selected + 1) % len,
+            Key::Character(c) if ctrl && c == "n" => menu.selected = (menu.selected + 1) % len,
+            Key::Named(NamedKey::ArrowUp) => menu.selected = (menu.selected + len - 1) % len,
+            Key::Character(c) if ctrl && c == "p" => {
+                menu.selected = (menu.selected + len - 1) % len
+            }
+            Key::Named(NamedKey::Enter | NamedKey::Tab) => {
+                self.accept_completion();
+                return true;
+            }
+            Key::Named(NamedKey::Escape) => {
+                self.completion = None;
+                return true;
+            }
+            _ => return false,
+        }
+        self.layout_completions();
+        true
+    }
+
+    /// Replaces the word being completed at every cursor with the selected completion.
+    fn accept_completion(&mut self) {
+        let Some(menu) = self.completion.take() else {
+            return;
+        };
+        let item = &menu.items[menu.matches[menu.selected]];
+        let (start, text) = match &item.text_edit {
+            Some(CompletionTextEdit::Edit(edit)) => (Some(edit.range.start), &edit.new_text),
+            Some(CompletionTextEdit::InsertAndReplace(edit)) => {
+                (Some(edit.replace.start), &edit.new_text)
+            }
+            None => (None, item.insert_text.as_ref().unwrap_or(&item.label)),
+        };
+        let head = self.selection.primary().head;
+        let buffer = self.buffer.borrow();
+        let start = match (start, &buffer.language_server) {
+            (Some(start), Some(server)) => {
+                (server.client.borrow().offset(&buffer.document, start)).min(head)
+            }
+            _ => menu.start,
+        };
+        drop(buffer);
+        // Every cursor replaces as much before it as the primary one does
+        let typed = head - start;
+        let text = text.clone();
+        self.edit(EditKind::Other, |this| {
+            let edits = this
+                .selection
+                .ranges()
+                .iter()
+                .map(|range| {
+                    let line_start = this
+                        .document()
+                        .byte_of_line(this.document().line_of_byte(range.head));
+                    (
+                        range.head.saturating_sub(typed).max(line_start)..range.head,
+                        text.clone(),
+                    )
+                })
+                .collect();
+            this.insert_each(edits);
+        });
+    }
+
+    /// Draws the hover popup or completion menu, if either is showing, by the primary cursor.
+    pub(super) fn render_popups<'draw>(
+        &mut self,
+        pixmap: &mut PixmapMut<'draw>,
+        paint: &mut Paint<'draw>,
+        rect: Rect,
+        transform: Transform,
+    ) {
+        let head = self.cursor_at(self.selection.primary().head);
+        let Some((x, line_top)) = self
+            .editor
+            .with_buffer(|buffer| buffer.cursor_position(&head))
+        else {
+            return;
+        };
+        let line_height = self.editor_metrics().line_height;
+
+        let mut fonts_guard = self.fonts.borrow_mut();
+        let fonts = &mut *fonts_guard;
+        let (buffer, selected, max_rows) = match (&mut self.hover, &mut self.completion) {
+            (_, Some(menu)) => {
+                let first = menu.selected.saturating_sub(COMPLETION_ROWS - 1);
+                (
+                    &mut menu.buffer,
+                    Some(menu.selected - first),
+                    COMPLETION_ROWS,
+                )
+            }
+            (Some(popup), None) => (&mut popup.buffer, None, 12),
+            (None, None) => return,
+        };
+
+        let padding = line_height / 4.0;
+        buffer.set_size(Some(rect.width() * 0.6), None);
+        buffer.shape_until_scroll(&mut fonts.font_system, false);
+        let (mut width, mut rows) = (0.0_f32, 0);
+        for run in buffer.layout_runs() {
+            width = width.max(run.line_w);
+            rows += 1;
+        }
+        let width = width + 2.0 * padding;
+        let height = rows.min(max_rows) as f32 * line_height;
+        buffer.set_size(Some(width), Some(height));
+        buffer.shape_until_scroll(&mut fonts.font_system, false);
+
+        // Below the cursor's line if it fits there, and above it if not
+        let below = line_top + line_height;
+        let y = if below + height <= rect.height() || line_top < height {
+            below
+        } else {
+            line_top - height
+        };
+        let x = x.min(rect.width() - width).max(0.0);
+
+        let mut renderer = EditorRenderer {
+            swash_cache: &mut fonts.swash_cache,
+            font_system: &mut fonts.font_system,
+            pixmap,
+            paint,
+            pixmap_paint: &PixmapPaint::default(),
+            transform: transform.pre_translate(x, y),
+        };
+        renderer.draw_popup(buffer, width, height, padding, selected, &self.theme);
+    }
+}
+
+/// Hover contents as plain text. Markdown is shown as it is, apart from code fences.
+fn hover_text(contents: HoverContents) -> String {
+    let marked = |marked: MarkedString| match marked {
+        MarkedString::String(text) => text,
+        MarkedString::LanguageString(code) => code.value,
+    };
+    let text = match contents {
+        HoverContents::Scalar(text) => marked(text),
+        HoverContents::Array(texts) => texts
+            .into_iter()
+            .map(marked)
+            .collect::<Vec<_>>()
+            .join("\n\n"),
+        HoverContents::Markup(markup) => markup.value,
+    };
+    text.lines()
+        .filter(|line| !line.trim_start().starts_with("```"))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The start of the identifier-like word that `byte` is at the end of.
+fn word_start(document: &Document, byte: usize) -> usize {
+    let mut start = byte;
+    while start > 0 {
+        let prev = document.prev_grapheme_boundary(start);
+        let is_word = document
+            .text()
+            .byte_slice(prev..start)
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_');
+        if !is_word {
+            break;
+        }
+        start = prev;
+    }
+    start
+}
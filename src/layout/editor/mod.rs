@@ -0,0 +1,1768 @@
+mod buffers;
+mod lsp;
+mod palette;
+mod plugins;
+pub mod render;
+mod search;
+
+use std::cell::{Ref, RefCell, RefMut};
+use std::iter;
+use std::ops::{Range, RangeInclusive};
+use std::path::{self, Path};
+use std::rc::Rc;
+use std::sync::Arc;
+
+use arboard::Clipboard;
+use cosmic_text::{
+    Action, Attrs, AttrsList, Buffer, BufferLine, Color as CTColor, Cursor, Edit,
+    Editor as CTEditor, Family, LineEnding, Metrics, Motion, Shaping,
+};
+use lsp_types::DiagnosticSeverity;
+use regex::Regex;
+use tiny_skia::{Paint, PixmapMut, PixmapPaint, Rect, Transform};
+use winit::{
+    event::{ElementState, MouseButton},
+    keyboard::{Key, NamedKey, SmolStr},
+};
+
+use crate::config::{Callback, Config, Theme};
+use crate::document::selection::{Range as SelectionRange, Selection};
+use crate::document::{movement, Change, Document, EditKind};
+use crate::{InputState, Waker};
+
+use super::keymap::{self, Command, KeyPress, Mode, Press};
+use super::modes::{self, ActiveModes, Hook, MajorMode};
+use super::status_bar::Status;
+use super::{Direction, EditorState, Fonts, Interactive, Request, Requests};
+use buffers::{BufferList, OpenBuffer, SharedBuffer};
+use lsp::{CompletionMenu, LanguageServer, LanguageServers, Popup};
+use palette::Palette;
+use render::{CursorShape, EditorRenderer, SearchMatch, SelectionSpan};
+use search::Search;
+
+pub struct Editor<'buffer> {
+    fonts: Rc<RefCell<Fonts>>,
+    metrics: Metrics,
+    theme: Arc<Theme>,
+    attrs: Attrs<'buffer>,
+    /// Lays out and scrolls the text. Its cursor follows the primary selection so that it keeps
+    /// that in view, but the selections themselves live in `selection`.
+    editor: CTEditor<'buffer>,
+    selection: Selection,
+    /// What the current buffer's modes come to, including its keymap.
+    modes: ActiveModes,
+    mode: Mode,
+    /// The count typed before a command in normal or select mode, e.g. the 3 in `3w`.
+    count: Option<usize>,
+    /// The keys typed so far of a sequence bound in the keymap, like the `g` of `g g`.
+    pending: Vec<KeyPress>,
+    /// The column each selection's vertical motions aim for, kept across a run of them so that
+    /// passing through a short line doesn't pull the cursor left for good.
+    preferred_columns: Vec<usize>,
+    /// Text yanked or deleted by `y`, `d` and `c`, one value per selection, which `p` and `P`
+    /// paste.
+    register: Vec<String>,
+    /// Missing when there's no display to own a clipboard, like when rendering headlessly.
+    clipboard: Option<Clipboard>,
+    buffers: BufferList,
+    /// The language servers of `buffers`, shared by every view along with them.
+    servers: LanguageServers,
+    /// The one of `buffers` being shown and edited.
+    buffer: SharedBuffer,
+    /// The document's version as of when `editor`'s lines were last brought up to date with it,
+    /// which falls behind when the document is edited through another view.
+    version: usize,
+    /// Where each tab was last drawn, horizontally within the editor.
+    tab_areas: Vec<Range<f32>>,
+    /// Set after refusing to close a buffer with unsaved changes, so that closing it again
+    /// straight away goes ahead.
+    close_warned: bool,
+    message: Option<Message>,
+    prompt: Option<Prompt>,
+    hover: Option<Popup>,
+    completion: Option<CompletionMenu>,
+    palette: Option<Palette>,
+    /// The last search, and the search bar while it's open.
+    search: Option<Search>,
+    /// Shared with the status bar, which shows the mode, the cursor position and so on.
+    status: Rc<RefCell<Status>>,
+    /// For language servers to wake the event loop with.
+    waker: Waker,
+    /// For asking the rest of the UI for things, like splitting the view.
+    requests: Requests,
+    /// What the editor was last configured with, which the modes of each buffer it shows are
+    /// worked out from.
+    config: Config,
+    /// How many plugins are running inside one another, through commands and hooks they set off.
+    plugin_depth: usize,
+    /// Whether keys come to this view, which only the focused one draws solid cursors for.
+    focused: bool,
+}
+
+/// A one-line notice shown along the bottom of the editor until the next key press.
+struct Message {
+    buffer: Buffer,
+    colour: CTColor,
+}
+
+/// A line of input asked for by a command, shown where messages go until it is submitted with
+/// Enter or dismissed with Escape.
+struct Prompt {
+    label: String,
+    input: String,
+    action: PromptAction,
+}
+
+enum PromptAction {
+    SplitSelection,
+    SelectMatches,
+    GoToLine,
+    SetMajorMode,
+    ToggleMinorMode,
+    /// Calls a plugin back with the input.
+    Plugin(Callback),
+}
+
+impl Editor<'_> {
+    pub fn new(
+        scale_factor: f64,
+        fonts: Rc<RefCell<Fonts>>,
+        status: Rc<RefCell<Status>>,
+        waker: Waker,
+        requests: Requests,
+    ) -> Self {
+        let metrics = Metrics::new(32.0, 48.0);
+        let buffer = OpenBuffer::new(Document::default(), None);
+        Self::view(
+            metrics,
+            metrics.scale(scale_factor as f32),
+            Rc::new(RefCell::new(vec![buffer.clone()])),
+            LanguageServers::default(),
+            buffer,
+            fonts,
+            status,
+            waker,
+            requests,
+            Arc::default(),
+        )
+    }
+
+    /// A view of `buffer`, one of `buffers`, which may be shown by other views as well.
+    /// `layout_metrics` are `metrics` scaled for the display, until the view is first drawn.
+    #[allow(clippy::too_many_arguments)]
+    fn view(
+        metrics: Metrics,
+        layout_metrics: Metrics,
+        buffers: BufferList,
+        servers: LanguageServers,
+        buffer: SharedBuffer,
+        fonts: Rc<RefCell<Fonts>>,
+        status: Rc<RefCell<Status>>,
+        waker: Waker,
+        requests: Requests,
+        theme: Arc<Theme>,
+    ) -> Self {
+        let text = Buffer::new(&mut fonts.borrow_mut().font_system, layout_metrics);
+        let attrs = Attrs::new().family(Family::Monospace);
+        let editor = CTEditor::new(text);
+        let clipboard = Clipboard::new()
+            .inspect_err(|err| eprintln!("Failed to initialise clipboard: {err}"))
+            .ok();
+
+        let modes = ActiveModes::new(&buffer.borrow().modes, &Config::default());
+        let mut this = Self {
+            fonts,
+            metrics,
+            theme,
+            attrs,
+            editor,
+            selection: Selection::point(0),
+            modes,
+            mode: Mode::Normal,
+            count: None,
+            pending: Vec::new(),
+            preferred_columns: Vec::new(),
+            register: Vec::new(),
+            clipboard,
+            buffers,
+            servers,
+            buffer,
+            version: 0,
+            tab_areas: Vec::new(),
+            close_warned: false,
+            message: None,
+            prompt: None,
+            hover: None,
+            completion: None,
+            palette: None,
+            search: None,
+            status,
+            waker,
+            requests,
+            config: Config::default(),
+            plugin_depth: 0,
+            focused: true,
+        };
+        this.show_buffer();
+        this
+    }
+
+    fn document(&self) -> Ref<'_, Document> {
+        Ref::map(self.buffer.borrow(), |buffer| &buffer.document)
+    }
+
+    fn document_mut(&self) -> RefMut<'_, Document> {
+        RefMut::map(self.buffer.borrow_mut(), |buffer| &mut buffer.document)
+    }
+
+    /// Rebuilds every `BufferLine` from the document, which is only needed when a whole new
+    /// document is loaded or this view has fallen too far behind the document's edits.
+    fn sync_all_lines(&mut self) {
+        let lines = self.buffer_lines(0..=self.document().len_lines() - 1);
+        self.editor.with_buffer_mut(|buffer| {
+            buffer.lines = lines;
+            buffer.set_redraw(true);
+        });
+        let version = self.document().version();
+        self.version = version;
+    }
+
+    fn buffer_lines(&self, lines: RangeInclusive<usize>) -> Vec<BufferLine> {
+        self.attrs_lists(lines.clone())
+            .into_iter()
+            .zip(lines)
+            .map(|(attrs_list, line)| {
+                let ending = if line + 1 < self.document().len_lines() {
+                    LineEnding::Lf
+                } else {
+                    LineEnding::None
+                };
+                BufferLine::new(
+                    self.document().line(line).to_string(),
+                    ending,
+                    attrs_list,
+                    Shaping::Advanced,
+                )
+            })
+            .collect()
+    }
+
+    /// The attributes for each of `lines`: `attrs`, with syntax highlighting on top.
+    fn attrs_lists(&self, lines: RangeInclusive<usize>) -> Vec<AttrsList> {
+        let (first, last) = (*lines.start(), *lines.end());
+        let start = self.document().byte_of_line(first);
+        let end = self.document().byte_of_line(last) + self.document().line(last).byte_len();
+        let mut attrs_lists: Vec<_> = lines.map(|_| AttrsList::new(&self.attrs)).collect();
+
+        for (range, capture) in self.document().highlights(start..end) {
+            let Some(attrs) = render::highlight_attrs(&self.attrs, capture, &self.theme) else {
+                continue;
+            };
+            let range = range.start.max(start)..range.end.min(end);
+            // Captures like block comments can span several lines
+            for line in
+                self.document().line_of_byte(range.start)..=self.document().line_of_byte(range.end)
+            {
+                let line_start = self.document().byte_of_line(line);
+                let line_end = line_start + self.document().line(line).byte_len();
+                let span =
+                    range.start.max(line_start) - line_start..range.end.min(line_end) - line_start;
+                if !span.is_empty() {
+                    attrs_lists[line - first].add_span(span, &attrs);
+                }
+            }
+        }
+        attrs_lists
+    }
+
+    /// Applies one replacement per selection, given in the same order as the selections, and
+    /// returns where the text of each one ended up. A replacement that overlaps the one before it
+    /// is trimmed so that it doesn't, and an exact repeat of it is skipped. Callers are
+    /// responsible for updating the selection and committing the edit to the undo history.
+    fn replace_each(&mut self, edits: Vec<(Range<usize>, String)>) -> Vec<Range<usize>> {
+        let mut spans: Vec<Range<usize>> = Vec::with_capacity(edits.len());
+        // How far the replacements so far have moved everything after them
+        let mut shift = 0;
+        let mut prev_end = 0;
+        for (i, (range, text)) in edits.iter().enumerate() {
+            if i > 0 && edits[i - 1] == edits[i] {
+                spans.push(spans[i - 1].clone());
+                continue;
+            }
+            let start = range.start.max(prev_end);
+            let end = range.end.max(start);
+            prev_end = end;
+
+            let new_start = start.checked_add_signed(shift).unwrap();
+            let change = self
+                .document_mut()
+                .replace(new_start..new_start + (end - start), text);
+            self.sync_changes(&[change]);
+            shift += text.len() as isize - (end - start) as isize;
+            spans.push(new_start..change.new_end);
+        }
+        spans
+    }
+
+    /// Like `replace_each`, leaving a cursor after each replacement.
+    fn insert_each(&mut self, edits: Vec<(Range<usize>, String)>) {
+        let spans = self.replace_each(edits);
+        self.set_ranges(
+            spans
+                .into_iter()
+                .map(|span| SelectionRange::point(span.end)),
+        );
+    }
+
+    /// Re-creates only the `BufferLine`s touched by `changes`, which were made in order since the
+    /// lines were last in step with the document. Each one counts lines in the text as it was
+    /// just before it, so the lines are spliced one change at a time and only the ones left
+    /// changed at the end are built from the document.
+    fn sync_changes(&mut self, changes: &[Change]) {
+        // The lines that need building, sorted and apart
+        let mut dirty: Vec<RangeInclusive<usize>> = Vec::new();
+        self.editor.with_buffer_mut(|buffer| {
+            for change in changes {
+                let added = change.new_end_line - change.start_line + 1;
+                let removed = change.old_end_line - change.start_line + 1;
+                let placeholder = BufferLine::new(
+                    "",
+                    LineEnding::None,
+                    AttrsList::new(&self.attrs),
+                    Shaping::Advanced,
+                );
+                buffer.lines.splice(
+                    change.start_line..=change.old_end_line,
+                    iter::repeat_n(placeholder, added),
+                );
+
+                let moved = |line: usize| line + added - removed;
+                let mut changed = change.start_line..=change.new_end_line;
+                let mut ranges = Vec::with_capacity(dirty.len() + 1);
+                for range in dirty.drain(..) {
+                    if *range.end() + 1 < change.start_line {
+                        ranges.push(range);
+                    } else if *range.start() > change.old_end_line + 1 {
+                        ranges.push(moved(*range.start())..=moved(*range.end()));
+                    } else {
+                        // Touching or overlapping the change, so they become one
+                        let end = if *range.end() > change.old_end_line {
+                            moved(*range.end())
+                        } else {
+                            *changed.end()
+                        };
+                        changed = *range.start().min(changed.start())..=end.max(*changed.end());
+                    }
+                }
+                let at = ranges.partition_point(|range| range.end() < changed.start());
+                ranges.insert(at, changed);
+                dirty = ranges;
+            }
+        });
+
+        let mut fonts = self.fonts.borrow_mut();
+        for range in dirty {
+            let lines = self.buffer_lines(range.clone());
+            self.editor.with_buffer_mut(|buffer| {
+                buffer.lines.splice(range.clone(), lines);
+                // cosmic-text only shapes lines again when it knows they've changed, and it stops
+                // drawing at the first unshaped line, so the new ones in view are shaped straight
+                // away
+                let scroll = buffer.scroll();
+                let rows = buffer.size().1.unwrap_or(0.0) / buffer.metrics().line_height;
+                let visible = scroll.line..=scroll.line + rows.ceil() as usize;
+                for line in range {
+                    if visible.contains(&line) {
+                        buffer.line_layout(&mut fonts.font_system, line);
+                    }
+                }
+                buffer.set_redraw(true);
+            });
+        }
+        drop(fonts);
+        let version = self.document().version();
+        self.version = version;
+    }
+
+    /// Reparses the document after edits and re-highlights wherever its syntax changed.
+    fn reparse(&mut self) {
+        let ranges = self.document_mut().reparse();
+        self.rehighlight(ranges);
+    }
+
+    /// Highlights the lines overlapping `ranges` again, keeping their text.
+    fn rehighlight(&mut self, ranges: Vec<Range<usize>>) {
+        let len = self.document().text().byte_len();
+        for range in ranges {
+            let first = self.document().line_of_byte(range.start.min(len));
+            let last = self.document().line_of_byte(range.end.min(len));
+            let attrs_lists = self.attrs_lists(first..=last);
+            self.editor.with_buffer_mut(|buffer| {
+                for (line, attrs_list) in buffer.lines[first..=last].iter_mut().zip(attrs_lists) {
+                    line.set_attrs_list(attrs_list);
+                }
+                buffer.set_redraw(true);
+            });
+        }
+    }
+
+    /// Runs `f` and commits whatever it edited as one undo step of the given kind.
+    fn edit(&mut self, kind: EditKind, f: impl FnOnce(&mut Self)) {
+        let before = self.selection.clone();
+        f(self);
+        self.reparse();
+        self.sync_language_server();
+        let after = self.selection.clone();
+        self.document_mut().commit(kind, before, after);
+    }
+
+    /// Replaces every selection (or inserts at every cursor) with `text`.
+    fn insert(&mut self, text: &str, kind: EditKind) {
+        self.edit(kind, |this| {
+            let edits = this
+                .selection
+                .ranges()
+                .iter()
+                .map(|range| (range.bytes(), text.to_owned()))
+                .collect();
+            this.insert_each(edits);
+        });
+    }
+
+    /// Deletes each selection, or from each cursor back to wherever `motion` goes. Deleting
+    /// single graphemes from cursors coalesces into one undo step with `EditKind::Delete`.
+    fn delete_backward(&mut self, kind: EditKind, motion: fn(&Document, usize) -> usize) {
+        self.delete(kind, |document, range| {
+            if range.is_empty() {
+                motion(document, range.start())..range.end()
+            } else {
+                range.bytes()
+            }
+        });
+    }
+
+    /// Deletes each selection, or the grapheme after each cursor.
+    fn delete_forward(&mut self) {
+        self.delete(EditKind::Delete, |document, range| {
+            if range.is_empty() {
+                range.start()..document.next_grapheme_boundary(range.end())
+            } else {
+                range.bytes()
+            }
+        });
+    }
+
+    /// Deletes what `target` picks for each selection, as an undo step of the given kind unless
+    /// any selection had text in it, which is never coalesced.
+    fn delete(
+        &mut self,
+        kind: EditKind,
+        target: impl Fn(&Document, SelectionRange) -> Range<usize>,
+    ) {
+        let ranges = self.selection.ranges();
+        let kind = if ranges.iter().all(SelectionRange::is_empty) {
+            kind
+        } else {
+            EditKind::Other
+        };
+        let edits = ranges
+            .iter()
+            .map(|&range| (target(&self.document(), range), String::new()))
+            .collect();
+        self.edit(kind, |this| this.insert_each(edits));
+    }
+
+    fn undo(&mut self) {
+        let change = self.document_mut().undo();
+        match change {
+            Some((changes, selection)) => self.restore(changes, selection),
+            None => self.show_message("Already at oldest change", self.theme.foreground),
+        }
+    }
+
+    fn redo(&mut self) {
+        let change = self.document_mut().redo();
+        match change {
+            Some((changes, selection)) => self.restore(changes, selection),
+            None => self.show_message("Already at newest change", self.theme.foreground),
+        }
+    }
+
+    fn travel(&mut self, forward: bool) {
+        let change = self.document_mut().travel(forward);
+        match change {
+            Some((changes, selection)) => self.restore(changes, selection),
+            None if forward => self.show_message("Already at newest change", self.theme.foreground),
+            None => self.show_message("Already at oldest change", self.theme.foreground),
+        }
+    }
+
+    fn restore(&mut self, changes: Vec<Change>, selection: Selection) {
+        self.sync_changes(&changes);
+        self.reparse();
+        self.sync_language_server();
+        self.set_selection(selection);
+    }
+
+    /// Runs a command from the keymap, repeating it `count` times where that makes sense.
+    fn execute(&mut self, command: Command, count: usize) {
+        use Command::*;
+
+        if let Some(minor) = self.modes.needed_for(command) {
+            let name = command.name();
+            return self.show_error(&format!("{name} only works in {} mode", minor.name));
+        }
+        if !matches!(command, MoveUp | MoveDown | ExtendUp | ExtendDown) {
+            self.preferred_columns.clear();
+        }
+        // Closing a buffer with unsaved changes only goes ahead if asked twice in a row
+        let discard = command == CloseBuffer && self.close_warned;
+        self.close_warned = false;
+        // In select mode every motion extends the selection
+        let extend = self.mode == Mode::Select;
+        let lines = count as isize;
+        match command {
+            MoveLeft => self.move_head(count, extend, Document::prev_grapheme_boundary),
+            MoveRight => self.move_head(count, extend, Document::next_grapheme_boundary),
+            MoveUp => self.move_vertically(-lines, extend),
+            MoveDown => self.move_vertically(lines, extend),
+            ExtendLeft => self.move_head(count, true, Document::prev_grapheme_boundary),
+            ExtendRight => self.move_head(count, true, Document::next_grapheme_boundary),
+            ExtendUp => self.move_vertically(-lines, true),
+            ExtendDown => self.move_vertically(lines, true),
+            MovePrevWord => self.move_head(count, extend, movement::prev_word_start),
+            MoveNextWord => self.move_head(count, extend, movement::next_word_start),
+            ExtendPrevWord => self.move_head(count, true, movement::prev_word_start),
+            ExtendNextWord => self.move_head(count, true, movement::next_word_start),
+            NextWordStart => self.select_word(count, extend, movement::next_word_start),
+            NextWordEnd => self.select_word(count, extend, movement::next_word_end),
+            PrevWordStart => self.select_word(count, extend, movement::prev_word_start),
+            LineStart => self.move_head(1, extend, movement::line_start),
+            LineEnd => self.move_head(1, extend, movement::line_end),
+            GoToStart => self.move_head(1, extend, movement::first_line_start),
+            GoToEnd => self.move_head(1, extend, movement::last_line_start),
+            PageUp => self.move_page(Motion::PageUp, extend),
+            PageDown => self.move_page(Motion::PageDown, extend),
+            GoToLine => self.open_prompt("go to line: ", PromptAction::GoToLine),
+            SelectLine => self.select_lines(count),
+            SelectAll => {
+                let len = self.document().text().byte_len();
+                self.set_selection(Selection::single(SelectionRange::new(0, len)));
+            }
+            CollapseSelection => self.set_selection(
+                self.selection
+                    .map(|range| SelectionRange::point(range.head)),
+            ),
+            CopySelectionBelow => self.copy_selection_vertically(lines),
+            CopySelectionAbove => self.copy_selection_vertically(-lines),
+            SplitSelection => self.open_prompt("split: ", PromptAction::SplitSelection),
+            SelectMatches => self.open_prompt("select: ", PromptAction::SelectMatches),
+            AlignSelections => self.align_selections(),
+            KeepPrimarySelection => self.set_selection(self.selection.keep_primary()),
+            RotateSelectionsForward => self.set_selection(self.selection.rotate(true)),
+            RotateSelectionsBackward => self.set_selection(self.selection.rotate(false)),
+            Delete | Change => {
+                self.yank();
+                self.edit(EditKind::Other, |this| {
+                    let edits = this
+                        .selection
+                        .ranges()
+                        .iter()
+                        .map(|&range| (this.target_range(range), String::new()))
+                        .collect();
+                    this.insert_each(edits);
+                });
+                self.mode = if command == Change {
+                    Mode::Insert
+                } else {
+                    Mode::Normal
+                };
+            }
+            Yank => {
+                self.yank();
+                self.mode = Mode::Normal;
+            }
+            PasteAfter => self.paste(true, count),
+            PasteBefore => self.paste(false, count),
+            InsertBefore => self.insert_at(|_, range| range.start()),
+            InsertAfter => self.insert_at(|document, range| {
+                // Append after the grapheme under the block cursor, but not past the line break
+                if range.is_empty() && range.end() != movement::line_end(document, range.end()) {
+                    document.next_grapheme_boundary(range.end())
+                } else {
+                    range.end()
+                }
+            }),
+            InsertLineStart => self
+                .insert_at(|document, range| movement::line_first_non_blank(document, range.head)),
+            InsertLineEnd => {
+                self.insert_at(|document, range| movement::line_end(document, range.head))
+            }
+            OpenBelow => self.open_line(true),
+            OpenAbove => self.open_line(false),
+            NormalMode => {
+                if self.mode == Mode::Normal {
+                    self.set_selection(
+                        self.selection
+                            .map(|range| SelectionRange::point(range.head)),
+                    );
+                }
+                self.mode = Mode::Normal;
+            }
+            ToggleSelectMode => {
+                self.mode = match self.mode {
+                    Mode::Select => Mode::Normal,
+                    _ => Mode::Select,
+                }
+            }
+            // A new line also starts a new undo step
+            InsertNewline => self.insert("\n", EditKind::Other),
+            NewlineAndIndent => self.newline_and_indent(),
+            InsertIndent => {
+                let indent = self.modes.settings.indent.clone();
+                self.insert(&indent, EditKind::Insert);
+            }
+            TrimWhitespace => self.trim_whitespace(),
+            DeleteBackward => {
+                self.delete_backward(EditKind::Delete, Document::prev_grapheme_boundary)
+            }
+            DeleteForward => self.delete_forward(),
+            DeleteWordBackward => self.delete_backward(EditKind::Other, movement::prev_word_start),
+            Copy => {
+                let text: Vec<_> = self
+                    .selection
+                    .ranges()
+                    .iter()
+                    .filter(|range| !range.is_empty())
+                    .map(|range| self.document().text().byte_slice(range.bytes()).to_string())
+                    .collect();
+                if let Some(clipboard) = self.clipboard.as_mut().filter(|_| !text.is_empty()) {
+                    let _ = clipboard.set_text(text.join("\n"));
+                }
+            }
+            Paste => {
+                if let Some(Ok(text)) = self.clipboard.as_mut().map(Clipboard::get_text) {
+                    self.insert(&text.replace("\r\n", "\n"), EditKind::Other);
+                }
+            }
+            Undo => self.undo(),
+            Redo => self.redo(),
+            Earlier => self.travel(false),
+            Later => self.travel(true),
+            Save => self.save(),
+            Hover => self.hover(),
+            Complete => self.complete(),
+            NextBuffer => self.cycle_buffer(count as isize),
+            PrevBuffer => self.cycle_buffer(-(count as isize)),
+            CloseBuffer => self.close_buffer(discard),
+            SplitRight => self.request(Request::Split(Direction::Right)),
+            SplitDown => self.request(Request::Split(Direction::Down)),
+            CloseSplit => self.request(Request::CloseSplit),
+            FocusLeft => self.request(Request::Focus(Direction::Left)),
+            FocusRight => self.request(Request::Focus(Direction::Right)),
+            FocusUp => self.request(Request::Focus(Direction::Up)),
+            FocusDown => self.request(Request::Focus(Direction::Down)),
+            OpenPalette => self.open_palette(),
+            PickFile => self.request(Request::PickFile),
+            Search => self.open_search(false),
+            Replace => self.open_search(true),
+            SearchNext => self.search_next(true),
+            SearchPrev => self.search_next(false),
+            SearchProject => self.search_project(),
+            NextTheme => self.request(Request::NextTheme),
+            SetMajorMode => self.open_prompt("major mode: ", PromptAction::SetMajorMode),
+            ToggleMinorMode => self.open_prompt("minor mode: ", PromptAction::ToggleMinorMode),
+            Plugin(index) => self.run_plugin_command(index),
+        }
+    }
+
+    /// Moves each head `count` times with `motion`, dragging the anchor along unless `extend`.
+    fn move_head(&mut self, count: usize, extend: bool, motion: fn(&Document, usize) -> usize) {
+        let block_cursor = self.mode != Mode::Insert;
+        let selection = {
+            let document = &self.document();
+            self.selection.map(|mut range| {
+                // Outside insert mode the cursor is the block over the last selected grapheme,
+                // so start from there when the selection is about to collapse anyway
+                if !extend && block_cursor && range.is_forward() {
+                    range.head = document.prev_grapheme_boundary(range.head);
+                }
+                for _ in 0..count {
+                    range.head = motion(document, range.head);
+                }
+                if !extend {
+                    range.anchor = range.head;
+                }
+                range
+            })
+        };
+        self.set_selection(selection);
+    }
+
+    /// Selects from each head to where `motion` takes it, like `w`, `e` and `b` do. Repeating
+    /// the motion selects the next word each time rather than growing the selection, unless
+    /// `extend`.
+    fn select_word(&mut self, count: usize, extend: bool, motion: fn(&Document, usize) -> usize) {
+        let selection = {
+            let document = &self.document();
+            self.selection.map(|mut range| {
+                for _ in 0..count {
+                    let head = motion(document, range.head);
+                    if head == range.head {
+                        break;
+                    }
+                    if !extend {
+                        range.anchor = range.head;
+                    }
+                    range.head = head;
+                }
+                range
+            })
+        };
+        self.set_selection(selection);
+    }
+
+    fn move_vertically(&mut self, lines: isize, extend: bool) {
+        if self.preferred_columns.len() != self.selection.ranges().len() {
+            let columns = {
+                let document = &self.document();
+                self.selection
+                    .ranges()
+                    .iter()
+                    .map(|range| movement::column(document, range.head))
+                    .collect()
+            };
+            self.preferred_columns = columns;
+        }
+
+        let selection = {
+            let document = &self.document();
+            let mut columns = self.preferred_columns.iter();
+            self.selection.map(|mut range| {
+                let column = *columns.next().unwrap();
+                range.head = movement::vertical(document, range.head, lines, column);
+                if !extend {
+                    range.anchor = range.head;
+                }
+                range
+            })
+        };
+        self.set_selection(selection);
+    }
+
+    /// Page motions depend on the layout, so these are left to `CTEditor` for the primary
+    /// selection, and the others move by as many lines as it did.
+    fn move_page(&mut self, motion: Motion, extend: bool) {
+        let primary = self.selection.primary();
+        self.editor.action(
+            &mut self.fonts.borrow_mut().font_system,
+            Action::Motion(motion),
+        );
+        let head = self.byte_of(self.editor.cursor());
+        let lines = self.document().line_of_byte(head) as isize
+            - self.document().line_of_byte(primary.head) as isize;
+
+        let selection = {
+            let document = &self.document();
+            self.selection.map(|range| {
+                let head = if range == primary {
+                    head
+                } else {
+                    let column = movement::column(document, range.head);
+                    movement::vertical(document, range.head, lines, column)
+                };
+                SelectionRange::new(if extend { range.anchor } else { head }, head)
+            })
+        };
+        self.set_selection(selection);
+    }
+
+    /// Selects the lines each selection touches, or the next `count` lines as well if it already
+    /// covers whole lines (so that `x` can be pressed repeatedly).
+    fn select_lines(&mut self, count: usize) {
+        let selection = {
+            let document = &self.document();
+            self.selection.map(|range| {
+                let (start, end) = (range.start(), range.end());
+                let line_start = movement::line_start(document, start);
+                let ends_at_line_start = end > start && end == movement::line_start(document, end);
+                let (mut end, repeats) = if ends_at_line_start && line_start == start {
+                    (end, count)
+                } else if ends_at_line_start {
+                    (end, count - 1)
+                } else {
+                    (movement::next_line_start(document, end), count - 1)
+                };
+                for _ in 0..repeats {
+                    end = movement::next_line_start(document, end);
+                }
+                SelectionRange::new(line_start, end)
+            })
+        };
+        self.set_selection(selection);
+    }
+
+    /// Adds a copy of each selection `lines` lines below (or above, if negative) it. The copy of
+    /// the primary selection becomes the new primary.
+    fn copy_selection_vertically(&mut self, lines: isize) {
+        let document = self.document();
+        let mut ranges = self.selection.ranges().to_vec();
+        let mut primary = self.selection.primary_index();
+        for (i, range) in self.selection.ranges().iter().enumerate() {
+            let moved =
+                |pos| movement::vertical(&document, pos, lines, movement::column(&document, pos));
+            let head = moved(range.head);
+            // There's nowhere to copy to past the first or last line
+            if document.line_of_byte(head) == document.line_of_byte(range.head) {
+                continue;
+            }
+            if i == self.selection.primary_index() {
+                primary = ranges.len();
+            }
+            ranges.push(SelectionRange::new(moved(range.anchor), head));
+        }
+        drop(document);
+        self.set_selection(Selection::new(ranges, primary));
+    }
+
+    /// Pads the selections with spaces so that the first one on each line all start in the same
+    /// column. The rest are left alone, since padding the first moves the rest of its line along.
+    fn align_selections(&mut self) {
+        let document = self.document();
+        let ranges = self.selection.ranges().to_vec();
+        let mut prev_line = None;
+        // The column of each selection that's the first on its line
+        let columns: Vec<_> = ranges
+            .iter()
+            .map(|range| {
+                let line = document.line_of_byte(range.start());
+                let first = prev_line != Some(line);
+                prev_line = Some(line);
+                first.then(|| movement::column(&document, range.start()))
+            })
+            .collect();
+        let Some(target) = columns.iter().flatten().copied().max() else {
+            return;
+        };
+
+        let edits = ranges
+            .iter()
+            .zip(&columns)
+            .map(|(range, column)| {
+                let padding = column.map_or(String::new(), |column| " ".repeat(target - column));
+                (range.start()..range.start(), padding)
+            })
+            .collect();
+
+        drop(document);
+        self.edit(EditKind::Other, |this| {
+            let spans = this.replace_each(edits);
+            this.set_ranges(ranges.iter().zip(spans).map(|(range, span)| {
+                let shift = span.end - range.start();
+                SelectionRange::new(range.anchor + shift, range.head + shift)
+            }));
+        });
+    }
+
+    /// What `d`, `c` and `y` act on: the selection, or the grapheme under the block cursor when
+    /// nothing is selected.
+    fn target_range(&self, range: SelectionRange) -> Range<usize> {
+        if range.is_empty() {
+            range.start()..self.document().next_grapheme_boundary(range.start())
+        } else {
+            range.bytes()
+        }
+    }
+
+    fn yank(&mut self) {
+        self.register = self
+            .selection
+            .ranges()
+            .iter()
+            .map(|&range| {
+                self.document()
+                    .text()
+                    .byte_slice(self.target_range(range))
+                    .to_string()
+            })
+            .collect();
+    }
+
+    /// Pastes the register `count` times after or before each selection, then selects the
+    /// pasted text. Each selection gets its own value if the register has as many as there are
+    /// selections, and all of them otherwise. A value ending in a line break was yanked as whole
+    /// lines, so it goes on lines of its own.
+    fn paste(&mut self, after: bool, count: usize) {
+        if self.register.is_empty() {
+            return;
+        }
+        let ranges = self.selection.ranges();
+        let values = if self.register.len() == ranges.len() {
+            self.register.clone()
+        } else {
+            vec![self.register.concat(); ranges.len()]
+        };
+
+        let document = self.document();
+        let edits = ranges
+            .iter()
+            .zip(values)
+            .map(|(range, value)| {
+                let linewise = value.ends_with('\n');
+                let last = if range.is_empty() {
+                    range.start()
+                } else {
+                    document.prev_grapheme_boundary(range.end())
+                };
+                let pos = match (after, linewise) {
+                    (true, true) => movement::next_line_start(&document, last),
+                    (true, false) => document.next_grapheme_boundary(last),
+                    (false, true) => movement::line_start(&document, range.start()),
+                    (false, false) => range.start(),
+                };
+
+                let mut text = value.repeat(count);
+                // Below a last line with no line break of its own, the break has to come first
+                if linewise && pos != movement::line_start(&document, pos) {
+                    text.pop();
+                    text.insert(0, '\n');
+                }
+                (pos..pos, text)
+            })
+            .collect();
+
+        drop(document);
+        self.edit(EditKind::Other, |this| {
+            let spans = this.replace_each(edits);
+            this.set_ranges(
+                spans
+                    .into_iter()
+                    .map(|span| SelectionRange::new(span.start, span.end)),
+            );
+        });
+    }
+
+    /// Puts a cursor wherever `pos` says for each selection and switches to insert mode.
+    fn insert_at(&mut self, pos: impl Fn(&Document, SelectionRange) -> usize) {
+        let document = self.document();
+        let selection = self
+            .selection
+            .map(|range| SelectionRange::point(pos(&document, range)));
+        drop(document);
+        self.set_selection(selection);
+        self.mode = Mode::Insert;
+    }
+
+    /// Opens a line below or above each selection's head, indented like the head's line, and
+    /// starts inserting on it.
+    fn open_line(&mut self, below: bool) {
+        let document = self.document();
+        let edits = self
+            .selection
+            .ranges()
+            .iter()
+            .map(|range| {
+                let line_start = movement::line_start(&document, range.head);
+                let indent_end = movement::line_first_non_blank(&document, range.head);
+                let indent = document.text().byte_slice(line_start..indent_end);
+                if below {
+                    let line_end = movement::line_end(&document, range.head);
+                    (line_end..line_end, format!("\n{indent}"))
+                } else {
+                    (line_start..line_start, format!("{indent}\n"))
+                }
+            })
+            .collect();
+
+        drop(document);
+        self.edit(EditKind::Other, |this| {
+            let spans = this.replace_each(edits);
+            // Above, the cursor goes before the inserted line break
+            let back = if below { 0 } else { 1 };
+            this.set_ranges(
+                spans
+                    .into_iter()
+                    .map(|span| SelectionRange::point(span.end - back)),
+            );
+        });
+        self.mode = Mode::Insert;
+    }
+
+    /// Breaks the line at each selection, starting the new line with as much of the old one's
+    /// indentation as came before the break.
+    fn newline_and_indent(&mut self) {
+        let document = self.document();
+        let edits = self
+            .selection
+            .ranges()
+            .iter()
+            .map(|range| {
+                let line_start = movement::line_start(&document, range.start());
+                let indent_end = movement::line_first_non_blank(&document, range.start());
+                let indent = document
+                    .text()
+                    .byte_slice(line_start..indent_end.min(range.start()));
+                (range.bytes(), format!("\n{indent}"))
+            })
+            .collect();
+        drop(document);
+        self.edit(EditKind::Other, |this| this.insert_each(edits));
+    }
+
+    /// Deletes whitespace from the end of every line, as one undo step. Selections in what's
+    /// deleted are moved to where it was.
+    fn trim_whitespace(&mut self) {
+        let edits: Vec<_> = {
+            let document = self.document();
+            (0..document.len_lines())
+                .filter_map(|line| {
+                    let text = document.line(line).to_string();
+                    let kept = text.trim_end().len();
+                    let start = document.byte_of_line(line);
+                    (kept < text.len()).then(|| (start + kept..start + text.len(), String::new()))
+                })
+                .collect()
+        };
+        if edits.is_empty() {
+            return;
+        }
+        let shift = |pos: usize| {
+            let before: usize = (edits.iter())
+                .take_while(|(range, _)| range.start < pos)
+                .map(|(range, _)| range.end.min(pos) - range.start)
+                .sum();
+            pos - before
+        };
+        let selection = self
+            .selection
+            .map(|range| SelectionRange::new(shift(range.anchor), shift(range.head)));
+        self.edit(EditKind::Other, |this| {
+            this.replace_each(edits);
+            this.set_selection(selection);
+        });
+    }
+
+    fn open_prompt(&mut self, label: impl Into<String>, action: PromptAction) {
+        self.prompt = Some(Prompt {
+            label: label.into(),
+            input: String::new(),
+            action,
+        });
+        self.show_prompt();
+    }
+
+    fn show_prompt(&mut self) {
+        if let Some(prompt) = &self.prompt {
+            let text = format!("{}{}", prompt.label, prompt.input);
+            self.show_message(&text, self.theme.foreground);
+        }
+    }
+
+    fn handle_prompt_key(&mut self, input_state: &InputState, key: Key<SmolStr>) -> bool {
+        let Some(mut prompt) = self.prompt.take() else {
+            return false;
+        };
+        self.message = None;
+        match key {
+            Key::Named(NamedKey::Escape) => return true,
+            Key::Named(NamedKey::Enter) => {
+                self.submit_prompt(prompt);
+                return true;
+            }
+            Key::Named(NamedKey::Backspace) => {
+                prompt.input.pop();
+            }
+            key => match key.to_text() {
+                Some(text) if !input_state.modifier_state.control_key() => {
+                    prompt.input.push_str(text)
+                }
+                _ => (),
+            },
+        }
+        self.prompt = Some(prompt);
+        self.show_prompt();
+        true
+    }
+
+    fn submit_prompt(&mut self, prompt: Prompt) {
+        let split = match prompt.action {
+            PromptAction::SplitSelection => true,
+            PromptAction::SelectMatches => false,
+            PromptAction::GoToLine => return self.go_to_line(&prompt.input),
+            PromptAction::SetMajorMode => return self.set_major_mode(&prompt.input),
+            PromptAction::ToggleMinorMode => return self.toggle_minor_mode(&prompt.input),
+            PromptAction::Plugin(callback) => {
+                return self.call_plugin(&callback, Some(prompt.input))
+            }
+        };
+        match Regex::new(&prompt.input) {
+            Ok(regex) => self.select_regex(&regex, split),
+            Err(err) => self.show_error(&format!("Invalid regex: {err}")),
+        }
+    }
+
+    /// Moves to a `line` or `line:column` typed into the go to line prompt, both counted from 1
+    /// and the column in characters. Positions past the end are clamped to it.
+    fn go_to_line(&mut self, input: &str) {
+        let mut parts = input.trim().splitn(2, ':').map(str::parse::<usize>);
+        let (line, column) = match (parts.next(), parts.next()) {
+            (Some(Ok(line)), None) => (line, 1),
+            (Some(Ok(line)), Some(Ok(column))) => (line, column),
+            _ => return self.show_error(&format!("Not a line or line:column: {input}")),
+        };
+        let line = line.clamp(1, self.document().len_lines()) - 1;
+        let pos = {
+            let document = self.document();
+            let column: usize = document
+                .line(line)
+                .chars()
+                .take(column.saturating_sub(1))
+                .map(char::len_utf8)
+                .sum();
+            document.byte_of_line(line) + column
+        };
+        self.set_selection(Selection::point(pos));
+    }
+
+    /// Replaces each selection with the matches of `regex` inside it, or with the text between
+    /// them if `split`.
+    fn select_regex(&mut self, regex: &Regex, split: bool) {
+        let mut ranges = Vec::new();
+        let mut primary = 0;
+        for (i, range) in self.selection.ranges().iter().enumerate() {
+            if i == self.selection.primary_index() {
+                primary = ranges.len();
+            }
+            let offset = range.start();
+            let text = self.document().text().byte_slice(range.bytes()).to_string();
+            let mut pieces = Vec::new();
+            let mut last = 0;
+            for found in regex.find_iter(&text) {
+                if split {
+                    pieces.push(last..found.start());
+                    last = found.end();
+                } else {
+                    pieces.push(found.range());
+                }
+            }
+            if split {
+                pieces.push(last..text.len());
+            }
+            ranges.extend(
+                pieces
+                    .into_iter()
+                    .filter(|piece| !piece.is_empty())
+                    .map(|piece| SelectionRange::new(offset + piece.start, offset + piece.end)),
+            );
+        }
+
+        if ranges.is_empty() {
+            return self.show_error("No selections remaining");
+        }
+        let primary = primary.min(ranges.len() - 1);
+        self.set_selection(Selection::new(ranges, primary));
+    }
+
+    fn set_selection(&mut self, selection: Selection) {
+        self.editor
+            .set_cursor(self.cursor_at(selection.primary().head));
+        self.selection = selection;
+    }
+
+    /// Replaces the selection with `ranges`, which correspond one to one with the current
+    /// selections, keeping the same one primary.
+    fn set_ranges(&mut self, ranges: impl Iterator<Item = SelectionRange>) {
+        let primary = self.selection.primary_index();
+        self.set_selection(Selection::new(ranges.collect(), primary));
+    }
+
+    /// Each selection in buffer positions, along with what its cursor covers.
+    fn selection_spans(&self) -> Vec<SelectionSpan> {
+        let block = self.mode != Mode::Insert;
+        self.selection
+            .ranges()
+            .iter()
+            .enumerate()
+            .map(|(i, range)| {
+                // The block cursor sits on the grapheme after the head, or the one before it
+                // when the selection extends forwards
+                let cursor = if !block {
+                    (range.head, range.head)
+                } else if range.is_forward() {
+                    (
+                        self.document().prev_grapheme_boundary(range.head),
+                        range.head,
+                    )
+                } else {
+                    let end = self
+                        .document()
+                        .next_grapheme_boundary(range.head)
+                        .min(movement::line_end(&self.document(), range.head));
+                    (range.head, end)
+                };
+                SelectionSpan {
+                    start: self.cursor_at(range.start()),
+                    end: self.cursor_at(range.end()),
+                    cursor: (self.cursor_at(cursor.0), self.cursor_at(cursor.1)),
+                    primary: i == self.selection.primary_index(),
+                }
+            })
+            .collect()
+    }
+
+    /// The search matches on the lines laid out for drawing, in buffer positions.
+    fn visible_search_matches(&self) -> Vec<SearchMatch> {
+        let lines = self.editor.with_buffer(|buffer| {
+            let mut runs = buffer.layout_runs();
+            let first = runs.next()?.line_i;
+            Some(first..=runs.last().map_or(first, |run| run.line_i))
+        });
+        let Some(lines) = lines else {
+            return Vec::new();
+        };
+        let (start, end) = {
+            let document = self.document();
+            let end = match document.len_lines() > lines.end() + 1 {
+                true => document.byte_of_line(lines.end() + 1),
+                false => document.text().byte_len(),
+            };
+            (document.byte_of_line(*lines.start()), end)
+        };
+        let matches = self.search_matches();
+        let first = matches.partition_point(|found| found.end <= start);
+        matches[first..]
+            .iter()
+            .take_while(|found| found.start < end)
+            .map(|found| SearchMatch {
+                start: self.cursor_at(found.start),
+                end: self.cursor_at(found.end),
+            })
+            .collect()
+    }
+
+    fn byte_of(&self, cursor: Cursor) -> usize {
+        self.document().byte_of_line(cursor.line) + cursor.index
+    }
+
+    fn cursor_at(&self, byte: usize) -> Cursor {
+        cursor_at(&self.document(), byte)
+    }
+
+    /// The document position under a point in the editor.
+    fn hit(&mut self, input_state: &InputState) -> Option<usize> {
+        // The text starts below the tabs
+        let y = input_state.mouse_pos_y as f32 - self.tabs_height();
+        let cursor = self
+            .editor
+            .with_buffer(|buffer| buffer.hit(input_state.mouse_pos_x as f32, y))?;
+        Some(self.byte_of(cursor))
+    }
+
+    fn save(&mut self) {
+        if !self.document().is_dirty() && self.document().path().is_some_and(|path| path.exists()) {
+            self.show_message("(No changes need to be saved)", self.theme.foreground);
+            return;
+        }
+        self.run_hooks(Hook::BeforeSave);
+
+        let mixed = self.document().has_mixed_line_endings();
+        let result = self.document_mut().save();
+        match result {
+            Ok(()) => {
+                if mixed {
+                    let message = format!(
+                        "Saved with every line ending in {}",
+                        self.document().line_ending()
+                    );
+                    self.show_message(&message, self.theme.warning);
+                }
+                self.notify_saved();
+            }
+            Err(err) => {
+                eprintln!("Failed to save: {err}");
+                self.show_error(&format!("Failed to save: {err}"));
+            }
+        }
+    }
+
+    /// Follows every document that was moved, or was in a directory that was, to its new path.
+    fn moved(&mut self, from: &Path, to: &Path) {
+        let mut error = None;
+        for buffer in self.buffers.borrow().iter() {
+            let mut buffer = buffer.borrow_mut();
+            let Some(path) = (buffer.document.path()).and_then(|path| path::absolute(path).ok())
+            else {
+                continue;
+            };
+            let Ok(rest) = path.strip_prefix(from) else {
+                continue;
+            };
+            // Joining an empty path would add a trailing slash
+            let path = if rest.as_os_str().is_empty() {
+                to.to_owned()
+            } else {
+                to.join(rest)
+            };
+            buffer.document.set_path(path);
+            buffer.modes.major = MajorMode::detect(&buffer.document);
+            // The language server only knows the document by its path
+            buffer.language_server = None;
+            buffer.diagnostics.clear();
+            match LanguageServer::start(&buffer.document, &self.servers, self.waker.clone()) {
+                Ok(server) => buffer.language_server = server,
+                Err(err) => error = Some(err),
+            }
+        }
+        if let Some(error) = error {
+            self.show_error(&error);
+        }
+    }
+
+    /// Tells the status bar about the current state of the editor, which is done just before
+    /// drawing since that's after anything that might have changed it.
+    fn update_status(&self) {
+        let mut status = self.status.borrow_mut();
+        let buffer = self.buffer.borrow();
+        let document = &buffer.document;
+        let head = self.selection.primary().head;
+        let line = document.line_of_byte(head);
+        let line_start = document.byte_of_line(line);
+        status.mode = self.mode.name();
+        status.pending = self.count.map_or(String::new(), |count| count.to_string());
+        if !self.pending.is_empty() {
+            status
+                .pending
+                .push_str(&KeyPress::describe_sequence(&self.pending));
+        }
+        status.file = buffer.name();
+        status.dirty = document.is_dirty();
+        status.line = line + 1;
+        status.column = document.text().byte_slice(line_start..head).chars().count() + 1;
+        status.selections = self.selection.ranges().len();
+        status.encoding = document.encoding().to_string();
+        status.line_ending = document.line_ending().to_string();
+        status.major_mode = self.modes.major().name;
+        let count = |severity| {
+            (buffer.diagnostics.iter())
+                .filter(|diagnostic| diagnostic.severity == Some(severity))
+                .count()
+        };
+        status.errors = count(DiagnosticSeverity::ERROR);
+        status.warnings = count(DiagnosticSeverity::WARNING);
+    }
+
+    /// The metrics the text is laid out with, scaled for the display.
+    fn editor_metrics(&self) -> Metrics {
+        self.editor.with_buffer(|buffer| buffer.metrics())
+    }
+
+    fn request(&self, request: Request) {
+        self.requests.borrow_mut().push(request);
+    }
+
+    fn show_error(&mut self, text: &str) {
+        self.show_message(text, self.theme.error);
+    }
+
+    fn show_message(&mut self, text: &str, colour: CTColor) {
+        let mut buffer = Buffer::new(&mut self.fonts.borrow_mut().font_system, self.metrics);
+        buffer.set_text(text, &self.attrs, Shaping::Advanced, None);
+        self.message = Some(Message { buffer, colour });
+    }
+
+    /// Carries out something another part of the UI asked for, returning whether there's anything
+    /// new to draw.
+    pub(super) fn handle_request(&mut self, request: Request) -> bool {
+        match request {
+            Request::Open(path) => self.open(path),
+            Request::OpenAt {
+                path,
+                line,
+                columns,
+            } => self.open_at(path, line, columns),
+            Request::Moved { from, to } => self.moved(&from, &to),
+            Request::GoToLine => self.execute(Command::GoToLine, 1),
+            Request::ShowError(text) => self.show_error(&text),
+            Request::ReplaceInFiles {
+                pattern,
+                replacement,
+                files,
+            } => self.replace_in_files(&pattern, &replacement, &files),
+            // The layout looks after splits, the file picker, the project search and the theme
+            // itself
+            Request::Split(_)
+            | Request::CloseSplit
+            | Request::Focus(_)
+            | Request::FocusEditor
+            | Request::PickFile
+            | Request::SearchProject(_)
+            | Request::CloseOverlay
+            | Request::NextTheme => return false,
+        }
+        true
+    }
+
+    /// Another view of the current buffer, to put alongside this one when splitting it.
+    pub(super) fn split(&mut self) -> Editor<'static> {
+        self.remember_position();
+        let mut view = Editor::view(
+            self.metrics,
+            self.editor_metrics(),
+            self.buffers.clone(),
+            self.servers.clone(),
+            self.buffer.clone(),
+            self.fonts.clone(),
+            self.status.clone(),
+            self.waker.clone(),
+            self.requests.clone(),
+            self.theme.clone(),
+        );
+        view.config = self.config.clone();
+        view.apply_modes();
+        view
+    }
+
+    /// What the view has in it, for checking the results of replayed input.
+    pub(super) fn state(&self) -> EditorState {
+        EditorState {
+            text: self.document().text().to_string(),
+            selection: self.selection.clone(),
+            mode: self.mode.name(),
+        }
+    }
+
+    /// Tells the status bar about itself, which is asked of the view requests go to just before
+    /// everything is drawn.
+    pub(super) fn report_status(&mut self) {
+        self.catch_up();
+        self.update_status();
+    }
+}
+
+impl Interactive for Editor<'static> {
+    fn handle_mouse_input(
+        &mut self,
+        input_state: &InputState,
+        button: MouseButton,
+        new_state: ElementState,
+    ) -> bool {
+        if new_state != ElementState::Pressed || button != MouseButton::Left {
+            return false;
+        }
+        self.catch_up();
+        if (input_state.mouse_pos_y as f32) < self.tabs_height() {
+            return match self.tab_at(input_state.mouse_pos_x as f32) {
+                Some(index) => {
+                    self.switch_buffer(index);
+                    true
+                }
+                None => false,
+            };
+        }
+        let Some(pos) = self.hit(input_state) else {
+            return false;
+        };
+        // Ctrl+click adds a cursor rather than moving the only one
+        let selection = if input_state.modifier_state.control_key() {
+            self.selection.push(SelectionRange::point(pos))
+        } else {
+            Selection::point(pos)
+        };
+        self.set_selection(selection);
+        true
+    }
+
+    fn handle_cursor_moved(&mut self, input_state: &InputState) -> bool {
+        if input_state.mouse_left_state != ElementState::Pressed {
+            return false;
+        }
+        let Some(pos) = self.hit(input_state) else {
+            return false;
+        };
+        let primary = self.selection.primary();
+        let selection = self.selection.map(|range| {
+            if range == primary {
+                SelectionRange::new(range.anchor, pos)
+            } else {
+                range
+            }
+        });
+        self.set_selection(selection);
+        true
+    }
+
+    fn handle_scroll(&mut self, _input_state: &InputState, pixel_delta: f32) {
+        self.editor.action(
+            &mut self.fonts.borrow_mut().font_system,
+            Action::Scroll {
+                pixels: -pixel_delta,
+            },
+        );
+    }
+
+    fn handle_keyboard_input(&mut self, input_state: &InputState, key: Key<SmolStr>) -> bool {
+        self.catch_up();
+        if self.palette.is_some() {
+            return self.handle_palette_key(input_state, key);
+        }
+        if self.prompt.is_some() {
+            return self.handle_prompt_key(input_state, key);
+        }
+        if self.searching() {
+            return self.handle_search_key(input_state, key);
+        }
+        self.message = None;
+        self.hover = None;
+        let modifiers = input_state.modifier_state;
+        if self.handle_completion_key(&key, modifiers) {
+            return true;
+        }
+        let press = KeyPress::new(key.clone(), modifiers);
+        match self.modes.keymap.press(&mut self.pending, self.mode, press) {
+            Press::Command(command) => {
+                let count = self.count.take().unwrap_or(1);
+                self.execute(command, count);
+                self.filter_completions();
+                return true;
+            }
+            Press::Pending => return true,
+            // Keys that started a sequence but didn't finish one are typed as they are in insert
+            // mode, where the rest might start another, and dropped along with the count elsewhere
+            Press::Unbound(keys) if keys.len() > 1 => {
+                if self.mode != Mode::Insert {
+                    self.count = None;
+                    return true;
+                }
+                let mut keys = keys.into_iter();
+                let (key, modifiers) = keys.next().unwrap().event();
+                let text = key
+                    .to_text()
+                    .filter(|_| !modifiers.control_key() && !modifiers.alt_key());
+                if let Some(text) = text {
+                    self.insert(text, EditKind::Insert);
+                }
+                for key in keys {
+                    let (key, modifier_state) = key.event();
+                    let input_state = InputState {
+                        modifier_state,
+                        ..*input_state
+                    };
+                    self.handle_keyboard_input(&input_state, key);
+                }
+                return true;
+            }
+            Press::Unbound(_) => (),
+        }
+
+        match (self.mode, key) {
+            (Mode::Insert, key) if !modifiers.control_key() && !modifiers.alt_key() => {
+                match key.to_text() {
+                    Some(text) => {
+                        self.insert(text, EditKind::Insert);
+                        self.filter_completions();
+                        self.complete_after(text);
+                        true
+                    }
+                    None => false,
+                }
+            }
+            // A leading 0 isn't a count, so that it stays free to bind
+            (Mode::Normal | Mode::Select, Key::Character(digit))
+                if digit.len() == 1
+                    && digit.as_bytes()[0].is_ascii_digit()
+                    && (digit != "0" || self.count.is_some()) =>
+            {
+                let digit = (digit.as_bytes()[0] - b'0') as usize;
+                let count = self.count.unwrap_or(0);
+                self.count = Some(count.saturating_mul(10).saturating_add(digit));
+                true
+            }
+            _ => false, // No changes
+        }
+    }
+
+    fn poll(&mut self) -> bool {
+        self.poll_language_servers()
+    }
+
+    fn configure(&mut self, config: &Config) {
+        if self.theme != config.theme {
+            self.theme = config.theme.clone();
+            // Syntax highlighting is coloured in as the lines are made
+            self.sync_all_lines();
+        }
+        self.config = config.clone();
+        self.apply_modes();
+        let config = &config.editor;
+        self.metrics = Metrics::new(config.font_size, config.line_height);
+    }
+
+    fn focus_gained(&mut self) {
+        self.focused = true;
+    }
+
+    fn focus_lost(&mut self) {
+        self.focused = false;
+        // A count or sequence started here shouldn't carry on when the focus comes back
+        self.count = None;
+        self.pending.clear();
+    }
+
+    fn render<'draw>(
+        &mut self,
+        pixmap: &mut PixmapMut<'draw>,
+        paint: &mut Paint<'draw>,
+        scale_factor: f64,
+        rect: Rect,
+    ) {
+        self.catch_up();
+        self.find_matches();
+        let background = self.theme.background;
+        paint.set_color_rgba8(
+            background.b(),
+            background.g(),
+            background.r(),
+            background.a(),
+        );
+        pixmap.fill_rect(rect, paint, Transform::identity(), None);
+        {
+            let mut fonts = self.fonts.borrow_mut();
+            let mut editor = self.editor.borrow_with(&mut fonts.font_system);
+            let metrics = self.metrics.scale(scale_factor as f32);
+            if metrics != editor.with_buffer(|buf| buf.metrics()) {
+                editor.with_buffer_mut(|buf| buf.set_metrics(metrics));
+            }
+        }
+
+        // The text goes below the tabs
+        self.render_tabs(pixmap, paint, rect);
+        let tabs_height = self.tabs_height();
+        let rect = Rect::from_xywh(
+            rect.x(),
+            rect.y() + tabs_height,
+            rect.width(),
+            (rect.height() - tabs_height).max(1.0),
+        )
+        .unwrap();
+        let transform = Transform::from_translate(rect.x(), rect.y());
+        let mut fonts_guard = self.fonts.borrow_mut();
+        let fonts = &mut *fonts_guard;
+
+        self.editor
+            .with_buffer_mut(|buf| buf.set_size(Some(rect.width()), Some(rect.height())));
+        paint.anti_alias = false;
+        self.editor.shape_as_needed(&mut fonts.font_system, true);
+
+        let spans = self.selection_spans();
+        let cursor_shape = match self.mode {
+            _ if !self.focused => CursorShape::Hollow,
+            Mode::Insert => CursorShape::Bar,
+            Mode::Normal | Mode::Select => CursorShape::Block,
+        };
+        let matches = self.visible_search_matches();
+        let underlines = self.diagnostic_underlines();
+        let mut editor_renderer = EditorRenderer {
+            swash_cache: &mut fonts.swash_cache,
+            font_system: &mut fonts.font_system,
+            pixmap,
+            paint,
+            pixmap_paint: &PixmapPaint::default(),
+            transform,
+        };
+        self.editor.with_buffer(|buffer| {
+            editor_renderer.draw_editor_buffer(
+                buffer,
+                &spans,
+                &matches,
+                &underlines,
+                cursor_shape,
+                &self.theme,
+            )
+        });
+
+        const SCROLLBAR_WIDTH: f32 = 15.0;
+        {
+            let mut start_line_opt = None;
+            let mut end_line = 0;
+            self.editor.with_buffer(|buffer| {
+                for run in buffer.layout_runs() {
+                    end_line = run.line_i;
+                    if start_line_opt.is_none() {
+                        start_line_opt = Some(end_line);
+                    }
+                }
+            });
+
+            let start_line = start_line_opt.unwrap_or(end_line);
+            let lines = self.editor.with_buffer(|buffer| buffer.lines.len());
+            let start_y = (start_line * rect.height() as usize) / lines;
+            let end_y = (end_line * rect.height() as usize) / lines;
+            let scrollbar = self.theme.scrollbar;
+            paint.set_color_rgba8(scrollbar.b(), scrollbar.g(), scrollbar.r(), scrollbar.a());
+            if end_y > start_y {
+                pixmap.fill_rect(
+                    Rect::from_xywh(
+                        rect.width() - SCROLLBAR_WIDTH * scale_factor as f32,
+                        start_y as f32,
+                        SCROLLBAR_WIDTH * scale_factor as f32,
+                        (end_y - start_y) as f32,
+                    )
+                    .unwrap(),
+                    paint,
+                    transform,
+                    None,
+                );
+            }
+        }
+
+        if let Some(message) = &mut self.message {
+            let metrics = self.metrics.scale(scale_factor as f32);
+            message.buffer.set_metrics_and_size(
+                metrics,
+                Some(rect.width()),
+                Some(metrics.line_height),
+            );
+            message
+                .buffer
+                .shape_until_scroll(&mut fonts.font_system, false);
+            let top = rect.height() - metrics.line_height;
+            let background = self.theme.message;
+            paint.set_color_rgba8(
+                background.b(),
+                background.g(),
+                background.r(),
+                background.a(),
+            );
+            pixmap.fill_rect(
+                Rect::from_xywh(0.0, top, rect.width(), metrics.line_height).unwrap(),
+                paint,
+                transform,
+                None,
+            );
+
+            let mut renderer = EditorRenderer {
+                swash_cache: &mut fonts.swash_cache,
+                font_system: &mut fonts.font_system,
+                pixmap,
+                paint,
+                pixmap_paint: &PixmapPaint::default(),
+                transform: transform.pre_translate(0.0, top),
+            };
+            renderer.draw_buffer(&message.buffer, message.colour);
+        }
+        drop(fonts_guard);
+        self.render_popups(pixmap, paint, rect, transform);
+        self.render_palette(pixmap, paint, rect, transform);
+
+        // TODO: Accessibility
+        // if let Some((x, y)) = editor.cursor_position() {
+        //     window.set_ime_cursor_area(PhysicalPosition::new(x, y), PhysicalSize::new(20, 20));
+        // }
+    }
+}
+
+/// Where `byte` is in the buffer's terms, as a line and an index into it.
+fn cursor_at(document: &Document, byte: usize) -> Cursor {
+    let line = document.line_of_byte(byte);
+    Cursor::new(line, byte - document.byte_of_line(line))
+}
@@ -0,0 +1,202 @@
+use cosmic_text::{Buffer, Metrics, Shaping};
+use tiny_skia::{Paint, PixmapMut, PixmapPaint, Rect, Transform};
+use winit::keyboard::{Key, NamedKey, SmolStr};
+
+use crate::fuzzy::{self, Match};
+use crate::InputState;
+
+use super::keymap::{Command, KeyPress};
+use super::plugins;
+use super::render::EditorRenderer;
+use super::Editor;
+
+/// How many commands are listed at once.
+const PALETTE_ROWS: usize = 10;
+
+/// A list of every command, narrowed down by fuzzily matching what's typed against their names,
+/// for running commands without remembering their keys.
+pub struct Palette {
+    query: String,
+    matches: Vec<Match<Command>>,
+    /// Index into `matches`.
+    selected: usize,
+    buffer: Buffer,
+}
+
+impl Editor<'_> {
+    pub(super) fn open_palette(&mut self) {
+        let buffer = Buffer::new(
+            &mut self.fonts.borrow_mut().font_system,
+            self.palette_metrics(),
+        );
+        self.palette = Some(Palette {
+            query: String::new(),
+            matches: Vec::new(),
+            selected: 0,
+            buffer,
+        });
+        self.filter_palette();
+    }
+
+    /// Smaller than the text, to fit a useful number of commands in.
+    fn palette_metrics(&self) -> Metrics {
+        let metrics = self.editor_metrics();
+        Metrics::new(metrics.font_size * 0.6, metrics.line_height * 0.6)
+    }
+
+    fn filter_palette(&mut self) {
+        let Some(palette) = &mut self.palette else {
+            return;
+        };
+        let plugins = &self.config.plugins;
+        let added = (0..plugins.commands().len()).map(Command::Plugin);
+        let commands = (Command::ALL.iter().copied())
+            .filter(|&command| self.modes.needed_for(command).is_none())
+            .chain(added)
+            .map(|command| (command, plugins::describe(plugins, command).0));
+        palette.matches = fuzzy::filter(&palette.query, commands, false);
+        palette.selected = 0;
+        self.layout_palette();
+    }
+
+    /// Refills the palette's buffer: what's been typed, then the matching commands around the
+    /// selected one with their keys in the current mode, then what the selected one does.
+    fn layout_palette(&mut self) {
+        let Some(palette) = &mut self.palette else {
+            return;
+        };
+        let plugins = &self.config.plugins;
+        let first = palette.selected.saturating_sub(PALETTE_ROWS - 1);
+        let shown = palette.matches.iter().skip(first).take(PALETTE_ROWS);
+        let name_width = (shown.clone())
+            .map(|found| plugins::describe(plugins, found.item).0.len())
+            .max()
+            .unwrap_or(0);
+
+        let mut spans = vec![(format!("> {}\n", palette.query), None)];
+        for found in shown {
+            let name = plugins::describe(plugins, found.item).0;
+            for (i, c) in name.chars().enumerate() {
+                let matched = found.indices.contains(&(i as u32));
+                spans.push((c.to_string(), matched.then_some(self.theme.matched)));
+            }
+            let keys = self.modes.keymap.keys_for(self.mode, found.item);
+            let keys: Vec<_> = keys
+                .iter()
+                .map(|keys| KeyPress::describe_sequence(keys))
+                .collect();
+            let padding = " ".repeat(name_width - name.len() + 2);
+            spans.push((
+                format!("{padding}{}\n", keys.join(", ")),
+                Some(self.theme.dimmed),
+            ));
+        }
+        let description = match palette.matches.get(palette.selected) {
+            Some(found) => plugins::describe(plugins, found.item).1,
+            None => "No matching commands",
+        };
+        spans.push((description.to_owned(), Some(self.theme.dimmed)));
+
+        let attrs = &self.attrs;
+        palette.buffer.set_rich_text(
+            spans.iter().map(|(text, colour)| {
+                let attrs = match colour {
+                    Some(colour) => attrs.clone().color(*colour),
+                    None => attrs.clone(),
+                };
+                (text.as_str(), attrs)
+            }),
+            attrs,
+            Shaping::Advanced,
+            None,
+        );
+    }
+
+    /// Handles a key press while the palette is open, which takes every key until it's closed.
+    pub(super) fn handle_palette_key(
+        &mut self,
+        input_state: &InputState,
+        key: Key<SmolStr>,
+    ) -> bool {
+        let Some(palette) = &mut self.palette else {
+            return false;
+        };
+        let ctrl = input_state.modifier_state.control_key();
+        let len = palette.matches.len().max(1);
+        match key {
+            Key::Named(NamedKey::Escape) => self.palette = None,
+            Key::Named(NamedKey::Enter) => {
+                let palette = self.palette.take().unwrap();
+                if let Some(found) = palette.matches.get(palette.selected) {
+                    self.execute(found.item, 1);
+                }
+            }
+            Key::Named(NamedKey::ArrowDown) => palette.selected = (palette.selected + 1) % len,
+            Key::Character(c) if ctrl && c == "n" => {
+                palette.selected = (palette.selected + 1) % len
+            }
+            Key::Named(NamedKey::ArrowUp) => palette.selected = (palette.selected + len - 1) % len,
+            Key::Character(c) if ctrl && c == "p" => {
+                palette.selected = (palette.selected + len - 1) % len
+            }
+            Key::Named(NamedKey::Backspace) => {
+                palette.query.pop();
+                self.filter_palette();
+            }
+            key => match key.to_text() {
+                Some(text) if !ctrl && !input_state.modifier_state.alt_key() => {
+                    palette.query.push_str(text);
+                    self.filter_palette();
+                }
+                _ => (),
+            },
+        }
+        self.layout_palette();
+        true
+    }
+
+    /// Draws the palette, if it's open, across the top of the editor.
+    pub(super) fn render_palette<'draw>(
+        &mut self,
+        pixmap: &mut PixmapMut<'draw>,
+        paint: &mut Paint<'draw>,
+        rect: Rect,
+        transform: Transform,
+    ) {
+        let metrics = self.palette_metrics();
+        let Some(palette) = &mut self.palette else {
+            return;
+        };
+        let mut fonts = self.fonts.borrow_mut();
+        let fonts = &mut *fonts;
+        let buffer = &mut palette.buffer;
+        let padding = metrics.line_height / 4.0;
+        buffer.set_metrics(metrics);
+        buffer.set_size(None, None);
+        buffer.shape_until_scroll(&mut fonts.font_system, false);
+        let (mut width, mut rows) = (0.0_f32, 0);
+        for run in buffer.layout_runs() {
+            width = width.max(run.line_w);
+            rows += 1;
+        }
+        // Wide enough that it doesn't change size with every key typed
+        let width = (width + 2.0 * padding)
+            .max(rect.width() * 0.6)
+            .min(rect.width());
+        let height = rows as f32 * metrics.line_height;
+        buffer.set_size(Some(width), Some(height));
+        buffer.shape_until_scroll(&mut fonts.font_system, false);
+
+        let first = palette.selected.saturating_sub(PALETTE_ROWS - 1);
+        let selected = (!palette.matches.is_empty()).then_some(1 + palette.selected - first);
+        let mut renderer = EditorRenderer {
+            swash_cache: &mut fonts.swash_cache,
+            font_system: &mut fonts.font_system,
+            pixmap,
+            paint,
+            pixmap_paint: &PixmapPaint::default(),
+            transform: transform.pre_translate((rect.width() - width) / 2.0, padding),
+        };
+        renderer.draw_popup(buffer, width, height, padding, selected, &self.theme);
+    }
+}
@@ -0,0 +1,98 @@
+use crate::config::{Callback, Context, Effect, Outcome, Plugins};
+use crate::document::EditKind;
+
+use super::keymap::Command;
+use super::modes::Hook;
+use super::{Editor, PromptAction};
+
+/// How deep plugins can go running commands and hooks that run plugins, so that ones that set
+/// each other off can't go on for ever.
+const MAX_PLUGIN_DEPTH: usize = 16;
+
+/// What `command` is called and what it does, looking plugins' commands up in `plugins`.
+pub(super) fn describe(plugins: &Plugins, command: Command) -> (&str, &str) {
+    match command {
+        Command::Plugin(index) => {
+            let command = &plugins.commands()[index];
+            (&command.name, &command.description)
+        }
+        command => (command.name(), command.description()),
+    }
+}
+
+impl Editor<'_> {
+    /// What a plugin can see of the current buffer.
+    fn plugin_context(&self) -> Context {
+        let buffer = self.buffer.borrow();
+        let document = &buffer.document;
+        Context {
+            text: document.text().to_string(),
+            path: (document.path()).map(|path| path.to_string_lossy().into_owned()),
+            selection: self.selection.clone(),
+            mode: self.mode.name(),
+            major_mode: self.modes.major().name,
+            minor_modes: self.modes.minor.iter().map(|mode| mode.name).collect(),
+        }
+    }
+
+    pub(super) fn run_plugin_command(&mut self, index: usize) {
+        let plugins = self.config.plugins.clone();
+        self.call_plugin(&plugins.commands()[index].callback, None);
+    }
+
+    /// Runs what the plugins hooked in at `hook`, in the order they hooked in.
+    pub(super) fn run_plugin_hooks(&mut self, hook: Hook) {
+        for callback in self.config.plugins.hooks(hook) {
+            self.call_plugin(&callback, None);
+        }
+    }
+
+    /// Calls back into a plugin with the current buffer, then does what it asked for, all of its
+    /// edits being one undo step.
+    pub(super) fn call_plugin(&mut self, callback: &Callback, input: Option<String>) {
+        if self.plugin_depth == MAX_PLUGIN_DEPTH {
+            return self.show_error("Plugins kept running each other, so they were stopped");
+        }
+        let plugins = self.config.plugins.clone();
+        let result = plugins.call(callback, input, self.plugin_context());
+        match result {
+            Ok(outcome) => {
+                self.plugin_depth += 1;
+                self.apply_outcome(outcome);
+                self.plugin_depth -= 1;
+            }
+            Err(err) => self.show_error(&err),
+        }
+    }
+
+    fn apply_outcome(&mut self, outcome: Outcome) {
+        let Outcome {
+            edits,
+            selection,
+            effects,
+        } = outcome;
+        if !edits.is_empty() {
+            self.edit(EditKind::Other, |this| {
+                for edits in edits {
+                    this.replace_each(edits);
+                }
+                this.set_selection(selection);
+            });
+        }
+        for effect in effects {
+            match effect {
+                Effect::Run(name) => match self.config.plugins.command(&name) {
+                    Some(command) => self.execute(command, 1),
+                    None => self.show_error(&format!("There's no command called {name}")),
+                },
+                Effect::Message(text) => self.show_message(&text, self.theme.foreground),
+                Effect::Error(text) => self.show_error(&text),
+                Effect::Prompt { label, callback } => {
+                    self.open_prompt(label, PromptAction::Plugin(callback))
+                }
+                Effect::SetMajorMode(name) => self.set_major_mode(&name),
+                Effect::ToggleMinorMode(name) => self.toggle_minor_mode(&name),
+            }
+        }
+    }
+}
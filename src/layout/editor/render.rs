@@ -0,0 +1,346 @@
+use std::cmp;
+
+use cosmic_text::{
+    Attrs, Buffer, Color as CTColor, Cursor, FontSystem, LayoutRun, PhysicalGlyph, Renderer, Style,
+    SwashCache, SwashContent, Weight,
+};
+use tiny_skia::{Paint, PixmapMut, PixmapPaint, PixmapRef, Rect, Transform};
+
+use crate::config::Theme;
+
+/// `attrs` styled for a highlight capture, in the style `theme` gives its scope.
+pub fn highlight_attrs<'a>(attrs: &Attrs<'a>, capture: &str, theme: &Theme) -> Option<Attrs<'a>> {
+    let style = theme.syntax_style(capture)?;
+    let weight = if style.bold {
+        Weight::BOLD
+    } else {
+        Weight::NORMAL
+    };
+    let font_style = if style.italic {
+        Style::Italic
+    } else {
+        Style::Normal
+    };
+    Some(
+        attrs
+            .clone()
+            .color(style.colour)
+            .weight(weight)
+            .style(font_style),
+    )
+}
+
+/// How cursors are drawn.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum CursorShape {
+    /// Covering the grapheme under it, outside insert mode.
+    Block,
+    /// A line before the grapheme, in insert mode.
+    Bar,
+    /// An outline of the block, in a view that doesn't have the focus.
+    Hollow,
+}
+
+/// A selection resolved to buffer positions, ready to be drawn.
+pub struct SelectionSpan {
+    pub start: Cursor,
+    pub end: Cursor,
+    /// What the cursor covers: a grapheme for the block cursor, or an empty range where the bar
+    /// cursor goes.
+    pub cursor: (Cursor, Cursor),
+    pub primary: bool,
+}
+
+/// Text matching a search, which is highlighted wherever it isn't selected.
+pub struct SearchMatch {
+    pub start: Cursor,
+    pub end: Cursor,
+}
+
+/// A line drawn under part of the text, like the ones under diagnostics.
+pub struct Underline {
+    pub start: Cursor,
+    pub end: Cursor,
+    pub colour: CTColor,
+}
+
+pub struct EditorRenderer<'draw, 'render> {
+    pub pixmap: &'render mut PixmapMut<'draw>,
+    pub paint: &'render mut Paint<'draw>,
+    pub pixmap_paint: &'render PixmapPaint,
+    pub font_system: &'render mut FontSystem,
+    pub swash_cache: &'render mut SwashCache,
+    pub transform: Transform,
+}
+
+impl EditorRenderer<'_, '_> {
+    /// Draws an already shaped buffer that isn't attached to a `CTEditor`, in `colour` except
+    /// where its attributes give a colour of their own. Glyphs that don't fit in the buffer's
+    /// width, which only happens when it doesn't wrap, are left out.
+    pub fn draw_buffer(&mut self, buffer: &Buffer, colour: CTColor) {
+        let width = buffer.size().0.unwrap_or(f32::INFINITY);
+        for run in buffer.layout_runs() {
+            for glyph in run.glyphs.iter().filter(|glyph| glyph.x + glyph.w <= width) {
+                self.glyph(
+                    glyph.physical((0., run.line_y), 1.0),
+                    glyph.color_opt.unwrap_or(colour),
+                );
+            }
+        }
+    }
+
+    /// Draws `buffer` on a bordered box filling `width` by `height` from the origin, with the
+    /// text inset by `padding` and the `selected` line highlighted.
+    pub fn draw_popup(
+        &mut self,
+        buffer: &Buffer,
+        width: f32,
+        height: f32,
+        padding: f32,
+        selected: Option<usize>,
+        theme: &Theme,
+    ) {
+        self.rectangle(
+            -1,
+            -1,
+            width as u32 + 2,
+            height as u32 + 2,
+            theme.popup_border,
+        );
+        self.rectangle(0, 0, width as u32, height as u32, theme.popup);
+        if let Some(run) =
+            selected.and_then(|line| buffer.layout_runs().find(|run| run.line_i == line))
+        {
+            self.rectangle(
+                0,
+                run.line_top as i32,
+                width as u32,
+                run.line_height as u32,
+                theme.current_item,
+            );
+        }
+        let transform = self.transform;
+        self.transform = transform.pre_translate(padding, 0.0);
+        self.draw_buffer(buffer, theme.foreground);
+        self.transform = transform;
+    }
+
+    /// Draws the editor's text along with every selection and cursor in it, and the search
+    /// matches under them. This stands in for `CTEditor::render`, which only knows about a single
+    /// selection.
+    pub fn draw_editor_buffer(
+        &mut self,
+        buffer: &Buffer,
+        spans: &[SelectionSpan],
+        matches: &[SearchMatch],
+        underlines: &[Underline],
+        shape: CursorShape,
+        theme: &Theme,
+    ) {
+        let block = shape != CursorShape::Bar;
+        let line_width = buffer.size().0.unwrap_or(0.0);
+        // Line breaks and the end of the document have no glyph for the block cursor to cover
+        let empty_block_width = buffer.metrics().font_size / 2.0;
+
+        for run in buffer.layout_runs() {
+            for found in matches {
+                if found.start.line <= run.line_i && run.line_i <= found.end.line {
+                    self.highlight(&run, found.start, found.end, line_width, theme.search_match);
+                }
+            }
+            for span in spans {
+                if span.start != span.end
+                    && span.start.line <= run.line_i
+                    && run.line_i <= span.end.line
+                {
+                    let colour = if span.primary {
+                        theme.selection
+                    } else {
+                        theme.secondary_selection
+                    };
+                    self.highlight(&run, span.start, span.end, line_width, colour);
+                }
+            }
+
+            for glyph in run.glyphs {
+                let selected = spans.iter().any(|span| {
+                    span.start != span.end
+                        && span.start.line <= run.line_i
+                        && run.line_i <= span.end.line
+                        && (span.start.line != run.line_i || glyph.end > span.start.index)
+                        && (span.end.line != run.line_i || glyph.start < span.end.index)
+                });
+                let colour = if selected {
+                    theme.selected_text
+                } else {
+                    glyph.color_opt.unwrap_or(theme.foreground)
+                };
+                self.glyph(glyph.physical((0., run.line_y), 1.0), colour);
+            }
+
+            let thickness = cmp::max(1, (run.line_height / 16.0) as u32);
+            let underline_y = (run.line_top + run.line_height) as i32 - thickness as i32;
+            for underline in underlines {
+                if underline.start.line > run.line_i || run.line_i > underline.end.line {
+                    continue;
+                }
+                let mut any = false;
+                for (x, width) in run.highlight(underline.start, underline.end) {
+                    any = true;
+                    self.rectangle(
+                        x as i32,
+                        underline_y,
+                        width as u32,
+                        thickness,
+                        underline.colour,
+                    );
+                }
+                // An underline with nothing under it, like one for a missing semicolon at the end
+                // of a line, still needs to show up
+                if !any && underline.start.line == run.line_i {
+                    if let Some(x) = run.cursor_position(&underline.start) {
+                        self.rectangle(
+                            x as i32,
+                            underline_y,
+                            empty_block_width as u32,
+                            thickness,
+                            underline.colour,
+                        );
+                    }
+                }
+            }
+
+            for span in spans {
+                let (start, end) = span.cursor;
+                if start.line != run.line_i {
+                    continue;
+                }
+                let colour = if span.primary {
+                    theme.cursor
+                } else {
+                    theme.secondary_cursor
+                };
+                let rect = if block {
+                    run.highlight(start, end)
+                        .find(|&(_, width)| width > 0.0)
+                        .or_else(|| Some((run.cursor_position(&start)?, empty_block_width)))
+                } else {
+                    run.cursor_position(&start).map(|x| (x, 1.0))
+                };
+                let Some((x, width)) = rect else {
+                    continue;
+                };
+                let (x, y) = (x as i32, run.line_top as i32);
+                let (width, height) = (cmp::max(1, width as u32), run.line_height as u32);
+                let solid = CTColor::rgba(colour.r(), colour.g(), colour.b(), 255);
+                match shape {
+                    CursorShape::Block => self.rectangle(x, y, width, height, colour),
+                    CursorShape::Bar => self.rectangle(x, y, width, height, solid),
+                    CursorShape::Hollow => {
+                        let (right, bottom) = (x + width as i32 - 1, y + height as i32 - 1);
+                        self.rectangle(x, y, width, 1, solid);
+                        self.rectangle(x, bottom, width, 1, solid);
+                        self.rectangle(x, y, 1, height, solid);
+                        self.rectangle(right, y, 1, height, solid);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Fills the part of `run` between `start` and `end`, running on to the edge of the line when
+    /// the selection carries on past it.
+    fn highlight(
+        &mut self,
+        run: &LayoutRun,
+        start: Cursor,
+        end: Cursor,
+        line_width: f32,
+        colour: CTColor,
+    ) {
+        let highlights: Vec<_> = run.highlight(start, end).collect();
+        if highlights.is_empty() {
+            if end.line > run.line_i {
+                let x = if start.line == run.line_i {
+                    run.cursor_position(&start).unwrap_or(0.0)
+                } else {
+                    0.0
+                };
+                self.span(x, line_width, run, colour);
+            }
+            return;
+        }
+
+        let last = highlights.len() - 1;
+        for (i, (x, width)) in highlights.into_iter().enumerate() {
+            let (mut min, mut max) = (x, x + width);
+            if i == last && end.line > run.line_i {
+                if run.rtl {
+                    min = 0.0;
+                } else {
+                    max = line_width;
+                }
+            }
+            self.span(min, max, run, colour);
+        }
+    }
+
+    fn span(&mut self, min: f32, max: f32, run: &LayoutRun, colour: CTColor) {
+        self.rectangle(
+            min as i32,
+            run.line_top as i32,
+            cmp::max(0, max as i32 - min as i32) as u32,
+            run.line_height as u32,
+            colour,
+        );
+    }
+}
+
+impl Renderer for EditorRenderer<'_, '_> {
+    fn rectangle(&mut self, x: i32, y: i32, w: u32, h: u32, color: CTColor) {
+        let colour = color;
+        let Some(rect) = Rect::from_xywh(x as f32, y as f32, w as f32, h as f32) else {
+            return;
+        };
+        self.paint
+            .set_color_rgba8(colour.b(), colour.g(), colour.r(), colour.a());
+        self.pixmap
+            .fill_rect(rect, self.paint, self.transform, None);
+    }
+
+    fn glyph(&mut self, physical_glyph: PhysicalGlyph, color: CTColor) {
+        let image = self
+            .swash_cache
+            .get_image(self.font_system, physical_glyph.cache_key)
+            .as_ref()
+            .unwrap();
+        if image.content != SwashContent::Mask || image.data.is_empty() {
+            return;
+        }
+
+        // Premultiplied, and with red and blue swapped like in `rectangle`
+        let colour = color;
+        let mut rgba = Vec::with_capacity(image.data.len() * 4);
+        for &byte in &image.data {
+            let alpha = byte as u32 * colour.a() as u32 / 255;
+            let premultiply = |channel: u8| (channel as u32 * alpha / 255) as u8;
+            rgba.extend_from_slice(&[
+                premultiply(colour.b()),
+                premultiply(colour.g()),
+                premultiply(colour.r()),
+                alpha as u8,
+            ]);
+        }
+
+        let pixmap =
+            PixmapRef::from_bytes(&rgba, image.placement.width, image.placement.height).unwrap();
+        self.pixmap.draw_pixmap(
+            physical_glyph.x + image.placement.left,
+            physical_glyph.y - image.placement.top,
+            pixmap,
+            self.pixmap_paint,
+            self.transform,
+            None,
+        );
+    }
+}
@@ -0,0 +1,365 @@
+use std::ops::Range;
+
+use winit::keyboard::{Key, NamedKey, SmolStr};
+
+use crate::document::selection::{Range as SelectionRange, Selection};
+use crate::document::EditKind;
+use crate::pattern::{self, Pattern};
+use crate::InputState;
+
+use super::Editor;
+
+/// What's being searched for, which is kept after the search bar closes so that `n` and `N` can
+/// carry on jumping between matches.
+pub struct Search {
+    pattern: Pattern,
+    replacement: String,
+    /// Whether the search bar asks for a replacement as well.
+    replacing: bool,
+    /// What the search bar is editing, or nothing once it's closed.
+    editing: Option<Field>,
+    /// Where everything matching is, over the whole document and in order.
+    matches: Vec<Range<usize>>,
+    /// The document version `matches` were found in, or nothing when they need finding again,
+    /// like after the query changes.
+    version: Option<usize>,
+    /// Why the query can't be searched for, when it isn't a valid regex.
+    error: Option<String>,
+    /// The selection from before the search bar was opened, to go back to if it's dismissed.
+    origin: Selection,
+}
+
+impl Search {
+    /// Closes the search bar without going back to where it was opened, and forgets the matches,
+    /// which are for a document that's no longer shown.
+    pub fn close(&mut self) {
+        self.editing = None;
+        self.version = None;
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Query,
+    Replacement,
+}
+
+impl Editor<'_> {
+    /// Opens the search bar, starting from the last search if there was one.
+    pub(super) fn open_search(&mut self, replacing: bool) {
+        let origin = self.selection.clone();
+        let search = self.search.get_or_insert_with(|| Search {
+            pattern: Pattern::default(),
+            replacement: String::new(),
+            replacing,
+            editing: None,
+            matches: Vec::new(),
+            version: None,
+            error: None,
+            origin: origin.clone(),
+        });
+        search.replacing = replacing;
+        search.editing = Some(Field::Query);
+        search.origin = origin;
+        search.version = None;
+        self.search_from_origin();
+    }
+
+    /// Whether there's a search bar taking key presses.
+    pub(super) fn searching(&self) -> bool {
+        self.search
+            .as_ref()
+            .is_some_and(|search| search.editing.is_some())
+    }
+
+    /// Finds every match of the search in the document again, unless it's already up to date.
+    pub(super) fn find_matches(&mut self) {
+        let version = self.document().version();
+        let up_to_date = |search: &Search| search.version == Some(version);
+        if self.search.as_ref().is_none_or(up_to_date) {
+            return;
+        }
+        let text = self.document().text().to_string();
+        let search = self.search.as_mut().unwrap();
+        search.version = Some(version);
+        search.matches.clear();
+        search.error = None;
+        if search.pattern.query.is_empty() {
+            return;
+        }
+        match search.pattern.build() {
+            Ok(regex) => search.matches = pattern::find_all(&regex, &text),
+            Err(err) => search.error = Some(err.to_string()),
+        }
+    }
+
+    /// The matches in the document as of the last `find_matches`, for highlighting while the
+    /// search bar is open.
+    pub(super) fn search_matches(&self) -> &[Range<usize>] {
+        match &self.search {
+            Some(search) if search.editing.is_some() => &search.matches,
+            _ => &[],
+        }
+    }
+
+    /// Selects the first match at or after where the primary selection was when the search bar
+    /// was opened, as the query is typed.
+    fn search_from_origin(&mut self) {
+        let Some(search) = &self.search else {
+            return;
+        };
+        let from = search.origin.primary().start();
+        let origin = search.origin.clone();
+        self.find_matches();
+        if !self.jump_to_match(from, true) {
+            self.set_selection(origin);
+        }
+        self.show_search();
+    }
+
+    /// Selects the first match starting at or after `from`, or the last one starting before it
+    /// if not `forward`, wrapping around the document. Returns whether there was one.
+    fn jump_to_match(&mut self, from: usize, forward: bool) -> bool {
+        let Some(search) = &self.search else {
+            return false;
+        };
+        let matches = &search.matches;
+        let index = matches.partition_point(|found| found.start < from);
+        let found = if forward {
+            matches.get(index).or(matches.first())
+        } else {
+            index
+                .checked_sub(1)
+                .map_or(matches.last(), |i| matches.get(i))
+        };
+        let Some(found) = found.cloned() else {
+            return false;
+        };
+        self.set_selection(Selection::single(SelectionRange::new(
+            found.start,
+            found.end,
+        )));
+        true
+    }
+
+    /// Jumps from the primary selection to the next match of the last search, or the previous one
+    /// if not `forward`.
+    pub(super) fn search_next(&mut self, forward: bool) {
+        if self.search.is_none() {
+            return self.show_error("Nothing has been searched for yet");
+        }
+        self.find_matches();
+        let primary = self.selection.primary();
+        let from = if forward {
+            primary.end()
+        } else {
+            primary.start()
+        };
+        if !self.jump_to_match(from, forward) {
+            self.show_search_error();
+        }
+    }
+
+    /// Which match the primary selection is on, if it's on one.
+    fn current_match(&self) -> Option<usize> {
+        let search = self.search.as_ref()?;
+        let primary = self.selection.primary();
+        search
+            .matches
+            .binary_search_by_key(&primary.start(), |found| found.start)
+            .ok()
+            .filter(|&i| search.matches[i].end == primary.end())
+    }
+
+    /// The matches with what each should be replaced by, or just the `only` one.
+    fn replacements(&self, only: Option<usize>) -> Vec<(Range<usize>, String)> {
+        let Some(search) = &self.search else {
+            return Vec::new();
+        };
+        let matches = match only {
+            Some(i) => &search.matches[i..=i],
+            None => &search.matches[..],
+        };
+        let Ok(regex) = search.pattern.build() else {
+            return Vec::new();
+        };
+        let text = self.document().text().to_string();
+        (search.pattern).replacements(&regex, &text, matches, &search.replacement)
+    }
+
+    /// Replaces the match the primary selection is on and moves on to the next one, or just
+    /// selects the next one if it isn't on a match.
+    fn replace_current(&mut self) {
+        self.find_matches();
+        let Some(current) = self.current_match() else {
+            let from = self.selection.primary().start();
+            if !self.jump_to_match(from, true) {
+                self.show_search_error();
+            }
+            return;
+        };
+        let edits = self.replacements(Some(current));
+        self.edit(EditKind::Other, |this| {
+            let spans = this.replace_each(edits);
+            this.set_selection(Selection::point(spans[0].end));
+        });
+        self.find_matches();
+        let from = self.selection.primary().head;
+        if !self.jump_to_match(from, true) {
+            self.show_search_error();
+        }
+    }
+
+    /// Replaces every match in the document as one undo step, selecting the replacements.
+    fn replace_all(&mut self) {
+        self.find_matches();
+        let edits = self.replacements(None);
+        if edits.is_empty() {
+            return self.show_search_error();
+        }
+        let count = edits.len();
+        self.edit(EditKind::Other, |this| {
+            let spans = this.replace_each(edits);
+            let ranges = (spans.into_iter())
+                .map(|span| SelectionRange::new(span.start, span.end))
+                .collect();
+            this.set_selection(Selection::new(ranges, 0));
+        });
+        if let Some(search) = &mut self.search {
+            search.editing = None;
+        }
+        let plural = if count == 1 { "" } else { "es" };
+        self.show_message(
+            &format!("Replaced {count} match{plural}"),
+            self.theme.foreground,
+        );
+    }
+
+    /// Handles a key press while the search bar is open, which takes every key until it's
+    /// closed.
+    pub(super) fn handle_search_key(
+        &mut self,
+        input_state: &InputState,
+        key: Key<SmolStr>,
+    ) -> bool {
+        let Some(search) = &mut self.search else {
+            return false;
+        };
+        let Some(field) = search.editing else {
+            return false;
+        };
+        self.message = None;
+        let modifiers = input_state.modifier_state;
+        let (ctrl, alt) = (modifiers.control_key(), modifiers.alt_key());
+        match key {
+            Key::Named(NamedKey::Escape) => {
+                search.editing = None;
+                let origin = search.origin.clone();
+                self.set_selection(origin);
+                return true;
+            }
+            Key::Named(NamedKey::Enter) if alt && search.replacing => {
+                self.replace_all();
+                return true;
+            }
+            Key::Named(NamedKey::Enter) if field == Field::Replacement => self.replace_current(),
+            Key::Named(NamedKey::Enter) if search.replacing => {
+                search.editing = Some(Field::Replacement)
+            }
+            Key::Named(NamedKey::Enter) => {
+                search.editing = None;
+                return true;
+            }
+            Key::Named(NamedKey::Tab) if search.replacing => {
+                search.editing = Some(match field {
+                    Field::Query => Field::Replacement,
+                    Field::Replacement => Field::Query,
+                })
+            }
+            Key::Named(NamedKey::ArrowDown) => self.search_next(true),
+            Key::Character(c) if ctrl && c == "n" => self.search_next(true),
+            Key::Named(NamedKey::ArrowUp) => self.search_next(false),
+            Key::Character(c) if ctrl && c == "p" => self.search_next(false),
+            Key::Character(c) if alt && c == "r" => {
+                search.pattern.regex = !search.pattern.regex;
+                search.version = None;
+                self.search_from_origin();
+            }
+            Key::Character(c) if alt && c == "c" => {
+                search.pattern.case_sensitive = !search.pattern.case_sensitive;
+                search.version = None;
+                self.search_from_origin();
+            }
+            Key::Named(NamedKey::Backspace) => {
+                match field {
+                    Field::Query => {
+                        search.pattern.query.pop();
+                        search.version = None;
+                    }
+                    Field::Replacement => {
+                        search.replacement.pop();
+                    }
+                }
+                if field == Field::Query {
+                    self.search_from_origin();
+                }
+            }
+            key => match key.to_text() {
+                Some(text) if !ctrl && !alt => match field {
+                    Field::Query => {
+                        search.pattern.query.push_str(text);
+                        search.version = None;
+                        self.search_from_origin();
+                    }
+                    Field::Replacement => search.replacement.push_str(text),
+                },
+                _ => (),
+            },
+        }
+        self.show_search();
+        true
+    }
+
+    /// Shows the search bar where messages go: what's being searched for and how, and which of
+    /// the matches the primary selection is on.
+    fn show_search(&mut self) {
+        let Some(search) = &self.search else {
+            return;
+        };
+        let Some(field) = search.editing else {
+            return;
+        };
+        let flags = search.pattern.flags();
+        let query = &search.pattern.query;
+        let found = match (&search.error, self.current_match()) {
+            (Some(error), _) => error.lines().last().unwrap_or(error).trim().to_owned(),
+            (None, _) if query.is_empty() => String::new(),
+            (None, _) if search.matches.is_empty() => "no matches".to_owned(),
+            (None, Some(current)) => format!("{}/{}", current + 1, search.matches.len()),
+            (None, None) => format!("{} matches", search.matches.len()),
+        };
+        let text = match field {
+            Field::Query if search.replacing => {
+                format!("replace{flags}: {query}  {found}")
+            }
+            Field::Query => format!("find{flags}: {query}  {found}"),
+            Field::Replacement => format!(
+                "replace {query:?}{flags} with: {}  {found}",
+                search.replacement
+            ),
+        };
+        let colour = if search.error.is_some() || (!query.is_empty() && search.matches.is_empty()) {
+            self.theme.error
+        } else {
+            self.theme.foreground
+        };
+        self.show_message(&text, colour);
+    }
+
+    fn show_search_error(&mut self) {
+        match self.search.as_ref().and_then(|search| search.error.clone()) {
+            Some(error) => self.show_error(&format!("Invalid regex: {error}")),
+            None => self.show_error("No matches"),
+        }
+    }
+}
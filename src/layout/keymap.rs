@@ -0,0 +1,897 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+
+use winit::keyboard::{Key, ModifiersState, NamedKey, SmolStr};
+
+/// Commands that keys can be bound to by name, as they are in the config.
+pub trait Named: Copy + Eq + 'static {
+    fn all() -> &'static [Self];
+
+    fn name(self) -> &'static str;
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::all()
+            .iter()
+            .copied()
+            .find(|command| command.name() == name)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Mode {
+    /// Keys are commands; motions replace the selection.
+    Normal,
+    /// Keys insert text.
+    Insert,
+    /// Like `Normal`, but motions extend the selection instead of replacing it.
+    Select,
+}
+
+impl Mode {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Normal => "normal",
+            Self::Insert => "insert",
+            Self::Select => "select",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Command {
+    MoveLeft,
+    MoveRight,
+    MoveUp,
+    MoveDown,
+    ExtendLeft,
+    ExtendRight,
+    ExtendUp,
+    ExtendDown,
+    ExtendPrevWord,
+    ExtendNextWord,
+    MovePrevWord,
+    MoveNextWord,
+    NextWordStart,
+    NextWordEnd,
+    PrevWordStart,
+    LineStart,
+    LineEnd,
+    PageUp,
+    PageDown,
+    GoToLine,
+    GoToStart,
+    GoToEnd,
+    SelectLine,
+    SelectAll,
+    CollapseSelection,
+    CopySelectionBelow,
+    CopySelectionAbove,
+    SplitSelection,
+    SelectMatches,
+    AlignSelections,
+    KeepPrimarySelection,
+    RotateSelectionsForward,
+    RotateSelectionsBackward,
+    Delete,
+    Change,
+    Yank,
+    PasteAfter,
+    PasteBefore,
+    InsertBefore,
+    InsertAfter,
+    InsertLineStart,
+    InsertLineEnd,
+    OpenBelow,
+    OpenAbove,
+    NormalMode,
+    ToggleSelectMode,
+    InsertNewline,
+    NewlineAndIndent,
+    InsertIndent,
+    TrimWhitespace,
+    DeleteBackward,
+    DeleteForward,
+    DeleteWordBackward,
+    Copy,
+    Paste,
+    Undo,
+    Redo,
+    Earlier,
+    Later,
+    Save,
+    Hover,
+    Complete,
+    NextBuffer,
+    PrevBuffer,
+    CloseBuffer,
+    SplitRight,
+    SplitDown,
+    CloseSplit,
+    FocusLeft,
+    FocusRight,
+    FocusUp,
+    FocusDown,
+    OpenPalette,
+    PickFile,
+    Search,
+    Replace,
+    SearchNext,
+    SearchPrev,
+    SearchProject,
+    NextTheme,
+    SetMajorMode,
+    ToggleMinorMode,
+    /// One of the commands plugins added, by where it is in their list. It isn't in `ALL`, since
+    /// there's no telling which there are until the plugins are loaded.
+    Plugin(usize),
+}
+
+impl Command {
+    /// Every command, in the order the command palette lists them before anything is typed.
+    pub const ALL: &[Self] = &[
+        Self::MoveLeft,
+        Self::MoveRight,
+        Self::MoveUp,
+        Self::MoveDown,
+        Self::ExtendLeft,
+        Self::ExtendRight,
+        Self::ExtendUp,
+        Self::ExtendDown,
+        Self::ExtendPrevWord,
+        Self::ExtendNextWord,
+        Self::MovePrevWord,
+        Self::MoveNextWord,
+        Self::NextWordStart,
+        Self::NextWordEnd,
+        Self::PrevWordStart,
+        Self::LineStart,
+        Self::LineEnd,
+        Self::PageUp,
+        Self::PageDown,
+        Self::GoToLine,
+        Self::GoToStart,
+        Self::GoToEnd,
+        Self::SelectLine,
+        Self::SelectAll,
+        Self::CollapseSelection,
+        Self::CopySelectionBelow,
+        Self::CopySelectionAbove,
+        Self::SplitSelection,
+        Self::SelectMatches,
+        Self::AlignSelections,
+        Self::KeepPrimarySelection,
+        Self::RotateSelectionsForward,
+        Self::RotateSelectionsBackward,
+        Self::Delete,
+        Self::Change,
+        Self::Yank,
+        Self::PasteAfter,
+        Self::PasteBefore,
+        Self::InsertBefore,
+        Self::InsertAfter,
+        Self::InsertLineStart,
+        Self::InsertLineEnd,
+        Self::OpenBelow,
+        Self::OpenAbove,
+        Self::NormalMode,
+        Self::ToggleSelectMode,
+        Self::InsertNewline,
+        Self::NewlineAndIndent,
+        Self::InsertIndent,
+        Self::TrimWhitespace,
+        Self::DeleteBackward,
+        Self::DeleteForward,
+        Self::DeleteWordBackward,
+        Self::Copy,
+        Self::Paste,
+        Self::Undo,
+        Self::Redo,
+        Self::Earlier,
+        Self::Later,
+        Self::Save,
+        Self::Hover,
+        Self::Complete,
+        Self::NextBuffer,
+        Self::PrevBuffer,
+        Self::CloseBuffer,
+        Self::SplitRight,
+        Self::SplitDown,
+        Self::CloseSplit,
+        Self::FocusLeft,
+        Self::FocusRight,
+        Self::FocusUp,
+        Self::FocusDown,
+        Self::OpenPalette,
+        Self::PickFile,
+        Self::Search,
+        Self::Replace,
+        Self::SearchNext,
+        Self::SearchPrev,
+        Self::SearchProject,
+        Self::NextTheme,
+        Self::SetMajorMode,
+        Self::ToggleMinorMode,
+    ];
+
+    /// What the command is called in the command palette.
+    pub fn name(self) -> &'static str {
+        self.describe().0
+    }
+
+    /// What the command does, in a few words.
+    pub fn description(self) -> &'static str {
+        self.describe().1
+    }
+
+    fn describe(self) -> (&'static str, &'static str) {
+        match self {
+            Self::MoveLeft => ("move_left", "Move each cursor a character to the left"),
+            Self::MoveRight => ("move_right", "Move each cursor a character to the right"),
+            Self::MoveUp => ("move_up", "Move each cursor up a line"),
+            Self::MoveDown => ("move_down", "Move each cursor down a line"),
+            Self::ExtendLeft => (
+                "extend_left",
+                "Extend each selection a character to the left",
+            ),
+            Self::ExtendRight => (
+                "extend_right",
+                "Extend each selection a character to the right",
+            ),
+            Self::ExtendUp => ("extend_up", "Extend each selection up a line"),
+            Self::ExtendDown => ("extend_down", "Extend each selection down a line"),
+            Self::ExtendPrevWord => (
+                "extend_prev_word",
+                "Extend each selection to the start of the previous word",
+            ),
+            Self::ExtendNextWord => (
+                "extend_next_word",
+                "Extend each selection to the start of the next word",
+            ),
+            Self::MovePrevWord => (
+                "move_prev_word",
+                "Move each cursor to the start of the previous word",
+            ),
+            Self::MoveNextWord => (
+                "move_next_word",
+                "Move each cursor to the start of the next word",
+            ),
+            Self::NextWordStart => (
+                "select_next_word_start",
+                "Select up to the start of the next word",
+            ),
+            Self::NextWordEnd => (
+                "select_next_word_end",
+                "Select up to the end of the next word",
+            ),
+            Self::PrevWordStart => (
+                "select_prev_word_start",
+                "Select back to the start of the previous word",
+            ),
+            Self::LineStart => ("line_start", "Move each cursor to the start of its line"),
+            Self::LineEnd => ("line_end", "Move each cursor to the end of its line"),
+            Self::PageUp => ("page_up", "Move up a page"),
+            Self::PageDown => ("page_down", "Move down a page"),
+            Self::GoToLine => ("go_to_line", "Go to a line, or a line and column"),
+            Self::GoToStart => ("go_to_start", "Go to the first line"),
+            Self::GoToEnd => ("go_to_end", "Go to the last line"),
+            Self::SelectLine => (
+                "select_line",
+                "Select the whole lines each selection touches",
+            ),
+            Self::SelectAll => ("select_all", "Select the whole buffer"),
+            Self::CollapseSelection => {
+                ("collapse_selection", "Shrink each selection to its cursor")
+            }
+            Self::CopySelectionBelow => (
+                "copy_selection_below",
+                "Add a copy of each selection on the line below",
+            ),
+            Self::CopySelectionAbove => (
+                "copy_selection_above",
+                "Add a copy of each selection on the line above",
+            ),
+            Self::SplitSelection => (
+                "split_selection",
+                "Split each selection wherever a regex matches",
+            ),
+            Self::SelectMatches => (
+                "select_matches",
+                "Select whatever a regex matches within each selection",
+            ),
+            Self::AlignSelections => (
+                "align_selections",
+                "Line the selections up in the same column",
+            ),
+            Self::KeepPrimarySelection => (
+                "keep_primary_selection",
+                "Drop every selection but the primary one",
+            ),
+            Self::RotateSelectionsForward => (
+                "rotate_selections_forward",
+                "Make the next selection the primary one",
+            ),
+            Self::RotateSelectionsBackward => (
+                "rotate_selections_backward",
+                "Make the previous selection the primary one",
+            ),
+            Self::Delete => ("delete", "Delete the selected text"),
+            Self::Change => ("change", "Delete the selected text and start inserting"),
+            Self::Yank => ("yank", "Copy the selected text to the register"),
+            Self::PasteAfter => ("paste_after", "Paste the register after each selection"),
+            Self::PasteBefore => ("paste_before", "Paste the register before each selection"),
+            Self::InsertBefore => ("insert_before", "Insert before each selection"),
+            Self::InsertAfter => ("insert_after", "Insert after each selection"),
+            Self::InsertLineStart => ("insert_line_start", "Insert at the start of each line"),
+            Self::InsertLineEnd => ("insert_line_end", "Insert at the end of each line"),
+            Self::OpenBelow => (
+                "open_below",
+                "Open a new line below each selection and insert on it",
+            ),
+            Self::OpenAbove => (
+                "open_above",
+                "Open a new line above each selection and insert on it",
+            ),
+            Self::NormalMode => ("normal_mode", "Go back to normal mode"),
+            Self::ToggleSelectMode => (
+                "toggle_select_mode",
+                "Switch between normal and select mode",
+            ),
+            Self::InsertNewline => ("insert_newline", "Insert a line break at each cursor"),
+            Self::NewlineAndIndent => (
+                "newline_and_indent",
+                "Insert a line break at each cursor, indenting like the line it breaks",
+            ),
+            Self::InsertIndent => ("insert_indent", "Insert the buffer's indent at each cursor"),
+            Self::TrimWhitespace => (
+                "trim_whitespace",
+                "Trim whitespace from the ends of the buffer's lines",
+            ),
+            Self::DeleteBackward => ("delete_backward", "Delete the character before each cursor"),
+            Self::DeleteForward => ("delete_forward", "Delete the character after each cursor"),
+            Self::DeleteWordBackward => {
+                ("delete_word_backward", "Delete the word before each cursor")
+            }
+            Self::Copy => ("copy", "Copy the selected text to the clipboard"),
+            Self::Paste => ("paste", "Paste from the clipboard"),
+            Self::Undo => ("undo", "Undo the last change"),
+            Self::Redo => ("redo", "Redo the last undone change"),
+            Self::Earlier => (
+                "earlier",
+                "Go to the change made before the current one, on any branch of the undo tree",
+            ),
+            Self::Later => (
+                "later",
+                "Go to the change made after the current one, on any branch of the undo tree",
+            ),
+            Self::Save => ("save", "Save the buffer to its file"),
+            Self::Hover => (
+                "hover",
+                "Show what the language server knows about the text at the cursor",
+            ),
+            Self::Complete => ("complete", "Ask the language server for completions"),
+            Self::NextBuffer => ("next_buffer", "Switch to the next buffer"),
+            Self::PrevBuffer => ("prev_buffer", "Switch to the previous buffer"),
+            Self::CloseBuffer => ("close_buffer", "Close the buffer"),
+            Self::SplitRight => (
+                "split_right",
+                "Split the view, with the new one on the right",
+            ),
+            Self::SplitDown => ("split_down", "Split the view, with the new one below"),
+            Self::CloseSplit => ("close_split", "Close the view"),
+            Self::FocusLeft => ("focus_left", "Focus the view to the left"),
+            Self::FocusRight => ("focus_right", "Focus the view to the right"),
+            Self::FocusUp => ("focus_up", "Focus the view above"),
+            Self::FocusDown => ("focus_down", "Focus the view below"),
+            Self::OpenPalette => ("open_palette", "Search for a command to run"),
+            Self::PickFile => ("pick_file", "Search for a file to open"),
+            Self::Search => ("search", "Search the buffer for text or a regex"),
+            Self::Replace => ("replace", "Search the buffer and replace what's found"),
+            Self::SearchNext => ("search_next", "Select the next match of the last search"),
+            Self::SearchPrev => (
+                "search_prev",
+                "Select the previous match of the last search",
+            ),
+            Self::SearchProject => ("search_project", "Search every file in the project"),
+            Self::NextTheme => ("next_theme", "Switch to the next built-in theme"),
+            Self::SetMajorMode => ("set_major_mode", "Choose the buffer's major mode"),
+            Self::ToggleMinorMode => (
+                "toggle_minor_mode",
+                "Turn one of the buffer's minor modes on or off",
+            ),
+            // The editor names plugins' commands itself, from the plugins' list
+            Self::Plugin(_) => ("plugin", "Run a command a plugin added"),
+        }
+    }
+}
+
+impl Named for Command {
+    fn all() -> &'static [Self] {
+        Self::ALL
+    }
+
+    fn name(self) -> &'static str {
+        self.describe().0
+    }
+}
+
+/// What keys do in the nav bar.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NavCommand {
+    Up,
+    Down,
+    First,
+    Last,
+    /// Goes to the directory containing the selected entry, collapsing it.
+    Out,
+    /// Expands the selected directory and goes into it.
+    In,
+    /// Opens the selected file or toggles the selected directory.
+    Activate,
+    Create,
+    Rename,
+    Delete,
+    /// Reads the files again after they've changed on disk.
+    Refresh,
+    /// Gives the focus back to the editor.
+    Leave,
+}
+
+impl Named for NavCommand {
+    fn all() -> &'static [Self] {
+        use NavCommand::*;
+        &[
+            Up, Down, First, Last, Out, In, Activate, Create, Rename, Delete, Refresh, Leave,
+        ]
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Up => "up",
+            Self::Down => "down",
+            Self::First => "first",
+            Self::Last => "last",
+            Self::Out => "go_out",
+            Self::In => "go_in",
+            Self::Activate => "activate",
+            Self::Create => "create",
+            Self::Rename => "rename",
+            Self::Delete => "delete",
+            Self::Refresh => "refresh",
+            Self::Leave => "leave",
+        }
+    }
+}
+
+/// What keys do wherever the focus is, before whatever has it sees them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GlobalCommand {
+    Quit,
+    /// Moves the focus on to the next pane: the nav bar, each editor view, then the status bar.
+    FocusNext,
+    FocusPrev,
+    /// Hides the nav bar, or shows it again.
+    ToggleNavBar,
+}
+
+impl Named for GlobalCommand {
+    fn all() -> &'static [Self] {
+        &[
+            Self::Quit,
+            Self::FocusNext,
+            Self::FocusPrev,
+            Self::ToggleNavBar,
+        ]
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Quit => "quit",
+            Self::FocusNext => "focus_next",
+            Self::FocusPrev => "focus_prev",
+            Self::ToggleNavBar => "toggle_nav_bar",
+        }
+    }
+}
+
+/// A key together with the modifiers held while pressing it. Shift is ignored for characters
+/// typed without Ctrl, since it is already reflected in the character itself.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct KeyPress {
+    key: Key<SmolStr>,
+    ctrl: bool,
+    alt: bool,
+    shift: bool,
+}
+
+impl KeyPress {
+    pub fn new(key: Key<SmolStr>, modifiers: ModifiersState) -> Self {
+        let ctrl = modifiers.control_key();
+        let (key, shift) = match key {
+            // Ctrl+Shift+Z and friends arrive as an uppercase letter
+            Key::Character(c) if ctrl => (
+                Key::Character(c.to_lowercase().into()),
+                modifiers.shift_key(),
+            ),
+            Key::Character(c) => (Key::Character(c), false),
+            key => (key, modifiers.shift_key()),
+        };
+        Self {
+            key,
+            ctrl,
+            alt: modifiers.alt_key(),
+            shift,
+        }
+    }
+
+    /// The key and modifiers that make up this key press, for synthesising input.
+    pub fn event(&self) -> (Key<SmolStr>, ModifiersState) {
+        let mut modifiers = ModifiersState::empty();
+        modifiers.set(ModifiersState::CONTROL, self.ctrl);
+        modifiers.set(ModifiersState::ALT, self.alt);
+        modifiers.set(ModifiersState::SHIFT, self.shift);
+        (self.key.clone(), modifiers)
+    }
+
+    /// Parses Kakoune-style key descriptions such as `w`, `C-s`, `S-left` or `A-C-z`.
+    pub fn parse(description: &str) -> Option<Self> {
+        let mut rest = description;
+        let mut modifiers = ModifiersState::empty();
+        while let Some((prefix, tail)) = rest.split_once('-').filter(|(_, tail)| !tail.is_empty()) {
+            modifiers |= match prefix {
+                "C" => ModifiersState::CONTROL,
+                "A" => ModifiersState::ALT,
+                "S" => ModifiersState::SHIFT,
+                _ => return None,
+            };
+            rest = tail;
+        }
+
+        let key = match NAMED_KEYS.iter().find(|(name, _)| *name == rest) {
+            Some(&(_, key)) => Key::Named(key),
+            None if rest.chars().count() == 1 => Key::Character(rest.into()),
+            None => return None,
+        };
+        Some(Self::new(key, modifiers))
+    }
+
+    /// Parses a sequence of key descriptions separated by spaces, like `g g` or `space f`.
+    pub fn parse_sequence(description: &str) -> Option<Vec<Self>> {
+        let keys: Option<Vec<_>> = description.split_whitespace().map(Self::parse).collect();
+        keys.filter(|keys| !keys.is_empty())
+    }
+
+    /// The same description `parse_sequence` takes.
+    pub fn describe_sequence(keys: &[Self]) -> String {
+        let keys: Vec<_> = keys.iter().map(ToString::to_string).collect();
+        keys.join(" ")
+    }
+}
+
+/// The names of keys that don't type a character, as used in key descriptions.
+const NAMED_KEYS: &[(&str, NamedKey)] = &[
+    ("left", NamedKey::ArrowLeft),
+    ("right", NamedKey::ArrowRight),
+    ("up", NamedKey::ArrowUp),
+    ("down", NamedKey::ArrowDown),
+    ("home", NamedKey::Home),
+    ("end", NamedKey::End),
+    ("pageup", NamedKey::PageUp),
+    ("pagedown", NamedKey::PageDown),
+    ("esc", NamedKey::Escape),
+    ("ret", NamedKey::Enter),
+    ("backspace", NamedKey::Backspace),
+    ("del", NamedKey::Delete),
+    ("tab", NamedKey::Tab),
+    ("space", NamedKey::Space),
+    ("f1", NamedKey::F1),
+    ("f2", NamedKey::F2),
+    ("f3", NamedKey::F3),
+    ("f4", NamedKey::F4),
+    ("f5", NamedKey::F5),
+    ("f6", NamedKey::F6),
+    ("f7", NamedKey::F7),
+    ("f8", NamedKey::F8),
+    ("f9", NamedKey::F9),
+    ("f10", NamedKey::F10),
+    ("f11", NamedKey::F11),
+    ("f12", NamedKey::F12),
+];
+
+/// The same description `KeyPress::parse` takes.
+impl fmt::Display for KeyPress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (held, prefix) in [(self.ctrl, "C-"), (self.alt, "A-"), (self.shift, "S-")] {
+            if held {
+                f.write_str(prefix)?;
+            }
+        }
+        match &self.key {
+            Key::Named(key) => match NAMED_KEYS.iter().find(|(_, named)| named == key) {
+                Some((name, _)) => f.write_str(name),
+                None => write!(f, "{key:?}"),
+            },
+            Key::Character(c) => f.write_str(c),
+            key => write!(f, "{key:?}"),
+        }
+    }
+}
+
+/// Keys bound to commands, each given every sequence that runs it.
+pub type Bindings<C> = Vec<(C, Vec<Vec<KeyPress>>)>;
+
+/// Which command each sequence of keys runs in each mode `M`. A sequence can be a single key or
+/// several in a row, like `g g`, and a key that starts a longer sequence only ever waits for the
+/// rest of it. What a pane does with keys that aren't bound, like inserting their text, is up to
+/// the pane.
+#[derive(Clone)]
+pub struct Keymap<M, C> {
+    bindings: HashMap<(M, Vec<KeyPress>), C>,
+}
+
+/// What pressing a key came to.
+pub enum Press<C> {
+    Command(C),
+    /// The keys so far start a longer sequence, so nothing happens until the rest of it.
+    Pending,
+    /// The keys so far, ending with this one, aren't bound to anything.
+    Unbound(Vec<KeyPress>),
+}
+
+impl<M: Copy + Eq + Hash, C: Named> Keymap<M, C> {
+    /// Looks up `key` after the keys already `pending` in `mode`, keeping it there if it's only
+    /// part of the way through a sequence and clearing them otherwise.
+    pub fn press(&self, pending: &mut Vec<KeyPress>, mode: M, key: KeyPress) -> Press<C> {
+        pending.push(key);
+        let longer = self.bindings.keys().any(|(bound_mode, keys)| {
+            *bound_mode == mode && keys.len() > pending.len() && keys.starts_with(pending)
+        });
+        if longer {
+            return Press::Pending;
+        }
+        let keys = (mode, std::mem::take(pending));
+        match self.bindings.get(&keys) {
+            Some(&command) => Press::Command(command),
+            None => Press::Unbound(keys.1),
+        }
+    }
+
+    /// The sequences that run `command` in `mode`, in a stable order.
+    pub fn keys_for(&self, mode: M, command: C) -> Vec<&[KeyPress]> {
+        let mut keys: Vec<_> = self
+            .bindings
+            .iter()
+            .filter(|((bound_mode, _), bound)| *bound_mode == mode && **bound == command)
+            .map(|((_, keys), _)| keys.as_slice())
+            .collect();
+        // Shorter descriptions first, which tend to be the ones meant to be typed
+        keys.sort_by_cached_key(|keys| {
+            let description = KeyPress::describe_sequence(keys);
+            (description.len(), description)
+        });
+        keys
+    }
+
+    fn bind(&mut self, modes: &[M], keys: &str, command: C) {
+        let keys = KeyPress::parse_sequence(keys).expect("Invalid default key binding");
+        for &mode in modes {
+            self.bindings.insert((mode, keys.clone()), command);
+        }
+    }
+
+    /// Binds `command` in `mode` to `sequences` instead of the keys it had, also taking them away
+    /// from whatever they ran before, along with any sequence that one of them starts or that
+    /// starts one of them, since those couldn't both be typed. No sequences unbinds it.
+    pub fn rebind(&mut self, mode: M, command: C, sequences: &[Vec<KeyPress>]) {
+        self.bindings.retain(|(bound_mode, keys), bound| {
+            *bound_mode != mode
+                || (*bound != command
+                    && !sequences
+                        .iter()
+                        .any(|new| keys.starts_with(new) || new.starts_with(keys)))
+        });
+        for keys in sequences {
+            self.bindings.insert((mode, keys.clone()), command);
+        }
+    }
+
+    /// Rebinds each of `bindings` in `mode` in turn.
+    pub fn rebind_all(&mut self, mode: M, bindings: &Bindings<C>) {
+        for (command, sequences) in bindings {
+            self.rebind(mode, *command, sequences);
+        }
+    }
+
+    /// The default keymap with `bindings` in `mode` in place of what they replace.
+    pub fn with(bindings: &[(M, &Bindings<C>)]) -> Self
+    where
+        Self: Default,
+    {
+        let mut keymap = Self::default();
+        for &(mode, bindings) in bindings {
+            keymap.rebind_all(mode, bindings);
+        }
+        keymap
+    }
+}
+
+impl Default for Keymap<Mode, Command> {
+    fn default() -> Self {
+        use Command::*;
+        use Mode::*;
+
+        const ANY: &[Mode] = &[Normal, Insert, Select];
+        const COMMAND: &[Mode] = &[Normal, Select];
+
+        let mut keymap = Self {
+            bindings: HashMap::new(),
+        };
+
+        for (keys, command) in [
+            (&["h", "left"][..], MoveLeft),
+            (&["l", "right"], MoveRight),
+            (&["k", "up"], MoveUp),
+            (&["j", "down"], MoveDown),
+            (&["H", "S-left"], ExtendLeft),
+            (&["L", "S-right"], ExtendRight),
+            (&["K", "S-up"], ExtendUp),
+            (&["J", "S-down"], ExtendDown),
+            (&["w"], NextWordStart),
+            (&["e"], NextWordEnd),
+            (&["b"], PrevWordStart),
+            (&["x"], SelectLine),
+            (&["%"], SelectAll),
+            (&[";"], CollapseSelection),
+            (&["C"], CopySelectionBelow),
+            (&["A-C"], CopySelectionAbove),
+            (&["S"], SplitSelection),
+            (&["s"], SelectMatches),
+            (&["&"], AlignSelections),
+            (&[","], KeepPrimarySelection),
+            (&[")"], RotateSelectionsForward),
+            (&["("], RotateSelectionsBackward),
+            (&["d"], Delete),
+            (&["c"], Change),
+            (&["y"], Yank),
+            (&["p"], PasteAfter),
+            (&["P"], PasteBefore),
+            (&["i"], InsertBefore),
+            (&["a"], InsertAfter),
+            (&["I"], InsertLineStart),
+            (&["A"], InsertLineEnd),
+            (&["o"], OpenBelow),
+            (&["O"], OpenAbove),
+            (&["v"], ToggleSelectMode),
+            (&["u"], Undo),
+            (&["U"], Redo),
+            (&["A-u"], Earlier),
+            (&["A-U"], Later),
+            (&["/"], Search),
+            (&["n"], SearchNext),
+            (&["N"], SearchPrev),
+            (&["g g", "g k"], GoToStart),
+            (&["g e", "g j"], GoToEnd),
+            (&["g h"], LineStart),
+            (&["g l"], LineEnd),
+            // Space leads into the more occasional commands
+            (&["space f"], PickFile),
+            (&["space /"], SearchProject),
+            (&["space ?"], OpenPalette),
+        ] {
+            for key in keys {
+                keymap.bind(COMMAND, key, command);
+            }
+        }
+
+        for (key, command) in [
+            ("left", MoveLeft),
+            ("right", MoveRight),
+            ("up", MoveUp),
+            ("down", MoveDown),
+            ("S-left", ExtendLeft),
+            ("S-right", ExtendRight),
+            ("S-up", ExtendUp),
+            ("S-down", ExtendDown),
+            ("ret", InsertNewline),
+            ("tab", InsertIndent),
+            ("backspace", DeleteBackward),
+            ("del", DeleteForward),
+            ("C-backspace", DeleteWordBackward),
+            ("C-space", Complete),
+        ] {
+            keymap.bind(&[Insert], key, command);
+        }
+
+        for (key, command) in [
+            ("esc", NormalMode),
+            ("C-left", MovePrevWord),
+            ("C-right", MoveNextWord),
+            ("C-S-left", ExtendPrevWord),
+            ("C-S-right", ExtendNextWord),
+            ("home", LineStart),
+            ("end", LineEnd),
+            ("pageup", PageUp),
+            ("pagedown", PageDown),
+            ("C-c", Copy),
+            ("C-v", Paste),
+            ("C-s", Save),
+            ("C-z", Undo),
+            ("C-S-z", Redo),
+            ("C-y", Redo),
+            ("C-k", Hover),
+            ("C-g", GoToLine),
+            ("C-pagedown", NextBuffer),
+            ("C-tab", NextBuffer),
+            ("C-pageup", PrevBuffer),
+            ("C-S-tab", PrevBuffer),
+            ("C-w", CloseBuffer),
+            ("A-v", SplitRight),
+            ("A-s", SplitDown),
+            ("A-q", CloseSplit),
+            ("A-h", FocusLeft),
+            ("A-left", FocusLeft),
+            ("A-l", FocusRight),
+            ("A-right", FocusRight),
+            ("A-k", FocusUp),
+            ("A-up", FocusUp),
+            ("A-j", FocusDown),
+            ("A-down", FocusDown),
+            ("C-S-p", OpenPalette),
+            ("C-p", PickFile),
+            ("C-f", Search),
+            ("C-h", Replace),
+            ("C-S-f", SearchProject),
+        ] {
+            keymap.bind(ANY, key, command);
+        }
+
+        keymap
+    }
+}
+
+impl Default for Keymap<(), NavCommand> {
+    fn default() -> Self {
+        use NavCommand::*;
+
+        let mut keymap = Self {
+            bindings: HashMap::new(),
+        };
+        for (key, command) in [
+            ("k", Up),
+            ("up", Up),
+            ("j", Down),
+            ("down", Down),
+            ("g", First),
+            ("home", First),
+            ("G", Last),
+            ("end", Last),
+            ("h", Out),
+            ("left", Out),
+            ("l", In),
+            ("right", In),
+            ("ret", Activate),
+            ("a", Create),
+            ("r", Rename),
+            ("d", Delete),
+            ("del", Delete),
+            ("R", Refresh),
+            ("esc", Leave),
+        ] {
+            keymap.bind(&[()], key, command);
+        }
+        keymap
+    }
+}
+
+impl Default for Keymap<(), GlobalCommand> {
+    fn default() -> Self {
+        let mut keymap = Self {
+            bindings: HashMap::new(),
+        };
+        keymap.bind(&[()], "C-q", GlobalCommand::Quit);
+        keymap.bind(&[()], "f6", GlobalCommand::FocusNext);
+        keymap.bind(&[()], "S-f6", GlobalCommand::FocusPrev);
+        keymap.bind(&[()], "C-b", GlobalCommand::ToggleNavBar);
+        keymap
+    }
+}
@@ -1,20 +1,46 @@
 mod editor;
+pub mod keymap;
+pub mod modes;
 mod nav_bar;
+mod panels;
+mod picker;
+mod project_search;
+mod splits;
 mod status_bar;
 
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::env;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+
+use cosmic_text::{FontSystem, SwashCache};
 use editor::Editor;
+pub use keymap::KeyPress;
+use keymap::{GlobalCommand, Keymap, Press};
 use nav_bar::NavBar;
-use status_bar::StatusBar;
+use picker::FilePicker;
+use project_search::{LastSearch, ProjectSearch};
+use status_bar::{Status, StatusBar};
 use taffy::{NodeId, TaffyTree};
-use tiny_skia::{Paint, PixmapMut, Rect};
+use tiny_skia::{Paint, PixmapMut, Rect, Transform};
 use winit::{
     event::{ElementState, MouseButton},
     keyboard::{Key, SmolStr},
 };
 
-use crate::InputState;
+use crate::config::{Config, LoadError, PanelSizes, Theme};
+use crate::document::selection::Selection;
+use crate::pattern::Pattern;
+use crate::{InputState, Waker};
 
-pub trait Interactive {
+/// A pane of the layout, or something drawn over it. Panes are told apart by downcasting, which
+/// is how the layout finds its editor views.
+pub trait Interactive: Any {
     fn handle_mouse_input(
         &mut self,
         input_state: &InputState,
@@ -28,6 +54,23 @@ pub trait Interactive {
 
     fn handle_keyboard_input(&mut self, input_state: &InputState, key: Key<SmolStr>) -> bool;
 
+    /// Picks up whatever background work has finished since the event loop was last woken,
+    /// returning whether there's anything new to draw.
+    fn poll(&mut self) -> bool {
+        false
+    }
+
+    /// Picks up the settings that apply to it, which happens on startup and whenever the config
+    /// file changes.
+    fn configure(&mut self, _config: &Config) {}
+
+    /// Called when keys start coming to it, by clicking it or moving the focus there with the
+    /// keyboard.
+    fn focus_gained(&mut self) {}
+
+    /// Called when keys stop coming to it because something else got the focus.
+    fn focus_lost(&mut self) {}
+
     // Is this a weird lifetime param name? No idea
     fn render<'draw>(
         &mut self,
@@ -38,81 +81,307 @@ pub trait Interactive {
     );
 }
 
+/// Something drawn over the whole layout, like the file picker, which takes all input until it's
+/// closed.
+pub trait Overlay: Interactive {
+    /// Waits for anything going on in the background that input depends on, like the file picker
+    /// finding files, for replaying input, where there's no event loop to be woken when it's done.
+    fn finish_background_work(&mut self) {}
+}
+
+/// Something one part of the UI asks of another, like the file tree asking for a file to be
+/// opened. They're queued up and passed on once the event that caused them has been handled.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Request {
+    Open(PathBuf),
+    /// Opens a file with part of a line selected, both counted in bytes from 0.
+    OpenAt {
+        path: PathBuf,
+        line: usize,
+        columns: Range<usize>,
+    },
+    /// A file or directory was moved, so anything open from inside it should follow.
+    Moved {
+        from: PathBuf,
+        to: PathBuf,
+    },
+    GoToLine,
+    /// Shows an error where the focused editor shows messages.
+    ShowError(String),
+    /// Splits the focused editor, putting a new view of the same buffer on the given side of it.
+    Split(Direction),
+    /// Closes the focused editor view, unless it's the only one.
+    CloseSplit,
+    /// Moves focus to the nearest editor view on the given side of the focused one.
+    Focus(Direction),
+    /// Gives the focus back to the editor view that had it last.
+    FocusEditor,
+    /// Opens the file picker over everything else.
+    PickFile,
+    /// Opens the project search over everything else, searching again for whatever it was last
+    /// searching for. Files open with unsaved changes are searched as they are in the editor,
+    /// whose text is given by their absolute paths.
+    SearchProject(HashMap<PathBuf, String>),
+    /// Closes whatever's drawn over everything else, like the file picker, giving input back to
+    /// the rest of the layout.
+    CloseOverlay,
+    /// Replaces every match of `pattern` in each of `files`, in their buffers for the ones that
+    /// are open and on disk for the rest.
+    ReplaceInFiles {
+        pattern: Pattern,
+        replacement: String,
+        files: Vec<PathBuf>,
+    },
+    /// Switches everything to the built-in theme after the one it's using, until the config
+    /// changes.
+    NextTheme,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+pub type Requests = Rc<RefCell<Vec<Request>>>;
+
+/// A copy of the parts of an editor that input can change.
+pub struct EditorState {
+    pub text: String,
+    pub selection: Selection,
+    pub mode: &'static str,
+}
+
+/// The font database and glyph cache, shared by everything that draws text.
+pub struct Fonts {
+    pub font_system: FontSystem,
+    pub swash_cache: SwashCache,
+}
+
 pub struct RootLayout {
     tree: TaffyTree<Box<dyn Interactive>>,
+    /// The grid everything else is laid out in. Nodes with an `Interactive` are panes, and the
+    /// rest just hold other nodes. The panes that are editors are views, split up by the nodes
+    /// holding them.
     root: NodeId,
-    nav_bar: NodeId,
+    /// The editor view that was focused last, which is the one requests go to.
     editor: NodeId,
-    status_bar: NodeId,
-    focused: Section,
-}
-
-enum Section {
-    NavBar,
-    Editor,
-    StatusBar,
+    /// The pane keys go to, when there's no overlay to take them.
+    focused: NodeId,
+    requests: Requests,
+    /// The divider being dragged, if any.
+    drag: Option<splits::Drag>,
+    /// The panel whose edge is being dragged, if any.
+    resizing: Option<panels::Panel>,
+    /// What the panels were dragged to, and where that's kept between sessions if anywhere.
+    panels: PanelSizes,
+    panels_path: Option<PathBuf>,
+    /// Drawn over everything else while they're open, in the order they were opened, with the
+    /// last taking all input.
+    overlays: Vec<Box<dyn Overlay>>,
+    /// What the project search was last searching for, which it starts out with when reopened.
+    last_search: Rc<RefCell<LastSearch>>,
+    /// For opening the picker and project search with.
+    fonts: Rc<RefCell<Fonts>>,
+    waker: Waker,
+    /// What everything was last configured with.
+    config: Config,
+    /// Keys that work wherever the focus is, looked up before the focused pane sees them.
+    keymap: Keymap<(), GlobalCommand>,
+    /// The keys typed so far of a sequence bound in `keymap`.
+    pending: Vec<KeyPress>,
+    quitting: bool,
+    /// Where the config comes from each time it changes, if it's being watched.
+    config_changes: Option<Receiver<Result<Config, LoadError>>>,
 }
 
 impl RootLayout {
-    pub fn new(scale_factor: f64) -> Self {
+    pub fn new(scale_factor: f64, waker: Waker) -> Self {
         use taffy::prelude::*;
 
+        let fonts = Rc::new(RefCell::new(Fonts {
+            font_system: FontSystem::new(),
+            swash_cache: SwashCache::new(),
+        }));
+        let status = Rc::new(RefCell::new(Status::default()));
+        let requests = Requests::default();
+
         let mut taffy: TaffyTree<_> = TaffyTree::new();
-        let nav_bar = taffy
+        let editor = taffy
             .new_leaf_with_context(
-                Style {
-                    grid_row: line(1),
-                    grid_column: line(1),
-                    ..Default::default()
-                },
-                Box::new(NavBar) as Box<dyn Interactive>,
+                splits::view_style(1.0),
+                Box::new(Editor::new(
+                    scale_factor,
+                    fonts.clone(),
+                    status.clone(),
+                    waker.clone(),
+                    requests.clone(),
+                )) as Box<dyn Interactive>,
             )
             .unwrap();
-        let editor = taffy
-            .new_leaf_with_context(
+        let editors = taffy
+            .new_with_children(
                 Style {
                     grid_row: line(1),
                     grid_column: line(2),
-                    ..Default::default()
+                    ..splits::split_style(Direction::Right, 1.0)
                 },
-                Box::new(Editor::new(scale_factor)) as Box<dyn Interactive>,
+                &[editor],
             )
             .unwrap();
-        let status_bar = taffy
-            .new_leaf_with_context(
-                Style {
-                    grid_row: line(2),
-                    grid_column: span(2),
-                    ..Default::default()
-                },
-                Box::new(StatusBar) as Box<dyn Interactive>,
-            )
-            .unwrap();
-
         let root = taffy
-            .new_with_children(
-                Style {
-                    display: Display::Grid,
-                    size: Size {
-                        width: percent(100.0_f32),
-                        height: percent(100.0_f32),
-                    },
-                    grid_template_rows: vec![auto(), length(36.0_f32)],
-                    grid_template_columns: vec![length(200.0_f32), auto()],
-                    ..Default::default()
+            .new_leaf(Style {
+                display: Display::Grid,
+                size: Size {
+                    width: percent(100.0_f32),
+                    height: percent(100.0_f32),
                 },
-                &[nav_bar, editor, status_bar],
-            )
+                grid_template_rows: vec![auto(), length(36.0_f32)],
+                grid_template_columns: vec![length(200.0_f32), auto()],
+                ..Default::default()
+            })
             .unwrap();
 
-        Self {
+        let mut layout = Self {
             tree: taffy,
-            nav_bar,
+            root,
             editor,
-            status_bar,
+            focused: editor,
+            requests: requests.clone(),
+            drag: None,
+            resizing: None,
+            panels: PanelSizes::default(),
+            panels_path: None,
+            overlays: Vec::new(),
+            last_search: Rc::default(),
+            fonts: fonts.clone(),
+            waker,
+            config: Config::default(),
+            keymap: Keymap::default(),
+            pending: Vec::new(),
+            quitting: false,
+            config_changes: None,
+        };
+        let nav_bar = NavBar::new(fonts.clone(), requests.clone());
+        layout.add_pane(
+            root,
+            Style {
+                grid_row: line(1),
+                grid_column: line(1),
+                ..Default::default()
+            },
+            Box::new(nav_bar),
+        );
+        layout.tree.add_child(root, editors).unwrap();
+        layout.add_pane(
             root,
-            focused: Section::Editor,
+            Style {
+                grid_row: line(2),
+                grid_column: span(2),
+                ..Default::default()
+            },
+            Box::new(StatusBar::new(fonts, status, requests)),
+        );
+        layout
+    }
+
+    /// Puts `pane` in the layout after whatever else is in `parent`, where it's drawn, configured
+    /// and sent input like the rest, starting with the config everything else has.
+    pub fn add_pane(
+        &mut self,
+        parent: NodeId,
+        style: taffy::Style,
+        mut pane: Box<dyn Interactive>,
+    ) -> NodeId {
+        pane.configure(&self.config);
+        let node = self.tree.new_leaf_with_context(style, pane).unwrap();
+        self.tree.add_child(parent, node).unwrap();
+        node
+    }
+
+    /// Draws `overlay` over everything else until it's closed, sending it all input meanwhile.
+    pub fn open_overlay(&mut self, mut overlay: Box<dyn Overlay>) {
+        overlay.configure(&self.config);
+        self.overlays.push(overlay);
+    }
+
+    /// Closes the overlay that was opened last, if there is one.
+    pub fn close_overlay(&mut self) -> Option<Box<dyn Overlay>> {
+        self.overlays.pop()
+    }
+
+    /// Every pane under `node`, in the order they were put in the tree.
+    fn panes(&self, node: NodeId) -> Vec<NodeId> {
+        if self.tree.get_node_context(node).is_some() {
+            return vec![node];
+        }
+        let children = self.tree.children(node).unwrap();
+        children
+            .into_iter()
+            .flat_map(|child| self.panes(child))
+            .collect()
+    }
+
+    /// The editor view that's pane `node`, if that's what it is.
+    fn view(&self, node: NodeId) -> Option<&Editor<'static>> {
+        let pane: &dyn Any = &**self.tree.get_node_context(node)?;
+        pane.downcast_ref()
+    }
+
+    fn view_mut(&mut self, node: NodeId) -> Option<&mut Editor<'static>> {
+        let pane: &mut dyn Any = &mut **self.tree.get_node_context_mut(node)?;
+        pane.downcast_mut()
+    }
+
+    fn is_view(&self, node: NodeId) -> bool {
+        self.view(node).is_some()
+    }
+
+    /// The editor view requests go to.
+    fn editor_mut(&mut self) -> &mut Editor<'static> {
+        self.view_mut(self.editor)
+            .expect("The last focused view is always an editor")
+    }
+
+    /// The state of the editor view that had the focus last, whether or not it still has it, for
+    /// checking the results of replayed input.
+    pub fn editor_state(&self) -> EditorState {
+        self.view(self.editor)
+            .expect("The last focused view is always an editor")
+            .state()
+    }
+
+    /// Whether the app has been asked to exit, by a key bound to `quit`.
+    pub fn quitting(&self) -> bool {
+        self.quitting
+    }
+
+    /// Opens the file at `path` in the focused editor view, which is done once the config has
+    /// been loaded so that the file's modes are set up with it.
+    pub fn open(&mut self, path: PathBuf) {
+        self.requests.borrow_mut().push(Request::Open(path));
+        self.handle_requests();
+    }
+
+    /// Loads the config at `path` and applies it, then if `watch` is set, applies it again
+    /// whenever it changes. A config with mistakes in it is reported and left out, keeping
+    /// whatever was there before.
+    pub fn load_config(&mut self, path: &Path, watch: bool) {
+        match Config::load(path) {
+            Ok(config) => self.configure(&config),
+            Err(err) => self.config_error(&err),
         }
+        if watch {
+            self.config_changes = Some(Config::watch(path.to_owned(), self.waker.clone()));
+        }
+    }
+
+    fn config_error(&mut self, err: &LoadError) {
+        eprintln!("{}", err.report());
+        self.editor_mut()
+            .handle_request(Request::ShowError(err.to_string()));
     }
 
     pub fn compute_layout(&mut self, width: f32, height: f32) {
@@ -129,7 +398,11 @@ impl RootLayout {
             .unwrap();
     }
 
-    fn get_hovered_node<const CHANGE_FOCUS: bool>(&mut self, input_state: &InputState) -> NodeId {
+    /// The pane under the mouse, which is nothing when it's over a divider between splits.
+    fn get_hovered_node<const CHANGE_FOCUS: bool>(
+        &mut self,
+        input_state: &InputState,
+    ) -> Option<NodeId> {
         let root = self.tree.layout(self.root).unwrap();
 
         let pos_x = input_state.mouse_pos_x.max(0.0).min(root.size.width as f64);
@@ -137,41 +410,156 @@ impl RootLayout {
             .mouse_pos_y
             .max(0.0)
             .min(root.size.height as f64);
-        if self.is_in_rect(self.editor, pos_x, pos_y) {
-            if CHANGE_FOCUS {
-                self.focused = Section::Editor;
-            }
-            self.editor
-        } else if self.is_in_rect(self.nav_bar, pos_x, pos_y) {
-            if CHANGE_FOCUS {
-                self.focused = Section::Editor;
-            }
-            self.nav_bar
-        } else if self.is_in_rect(self.status_bar, pos_x, pos_y) {
-            if CHANGE_FOCUS {
-                self.focused = Section::Editor;
-            }
-            self.status_bar
-        } else {
-            // Curious as to whether this can ever be triggered
-            unreachable!("Inexhaustive mouse location check")
+        let node = self.pane_at(self.root, pos_x, pos_y)?;
+        if CHANGE_FOCUS {
+            self.set_focus(node);
+        }
+        Some(node)
+    }
+
+    /// The pane at the given position under `node`, looking through the children drawn last
+    /// first, since they're the ones on top.
+    fn pane_at(&self, node: NodeId, pos_x: f64, pos_y: f64) -> Option<NodeId> {
+        if self.tree.get_node_context(node).is_some() {
+            return Some(node);
+        }
+        let children = self.tree.children(node).unwrap();
+        let child = children
+            .into_iter()
+            .rev()
+            .find(|&child| self.is_in_rect(child, pos_x, pos_y))?;
+        self.pane_at(child, pos_x, pos_y)
+    }
+
+    /// Sends keys to the pane `node` from now on, telling whatever had the focus that it's lost
+    /// it and `node` that it's got it.
+    fn set_focus(&mut self, node: NodeId) {
+        let before = std::mem::replace(&mut self.focused, node);
+        if self.is_view(node) {
+            self.editor = node;
+        }
+        if node == before {
+            return;
+        }
+        // A view that was just closed isn't there to tell
+        if let Some(before) = self.tree.get_node_context_mut(before) {
+            before.focus_lost();
         }
+        self.tree.get_node_context_mut(node).unwrap().focus_gained();
+    }
+
+    /// Moves the focus on to the next pane, or back to the previous one, in the order they're in
+    /// the tree, and round again.
+    fn cycle_focus(&mut self, forward: bool) {
+        let mut panes = self.panes(self.root);
+        panes.retain(|&pane| !self.is_hidden(pane));
+        let index = (panes.iter())
+            .position(|&pane| pane == self.focused)
+            .unwrap_or(0);
+        let next = match forward {
+            true => (index + 1) % panes.len(),
+            false => (index + panes.len() - 1) % panes.len(),
+        };
+        self.set_focus(panes[next]);
     }
 
-    fn get_focused_node(&mut self) -> NodeId {
-        match self.focused {
-            Section::NavBar => self.nav_bar,
-            Section::Editor => self.editor,
-            Section::StatusBar => self.status_bar,
+    /// Passes on whatever was requested while handling the last event, returning whether there's
+    /// anything new to draw.
+    fn handle_requests(&mut self) -> bool {
+        let requests: Vec<_> = self.requests.borrow_mut().drain(..).collect();
+        let mut redraw = false;
+        for request in requests {
+            redraw |= match request {
+                Request::Split(direction) => self.split(direction),
+                Request::CloseSplit => self.close_split(),
+                Request::Focus(direction) => self.focus(direction),
+                Request::FocusEditor => {
+                    self.set_focus(self.editor);
+                    true
+                }
+                Request::PickFile => {
+                    self.open_overlay(Box::new(FilePicker::new(
+                        working_directory(),
+                        self.fonts.clone(),
+                        self.requests.clone(),
+                        self.waker.clone(),
+                    )));
+                    true
+                }
+                Request::SearchProject(unsaved) => {
+                    self.open_overlay(Box::new(ProjectSearch::new(
+                        working_directory(),
+                        unsaved,
+                        self.last_search.clone(),
+                        self.fonts.clone(),
+                        self.requests.clone(),
+                        self.waker.clone(),
+                    )));
+                    true
+                }
+                Request::CloseOverlay => self.close_overlay().is_some(),
+                Request::NextTheme => {
+                    let names: Vec<_> = Theme::built_in_names().collect();
+                    let current = names
+                        .iter()
+                        .position(|&name| name == self.config.theme.name);
+                    let next = current.map_or(0, |i| (i + 1) % names.len());
+                    let config = Config {
+                        theme: Arc::new(Theme::built_in(names[next]).unwrap()),
+                        ..self.config.clone()
+                    };
+                    self.configure(&config);
+                    true
+                }
+                // Everything else is for the editor, which gets the focus for what's opened in it
+                // and for answering the go to line prompt
+                request => {
+                    if let Request::Open(_) | Request::OpenAt { .. } | Request::GoToLine = request {
+                        self.set_focus(self.editor);
+                    }
+                    self.editor_mut().handle_request(request)
+                }
+            };
         }
+        redraw
+    }
+
+    /// Waits for anything going on in the background that input depends on, like the file picker
+    /// finding files, for replaying input, where there's no event loop to be woken when it's done.
+    pub fn finish_background_work(&mut self) {
+        for overlay in &mut self.overlays {
+            overlay.finish_background_work();
+        }
+    }
+
+    /// Passes a key to the overlay if there is one, or otherwise the focused pane.
+    fn handle_focused_key(&mut self, input_state: &InputState, key: Key<SmolStr>) -> bool {
+        if let Some(overlay) = self.overlay() {
+            let redraw = overlay.handle_keyboard_input(input_state, key);
+            return redraw | self.handle_requests();
+        }
+        let node = self.focused;
+        let input_state = self.map_mouse_pos(input_state, node);
+
+        let redraw = self
+            .tree
+            .get_node_context_mut(node)
+            .unwrap()
+            .handle_keyboard_input(&input_state, key);
+        redraw | self.handle_requests()
+    }
+
+    /// Whatever's drawn over the rest of the layout and taking all input, if anything is.
+    fn overlay(&mut self) -> Option<&mut dyn Overlay> {
+        Some(self.overlays.last_mut()?.as_mut())
     }
 
     fn map_mouse_pos(&self, input_state: &InputState, node: NodeId) -> InputState {
-        let layout = self.tree.layout(node).unwrap();
+        let rect = self.get_rect(node);
 
         InputState {
-            mouse_pos_x: input_state.mouse_pos_x - layout.location.x as f64,
-            mouse_pos_y: input_state.mouse_pos_y - layout.location.y as f64,
+            mouse_pos_x: input_state.mouse_pos_x - rect.x() as f64,
+            mouse_pos_y: input_state.mouse_pos_y - rect.y() as f64,
             ..*input_state
         }
     }
@@ -187,15 +575,24 @@ impl RootLayout {
             && pos_y < node_rect.y() + node_rect.height()
     }
 
+    /// Whether `node` has been squashed down to nothing, like the nav bar when it's collapsed.
+    fn is_hidden(&self, node: NodeId) -> bool {
+        let size = self.tree.layout(node).unwrap().size;
+        size.width == 0.0 || size.height == 0.0
+    }
+
+    /// Where `node` is in the window. Taffy positions nodes relative to their parents.
     fn get_rect(&self, node: NodeId) -> Rect {
         let layout = self.tree.layout(node).unwrap();
-        Rect::from_xywh(
-            layout.location.x,
-            layout.location.y,
-            layout.size.width,
-            layout.size.height,
-        )
-        .unwrap()
+        let (mut x, mut y) = (layout.location.x, layout.location.y);
+        let mut parent = self.tree.parent(node);
+        while let Some(node) = parent {
+            let location = self.tree.layout(node).unwrap().location;
+            x += location.x;
+            y += location.y;
+            parent = self.tree.parent(node);
+        }
+        Rect::from_xywh(x, y, layout.size.width, layout.size.height).unwrap()
     }
 }
 
@@ -206,19 +603,49 @@ impl Interactive for RootLayout {
         button: MouseButton,
         new_state: ElementState,
     ) -> bool {
-        let node = self.get_hovered_node::<true>(input_state);
+        if let Some(overlay) = self.overlay() {
+            let redraw = overlay.handle_mouse_input(input_state, button, new_state);
+            return redraw | self.handle_requests();
+        }
+        if button == MouseButton::Left
+            && (self.drag_panel(input_state, new_state)
+                || self.drag_divider(input_state, new_state))
+        {
+            return true;
+        }
+        // Only pressing changes focus, so releasing over whatever a click focused keeps it
+        let node = match new_state {
+            ElementState::Pressed => self.get_hovered_node::<true>(input_state),
+            ElementState::Released => self.get_hovered_node::<false>(input_state),
+        };
+        let Some(node) = node else {
+            return false;
+        };
         let input_state = self.map_mouse_pos(input_state, node);
 
-        self.tree
+        let redraw = self
+            .tree
             .get_node_context_mut(node)
             .unwrap()
-            .handle_mouse_input(&input_state, button, new_state)
+            .handle_mouse_input(&input_state, button, new_state);
+        redraw | self.handle_requests()
     }
 
     fn handle_cursor_moved(&mut self, input_state: &InputState) -> bool {
+        if let Some(overlay) = self.overlay() {
+            return overlay.handle_cursor_moved(input_state);
+        }
+        if self.resizing.is_some() {
+            return self.resize_panel(input_state);
+        }
+        if self.drag.is_some() {
+            return self.resize_split(input_state);
+        }
         // Not entirely sure about this, should cursor movement events be sent only to the focused node?
-        let node = self.get_hovered_node::<false>(input_state);
-        let input_state = self.map_mouse_pos(&input_state, node);
+        let Some(node) = self.get_hovered_node::<false>(input_state) else {
+            return false;
+        };
+        let input_state = self.map_mouse_pos(input_state, node);
 
         self.tree
             .get_node_context_mut(node)
@@ -227,8 +654,13 @@ impl Interactive for RootLayout {
     }
 
     fn handle_scroll(&mut self, input_state: &InputState, pixel_delta: f32) {
-        let node = self.get_hovered_node::<false>(input_state);
-        let input_state = self.map_mouse_pos(&input_state, node);
+        if let Some(overlay) = self.overlay() {
+            return overlay.handle_scroll(input_state, pixel_delta);
+        }
+        let Some(node) = self.get_hovered_node::<false>(input_state) else {
+            return;
+        };
+        let input_state = self.map_mouse_pos(input_state, node);
 
         self.tree
             .get_node_context_mut(node)
@@ -237,13 +669,89 @@ impl Interactive for RootLayout {
     }
 
     fn handle_keyboard_input(&mut self, input_state: &InputState, key: Key<SmolStr>) -> bool {
-        let node = self.get_focused_node();
-        let input_state = self.map_mouse_pos(&input_state, node);
+        let press = KeyPress::new(key.clone(), input_state.modifier_state);
+        let mut keys = match self.keymap.press(&mut self.pending, (), press) {
+            Press::Command(GlobalCommand::Quit) => {
+                self.quitting = true;
+                return false;
+            }
+            // Overlays keep the focus until they're closed
+            Press::Command(GlobalCommand::FocusNext | GlobalCommand::FocusPrev)
+                if self.overlay().is_some() =>
+            {
+                return false;
+            }
+            Press::Command(GlobalCommand::FocusNext) => {
+                self.cycle_focus(true);
+                return true;
+            }
+            Press::Command(GlobalCommand::FocusPrev) => {
+                self.cycle_focus(false);
+                return true;
+            }
+            Press::Command(GlobalCommand::ToggleNavBar) => {
+                self.toggle_nav_bar();
+                return true;
+            }
+            Press::Pending => return false,
+            Press::Unbound(keys) => keys,
+        };
+        // What the global keymap doesn't want goes to whatever has the focus, including the keys
+        // of a sequence that turned out not to be bound, which were held back until now
+        keys.pop();
+        let mut redraw = false;
+        for earlier in keys {
+            let (key, modifier_state) = earlier.event();
+            let input_state = InputState {
+                modifier_state,
+                ..*input_state
+            };
+            redraw |= self.handle_focused_key(&input_state, key);
+        }
+        redraw | self.handle_focused_key(input_state, key)
+    }
 
-        self.tree
-            .get_node_context_mut(node)
-            .unwrap()
-            .handle_keyboard_input(&input_state, key)
+    fn poll(&mut self) -> bool {
+        let mut redraw = false;
+        let changes: Vec<_> = self
+            .config_changes
+            .iter()
+            .flat_map(Receiver::try_iter)
+            .collect();
+        for change in changes {
+            match change {
+                Ok(config) => self.configure(&config),
+                Err(err) => self.config_error(&err),
+            }
+            redraw = true;
+        }
+        // The editor views share their buffers, and so their language servers, so only the
+        // focused one needs to check on them
+        let mut panes = self.panes(self.root);
+        panes.retain(|&pane| pane == self.editor || !self.is_view(pane));
+        for node in panes {
+            redraw |= self.tree.get_node_context_mut(node).unwrap().poll();
+        }
+        for overlay in &mut self.overlays {
+            redraw |= overlay.poll();
+        }
+        redraw
+    }
+
+    fn configure(&mut self, config: &Config) {
+        for node in self.panes(self.root) {
+            self.tree
+                .get_node_context_mut(node)
+                .unwrap()
+                .configure(config);
+        }
+        for overlay in &mut self.overlays {
+            overlay.configure(config);
+        }
+        self.keymap = Keymap::with(&[((), &config.keys.global)]);
+        self.pending.clear();
+        self.config = config.clone();
+        self.apply_panel_sizes();
     }
 
     fn render<'draw>(
@@ -255,24 +763,39 @@ impl Interactive for RootLayout {
     ) {
         self.compute_layout(rect.width(), rect.height());
 
-        let nav_bar_rect = self.get_rect(self.nav_bar);
-        self.tree
-            .get_node_context_mut(self.nav_bar)
-            .unwrap()
-            .render(pixmap, paint, scale_factor, nav_bar_rect);
-
-        let editor_rect = self.get_rect(self.editor);
-        self.tree.get_node_context_mut(self.editor).unwrap().render(
-            pixmap,
-            paint,
-            scale_factor,
-            editor_rect,
-        );
+        // The dividers are whatever the panes in the nodes holding them leave uncovered
+        let divider = self.config.theme.divider;
+        paint.set_color_rgba8(divider.b(), divider.g(), divider.r(), divider.a());
+        let mut nodes = vec![self.root];
+        while let Some(node) = nodes.pop() {
+            if self.tree.get_node_context(node).is_none() {
+                pixmap.fill_rect(self.get_rect(node), paint, Transform::identity(), None);
+            }
+            nodes.extend(self.tree.children(node).unwrap());
+        }
 
-        let status_bar_rect = self.get_rect(self.status_bar);
-        self.tree
-            .get_node_context_mut(self.status_bar)
-            .unwrap()
-            .render(pixmap, paint, scale_factor, status_bar_rect);
+        // The status bar shows the view requests go to, whatever has the focus
+        self.editor_mut().report_status();
+        let mut panes = self.panes(self.root);
+        // Nothing's drawn of a panel that's been collapsed
+        panes.retain(|&pane| !self.is_hidden(pane));
+        for pane in panes {
+            let pane_rect = self.get_rect(pane);
+            self.tree.get_node_context_mut(pane).unwrap().render(
+                pixmap,
+                paint,
+                scale_factor,
+                pane_rect,
+            );
+        }
+
+        for overlay in &mut self.overlays {
+            overlay.render(pixmap, paint, scale_factor, rect);
+        }
     }
 }
+
+/// Where the file picker and project search look for files.
+fn working_directory() -> PathBuf {
+    env::current_dir().unwrap_or_else(|_| PathBuf::from("."))
+}
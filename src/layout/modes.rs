@@ -0,0 +1,316 @@
+use std::path::Path;
+
+use crate::config::{Config, ModeConfig};
+use crate::document::Document;
+
+use super::keymap::{Command, KeyPress, Keymap, Mode};
+
+/// A point in a buffer's life when its modes run commands.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Hook {
+    /// Once the buffer's file has been opened.
+    Open,
+    /// Before the buffer is saved, when there's anything to save.
+    BeforeSave,
+    /// Once the buffer's major mode has changed or a minor mode has been toggled.
+    ModeChange,
+}
+
+impl Hook {
+    pub const ALL: &[Self] = &[Self::Open, Self::BeforeSave, Self::ModeChange];
+
+    /// What the hook is called in the config.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Open => "open",
+            Self::BeforeSave => "before_save",
+            Self::ModeChange => "mode_change",
+        }
+    }
+}
+
+/// What kind of text a buffer has, which decides how it's edited. Every buffer has exactly one,
+/// picked by its file's extension, or failing that by the interpreter its `#!` line runs.
+#[derive(Debug, PartialEq, Eq)]
+pub struct MajorMode {
+    pub name: &'static str,
+    pub extensions: &'static [&'static str],
+    /// Interpreters named by a `#!` line, like `bash` in `#!/bin/bash` or `#!/usr/bin/env bash`.
+    pub interpreters: &'static [&'static str],
+    /// What indenting by one level inserts.
+    pub indent: &'static str,
+    /// Minor modes that are on in its buffers to begin with, in the order they're turned on.
+    pub minor_modes: &'static [&'static str],
+}
+
+/// Something extra a buffer does on top of its major mode. Any number of them can be on at once,
+/// whether the major mode or the config turns them on or they're toggled by hand.
+#[derive(Debug, PartialEq, Eq)]
+pub struct MinorMode {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// Commands that only run while it's on.
+    pub commands: &'static [Command],
+    /// Keys it binds while it's on, replacing whatever the command had before.
+    pub keys: &'static [(Mode, Command, &'static [&'static str])],
+    pub hooks: &'static [(Hook, Command)],
+}
+
+/// The first one is for anything none of the others are for.
+const MAJOR_MODES: &[MajorMode] = &[
+    MajorMode {
+        name: "text",
+        extensions: &["txt"],
+        interpreters: &[],
+        indent: "\t",
+        minor_modes: &[],
+    },
+    MajorMode {
+        name: "markdown",
+        extensions: &["md", "markdown"],
+        interpreters: &[],
+        indent: "  ",
+        minor_modes: &["auto_indent"],
+    },
+    MajorMode {
+        name: "rust",
+        extensions: &["rs"],
+        interpreters: &[],
+        indent: "    ",
+        minor_modes: &["auto_indent", "trim_whitespace"],
+    },
+    MajorMode {
+        name: "shell",
+        extensions: &["sh", "bash", "zsh"],
+        interpreters: &["sh", "bash", "zsh", "dash"],
+        indent: "    ",
+        minor_modes: &["auto_indent", "trim_whitespace"],
+    },
+    MajorMode {
+        name: "python",
+        extensions: &["py"],
+        interpreters: &["python", "python3"],
+        indent: "    ",
+        minor_modes: &["auto_indent", "trim_whitespace"],
+    },
+    MajorMode {
+        name: "toml",
+        extensions: &["toml"],
+        interpreters: &[],
+        indent: "    ",
+        minor_modes: &["trim_whitespace"],
+    },
+];
+
+const MINOR_MODES: &[MinorMode] = &[
+    MinorMode {
+        name: "auto_indent",
+        description: "new lines start indented like the line they were broken from",
+        commands: &[Command::NewlineAndIndent],
+        keys: &[(Mode::Insert, Command::NewlineAndIndent, &["ret"])],
+        hooks: &[],
+    },
+    MinorMode {
+        name: "trim_whitespace",
+        description: "whitespace at the ends of lines is trimmed before saving",
+        commands: &[],
+        keys: &[],
+        hooks: &[(Hook::BeforeSave, Command::TrimWhitespace)],
+    },
+];
+
+impl MajorMode {
+    pub fn all() -> &'static [Self] {
+        MAJOR_MODES
+    }
+
+    pub fn named(name: &str) -> Option<&'static Self> {
+        MAJOR_MODES.iter().find(|mode| mode.name == name)
+    }
+
+    /// The mode for `document`'s extension, or the interpreter in its `#!` line.
+    pub fn detect(document: &Document) -> &'static Self {
+        let extension = document
+            .path()
+            .and_then(Path::extension)
+            .and_then(|extension| extension.to_str());
+        let by_extension = extension.and_then(|extension| {
+            MAJOR_MODES
+                .iter()
+                .find(|mode| mode.extensions.contains(&extension))
+        });
+        let by_interpreter = || {
+            let line = document.line(0).to_string();
+            let interpreter = interpreter(&line)?;
+            MAJOR_MODES
+                .iter()
+                .find(|mode| mode.interpreters.contains(&interpreter))
+        };
+        by_extension
+            .or_else(by_interpreter)
+            .unwrap_or(&MAJOR_MODES[0])
+    }
+}
+
+/// The program a `#!` line runs, looking past `env` to the one it runs.
+fn interpreter(line: &str) -> Option<&str> {
+    let mut words = line.strip_prefix("#!")?.split_whitespace();
+    let program = Path::new(words.next()?).file_name()?.to_str()?;
+    match program {
+        "env" => words.find(|word| !word.starts_with('-') && !word.contains('=')),
+        program => Some(program),
+    }
+}
+
+impl MinorMode {
+    pub fn all() -> &'static [Self] {
+        MINOR_MODES
+    }
+
+    pub fn named(name: &str) -> Option<&'static Self> {
+        MINOR_MODES.iter().find(|mode| mode.name == name)
+    }
+}
+
+/// The modes chosen for a buffer itself, on top of what its major mode and the config turn on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BufferModes {
+    pub major: &'static MajorMode,
+    /// Minor modes toggled by hand, in order, each flipping whether that mode is on.
+    toggled: Vec<&'static MinorMode>,
+}
+
+impl BufferModes {
+    pub fn detect(document: &Document) -> Self {
+        Self {
+            major: MajorMode::detect(document),
+            toggled: Vec::new(),
+        }
+    }
+
+    pub fn toggle(&mut self, minor: &'static MinorMode) {
+        match self.toggled.iter().position(|&toggled| toggled == minor) {
+            Some(index) => {
+                self.toggled.remove(index);
+            }
+            None => self.toggled.push(minor),
+        }
+    }
+}
+
+/// Settings a buffer takes from its modes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Settings {
+    /// What indenting by one level inserts.
+    pub indent: String,
+}
+
+/// What a buffer's modes come to together. Each mode takes precedence over what comes before it:
+/// the major mode over the default keys, the plugins' keys and the config's `keys`, and each minor
+/// mode over the major mode and the minor modes turned on before it. What the config says about a
+/// mode applies straight after the mode's own keys, settings and hooks, and so takes precedence
+/// over them.
+pub struct ActiveModes {
+    /// What they were worked out from, to tell when that's changed.
+    pub buffer: BufferModes,
+    pub minor: Vec<&'static MinorMode>,
+    pub keymap: Keymap<Mode, Command>,
+    pub settings: Settings,
+    /// Every mode's hooks, in the order they run.
+    hooks: Vec<(Hook, Command)>,
+}
+
+impl ActiveModes {
+    pub fn new(buffer: &BufferModes, config: &Config) -> Self {
+        let major = buffer.major;
+        let configured = |name: &str| config.modes.iter().find(|mode| mode.name == name);
+
+        let mut minor: Vec<&'static MinorMode> = Vec::new();
+        let built_in = major.minor_modes.iter().map(|name| {
+            MinorMode::named(name).expect("Major modes only turn on minor modes that exist")
+        });
+        let from_config = configured(major.name)
+            .into_iter()
+            .flat_map(|mode| mode.minor_modes.iter().copied());
+        for mode in built_in.chain(from_config) {
+            if !minor.contains(&mode) {
+                minor.push(mode);
+            }
+        }
+        for &mode in &buffer.toggled {
+            match minor.iter().position(|&on| on == mode) {
+                Some(index) => {
+                    minor.remove(index);
+                }
+                None => minor.push(mode),
+            }
+        }
+
+        // Plugins bind keys before the config does, so that the config has the last word
+        let mut keymap = Keymap::default();
+        for (mode, command, sequences) in config.plugins.keys() {
+            keymap.rebind(*mode, *command, sequences);
+        }
+        let keys = &config.keys;
+        keymap.rebind_all(Mode::Normal, &keys.normal);
+        keymap.rebind_all(Mode::Insert, &keys.insert);
+        keymap.rebind_all(Mode::Select, &keys.select);
+        let mut this = Self {
+            buffer: buffer.clone(),
+            minor: Vec::new(),
+            keymap,
+            settings: Settings {
+                indent: major.indent.to_owned(),
+            },
+            hooks: Vec::new(),
+        };
+        if let Some(mode) = configured(major.name) {
+            this.apply(mode);
+        }
+        for &mode in &minor {
+            for (mode, command, sequences) in mode.keys {
+                let sequences: Vec<_> = (sequences.iter())
+                    .map(|keys| KeyPress::parse_sequence(keys).expect("Invalid mode key binding"))
+                    .collect();
+                this.keymap.rebind(*mode, *command, &sequences);
+            }
+            this.hooks.extend(mode.hooks);
+            if let Some(mode) = configured(mode.name) {
+                this.apply(mode);
+            }
+        }
+        this.minor = minor;
+        this
+    }
+
+    fn apply(&mut self, config: &ModeConfig) {
+        self.keymap.rebind_all(Mode::Normal, &config.keys.normal);
+        self.keymap.rebind_all(Mode::Insert, &config.keys.insert);
+        self.keymap.rebind_all(Mode::Select, &config.keys.select);
+        if let Some(indent) = &config.indent {
+            self.settings.indent.clone_from(indent);
+        }
+        for (hook, commands) in &config.hooks {
+            (self.hooks).extend(commands.iter().map(|&command| (*hook, command)));
+        }
+    }
+
+    pub fn major(&self) -> &'static MajorMode {
+        self.buffer.major
+    }
+
+    /// The minor mode `command` needs to be on to run, if it isn't already.
+    pub fn needed_for(&self, command: Command) -> Option<&'static MinorMode> {
+        MINOR_MODES
+            .iter()
+            .find(|mode| mode.commands.contains(&command) && !self.minor.contains(mode))
+    }
+
+    /// The commands to run at `hook`, in order.
+    pub fn hooks(&self, hook: Hook) -> Vec<Command> {
+        (self.hooks.iter())
+            .filter(|(bound, _)| *bound == hook)
+            .map(|&(_, command)| command)
+            .collect()
+    }
+}
@@ -0,0 +1,446 @@
+mod tree;
+
+use std::cell::RefCell;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::Arc;
+
+use cosmic_text::{Attrs, Buffer, Color as CTColor, Family, Metrics, Shaping};
+use tiny_skia::{Paint, PixmapMut, PixmapPaint, Rect, Transform};
+use winit::{
+    event::{ElementState, MouseButton},
+    keyboard::{Key, NamedKey, SmolStr},
+};
+
+use crate::config::{Config, Theme};
+use crate::InputState;
+
+use super::editor::render::EditorRenderer;
+use super::keymap::{KeyPress, Keymap, NavCommand, Press};
+use super::{Fonts, Interactive, Request, Requests};
+use tree::{EntryKind, FileTree};
+
+/// The files in the working directory, as a tree that can be browsed with the keyboard or mouse.
+/// `j`/`k` move up and down, `h`/`l` go out of and into directories, Enter (or a click) opens
+/// the file or toggles the directory, and `a`, `r` and `d` create, rename and delete. `R` reads
+/// the files again after they've changed on disk. Those are the default keys, and the config can
+/// bind others.
+pub struct NavBar {
+    fonts: Rc<RefCell<Fonts>>,
+    requests: Requests,
+    metrics: Metrics,
+    theme: Arc<Theme>,
+    keymap: Keymap<(), NavCommand>,
+    /// The keys typed so far of a sequence bound in the keymap.
+    pending: Vec<KeyPress>,
+    tree: FileTree,
+    selected: usize,
+    /// The first entry shown.
+    scroll: usize,
+    /// How many entries and how tall each one was when last drawn, for mapping the mouse to them.
+    rows: usize,
+    row_height: f32,
+    prompt: Option<Prompt>,
+    /// An error shown along the bottom until the next key press.
+    error: Option<String>,
+    /// Whether keys come here, which is shown by marking the selected entry.
+    focused: bool,
+}
+
+/// A question asked along the bottom of the tree before changing anything on disk.
+struct Prompt {
+    label: String,
+    input: String,
+    action: PromptAction,
+}
+
+enum PromptAction {
+    /// Creates the path typed, relative to this directory, as a directory if it ends in a slash.
+    Create(PathBuf),
+    /// Moves this to the path typed, relative to the root.
+    Rename(PathBuf),
+    /// Deletes the entry at this index if the answer is yes.
+    Delete(usize),
+}
+
+impl NavBar {
+    pub fn new(fonts: Rc<RefCell<Fonts>>, requests: Requests) -> Self {
+        let root = env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+        Self {
+            fonts,
+            requests,
+            metrics: Metrics::new(18.0, 28.0),
+            theme: Arc::default(),
+            keymap: Keymap::default(),
+            pending: Vec::new(),
+            tree: FileTree::new(root),
+            selected: 0,
+            scroll: 0,
+            rows: 0,
+            row_height: 28.0,
+            prompt: None,
+            error: None,
+            focused: false,
+        }
+    }
+
+    fn run(&mut self, command: NavCommand) {
+        use NavCommand::*;
+
+        match command {
+            Up => self.select(self.selected.saturating_sub(1)),
+            Down => self.select(self.selected + 1),
+            First => self.select(0),
+            Last => self.select(usize::MAX),
+            Out => self.go_out(),
+            In => self.go_in(),
+            Activate => self.activate(),
+            Create => self.open_prompt(PromptAction::Create(self.target_dir())),
+            Rename => {
+                if let Some(entry) = self.tree.entries().get(self.selected) {
+                    self.open_prompt(PromptAction::Rename(entry.path.clone()));
+                }
+            }
+            Delete => {
+                if self.selected < self.tree.entries().len() {
+                    self.open_prompt(PromptAction::Delete(self.selected));
+                }
+            }
+            Refresh => {
+                self.tree.refresh();
+                self.select(self.selected);
+            }
+            Leave => self.requests.borrow_mut().push(Request::FocusEditor),
+        }
+    }
+
+    fn select(&mut self, index: usize) {
+        self.selected = index.min(self.tree.entries().len().saturating_sub(1));
+    }
+
+    /// Opens the selected file, or expands or collapses the selected directory.
+    fn activate(&mut self) {
+        let Some(entry) = self.tree.entries().get(self.selected) else {
+            return;
+        };
+        match entry.kind {
+            EntryKind::File => {
+                let path = entry.path.clone();
+                self.requests.borrow_mut().push(Request::Open(path));
+            }
+            EntryKind::Directory { .. } => self.tree.toggle(self.selected),
+        }
+    }
+
+    /// Collapses the selected directory, or failing that goes up to the one containing it.
+    fn go_out(&mut self) {
+        match self
+            .tree
+            .entries()
+            .get(self.selected)
+            .map(|entry| entry.kind)
+        {
+            Some(EntryKind::Directory { expanded: true }) => self.tree.collapse(self.selected),
+            Some(_) => {
+                if let Some(parent) = self.tree.parent(self.selected) {
+                    self.selected = parent;
+                }
+            }
+            None => (),
+        }
+    }
+
+    /// Expands the selected directory, or goes into it if it already is.
+    fn go_in(&mut self) {
+        match self
+            .tree
+            .entries()
+            .get(self.selected)
+            .map(|entry| entry.kind)
+        {
+            Some(EntryKind::Directory { expanded: false }) => self.tree.expand(self.selected),
+            Some(EntryKind::Directory { expanded: true }) => self.select(self.selected + 1),
+            _ => (),
+        }
+    }
+
+    /// The directory new files go in: the selected directory, or the one containing the selected
+    /// file.
+    fn target_dir(&self) -> PathBuf {
+        match self.tree.entries().get(self.selected) {
+            Some(entry) if matches!(entry.kind, EntryKind::Directory { .. }) => entry.path.clone(),
+            Some(entry) => entry.path.parent().unwrap_or(self.tree.root()).to_owned(),
+            None => self.tree.root().to_owned(),
+        }
+    }
+
+    fn relative<'a>(&self, path: &'a Path) -> &'a Path {
+        path.strip_prefix(self.tree.root()).unwrap_or(path)
+    }
+
+    fn open_prompt(&mut self, action: PromptAction) {
+        let (label, input) = match &action {
+            PromptAction::Create(dir) => {
+                let dir = self.relative(dir).display().to_string();
+                let label = if dir.is_empty() {
+                    "new: ".to_owned()
+                } else {
+                    format!("new in {dir}/: ")
+                };
+                (label, String::new())
+            }
+            PromptAction::Rename(path) => (
+                "rename to: ".to_owned(),
+                self.relative(path).display().to_string(),
+            ),
+            PromptAction::Delete(index) => (
+                format!("delete {}? (y/n) ", self.tree.entries()[*index].name),
+                String::new(),
+            ),
+        };
+        self.prompt = Some(Prompt {
+            label,
+            input,
+            action,
+        });
+    }
+
+    fn handle_prompt_key(&mut self, input_state: &InputState, key: Key<SmolStr>) {
+        let Some(mut prompt) = self.prompt.take() else {
+            return;
+        };
+        if let PromptAction::Delete(index) = prompt.action {
+            // Anything but yes is no
+            if key == Key::Character("y".into()) {
+                let result = self.tree.delete(index);
+                self.select(index);
+                self.report(result);
+            }
+            return;
+        }
+        match key {
+            Key::Named(NamedKey::Escape) => return,
+            Key::Named(NamedKey::Enter) => return self.submit_prompt(prompt),
+            Key::Named(NamedKey::Backspace) => {
+                prompt.input.pop();
+            }
+            key => match key.to_text() {
+                Some(text) if !input_state.modifier_state.control_key() => {
+                    prompt.input.push_str(text)
+                }
+                _ => (),
+            },
+        }
+        self.prompt = Some(prompt);
+    }
+
+    fn submit_prompt(&mut self, prompt: Prompt) {
+        let input = prompt.input.trim();
+        if input.is_empty() {
+            return;
+        }
+        let (result, path) = match prompt.action {
+            PromptAction::Create(dir) => {
+                let path = dir.join(input);
+                (self.tree.create(&path, input.ends_with('/')), path)
+            }
+            PromptAction::Rename(from) => {
+                let to = self.tree.root().join(input);
+                let result = self.tree.rename(&from, &to);
+                if result.is_ok() {
+                    let moved = Request::Moved {
+                        from,
+                        to: to.clone(),
+                    };
+                    self.requests.borrow_mut().push(moved);
+                }
+                (result, to)
+            }
+            PromptAction::Delete(_) => unreachable!("Deleting is confirmed with a single key"),
+        };
+        if result.is_ok() {
+            if let Some(index) = self.tree.reveal(&path) {
+                self.selected = index;
+            }
+        }
+        self.report(result);
+    }
+
+    fn report(&mut self, result: std::io::Result<()>) {
+        if let Err(err) = result {
+            self.error = Some(err.to_string());
+        }
+    }
+}
+
+impl Interactive for NavBar {
+    fn handle_mouse_input(
+        &mut self,
+        input_state: &InputState,
+        button: MouseButton,
+        new_state: ElementState,
+    ) -> bool {
+        if button != MouseButton::Left || new_state != ElementState::Pressed {
+            return false;
+        }
+        self.prompt = None;
+        self.error = None;
+        let row = (input_state.mouse_pos_y as f32 / self.row_height) as usize;
+        let index = self.scroll + row;
+        if row < self.rows && index < self.tree.entries().len() {
+            self.selected = index;
+            self.activate();
+        }
+        true
+    }
+
+    fn handle_cursor_moved(&mut self, _input_state: &InputState) -> bool {
+        false
+    }
+
+    fn handle_scroll(&mut self, _input_state: &InputState, pixel_delta: f32) {
+        let rows = (-pixel_delta / self.row_height).round() as isize;
+        let max = self.tree.entries().len().saturating_sub(self.rows);
+        self.scroll = self.scroll.saturating_add_signed(rows).min(max);
+    }
+
+    fn handle_keyboard_input(&mut self, input_state: &InputState, key: Key<SmolStr>) -> bool {
+        self.error = None;
+        if self.prompt.is_some() {
+            self.handle_prompt_key(input_state, key);
+            return true;
+        }
+        let press = KeyPress::new(key, input_state.modifier_state);
+        match self.keymap.press(&mut self.pending, (), press) {
+            Press::Command(command) => self.run(command),
+            Press::Pending => (),
+            // Only worth redrawing to stop showing the keys of a sequence that went nowhere
+            Press::Unbound(keys) => return keys.len() > 1,
+        }
+        true
+    }
+
+    fn configure(&mut self, config: &Config) {
+        self.theme = config.theme.clone();
+        self.keymap = Keymap::with(&[((), &config.keys.nav_bar)]);
+        self.pending.clear();
+        let config = &config.nav_bar;
+        self.metrics = Metrics::new(config.font_size, config.line_height);
+    }
+
+    fn focus_gained(&mut self) {
+        self.focused = true;
+    }
+
+    fn focus_lost(&mut self) {
+        self.focused = false;
+        // A sequence started here shouldn't carry on wherever the focus went
+        self.pending.clear();
+    }
+
+    fn render<'draw>(
+        &mut self,
+        pixmap: &mut PixmapMut<'draw>,
+        paint: &mut Paint<'draw>,
+        scale_factor: f64,
+        rect: Rect,
+    ) {
+        let theme = self.theme.clone();
+        let background = theme.panel;
+        paint.set_color_rgba8(
+            background.b(),
+            background.g(),
+            background.r(),
+            background.a(),
+        );
+        pixmap.fill_rect(rect, paint, Transform::identity(), None);
+
+        let metrics = self.metrics.scale(scale_factor as f32);
+        let bottom_line = match (&self.prompt, &self.error) {
+            (Some(prompt), _) => Some((
+                format!("{}{}", prompt.label, prompt.input),
+                theme.foreground,
+            )),
+            (None, Some(error)) => Some((error.clone(), theme.error)),
+            (None, None) if !self.pending.is_empty() => {
+                Some((KeyPress::describe_sequence(&self.pending), theme.dimmed))
+            }
+            (None, None) => None,
+        };
+        self.row_height = metrics.line_height;
+        self.rows = (rect.height() / metrics.line_height) as usize - bottom_line.is_some() as usize;
+
+        // Keep the selected entry in view
+        if self.selected < self.scroll {
+            self.scroll = self.selected;
+        } else if self.selected >= self.scroll + self.rows {
+            self.scroll = self.selected + 1 - self.rows.max(1);
+        }
+
+        let attrs = Attrs::new().family(Family::Monospace);
+        let visible = self.tree.entries().iter().skip(self.scroll).take(self.rows);
+        let lines: Vec<_> = visible
+            .map(|entry| {
+                let (marker, colour) = match entry.kind {
+                    EntryKind::File => ("  ", theme.foreground),
+                    EntryKind::Directory { expanded: false } => ("▸ ", theme.directory),
+                    EntryKind::Directory { expanded: true } => ("▾ ", theme.directory),
+                };
+                let text = format!("{}{marker}{}\n", "  ".repeat(entry.depth), entry.name);
+                (text, colour)
+            })
+            .collect();
+        let spans = lines
+            .iter()
+            .map(|(text, colour)| (text.as_str(), attrs.clone().color(*colour)));
+
+        let mut fonts = self.fonts.borrow_mut();
+        let fonts = &mut *fonts;
+        let padding = metrics.font_size / 2.0;
+        let mut buffer = Buffer::new(&mut fonts.font_system, metrics);
+        buffer.set_rich_text(spans, &attrs, Shaping::Advanced, None);
+        buffer.set_size(None, Some(rect.height()));
+        buffer.shape_until_scroll(&mut fonts.font_system, false);
+
+        // Red and blue are swapped, like everything else drawn for softbuffer
+        let mut fill = |colour: CTColor, y: f32, width: f32| {
+            paint.set_color_rgba8(colour.b(), colour.g(), colour.r(), colour.a());
+            let row = Rect::from_xywh(rect.x(), y, width, metrics.line_height).unwrap();
+            pixmap.fill_rect(row, paint, Transform::identity(), None);
+        };
+        if let Some(row) = self.selected.checked_sub(self.scroll) {
+            let y = rect.y() + row as f32 * metrics.line_height;
+            fill(theme.current_item, y, rect.width());
+            // With a solid cursor-coloured edge while it has the focus
+            if self.focused {
+                let cursor = theme.cursor;
+                let cursor = CTColor::rgba(cursor.r(), cursor.g(), cursor.b(), 255);
+                fill(cursor, y, padding / 2.0);
+            }
+        }
+        let bottom_top = rect.y() + rect.height() - metrics.line_height;
+        let bottom_buffer = bottom_line.map(|(text, colour)| {
+            fill(theme.message, bottom_top, rect.width());
+            let mut buffer = Buffer::new(&mut fonts.font_system, metrics);
+            buffer.set_text(&text, &attrs, Shaping::Advanced, None);
+            buffer.set_size(Some(rect.width() - padding), Some(metrics.line_height));
+            buffer.shape_until_scroll(&mut fonts.font_system, false);
+            (buffer, colour)
+        });
+
+        let mut renderer = EditorRenderer {
+            swash_cache: &mut fonts.swash_cache,
+            font_system: &mut fonts.font_system,
+            pixmap,
+            paint,
+            pixmap_paint: &PixmapPaint::default(),
+            transform: Transform::from_translate(rect.x() + padding, rect.y()),
+        };
+        renderer.draw_buffer(&buffer, theme.foreground);
+        if let Some((buffer, colour)) = bottom_buffer {
+            renderer.transform = Transform::from_translate(rect.x() + padding, bottom_top);
+            renderer.draw_buffer(&buffer, colour);
+        }
+    }
+}
@@ -0,0 +1,199 @@
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use ignore::WalkBuilder;
+
+/// The files under a directory, as a flat list of whatever is currently visible. Directories are
+/// only read when they're expanded.
+pub struct FileTree {
+    root: PathBuf,
+    entries: Vec<Entry>,
+}
+
+pub struct Entry {
+    pub path: PathBuf,
+    pub name: String,
+    /// How many directories down from the root this is, the root's own children being 0.
+    pub depth: usize,
+    pub kind: EntryKind,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Directory { expanded: bool },
+}
+
+impl FileTree {
+    pub fn new(root: PathBuf) -> Self {
+        let mut tree = Self {
+            root,
+            entries: Vec::new(),
+        };
+        tree.refresh();
+        tree
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// Expands a collapsed directory or collapses an expanded one.
+    pub fn toggle(&mut self, index: usize) {
+        match self.entries[index].kind {
+            EntryKind::Directory { expanded: false } => self.expand(index),
+            EntryKind::Directory { expanded: true } => self.collapse(index),
+            EntryKind::File => (),
+        }
+    }
+
+    pub fn expand(&mut self, index: usize) {
+        let entry = &self.entries[index];
+        if entry.kind != (EntryKind::Directory { expanded: false }) {
+            return;
+        }
+        let children = read_dir(&entry.path, entry.depth + 1);
+        self.entries[index].kind = EntryKind::Directory { expanded: true };
+        self.entries.splice(index + 1..index + 1, children);
+    }
+
+    pub fn collapse(&mut self, index: usize) {
+        let entry = &self.entries[index];
+        if entry.kind != (EntryKind::Directory { expanded: true }) {
+            return;
+        }
+        let end = self.subtree_end(index);
+        self.entries[index].kind = EntryKind::Directory { expanded: false };
+        self.entries.drain(index + 1..end);
+    }
+
+    /// The index of the directory containing an entry, unless that's the root.
+    pub fn parent(&self, index: usize) -> Option<usize> {
+        let depth = self.entries[index].depth;
+        self.entries[..index]
+            .iter()
+            .rposition(|entry| entry.depth < depth)
+    }
+
+    /// Where the entries inside the directory at `index` end.
+    fn subtree_end(&self, index: usize) -> usize {
+        let depth = self.entries[index].depth;
+        self.entries[index + 1..]
+            .iter()
+            .position(|entry| entry.depth <= depth)
+            .map_or(self.entries.len(), |end| index + 1 + end)
+    }
+
+    /// Reads everything again, keeping the same directories expanded, after files have been
+    /// changed on disk.
+    pub fn refresh(&mut self) {
+        let expanded: HashSet<_> = self
+            .entries
+            .drain(..)
+            .filter(|entry| entry.kind == EntryKind::Directory { expanded: true })
+            .map(|entry| entry.path)
+            .collect();
+        self.entries = read_dir(&self.root, 0);
+        let mut i = 0;
+        while i < self.entries.len() {
+            if expanded.contains(&self.entries[i].path) {
+                self.expand(i);
+            }
+            i += 1;
+        }
+    }
+
+    /// The index of the entry for `path`, expanding the directories on the way to it.
+    pub fn reveal(&mut self, path: &Path) -> Option<usize> {
+        let mut i = 0;
+        while i < self.entries.len() {
+            let entry = &self.entries[i];
+            if entry.path == path {
+                return Some(i);
+            }
+            if path.starts_with(&entry.path) {
+                self.expand(i);
+            }
+            i += 1;
+        }
+        None
+    }
+
+    /// Creates an empty file at `path`, or a directory if `directory`.
+    pub fn create(&mut self, path: &Path, directory: bool) -> io::Result<()> {
+        if directory {
+            fs::create_dir_all(path)?;
+        } else {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::File::create_new(path)?;
+        }
+        self.refresh();
+        Ok(())
+    }
+
+    /// Moves `from` to `to`, refusing to replace anything already there.
+    pub fn rename(&mut self, from: &Path, to: &Path) -> io::Result<()> {
+        if to.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already exists", to.display()),
+            ));
+        }
+        fs::rename(from, to)?;
+        self.refresh();
+        Ok(())
+    }
+
+    /// Deletes the entry at `index`, along with everything in it if it's a directory.
+    pub fn delete(&mut self, index: usize) -> io::Result<()> {
+        let entry = &self.entries[index];
+        match entry.kind {
+            EntryKind::File => fs::remove_file(&entry.path)?,
+            EntryKind::Directory { .. } => fs::remove_dir_all(&entry.path)?,
+        }
+        self.refresh();
+        Ok(())
+    }
+}
+
+/// The entries directly inside `dir`, leaving out whatever git would ignore, with directories
+/// first and each group sorted by name.
+fn read_dir(dir: &Path, depth: usize) -> Vec<Entry> {
+    let walk = WalkBuilder::new(dir)
+        .max_depth(Some(1))
+        .hidden(false)
+        .filter_entry(|entry| entry.file_name() != ".git")
+        .build();
+    let mut entries: Vec<_> = walk
+        .filter_map(Result::ok)
+        .filter(|entry| entry.depth() == 1)
+        .map(|entry| {
+            let is_dir = entry.file_type().is_some_and(|kind| kind.is_dir());
+            Entry {
+                name: entry.file_name().to_string_lossy().into_owned(),
+                path: entry.into_path(),
+                depth,
+                kind: if is_dir {
+                    EntryKind::Directory { expanded: false }
+                } else {
+                    EntryKind::File
+                },
+            }
+        })
+        .collect();
+    entries.sort_by(|a, b| match (a.kind, b.kind) {
+        (EntryKind::Directory { .. }, EntryKind::File) => Ordering::Less,
+        (EntryKind::File, EntryKind::Directory { .. }) => Ordering::Greater,
+        _ => a.name.to_lowercase().cmp(&b.name.to_lowercase()),
+    });
+    entries
+}
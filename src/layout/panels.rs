@@ -0,0 +1,135 @@
+use std::path::PathBuf;
+
+use taffy::prelude::{auto, length};
+use winit::event::ElementState;
+
+use crate::config::PanelSizes;
+use crate::InputState;
+
+use super::splits::MIN_SIZE;
+use super::RootLayout;
+
+/// How far either side of a panel's edge it can be grabbed from.
+const GRAB_DISTANCE: f32 = 3.0;
+/// How short dragging the status bar can make it.
+const MIN_STATUS_BAR_HEIGHT: f32 = 24.0;
+
+/// A panel whose edge with the editor views is being dragged.
+#[derive(Clone, Copy)]
+pub enum Panel {
+    NavBar,
+    StatusBar,
+}
+
+impl RootLayout {
+    /// Sizes the panels as they were last dragged to according to the file at `path`, and writes
+    /// them there whenever they're dragged again.
+    pub fn remember_panels(&mut self, path: PathBuf) {
+        match PanelSizes::load(&path) {
+            Ok(sizes) => self.panels = sizes,
+            Err(err) => eprintln!("{}", err.report()),
+        }
+        self.panels_path = Some(path);
+        self.apply_panel_sizes();
+    }
+
+    /// Lays the panels out at the sizes they were dragged to, or the config's for any that
+    /// haven't been.
+    pub(super) fn apply_panel_sizes(&mut self) {
+        let nav_bar = &self.panels.nav_bar;
+        let width = match nav_bar.collapsed {
+            true => 0.0,
+            false => nav_bar.width.unwrap_or(self.config.nav_bar.width),
+        };
+        let height = (self.panels.status_bar.height).unwrap_or(self.config.status_bar.height);
+        let mut style = self.tree.style(self.root).unwrap().clone();
+        style.grid_template_rows = vec![auto(), length(height)];
+        style.grid_template_columns = vec![length(width), auto()];
+        self.tree.set_style(self.root, style).unwrap();
+
+        // Laid out again straight away to find out whether the focused pane was just hidden
+        let size = self.tree.layout(self.root).unwrap().size;
+        self.compute_layout(size.width, size.height);
+        if self.panels.nav_bar.collapsed && self.is_hidden(self.focused) {
+            self.set_focus(self.editor);
+        }
+    }
+
+    /// Hides the nav bar, or shows it again as wide as it was.
+    pub(super) fn toggle_nav_bar(&mut self) {
+        self.panels.nav_bar.collapsed = !self.panels.nav_bar.collapsed;
+        self.apply_panel_sizes();
+        self.save_panel_sizes();
+    }
+
+    /// Starts dragging the edge of the panel under the mouse when it's pressed, or stops dragging
+    /// and keeps the new size when it's released, returning whether either happened.
+    pub(super) fn drag_panel(&mut self, input_state: &InputState, state: ElementState) -> bool {
+        match state {
+            ElementState::Pressed => {
+                let x = input_state.mouse_pos_x as f32;
+                let y = input_state.mouse_pos_y as f32;
+                self.resizing = self.panel_edge_at(x, y);
+                self.resizing.is_some()
+            }
+            ElementState::Released => {
+                if self.resizing.take().is_none() {
+                    return false;
+                }
+                self.save_panel_sizes();
+                true
+            }
+        }
+    }
+
+    /// The panel whose edge with the editor views is at the given position, which is still there
+    /// to be grabbed at the side of the window when the nav bar is collapsed.
+    fn panel_edge_at(&self, x: f32, y: f32) -> Option<Panel> {
+        let editors = self.get_rect(self.top_split());
+        if (y - editors.bottom()).abs() <= GRAB_DISTANCE {
+            Some(Panel::StatusBar)
+        } else if (x - editors.left()).abs() <= GRAB_DISTANCE && y < editors.bottom() {
+            Some(Panel::NavBar)
+        } else {
+            None
+        }
+    }
+
+    /// Moves the edge being dragged to the mouse, as far as the panel and the editor views can
+    /// shrink. Dragging the nav bar to under half as wide as it can be collapses it, and dragging
+    /// it back out opens it up again.
+    pub(super) fn resize_panel(&mut self, input_state: &InputState) -> bool {
+        let Some(panel) = self.resizing else {
+            return false;
+        };
+        let root = self.tree.layout(self.root).unwrap().size;
+        match panel {
+            Panel::NavBar => {
+                let width = input_state.mouse_pos_x as f32;
+                let nav_bar = &mut self.panels.nav_bar;
+                nav_bar.collapsed = width < MIN_SIZE / 2.0;
+                if !nav_bar.collapsed {
+                    let max = (root.width - MIN_SIZE).max(MIN_SIZE);
+                    nav_bar.width = Some(width.clamp(MIN_SIZE, max));
+                }
+            }
+            Panel::StatusBar => {
+                let height = root.height - input_state.mouse_pos_y as f32;
+                let max = (root.height - MIN_SIZE).max(MIN_STATUS_BAR_HEIGHT);
+                self.panels.status_bar.height = Some(height.clamp(MIN_STATUS_BAR_HEIGHT, max));
+            }
+        }
+        self.apply_panel_sizes();
+        true
+    }
+
+    /// Writes the panels' sizes out for next time, if they're being remembered.
+    fn save_panel_sizes(&self) {
+        let Some(path) = &self.panels_path else {
+            return;
+        };
+        if let Err(err) = self.panels.save(path) {
+            eprintln!("Could not save panel sizes to {}: {err}", path.display());
+        }
+    }
+}
@@ -0,0 +1,488 @@
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::fs::File;
+use std::io::Read;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+
+use cosmic_text::{Attrs, Buffer, Color as CTColor, Family, Metrics, Shaping, Wrap};
+use ignore::WalkBuilder;
+use tiny_skia::{Paint, PixmapMut, PixmapPaint, Rect, Transform};
+use winit::{
+    event::{ElementState, MouseButton},
+    keyboard::{Key, NamedKey, SmolStr},
+};
+
+use crate::config::{Config, Theme};
+use crate::fuzzy::{self, Match};
+use crate::{InputState, Waker};
+
+use super::editor::render::EditorRenderer;
+use super::{Fonts, Interactive, Overlay, Request, Requests};
+
+/// How many files are found before they're sent over to be shown, so the list fills in as the
+/// walk goes without waking the UI for every file.
+const BATCH_SIZE: usize = 256;
+/// How much of the selected file is read for the preview.
+const PREVIEW_BYTES: u64 = 16 * 1024;
+
+/// Every file in the working directory, leaving out whatever git would ignore, narrowed down by
+/// fuzzily matching what's typed against their paths. The files are found on another thread, and
+/// the selected one is previewed alongside the list. It's drawn over the rest of the layout, which
+/// it takes all input from until it's closed.
+pub struct FilePicker {
+    fonts: Rc<RefCell<Fonts>>,
+    requests: Requests,
+    metrics: Metrics,
+    theme: Arc<Theme>,
+    root: PathBuf,
+    /// Relative to `root`, in the order they were found.
+    files: Vec<String>,
+    /// Where more files come from, until they've all been found.
+    walk: Option<Receiver<Vec<String>>>,
+    query: String,
+    /// Indices into `files`, best first and then in order of their paths.
+    matches: Vec<Match<usize>>,
+    /// Index into `matches`.
+    selected: usize,
+    /// The first match shown.
+    scroll: usize,
+    /// The file previewed and the start of what's in it, so it's only read again once the
+    /// selection changes.
+    preview: Option<(usize, String)>,
+    /// Where the list of matches was when last drawn, how many rows it had room for and how tall
+    /// each one was, for mapping the mouse to them.
+    list_rect: Option<Rect>,
+    rows: usize,
+    row_height: f32,
+}
+
+impl FilePicker {
+    pub fn new(root: PathBuf, fonts: Rc<RefCell<Fonts>>, requests: Requests, waker: Waker) -> Self {
+        let (sender, walk) = mpsc::channel();
+        let walk_root = root.clone();
+        thread::spawn(move || {
+            let walk = WalkBuilder::new(&walk_root)
+                .hidden(false)
+                .filter_entry(|entry| entry.file_name() != ".git")
+                .build();
+            let mut batch = Vec::new();
+            for entry in walk.filter_map(Result::ok) {
+                if !entry.file_type().is_some_and(|kind| kind.is_file()) {
+                    continue;
+                }
+                let path = entry
+                    .path()
+                    .strip_prefix(&walk_root)
+                    .unwrap_or(entry.path());
+                batch.push(path.to_string_lossy().into_owned());
+                if batch.len() == BATCH_SIZE {
+                    // Nobody's listening once the picker's been closed
+                    if sender.send(std::mem::take(&mut batch)).is_err() {
+                        return;
+                    }
+                    waker.wake();
+                }
+            }
+            if !batch.is_empty() && sender.send(batch).is_ok() {
+                waker.wake();
+            }
+        });
+
+        Self {
+            fonts,
+            requests,
+            metrics: Metrics::new(18.0, 28.0),
+            theme: Arc::default(),
+            root,
+            files: Vec::new(),
+            walk: Some(walk),
+            query: String::new(),
+            matches: Vec::new(),
+            selected: 0,
+            scroll: 0,
+            preview: None,
+            list_rect: None,
+            rows: 0,
+            row_height: 28.0,
+        }
+    }
+
+    /// Blocks until every file has been found, for when there's no event loop to be woken, like
+    /// when replaying input.
+    fn finish_walk(&mut self) {
+        let Some(walk) = self.walk.take() else {
+            return;
+        };
+        self.add_files(walk.iter().flatten().collect());
+    }
+
+    /// Adds files the walk found, matching only them against the query and merging them into the
+    /// matches so far, with the same file kept selected as the list fills in around it.
+    fn add_files(&mut self, files: Vec<String>) {
+        if files.is_empty() {
+            return;
+        }
+        let selected = self.selected_file();
+        let start = self.files.len();
+        self.files.extend(files);
+        let found = matches(&self.query, &self.files, start..self.files.len());
+        let matches = std::mem::take(&mut self.matches);
+        self.matches = merge(&self.files, matches, found);
+        let index = selected.and_then(|file| {
+            let mut matches = self.matches.iter();
+            matches.position(|found| found.item == file)
+        });
+        self.select(index.unwrap_or(0));
+    }
+
+    fn filter(&mut self) {
+        self.matches = matches(&self.query, &self.files, 0..self.files.len());
+        self.select(0);
+    }
+
+    fn select(&mut self, index: usize) {
+        self.selected = index.min(self.matches.len().saturating_sub(1));
+    }
+
+    fn selected_file(&self) -> Option<usize> {
+        self.matches.get(self.selected).map(|found| found.item)
+    }
+
+    /// Opens the selected file in the focused editor and closes the picker.
+    fn open_selected(&mut self) {
+        let mut requests = self.requests.borrow_mut();
+        if let Some(file) = self.selected_file() {
+            requests.push(Request::Open(self.root.join(&self.files[file])));
+        }
+        requests.push(Request::CloseOverlay);
+    }
+
+    /// The start of the selected file, or why it can't be shown.
+    fn preview(&mut self) -> &str {
+        let Some(file) = self.selected_file() else {
+            return "";
+        };
+        if self
+            .preview
+            .as_ref()
+            .is_none_or(|(shown, _)| *shown != file)
+        {
+            let path = self.root.join(&self.files[file]);
+            let mut bytes = Vec::new();
+            let text = match File::open(path)
+                .and_then(|file| file.take(PREVIEW_BYTES).read_to_end(&mut bytes))
+            {
+                // Anything with a nul in it is unlikely to be text
+                Ok(_) if bytes.contains(&0) => "(binary file)".to_owned(),
+                Ok(_) => String::from_utf8_lossy(&bytes).into_owned(),
+                Err(err) => format!("({err})"),
+            };
+            self.preview = Some((file, text));
+        }
+        &self.preview.as_ref().unwrap().1
+    }
+}
+
+/// The files in `range` that match `query`, in the order the picker shows them.
+fn matches(query: &str, files: &[String], range: Range<usize>) -> Vec<Match<usize>> {
+    let mut matches = fuzzy::filter(query, range.map(|file| (file, &files[file])), true);
+    matches.sort_by(|a, b| order(files, a, b));
+    matches
+}
+
+/// Merges two lists of matches that are each in the order the picker shows them.
+fn merge(files: &[String], a: Vec<Match<usize>>, b: Vec<Match<usize>>) -> Vec<Match<usize>> {
+    let mut merged = Vec::with_capacity(a.len() + b.len());
+    let (mut a, mut b) = (a.into_iter().peekable(), b.into_iter().peekable());
+    loop {
+        let next = match (a.peek(), b.peek()) {
+            (Some(first), Some(second)) if order(files, first, second).is_le() => a.next(),
+            (_, Some(_)) => b.next(),
+            (Some(_), None) => a.next(),
+            (None, None) => break,
+        };
+        merged.extend(next);
+    }
+    merged
+}
+
+/// Better matches first, and then files in order of their paths.
+fn order(files: &[String], a: &Match<usize>, b: &Match<usize>) -> Ordering {
+    (b.score.cmp(&a.score)).then_with(|| files[a.item].cmp(&files[b.item]))
+}
+
+impl Interactive for FilePicker {
+    fn handle_mouse_input(
+        &mut self,
+        input_state: &InputState,
+        button: MouseButton,
+        new_state: ElementState,
+    ) -> bool {
+        if button != MouseButton::Left || new_state != ElementState::Pressed {
+            return false;
+        }
+        let Some(list) = self.list_rect else {
+            return false;
+        };
+        let (x, y) = (
+            input_state.mouse_pos_x as f32,
+            input_state.mouse_pos_y as f32,
+        );
+        if x < list.left() || x >= list.right() || y < list.top() || y >= list.bottom() {
+            // Clicking anywhere else gives up on picking
+            self.requests.borrow_mut().push(Request::CloseOverlay);
+            return true;
+        }
+        let row = ((y - list.top()) / self.row_height) as usize;
+        if row < self.rows && self.scroll + row < self.matches.len() {
+            self.selected = self.scroll + row;
+            self.open_selected();
+        }
+        true
+    }
+
+    fn handle_cursor_moved(&mut self, _input_state: &InputState) -> bool {
+        false
+    }
+
+    fn handle_scroll(&mut self, _input_state: &InputState, pixel_delta: f32) {
+        let rows = (-pixel_delta / self.row_height).round() as isize;
+        self.select(self.selected.saturating_add_signed(rows));
+    }
+
+    fn handle_keyboard_input(&mut self, input_state: &InputState, key: Key<SmolStr>) -> bool {
+        let ctrl = input_state.modifier_state.control_key();
+        match key {
+            Key::Named(NamedKey::Escape) => self.requests.borrow_mut().push(Request::CloseOverlay),
+            Key::Named(NamedKey::Enter) => self.open_selected(),
+            Key::Named(NamedKey::ArrowDown) => self.select(self.selected + 1),
+            Key::Character(c) if ctrl && c == "n" => self.select(self.selected + 1),
+            Key::Named(NamedKey::ArrowUp) => self.select(self.selected.saturating_sub(1)),
+            Key::Character(c) if ctrl && c == "p" => self.select(self.selected.saturating_sub(1)),
+            Key::Named(NamedKey::PageDown) => self.select(self.selected + self.rows.max(1)),
+            Key::Named(NamedKey::PageUp) => {
+                self.select(self.selected.saturating_sub(self.rows.max(1)))
+            }
+            Key::Named(NamedKey::Backspace) => {
+                self.query.pop();
+                self.filter();
+            }
+            key => match key.to_text() {
+                Some(text) if !ctrl && !input_state.modifier_state.alt_key() => {
+                    self.query.push_str(text);
+                    self.filter();
+                }
+                _ => return false,
+            },
+        }
+        true
+    }
+
+    fn poll(&mut self) -> bool {
+        let Some(walk) = &self.walk else {
+            return false;
+        };
+        let mut files = Vec::new();
+        let mut done = false;
+        loop {
+            match walk.try_recv() {
+                Ok(batch) => files.extend(batch),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.walk = None;
+                    done = true;
+                    break;
+                }
+            }
+        }
+        let found = !files.is_empty();
+        self.add_files(files);
+        // The count of files found changes once the walk is done, even without new files
+        found || done
+    }
+
+    fn configure(&mut self, config: &Config) {
+        self.theme = config.theme.clone();
+    }
+
+    fn render<'draw>(
+        &mut self,
+        pixmap: &mut PixmapMut<'draw>,
+        paint: &mut Paint<'draw>,
+        scale_factor: f64,
+        rect: Rect,
+    ) {
+        let metrics = self.metrics.scale(scale_factor as f32);
+        let padding = metrics.font_size / 2.0;
+        let width = (rect.width() * 0.8)
+            .max(metrics.font_size * 20.0)
+            .min(rect.width());
+        let height = (rect.height() * 0.7).min(rect.height() - 2.0);
+        // A window too small to fit anything in gets nothing
+        let Some(overlay) = Rect::from_xywh(
+            rect.x() + (rect.width() - width) / 2.0,
+            rect.y() + (rect.height() - height) / 4.0,
+            width,
+            height,
+        ) else {
+            return;
+        };
+        let header_height = metrics.line_height.min(height);
+        let list_width = (width * 0.4).round();
+        let Some(list) = Rect::from_xywh(
+            overlay.x(),
+            overlay.y() + header_height,
+            list_width,
+            height - header_height,
+        ) else {
+            return;
+        };
+        self.list_rect = Some(list);
+        self.row_height = metrics.line_height;
+        self.rows = ((list.height() / metrics.line_height) as usize).max(1);
+
+        // Keep the selected file in view
+        if self.selected < self.scroll {
+            self.scroll = self.selected;
+        } else if self.selected >= self.scroll + self.rows {
+            self.scroll = self.selected + 1 - self.rows;
+        }
+
+        let theme = self.theme.clone();
+        // Red and blue are swapped, like everything else drawn for softbuffer
+        let mut fill = |colour: CTColor, rect: Rect| {
+            paint.set_color_rgba8(colour.b(), colour.g(), colour.r(), colour.a());
+            pixmap.fill_rect(rect, paint, Transform::identity(), None);
+        };
+        fill(theme.popup_border, overlay.outset(1.0, 1.0).unwrap());
+        fill(theme.popup, overlay);
+        // Between the list and the preview
+        fill(
+            theme.popup_border,
+            Rect::from_xywh(list.right(), list.top(), 1.0, list.height()).unwrap(),
+        );
+        if let Some(row) = self.selected.checked_sub(self.scroll) {
+            if !self.matches.is_empty() {
+                let top = list.top() + row as f32 * metrics.line_height;
+                fill(
+                    theme.current_item,
+                    Rect::from_xywh(list.x(), top, list.width(), metrics.line_height).unwrap(),
+                );
+            }
+        }
+
+        let attrs = Attrs::new().family(Family::Monospace);
+        let searching = if self.walk.is_some() { "…" } else { "" };
+        let header = [
+            (format!("> {}", self.query), None),
+            (
+                format!("  {}/{}{searching}", self.matches.len(), self.files.len()),
+                Some(theme.dimmed),
+            ),
+        ];
+        let mut rows = Vec::new();
+        for found in self.matches.iter().skip(self.scroll).take(self.rows) {
+            for (i, c) in self.files[found.item].chars().enumerate() {
+                let matched = found.indices.contains(&(i as u32));
+                rows.push((c.to_string(), matched.then_some(theme.matched)));
+            }
+            rows.push(("\n".to_owned(), None));
+        }
+
+        let preview = [(self.preview().to_owned(), None)];
+
+        let mut fonts = self.fonts.borrow_mut();
+        let fonts = &mut *fonts;
+        let mut text_buffer = |spans: &[(String, Option<CTColor>)], width: f32, height: f32| {
+            let spans = spans.iter().map(|(text, colour)| {
+                let attrs = match colour {
+                    Some(colour) => attrs.clone().color(*colour),
+                    None => attrs.clone(),
+                };
+                (text.as_str(), attrs)
+            });
+            let mut buffer = Buffer::new(&mut fonts.font_system, metrics);
+            buffer.set_wrap(Wrap::None);
+            buffer.set_rich_text(spans, &attrs, Shaping::Advanced, None);
+            buffer.set_size(Some(width.max(0.0)), Some(height.max(0.0)));
+            buffer.shape_until_scroll(&mut fonts.font_system, false);
+            buffer
+        };
+        let header = text_buffer(&header, width - padding * 2.0, metrics.line_height);
+        let rows = text_buffer(&rows, list.width() - padding * 2.0, list.height());
+        let preview_width = width - list_width - padding * 2.0;
+        let preview = text_buffer(&preview, preview_width, list.height());
+
+        let mut renderer = EditorRenderer {
+            swash_cache: &mut fonts.swash_cache,
+            font_system: &mut fonts.font_system,
+            pixmap,
+            paint,
+            pixmap_paint: &PixmapPaint::default(),
+            transform: Transform::from_translate(overlay.x() + padding, overlay.y()),
+        };
+        renderer.draw_buffer(&header, theme.foreground);
+        renderer.transform = Transform::from_translate(list.x() + padding, list.y());
+        renderer.draw_buffer(&rows, theme.foreground);
+        renderer.transform = Transform::from_translate(list.right() + padding, list.y());
+        renderer.draw_buffer(&preview, theme.foreground);
+    }
+}
+
+impl Overlay for FilePicker {
+    fn finish_background_work(&mut self) {
+        self.finish_walk();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{matches, merge};
+
+    fn paths(files: &[String], matches: &[crate::fuzzy::Match<usize>]) -> Vec<String> {
+        matches
+            .iter()
+            .map(|found| files[found.item].clone())
+            .collect()
+    }
+
+    #[test]
+    fn matching_in_batches_is_matching_at_once() {
+        let files: Vec<String> = [
+            "src/main.rs",
+            "README.md",
+            "src/layout/mod.rs",
+            "src/layout/picker.rs",
+            "Cargo.toml",
+            "src/lsp/mod.rs",
+            "replays/picker.replay",
+            "src/document/mod.rs",
+        ]
+        .map(String::from)
+        .to_vec();
+        for query in ["", "mod", "pick", "rs", "nothing like it"] {
+            let at_once = matches(query, &files, 0..files.len());
+            let mut in_batches = Vec::new();
+            for start in (0..files.len()).step_by(3) {
+                let end = (start + 3).min(files.len());
+                in_batches = merge(&files, in_batches, matches(query, &files, start..end));
+            }
+            assert_eq!(
+                paths(&files, &in_batches),
+                paths(&files, &at_once),
+                "for {query:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn ties_are_in_order_of_path() {
+        let files: Vec<String> = ["b", "c", "a"].map(String::from).to_vec();
+        assert_eq!(paths(&files, &matches("", &files, 0..3)), ["a", "b", "c"]);
+    }
+}
@@ -0,0 +1,791 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::ops::Range;
+use std::path::{self, Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+
+use cosmic_text::{Attrs, Buffer, Color as CTColor, Family, Metrics, Shaping, Wrap};
+use ignore::overrides::{Override, OverrideBuilder};
+use ignore::WalkBuilder;
+use regex::Regex;
+use tiny_skia::{Paint, PixmapMut, PixmapPaint, Rect, Transform};
+use winit::{
+    event::{ElementState, MouseButton},
+    keyboard::{Key, NamedKey, SmolStr},
+};
+
+use crate::config::{Config, Theme};
+use crate::document::TextEncoding;
+use crate::pattern::{self, Pattern};
+use crate::{InputState, Waker};
+
+use super::editor::render::EditorRenderer;
+use super::{Fonts, Interactive, Overlay, Request, Requests};
+
+/// How many matches are found before the search gives up, so that searching for something
+/// everywhere doesn't use up all the memory there is.
+const MAX_HITS: usize = 10_000;
+/// How many lines around the selected match the preview shows before it.
+const CONTEXT_LINES: usize = 3;
+
+/// A match in one of the files searched.
+struct Hit {
+    /// Index into `files`.
+    file: usize,
+    /// Counted from 0.
+    line: usize,
+    /// Where the match is in `text`, in bytes.
+    columns: Range<usize>,
+    /// The lines the match is on, without the last one's line ending, so that a regex finds the
+    /// same match in them to work out its replacement with.
+    text: String,
+}
+
+impl Hit {
+    /// The line the match starts on and where the match is in it, cut short at the end of the
+    /// line if it goes on over several, which is all there's room to show.
+    fn first_line(&self) -> (&str, Range<usize>) {
+        let line = self.text.split('\n').next().unwrap_or_default();
+        (line, self.columns.start..self.columns.end.min(line.len()))
+    }
+}
+
+/// The matches in one file, as they're sent over from the search.
+struct FileHits {
+    /// Relative to the root.
+    path: String,
+    hits: Vec<(usize, Range<usize>, String)>,
+}
+
+/// The parts of the search bar, which Tab moves between.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+enum Field {
+    #[default]
+    Query,
+    Include,
+    Exclude,
+    Replacement,
+}
+
+/// What the project search was searching for when it was last closed, which the next one starts
+/// out with.
+#[derive(Clone, Default)]
+pub struct LastSearch {
+    pattern: Pattern,
+    include: String,
+    exclude: String,
+    replacement: String,
+    field: Field,
+}
+
+/// Searches every file in the working directory for text or a regex, leaving out whatever git
+/// would ignore and narrowed down by globs of files to include or exclude. Files open in the
+/// editor with unsaved changes are searched as they are there. The search runs on
+/// another thread, with matches listed as they're found and the selected one previewed alongside
+/// the list, or what replacing it would do. It's drawn over the rest of the layout like the file
+/// picker, and leaves what was searched for behind for the next one when it's closed.
+pub struct ProjectSearch {
+    fonts: Rc<RefCell<Fonts>>,
+    requests: Requests,
+    waker: Waker,
+    metrics: Metrics,
+    theme: Arc<Theme>,
+    root: PathBuf,
+    /// The text of files open in the editor with unsaved changes, by their absolute paths, which
+    /// is searched instead of what's on disk.
+    unsaved: Arc<HashMap<PathBuf, String>>,
+    /// Where what's being searched for is left once this is closed.
+    last: Rc<RefCell<LastSearch>>,
+    pattern: Pattern,
+    /// Globs of files to search, or every file if there are none, and globs of files not to
+    /// search, each separated by commas or spaces.
+    include: String,
+    exclude: String,
+    replacement: String,
+    field: Field,
+    /// What the matches were found with, for working out their replacements.
+    regex: Option<Regex>,
+    /// Relative to `root`, in the order they were searched.
+    files: Vec<String>,
+    hits: Vec<Hit>,
+    /// Where more matches come from, until the search is done, and what tells it to stop early.
+    search: Option<(Receiver<FileHits>, Arc<AtomicBool>)>,
+    /// Why the search can't be done, like the query not being a valid regex.
+    error: Option<String>,
+    /// Index into `hits`.
+    selected: usize,
+    /// The first match shown.
+    scroll: usize,
+    /// The file previewed and what's in it, so it's only read again once the selection moves to
+    /// another file.
+    preview: Option<(usize, String)>,
+    /// Where the list of matches was when last drawn, how many rows it had room for and how tall
+    /// each one was, for mapping the mouse to them.
+    list_rect: Option<Rect>,
+    rows: usize,
+    row_height: f32,
+}
+
+impl ProjectSearch {
+    /// Starts searching for whatever `last` says was searched for last.
+    pub fn new(
+        root: PathBuf,
+        unsaved: HashMap<PathBuf, String>,
+        last: Rc<RefCell<LastSearch>>,
+        fonts: Rc<RefCell<Fonts>>,
+        requests: Requests,
+        waker: Waker,
+    ) -> Self {
+        let LastSearch {
+            pattern,
+            include,
+            exclude,
+            replacement,
+            field,
+        } = last.borrow().clone();
+        let mut search = Self {
+            fonts,
+            requests,
+            waker,
+            metrics: Metrics::new(18.0, 28.0),
+            theme: Arc::default(),
+            root,
+            unsaved: Arc::new(unsaved),
+            last,
+            pattern,
+            include,
+            exclude,
+            replacement,
+            field,
+            regex: None,
+            files: Vec::new(),
+            hits: Vec::new(),
+            search: None,
+            error: None,
+            selected: 0,
+            scroll: 0,
+            preview: None,
+            list_rect: None,
+            rows: 0,
+            row_height: 28.0,
+        };
+        search.restart();
+        search
+    }
+
+    /// Stops whatever search is going on and starts again from scratch, since the query changed.
+    fn restart(&mut self) {
+        self.stop();
+        self.files.clear();
+        self.hits.clear();
+        self.regex = None;
+        self.error = None;
+        self.preview = None;
+        self.selected = 0;
+        self.scroll = 0;
+        if self.pattern.query.is_empty() {
+            return;
+        }
+        let regex = match self.pattern.build() {
+            Ok(regex) => regex,
+            Err(err) => {
+                self.error = Some(format!("Invalid regex: {err}"));
+                return;
+            }
+        };
+        let overrides = match self.overrides() {
+            Ok(overrides) => overrides,
+            Err(err) => {
+                self.error = Some(format!("Invalid glob: {err}"));
+                return;
+            }
+        };
+        let (sender, receiver) = mpsc::channel();
+        let stopped = Arc::new(AtomicBool::new(false));
+        let (root, unsaved) = (self.root.clone(), self.unsaved.clone());
+        let (search_regex, search_stopped) = (regex.clone(), stopped.clone());
+        let waker = self.waker.clone();
+        thread::spawn(move || {
+            search(
+                &root,
+                &unsaved,
+                &search_regex,
+                overrides,
+                &sender,
+                &search_stopped,
+                &waker,
+            )
+        });
+        self.regex = Some(regex);
+        self.search = Some((receiver, stopped));
+    }
+
+    fn stop(&mut self) {
+        if let Some((_, stopped)) = self.search.take() {
+            stopped.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// The include and exclude globs in the form the walk takes them, where excluding is a glob
+    /// starting with `!`.
+    fn overrides(&self) -> Result<Override, ignore::Error> {
+        let mut builder = OverrideBuilder::new(&self.root);
+        let globs = |globs: &str| {
+            (globs.split([',', ' ']))
+                .filter(|glob| !glob.is_empty())
+                .map(str::to_owned)
+                .collect::<Vec<_>>()
+        };
+        for glob in globs(&self.include) {
+            builder.add(&glob)?;
+        }
+        for glob in globs(&self.exclude) {
+            builder.add(&format!("!{glob}"))?;
+        }
+        builder.build()
+    }
+
+    /// Blocks until the search is done, for when there's no event loop to be woken, like when
+    /// replaying input.
+    fn finish_search(&mut self) {
+        let Some((receiver, _)) = self.search.take() else {
+            return;
+        };
+        for file in receiver.iter() {
+            self.add(file);
+        }
+    }
+
+    fn add(&mut self, file: FileHits) {
+        let index = self.files.len();
+        self.files.push(file.path);
+        let hits = (file.hits.into_iter()).map(|(line, columns, text)| Hit {
+            file: index,
+            line,
+            columns,
+            text,
+        });
+        self.hits.extend(hits);
+    }
+
+    fn select(&mut self, index: usize) {
+        self.selected = index.min(self.hits.len().saturating_sub(1));
+    }
+
+    /// Whether there's a replacement to preview and carry out.
+    fn replacing(&self) -> bool {
+        self.field == Field::Replacement || !self.replacement.is_empty()
+    }
+
+    fn edit_field(&mut self, edit: impl FnOnce(&mut String)) {
+        let field = match self.field {
+            Field::Query => &mut self.pattern.query,
+            Field::Include => &mut self.include,
+            Field::Exclude => &mut self.exclude,
+            Field::Replacement => &mut self.replacement,
+        };
+        edit(field);
+        // The replacement only changes the preview
+        if self.field != Field::Replacement {
+            self.restart();
+        }
+    }
+
+    /// Opens the file of the selected match in the focused editor with the match selected, and
+    /// closes the search.
+    fn open_selected(&mut self) {
+        let mut requests = self.requests.borrow_mut();
+        if let Some(hit) = self.hits.get(self.selected) {
+            requests.push(Request::OpenAt {
+                path: self.root.join(&self.files[hit.file]),
+                line: hit.line,
+                columns: hit.first_line().1,
+            });
+        }
+        requests.push(Request::CloseOverlay);
+    }
+
+    /// Has the editor replace every match in every file that had one, and closes the search.
+    fn replace_all(&mut self) {
+        self.finish_search();
+        if self.hits.is_empty() {
+            return;
+        }
+        let files = self.files.iter().map(|file| self.root.join(file)).collect();
+        let mut requests = self.requests.borrow_mut();
+        requests.push(Request::ReplaceInFiles {
+            pattern: self.pattern.clone(),
+            replacement: self.replacement.clone(),
+            files,
+        });
+        requests.push(Request::CloseOverlay);
+    }
+
+    /// The first line of `hit` with its match replaced. The replacement is worked out on every
+    /// line the match is on, since its capture groups can reach past the first.
+    fn replaced(&self, hit: &Hit) -> String {
+        let mut text = hit.text.clone();
+        if let Some(regex) = &self.regex {
+            let found = [hit.columns.clone()];
+            let replacements =
+                (self.pattern).replacements(regex, &hit.text, &found, &self.replacement);
+            let (range, replacement) = &replacements[0];
+            text.replace_range(range.clone(), replacement);
+        }
+        match text.split_once('\n') {
+            Some((line, _)) => line.to_owned(),
+            None => text,
+        }
+    }
+
+    /// What's shown alongside the list: the lines around the selected match or, when replacing,
+    /// every line with a match in the same file before and after replacing it.
+    fn preview(&mut self) -> Vec<(String, Option<CTColor>)> {
+        let Some(hit) = self.hits.get(self.selected) else {
+            return Vec::new();
+        };
+        let file = hit.file;
+        if self.replacing() {
+            let mut spans = Vec::new();
+            let first = self.hits.partition_point(|hit| hit.file < file);
+            let hits = self.hits[first..].iter().take_while(|hit| hit.file == file);
+            for hit in hits {
+                let number = hit.line + 1;
+                spans.push((
+                    format!("{number:>4} - {}\n", hit.first_line().0),
+                    Some(self.theme.removed),
+                ));
+                let replaced = self.replaced(hit);
+                spans.push((
+                    format!("{number:>4} + {replaced}\n"),
+                    Some(self.theme.added),
+                ));
+            }
+            return spans;
+        }
+
+        let (line, columns) = (hit.line, hit.first_line().1);
+        if self
+            .preview
+            .as_ref()
+            .is_none_or(|(shown, _)| *shown != file)
+        {
+            let path = self.root.join(&self.files[file]);
+            let text = match unsaved_text(&self.unsaved, &path) {
+                Some(text) => text.to_owned(),
+                None => read_text(&path).unwrap_or_default(),
+            };
+            self.preview = Some((file, text));
+        }
+        let text = &self.preview.as_ref().unwrap().1;
+        let mut spans = Vec::new();
+        let first = line.saturating_sub(CONTEXT_LINES);
+        for (i, text) in text
+            .split('\n')
+            .enumerate()
+            .skip(first)
+            .take(self.rows.max(1))
+        {
+            spans.push((format!("{:>4}  ", i + 1), Some(self.theme.dimmed)));
+            if i == line && columns.end <= text.len() {
+                spans.push((text[..columns.start].to_owned(), None));
+                spans.push((text[columns.clone()].to_owned(), Some(self.theme.matched)));
+                spans.push((text[columns.end..].to_owned(), None));
+            } else {
+                spans.push((text.to_owned(), None));
+            }
+            spans.push(("\n".to_owned(), None));
+        }
+        spans
+    }
+}
+
+impl Drop for ProjectSearch {
+    fn drop(&mut self) {
+        self.stop();
+        *self.last.borrow_mut() = LastSearch {
+            pattern: self.pattern.clone(),
+            include: self.include.clone(),
+            exclude: self.exclude.clone(),
+            replacement: self.replacement.clone(),
+            field: self.field,
+        };
+    }
+}
+
+/// Reads the file at `path` as text with `\n` line endings, like opening it in the editor would,
+/// or nothing if it can't be read or isn't text.
+fn read_text(path: &Path) -> Option<String> {
+    let bytes = fs::read(path).ok()?;
+    // Anything with a nul in it is unlikely to be text
+    if bytes.contains(&0) {
+        return None;
+    }
+    let (_, text) = TextEncoding::decode(&bytes);
+    Some(text.replace("\r\n", "\n"))
+}
+
+/// The text of the file at `path` in the editor, if it's open there with unsaved changes.
+fn unsaved_text<'a>(unsaved: &'a HashMap<PathBuf, String>, path: &Path) -> Option<&'a str> {
+    let path = path::absolute(path).ok()?;
+    unsaved.get(&path).map(String::as_str)
+}
+
+/// Searches every file under `root` that `overrides` allows, in order of their paths, sending
+/// over the matches in each file that has any until there are `MAX_HITS` of them or `stopped`
+/// is set. Files in `unsaved` are searched as they are in the editor.
+fn search(
+    root: &Path,
+    unsaved: &HashMap<PathBuf, String>,
+    regex: &Regex,
+    overrides: Override,
+    sender: &Sender<FileHits>,
+    stopped: &AtomicBool,
+    waker: &Waker,
+) {
+    let walk = WalkBuilder::new(root)
+        .hidden(false)
+        .filter_entry(|entry| entry.file_name() != ".git")
+        .overrides(overrides)
+        .sort_by_file_name(|a, b| a.cmp(b))
+        .build();
+    let mut count = 0;
+    for entry in walk.filter_map(Result::ok) {
+        if stopped.load(Ordering::Relaxed) || count >= MAX_HITS {
+            return;
+        }
+        if !entry.file_type().is_some_and(|kind| kind.is_file()) {
+            continue;
+        }
+        let read;
+        let text = match unsaved_text(unsaved, entry.path()) {
+            Some(text) => text,
+            None => match read_text(entry.path()) {
+                Some(text) => {
+                    read = text;
+                    &read
+                }
+                None => continue,
+            },
+        };
+        let matches = pattern::find_all(regex, text);
+        if matches.is_empty() {
+            continue;
+        }
+        let mut hits = Vec::new();
+        let mut lines = text.split('\n').enumerate();
+        let mut line = lines.next();
+        let mut line_start = 0;
+        for found in matches.into_iter().take(MAX_HITS - count) {
+            // Move on to the line the match starts on
+            while let Some((_, line_text)) = line {
+                if found.start <= line_start + line_text.len() {
+                    break;
+                }
+                line_start += line_text.len() + 1;
+                line = lines.next();
+            }
+            let Some((i, _)) = line else {
+                break;
+            };
+            // Through to the end of the line the match ends on
+            let end = (text[found.end..].find('\n')).map_or(text.len(), |end| found.end + end);
+            hits.push((
+                i,
+                found.start - line_start..found.end - line_start,
+                text[line_start..end].to_owned(),
+            ));
+        }
+        count += hits.len();
+        let path = entry.path().strip_prefix(root).unwrap_or(entry.path());
+        let file = FileHits {
+            path: path.to_string_lossy().into_owned(),
+            hits,
+        };
+        // Nobody's listening once the search has been started again
+        if sender.send(file).is_err() {
+            return;
+        }
+        waker.wake();
+    }
+}
+
+impl Interactive for ProjectSearch {
+    fn handle_mouse_input(
+        &mut self,
+        input_state: &InputState,
+        button: MouseButton,
+        new_state: ElementState,
+    ) -> bool {
+        if button != MouseButton::Left || new_state != ElementState::Pressed {
+            return false;
+        }
+        let Some(list) = self.list_rect else {
+            return false;
+        };
+        let (x, y) = (
+            input_state.mouse_pos_x as f32,
+            input_state.mouse_pos_y as f32,
+        );
+        if x < list.left() || x >= list.right() || y < list.top() || y >= list.bottom() {
+            // Clicking anywhere else gives up on searching
+            self.requests.borrow_mut().push(Request::CloseOverlay);
+            return true;
+        }
+        let row = ((y - list.top()) / self.row_height) as usize;
+        if row < self.rows && self.scroll + row < self.hits.len() {
+            self.selected = self.scroll + row;
+            self.open_selected();
+        }
+        true
+    }
+
+    fn handle_cursor_moved(&mut self, _input_state: &InputState) -> bool {
+        false
+    }
+
+    fn handle_scroll(&mut self, _input_state: &InputState, pixel_delta: f32) {
+        let rows = (-pixel_delta / self.row_height).round() as isize;
+        self.select(self.selected.saturating_add_signed(rows));
+    }
+
+    fn handle_keyboard_input(&mut self, input_state: &InputState, key: Key<SmolStr>) -> bool {
+        let modifiers = input_state.modifier_state;
+        let (ctrl, alt, shift) = (
+            modifiers.control_key(),
+            modifiers.alt_key(),
+            modifiers.shift_key(),
+        );
+        match key {
+            Key::Named(NamedKey::Escape) => {
+                self.requests.borrow_mut().push(Request::CloseOverlay);
+            }
+            Key::Named(NamedKey::Enter) if alt && self.replacing() => self.replace_all(),
+            Key::Named(NamedKey::Enter) => self.open_selected(),
+            Key::Named(NamedKey::Tab) => {
+                self.field = match (self.field, shift) {
+                    (Field::Query, false) | (Field::Exclude, true) => Field::Include,
+                    (Field::Include, false) | (Field::Replacement, true) => Field::Exclude,
+                    (Field::Exclude, false) | (Field::Query, true) => Field::Replacement,
+                    (Field::Replacement, false) | (Field::Include, true) => Field::Query,
+                }
+            }
+            Key::Named(NamedKey::ArrowDown) => self.select(self.selected + 1),
+            Key::Character(c) if ctrl && c == "n" => self.select(self.selected + 1),
+            Key::Named(NamedKey::ArrowUp) => self.select(self.selected.saturating_sub(1)),
+            Key::Character(c) if ctrl && c == "p" => self.select(self.selected.saturating_sub(1)),
+            Key::Named(NamedKey::PageDown) => self.select(self.selected + self.rows.max(1)),
+            Key::Named(NamedKey::PageUp) => {
+                self.select(self.selected.saturating_sub(self.rows.max(1)))
+            }
+            Key::Character(c) if alt && c == "r" => {
+                self.pattern.regex = !self.pattern.regex;
+                self.restart();
+            }
+            Key::Character(c) if alt && c == "c" => {
+                self.pattern.case_sensitive = !self.pattern.case_sensitive;
+                self.restart();
+            }
+            Key::Named(NamedKey::Backspace) => self.edit_field(|field| {
+                field.pop();
+            }),
+            key => match key.to_text() {
+                Some(text) if !ctrl && !alt => self.edit_field(|field| field.push_str(text)),
+                _ => return false,
+            },
+        }
+        true
+    }
+
+    fn poll(&mut self) -> bool {
+        let Some((receiver, _)) = &self.search else {
+            return false;
+        };
+        let mut files = Vec::new();
+        let done = loop {
+            match receiver.try_recv() {
+                Ok(file) => files.push(file),
+                Err(TryRecvError::Empty) => break false,
+                Err(TryRecvError::Disconnected) => break true,
+            }
+        };
+        let found = !files.is_empty();
+        for file in files {
+            self.add(file);
+        }
+        if done {
+            self.search = None;
+        }
+        // The count of matches stops showing that it's still searching once it's done
+        found || done
+    }
+
+    fn configure(&mut self, config: &Config) {
+        self.theme = config.theme.clone();
+    }
+
+    fn render<'draw>(
+        &mut self,
+        pixmap: &mut PixmapMut<'draw>,
+        paint: &mut Paint<'draw>,
+        scale_factor: f64,
+        rect: Rect,
+    ) {
+        let metrics = self.metrics.scale(scale_factor as f32);
+        let padding = metrics.font_size / 2.0;
+        let width = (rect.width() * 0.8)
+            .max(metrics.font_size * 20.0)
+            .min(rect.width());
+        let height = (rect.height() * 0.7).min(rect.height() - 2.0);
+        // A window too small to fit anything in gets nothing
+        let Some(overlay) = Rect::from_xywh(
+            rect.x() + (rect.width() - width) / 2.0,
+            rect.y() + (rect.height() - height) / 4.0,
+            width,
+            height,
+        ) else {
+            return;
+        };
+        // The query and what's found go on the first line, with the globs and replacement under
+        let header_height = (metrics.line_height * 2.0).min(height);
+        let list_width = (width * 0.5).round();
+        let Some(list) = Rect::from_xywh(
+            overlay.x(),
+            overlay.y() + header_height,
+            list_width,
+            height - header_height,
+        ) else {
+            return;
+        };
+        self.list_rect = Some(list);
+        self.row_height = metrics.line_height;
+        self.rows = ((list.height() / metrics.line_height) as usize).max(1);
+
+        // Keep the selected match in view
+        if self.selected < self.scroll {
+            self.scroll = self.selected;
+        } else if self.selected >= self.scroll + self.rows {
+            self.scroll = self.selected + 1 - self.rows;
+        }
+
+        let theme = self.theme.clone();
+        // Red and blue are swapped, like everything else drawn for softbuffer
+        let mut fill = |colour: CTColor, rect: Rect| {
+            paint.set_color_rgba8(colour.b(), colour.g(), colour.r(), colour.a());
+            pixmap.fill_rect(rect, paint, Transform::identity(), None);
+        };
+        fill(theme.popup_border, overlay.outset(1.0, 1.0).unwrap());
+        fill(theme.popup, overlay);
+        // Between the header and everything else, and between the list and the preview
+        fill(
+            theme.popup_border,
+            Rect::from_xywh(overlay.x(), list.top() - 1.0, width, 1.0).unwrap(),
+        );
+        fill(
+            theme.popup_border,
+            Rect::from_xywh(list.right(), list.top(), 1.0, list.height()).unwrap(),
+        );
+        if let Some(row) = self.selected.checked_sub(self.scroll) {
+            if !self.hits.is_empty() {
+                let top = list.top() + row as f32 * metrics.line_height;
+                fill(
+                    theme.current_item,
+                    Rect::from_xywh(list.x(), top, list.width(), metrics.line_height).unwrap(),
+                );
+            }
+        }
+
+        let label = |field: Field, label: &str| {
+            let colour = if self.field == field {
+                theme.matched
+            } else {
+                theme.dimmed
+            };
+            (label.to_owned(), Some(colour))
+        };
+        let searching = if self.search.is_some() { "…" } else { "" };
+        let found = match &self.error {
+            Some(error) => error.lines().last().unwrap_or(error).trim().to_owned(),
+            None if self.pattern.query.is_empty() => String::new(),
+            None => {
+                let (hits, files) = (self.hits.len(), self.files.len());
+                let matches = if hits == 1 { "match" } else { "matches" };
+                let in_files = if files == 1 { "file" } else { "files" };
+                format!("{hits} {matches} in {files} {in_files}{searching}")
+            }
+        };
+        let header = [
+            label(Field::Query, &format!("find{}: ", self.pattern.flags())),
+            (self.pattern.query.clone(), None),
+            (format!("  {found}\n"), Some(theme.dimmed)),
+            label(Field::Include, "in: "),
+            (self.include.clone(), None),
+            label(Field::Exclude, "  not in: "),
+            (self.exclude.clone(), None),
+            label(Field::Replacement, "  replace with: "),
+            (self.replacement.clone(), None),
+        ];
+        let mut rows = Vec::new();
+        for hit in self.hits.iter().skip(self.scroll).take(self.rows) {
+            let location = format!("{}:{}: ", self.files[hit.file], hit.line + 1);
+            rows.push((location, Some(theme.dimmed)));
+            let (text, columns) = hit.first_line();
+            // Indentation would only push the match out of view
+            let indent = (text.len() - text.trim_start().len()).min(columns.start);
+            rows.push((text[indent..columns.start].to_owned(), None));
+            rows.push((text[columns.clone()].to_owned(), Some(theme.matched)));
+            rows.push((text[columns.end..].to_owned(), None));
+            rows.push(("\n".to_owned(), None));
+        }
+
+        let preview = self.preview();
+
+        let attrs = Attrs::new().family(Family::Monospace);
+        let mut fonts = self.fonts.borrow_mut();
+        let fonts = &mut *fonts;
+        let mut text_buffer = |spans: &[(String, Option<CTColor>)], width: f32, height: f32| {
+            let spans = spans.iter().map(|(text, colour)| {
+                let attrs = match colour {
+                    Some(colour) => attrs.clone().color(*colour),
+                    None => attrs.clone(),
+                };
+                (text.as_str(), attrs)
+            });
+            let mut buffer = Buffer::new(&mut fonts.font_system, metrics);
+            buffer.set_wrap(Wrap::None);
+            buffer.set_rich_text(spans, &attrs, Shaping::Advanced, None);
+            buffer.set_size(Some(width.max(0.0)), Some(height.max(0.0)));
+            buffer.shape_until_scroll(&mut fonts.font_system, false);
+            buffer
+        };
+        let header = text_buffer(&header, width - padding * 2.0, header_height);
+        let rows = text_buffer(&rows, list.width() - padding * 2.0, list.height());
+        let preview_width = width - list_width - padding * 2.0;
+        let preview = text_buffer(&preview, preview_width, list.height());
+
+        let mut renderer = EditorRenderer {
+            swash_cache: &mut fonts.swash_cache,
+            font_system: &mut fonts.font_system,
+            pixmap,
+            paint,
+            pixmap_paint: &PixmapPaint::default(),
+            transform: Transform::from_translate(overlay.x() + padding, overlay.y()),
+        };
+        renderer.draw_buffer(&header, theme.foreground);
+        renderer.transform = Transform::from_translate(list.x() + padding, list.y());
+        renderer.draw_buffer(&rows, theme.foreground);
+        renderer.transform = Transform::from_translate(list.right() + padding, list.y());
+        renderer.draw_buffer(&preview, theme.foreground);
+    }
+}
+
+impl Overlay for ProjectSearch {
+    fn finish_background_work(&mut self) {
+        self.finish_search();
+    }
+}
@@ -0,0 +1,276 @@
+use taffy::prelude::*;
+use winit::event::ElementState;
+
+use crate::InputState;
+
+use super::{Direction, RootLayout};
+
+/// How wide the gaps between views are. Dragging one resizes the views on either side of it.
+const DIVIDER_WIDTH: f32 = 4.0;
+/// How small dragging a divider can make a view, or the views as a whole.
+pub(super) const MIN_SIZE: f32 = 80.0;
+
+/// A divider being dragged, the one after child `index` of `split`.
+pub struct Drag {
+    split: NodeId,
+    index: usize,
+}
+
+/// How a view or split is sized along the split containing it, `share` being its part of the
+/// space relative to the others in it.
+pub fn view_style(share: f32) -> Style {
+    Style {
+        flex_grow: share,
+        flex_shrink: 1.0,
+        flex_basis: length(0.0),
+        ..Default::default()
+    }
+}
+
+/// A split laying out its views side by side when splitting to the left or right, and one above
+/// the other otherwise.
+pub fn split_style(direction: Direction, share: f32) -> Style {
+    Style {
+        display: Display::Flex,
+        flex_direction: flex_direction(direction),
+        gap: length(DIVIDER_WIDTH),
+        ..view_style(share)
+    }
+}
+
+fn flex_direction(direction: Direction) -> FlexDirection {
+    match direction {
+        Direction::Left | Direction::Right => FlexDirection::Row,
+        Direction::Up | Direction::Down => FlexDirection::Column,
+    }
+}
+
+impl RootLayout {
+    /// Every editor view under `node`, in order from the top left.
+    pub(super) fn views(&self, node: NodeId) -> Vec<NodeId> {
+        let mut panes = self.panes(node);
+        panes.retain(|&pane| self.is_view(pane));
+        panes
+    }
+
+    /// The split holding all the others, found by going up from a view to just below the root.
+    pub(super) fn top_split(&self) -> NodeId {
+        let mut node = self.editor;
+        while let Some(parent) = self.tree.parent(node).filter(|&parent| parent != self.root) {
+            node = parent;
+        }
+        node
+    }
+
+    fn share(&self, node: NodeId) -> f32 {
+        self.tree.style(node).unwrap().flex_grow
+    }
+
+    fn set_share(&mut self, node: NodeId, share: f32) {
+        let style = Style {
+            flex_grow: share,
+            ..self.tree.style(node).unwrap().clone()
+        };
+        self.tree.set_style(node, style).unwrap();
+    }
+
+    fn child_index(&self, parent: NodeId, child: NodeId) -> usize {
+        let children = self.tree.children(parent).unwrap();
+        children.iter().position(|&node| node == child).unwrap()
+    }
+
+    /// Puts a new view of the focused editor's buffer on the given side of it, and focuses that.
+    pub(super) fn split(&mut self, direction: Direction) -> bool {
+        let view = self.editor_mut().split();
+        let new = self
+            .tree
+            .new_leaf_with_context(view_style(1.0), Box::new(view))
+            .unwrap();
+        let parent = self.tree.parent(self.editor).unwrap();
+        let index = self.child_index(parent, self.editor);
+        let after = matches!(direction, Direction::Right | Direction::Down);
+
+        // The top split only ever has one view in it when nothing has been split yet, and then it
+        // can just be turned the right way
+        if self.tree.child_count(parent) == 1 {
+            let style = Style {
+                flex_direction: flex_direction(direction),
+                ..self.tree.style(parent).unwrap().clone()
+            };
+            self.tree.set_style(parent, style).unwrap();
+        }
+
+        if self.tree.style(parent).unwrap().flex_direction == flex_direction(direction) {
+            // Already split the same way, so the new view goes alongside, taking half the space
+            let share = self.share(self.editor) / 2.0;
+            self.set_share(self.editor, share);
+            self.set_share(new, share);
+            let index = if after { index + 1 } else { index };
+            self.tree.insert_child_at_index(parent, index, new).unwrap();
+        } else {
+            let share = self.share(self.editor);
+            let split = self
+                .tree
+                .new_with_children(split_style(direction, share), &[])
+                .unwrap();
+            self.tree
+                .replace_child_at_index(parent, index, split)
+                .unwrap();
+            self.set_share(self.editor, 1.0);
+            let children = if after {
+                [self.editor, new]
+            } else {
+                [new, self.editor]
+            };
+            for child in children {
+                self.tree.add_child(split, child).unwrap();
+            }
+        }
+
+        self.set_focus(new);
+        true
+    }
+
+    /// Closes the focused view, giving its space to the one next to it, which gets focus. The
+    /// last view can't be closed.
+    pub(super) fn close_split(&mut self) -> bool {
+        let view = self.editor;
+        let top = self.top_split();
+        let parent = self.tree.parent(view).unwrap();
+        let siblings = self.tree.children(parent).unwrap();
+        if siblings.len() == 1 {
+            return false;
+        }
+        let index = self.child_index(parent, view);
+        let neighbour = siblings[if index == 0 { 1 } else { index - 1 }];
+        let share = self.share(neighbour) + self.share(view);
+        self.set_share(neighbour, share);
+        self.tree.remove_child(parent, view).unwrap();
+        // Removing the node alone would leave its context behind
+        self.tree.set_node_context(view, None).unwrap();
+        self.tree.remove(view).unwrap();
+
+        // A split left with one thing in it is replaced by that thing
+        if self.tree.child_count(parent) == 1 && parent != top {
+            let grandparent = self.tree.parent(parent).unwrap();
+            let index = self.child_index(grandparent, parent);
+            let share = self.share(parent);
+            self.tree.remove_child(parent, neighbour).unwrap();
+            self.tree
+                .replace_child_at_index(grandparent, index, neighbour)
+                .unwrap();
+            self.tree.remove(parent).unwrap();
+            self.set_share(neighbour, share);
+        }
+
+        let views = self.views(neighbour);
+        self.set_focus(views[0]);
+        true
+    }
+
+    /// Focuses the nearest view on the given side of the focused one, if there is one.
+    pub(super) fn focus(&mut self, direction: Direction) -> bool {
+        let from = self.get_rect(self.editor);
+        let centre = |left: f32, right: f32| (left + right) / 2.0;
+        // How far along `direction` each view is, then how far off to the side
+        let nearest = self
+            .views(self.root)
+            .into_iter()
+            .filter_map(|view| {
+                let rect = self.get_rect(view);
+                let (ahead, aside) = match direction {
+                    Direction::Left | Direction::Right => (
+                        match direction {
+                            Direction::Left => from.left() - rect.right(),
+                            _ => rect.left() - from.right(),
+                        },
+                        centre(rect.top(), rect.bottom()) - centre(from.top(), from.bottom()),
+                    ),
+                    Direction::Up | Direction::Down => (
+                        match direction {
+                            Direction::Up => from.top() - rect.bottom(),
+                            _ => rect.top() - from.bottom(),
+                        },
+                        centre(rect.left(), rect.right()) - centre(from.left(), from.right()),
+                    ),
+                };
+                (ahead >= 0.0).then_some((ahead, aside.abs(), view))
+            })
+            .min_by(|a, b| a.0.total_cmp(&b.0).then(a.1.total_cmp(&b.1)));
+
+        let Some((_, _, view)) = nearest else {
+            return false;
+        };
+        self.set_focus(view);
+        true
+    }
+
+    /// Starts dragging the divider under the mouse when it's pressed, or stops dragging when it's
+    /// released, returning whether either happened.
+    pub(super) fn drag_divider(&mut self, input_state: &InputState, state: ElementState) -> bool {
+        match state {
+            ElementState::Pressed => {
+                let x = input_state.mouse_pos_x as f32;
+                let y = input_state.mouse_pos_y as f32;
+                self.drag = self.divider_at(x, y);
+                self.drag.is_some()
+            }
+            ElementState::Released => self.drag.take().is_some(),
+        }
+    }
+
+    fn divider_at(&self, x: f32, y: f32) -> Option<Drag> {
+        let mut splits = vec![self.top_split()];
+        while let Some(split) = splits.pop() {
+            let children = self.tree.children(split).unwrap();
+            let row = self.tree.style(split).unwrap().flex_direction == FlexDirection::Row;
+            for (index, pair) in children.windows(2).enumerate() {
+                let (a, b) = (self.get_rect(pair[0]), self.get_rect(pair[1]));
+                let hit = if row {
+                    x >= a.right() && x < b.left() && y >= a.top() && y < a.bottom()
+                } else {
+                    y >= a.bottom() && y < b.top() && x >= a.left() && x < a.right()
+                };
+                if hit {
+                    return Some(Drag { split, index });
+                }
+            }
+            splits.extend(children);
+        }
+        None
+    }
+
+    /// Moves the divider being dragged to the mouse, as far as the views either side of it can
+    /// shrink.
+    pub(super) fn resize_split(&mut self, input_state: &InputState) -> bool {
+        let Some(Drag { split, index }) = self.drag else {
+            return false;
+        };
+        let children = self.tree.children(split).unwrap();
+        let (a, b) = (children[index], children[index + 1]);
+        let (rect_a, rect_b) = (self.get_rect(a), self.get_rect(b));
+        let (start, total, pos) =
+            if self.tree.style(split).unwrap().flex_direction == FlexDirection::Row {
+                (
+                    rect_a.left(),
+                    rect_a.width() + rect_b.width(),
+                    input_state.mouse_pos_x as f32,
+                )
+            } else {
+                (
+                    rect_a.top(),
+                    rect_a.height() + rect_b.height(),
+                    input_state.mouse_pos_y as f32,
+                )
+            };
+        if total < 2.0 * MIN_SIZE {
+            return false;
+        }
+        let size = (pos - start - DIVIDER_WIDTH / 2.0).clamp(MIN_SIZE, total - MIN_SIZE);
+        // The two views share the same space between them as before, just differently
+        let shares = self.share(a) + self.share(b);
+        self.set_share(a, shares * size / total);
+        self.set_share(b, shares * (total - size) / total);
+        true
+    }
+}
@@ -1,43 +1,349 @@
-use tiny_skia::{Paint, PixmapMut, Rect, Transform};
+use std::cell::RefCell;
+use std::ops::Range;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use cosmic_text::{Attrs, Buffer, Color as CTColor, Family, Metrics, Shaping};
+use tiny_skia::{Paint, PixmapMut, PixmapPaint, Rect, Transform};
 use winit::{
     event::{ElementState, MouseButton},
-    keyboard::{Key, SmolStr},
+    keyboard::{Key, NamedKey, SmolStr},
 };
 
+use crate::config::{Config, Theme};
 use crate::InputState;
 
-use super::Interactive;
+use super::editor::render::EditorRenderer;
+use super::{Fonts, Interactive, Request, Requests};
+
+/// What the status bar shows, kept up to date by the rest of the UI.
+#[derive(Default)]
+pub struct Status {
+    pub mode: &'static str,
+    /// The count and keys typed so far towards a command, shown until it's finished.
+    pub pending: String,
+    /// The file name, or a placeholder for a document that hasn't been saved anywhere yet.
+    pub file: String,
+    pub dirty: bool,
+    /// The primary cursor's line and column, counted from 1, the column in characters.
+    pub line: usize,
+    pub column: usize,
+    pub selections: usize,
+    pub encoding: String,
+    pub line_ending: String,
+    pub major_mode: &'static str,
+    pub errors: usize,
+    pub warnings: usize,
+}
+
+/// A piece of text in the status bar, which may have a background and do something when clicked.
+struct Segment {
+    text: String,
+    /// The theme's foreground unless it's given another colour.
+    colour: Option<CTColor>,
+    background: Option<CTColor>,
+    /// What clicking it asks for.
+    action: Option<Request>,
+    /// When the bar is too narrow for everything, segments with the lowest priority go first.
+    priority: u8,
+}
+
+impl Segment {
+    fn new(text: String, priority: u8) -> Self {
+        Self {
+            text,
+            colour: None,
+            background: None,
+            action: None,
+            priority,
+        }
+    }
+
+    fn colour(self, colour: CTColor) -> Self {
+        Self {
+            colour: Some(colour),
+            ..self
+        }
+    }
+}
+
+pub struct StatusBar {
+    fonts: Rc<RefCell<Fonts>>,
+    status: Rc<RefCell<Status>>,
+    requests: Requests,
+    metrics: Metrics,
+    theme: Arc<Theme>,
+    /// Where the clickable segments were last drawn, horizontally within the bar.
+    hit_areas: Vec<(Range<f32>, Request)>,
+    hovered: Option<Request>,
+    /// Whether keys come here, which is shown by marking the selected clickable segment.
+    focused: bool,
+    /// Index into `hit_areas` of the segment Enter clicks while the bar has the focus.
+    selected: usize,
+}
+
+impl StatusBar {
+    pub fn new(fonts: Rc<RefCell<Fonts>>, status: Rc<RefCell<Status>>, requests: Requests) -> Self {
+        Self {
+            fonts,
+            status,
+            requests,
+            metrics: Metrics::new(20.0, 36.0),
+            theme: Arc::default(),
+            hit_areas: Vec::new(),
+            hovered: None,
+            focused: false,
+            selected: 0,
+        }
+    }
+
+    fn action_at(&self, input_state: &InputState) -> Option<Request> {
+        let x = input_state.mouse_pos_x as f32;
+        self.hit_areas
+            .iter()
+            .find(|(area, _)| area.contains(&x))
+            .map(|(_, action)| action.clone())
+    }
+
+    /// The segments along the left edge and those along the right edge.
+    fn segments(&self) -> (Vec<Segment>, Vec<Segment>) {
+        let status = self.status.borrow();
+        let mode_background = match status.mode {
+            "insert" => self.theme.insert_mode,
+            "select" => self.theme.select_mode,
+            _ => self.theme.normal_mode,
+        };
+        let mut file = status.file.clone();
+        if status.dirty {
+            file.push_str(" [+]");
+        }
+        let left = vec![
+            Segment {
+                background: Some(mode_background),
+                ..Segment::new(status.mode.to_uppercase(), 9).colour(self.theme.background)
+            },
+            Segment {
+                action: Some(Request::PickFile),
+                ..Segment::new(file, 7)
+            },
+        ];
 
-pub struct StatusBar;
+        let plural = |count: usize, noun: &str| {
+            format!("{count} {noun}{}", if count == 1 { "" } else { "s" })
+        };
+        let mut right = vec![
+            Segment::new(plural(status.errors, "error"), 5).colour(self.theme.error),
+            Segment::new(plural(status.warnings, "warning"), 4).colour(self.theme.warning),
+            Segment {
+                action: Some(Request::GoToLine),
+                ..Segment::new(format!("{}:{}", status.line, status.column), 8)
+            },
+        ];
+        if status.selections > 1 {
+            right.push(Segment::new(plural(status.selections, "selection"), 6));
+        }
+        if !status.pending.is_empty() {
+            right.insert(0, Segment::new(status.pending.clone(), 6));
+        }
+        right.extend([
+            Segment::new(status.encoding.clone(), 2),
+            Segment::new(status.line_ending.clone(), 1),
+            Segment::new(status.major_mode.to_owned(), 3),
+        ]);
+        (left, right)
+    }
+}
 
 impl Interactive for StatusBar {
     fn handle_mouse_input(
         &mut self,
-        _input_state: &InputState,
-        _button: MouseButton,
-        _new_state: ElementState,
+        input_state: &InputState,
+        button: MouseButton,
+        new_state: ElementState,
     ) -> bool {
-        false
+        if button != MouseButton::Left || new_state != ElementState::Pressed {
+            return false;
+        }
+        let Some(action) = self.action_at(input_state) else {
+            return false;
+        };
+        self.requests.borrow_mut().push(action);
+        true
     }
 
-    fn handle_cursor_moved(&mut self, _input_state: &InputState) -> bool {
-        false
+    fn handle_cursor_moved(&mut self, input_state: &InputState) -> bool {
+        let hovered = self.action_at(input_state);
+        let changed = hovered != self.hovered;
+        self.hovered = hovered;
+        changed
     }
 
     fn handle_scroll(&mut self, _input_state: &InputState, _pixel_delta: f32) {}
 
-    fn handle_keyboard_input(&mut self, _input_state: &InputState, _key: Key<SmolStr>) -> bool {
-        false
+    /// Left and right (or Tab) move between the clickable segments, Enter clicks the selected one
+    /// and Escape gives the focus back to the editor.
+    fn handle_keyboard_input(&mut self, input_state: &InputState, key: Key<SmolStr>) -> bool {
+        let len = self.hit_areas.len().max(1);
+        let shift = input_state.modifier_state.shift_key();
+        match key {
+            Key::Named(NamedKey::ArrowLeft) => self.selected = (self.selected + len - 1) % len,
+            Key::Named(NamedKey::Tab) if shift => self.selected = (self.selected + len - 1) % len,
+            Key::Named(NamedKey::ArrowRight | NamedKey::Tab) => {
+                self.selected = (self.selected + 1) % len
+            }
+            Key::Named(NamedKey::Enter | NamedKey::Space) => {
+                let Some((_, action)) = self.hit_areas.get(self.selected) else {
+                    return false;
+                };
+                self.requests.borrow_mut().push(action.clone());
+            }
+            Key::Named(NamedKey::Escape) => self.requests.borrow_mut().push(Request::FocusEditor),
+            _ => return false,
+        }
+        true
+    }
+
+    fn configure(&mut self, config: &Config) {
+        self.theme = config.theme.clone();
+        let config = &config.status_bar;
+        self.metrics = Metrics::new(config.font_size, config.height);
+    }
+
+    fn focus_gained(&mut self) {
+        self.focused = true;
     }
 
-    fn render(
+    fn focus_lost(&mut self) {
+        self.focused = false;
+    }
+
+    fn render<'draw>(
         &mut self,
-        pixmap: &mut PixmapMut,
-        paint: &mut Paint,
-        _scale_factor: f64,
+        pixmap: &mut PixmapMut<'draw>,
+        paint: &mut Paint<'draw>,
+        scale_factor: f64,
         rect: Rect,
     ) {
-        paint.set_color_rgba8(64, 64, 64, 255);
+        let background = self.theme.status;
+        paint.set_color_rgba8(
+            background.b(),
+            background.g(),
+            background.r(),
+            background.a(),
+        );
         pixmap.fill_rect(rect, paint, Transform::identity(), None);
+
+        let (left, right) = self.segments();
+        let mut fonts = self.fonts.borrow_mut();
+        let fonts = &mut *fonts;
+        let metrics = self.metrics.scale(scale_factor as f32);
+        let attrs = Attrs::new().family(Family::Monospace);
+        let padding = metrics.font_size / 2.0;
+        let mut shape = |segment: &Segment| {
+            let mut buffer = Buffer::new(&mut fonts.font_system, metrics);
+            buffer.set_text(&segment.text, &attrs, Shaping::Advanced, None);
+            buffer.set_size(None, Some(rect.height()));
+            buffer.shape_until_scroll(&mut fonts.font_system, false);
+            let width = buffer
+                .layout_runs()
+                .map(|run| run.line_w)
+                .fold(0.0, f32::max);
+            (buffer, width + 2.0 * padding)
+        };
+
+        let mut shaped: Vec<_> = left
+            .iter()
+            .map(|segment| (segment, true))
+            .chain(right.iter().map(|segment| (segment, false)))
+            .map(|(segment, on_left)| (segment, on_left, shape(segment)))
+            .collect();
+        while shaped.iter().map(|(_, _, (_, width))| width).sum::<f32>() > rect.width() {
+            let (lowest, _) = shaped
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, (segment, ..))| segment.priority)
+                .unwrap();
+            shaped.remove(lowest);
+        }
+
+        // Left segments start at the left edge, and right ones so as to end at the right edge
+        let mut left_x = 0.0;
+        let mut right_x = rect.width()
+            - shaped
+                .iter()
+                .filter(|(_, on_left, _)| !on_left)
+                .map(|(_, _, (_, width))| width)
+                .sum::<f32>();
+        let mut placed = Vec::new();
+        for (segment, on_left, (buffer, width)) in shaped {
+            let x = if on_left { &mut left_x } else { &mut right_x };
+            placed.push((segment, buffer, *x, width));
+            *x += width;
+        }
+
+        self.hit_areas.clear();
+        // Segments drop out as the bar narrows, so the selected one might have
+        let clickable = placed
+            .iter()
+            .filter(|(segment, ..)| segment.action.is_some());
+        self.selected = self.selected.min(clickable.count().saturating_sub(1));
+        for (segment, buffer, x, width) in placed {
+            let selected = match &segment.action {
+                Some(action) => {
+                    self.hit_areas.push((x..x + width, action.clone()));
+                    self.focused && self.hit_areas.len() - 1 == self.selected
+                }
+                None => false,
+            };
+            let background = match segment.background {
+                _ if selected => Some(self.theme.status_hovered),
+                _ if segment.action.is_some() && segment.action == self.hovered => {
+                    Some(self.theme.status_hovered)
+                }
+                background => background,
+            };
+            if let Some(colour) = background {
+                // With red and blue swapped, like everything else drawn for softbuffer
+                paint.set_color_rgba8(colour.b(), colour.g(), colour.r(), colour.a());
+                pixmap.fill_rect(
+                    Rect::from_xywh(rect.x() + x, rect.y(), width, rect.height()).unwrap(),
+                    paint,
+                    Transform::identity(),
+                    None,
+                );
+            }
+            // Underlined in the cursor's colour, to tell it apart from one that's only hovered
+            if selected {
+                let cursor = self.theme.cursor;
+                let thickness = padding / 4.0;
+                paint.set_color_rgba8(cursor.b(), cursor.g(), cursor.r(), 255);
+                pixmap.fill_rect(
+                    Rect::from_xywh(
+                        rect.x() + x,
+                        rect.y() + rect.height() - thickness,
+                        width,
+                        thickness,
+                    )
+                    .unwrap(),
+                    paint,
+                    Transform::identity(),
+                    None,
+                );
+            }
+
+            // With the text centred vertically
+            let mut renderer = EditorRenderer {
+                swash_cache: &mut fonts.swash_cache,
+                font_system: &mut fonts.font_system,
+                pixmap,
+                paint,
+                pixmap_paint: &PixmapPaint::default(),
+                transform: Transform::from_translate(
+                    rect.x() + x + padding,
+                    rect.y() + (rect.height() - metrics.line_height) / 2.0,
+                ),
+            };
+            renderer.draw_buffer(&buffer, segment.colour.unwrap_or(self.theme.foreground));
+        }
     }
 }
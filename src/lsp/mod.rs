@@ -0,0 +1,505 @@
+//! A client for the Language Server Protocol, talking JSON-RPC to a server over its stdio.
+
+#[cfg(test)]
+mod tests;
+mod transport;
+
+use std::collections::HashMap;
+use std::io::{self, BufReader, Read, Write};
+use std::mem;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+use lsp_types::notification::{
+    Cancel, DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, DidSaveTextDocument,
+    Exit, Initialized, Notification, PublishDiagnostics, ShowMessage,
+};
+use lsp_types::request::{Completion, HoverRequest, Initialize, Request, Shutdown};
+use lsp_types::{
+    CancelParams, ClientCapabilities, ClientInfo, CompletionClientCapabilities, CompletionItem,
+    CompletionItemCapability, CompletionParams, CompletionResponse, ConfigurationParams,
+    DidChangeTextDocumentParams, DidCloseTextDocumentParams, DidOpenTextDocumentParams,
+    DidSaveTextDocumentParams, GeneralClientCapabilities, Hover, HoverClientCapabilities,
+    HoverParams, InitializeParams, InitializeResult, InitializedParams, MarkupKind, MessageType,
+    NumberOrString, Position, PositionEncodingKind, PublishDiagnosticsParams, Range,
+    ServerCapabilities, ShowMessageParams, TextDocumentClientCapabilities,
+    TextDocumentContentChangeEvent, TextDocumentIdentifier, TextDocumentItem,
+    TextDocumentPositionParams, TextDocumentSyncCapability, TextDocumentSyncClientCapabilities,
+    TextDocumentSyncKind, Url, VersionedTextDocumentIdentifier, WorkspaceFolder,
+};
+use serde_json::{json, Value};
+
+use crate::document::{Document, LineColumn, TextChange};
+use crate::Waker;
+
+/// Something a language server told us, as picked up by `Client::poll`.
+pub enum Event {
+    Diagnostics(PublishDiagnosticsParams),
+    Hover(Option<Hover>),
+    Completion(Vec<CompletionItem>),
+    Message(MessageType, String),
+    /// The server went away, or sent something that couldn't be read. Nothing more will come
+    /// from this client.
+    Exited(String),
+}
+
+/// What a request we sent was for, so that its response can be handled once it comes.
+enum Pending {
+    Initialize,
+    Hover,
+    Completion,
+    Shutdown,
+}
+
+/// A connection to one language server. Messages from the server are read on a thread of their
+/// own, which wakes the event loop so that the UI can `poll` for them.
+pub struct Client {
+    writer: Box<dyn Write>,
+    incoming: Receiver<io::Result<Value>>,
+    /// Only there if we started the server ourselves.
+    child: Option<Child>,
+    next_id: i64,
+    pending: HashMap<i64, Pending>,
+    /// Messages sent before the server has answered `initialize`, which have to wait for it.
+    queued: Vec<Value>,
+    /// What the server can do, once it has said.
+    capabilities: Option<ServerCapabilities>,
+    /// Whether positions count UTF-16 code units, which is the default, rather than bytes.
+    utf16: bool,
+    exited: bool,
+}
+
+impl Client {
+    /// Spawns `command` in `root` and starts initialising it as the server for `root`.
+    pub fn start(command: &[&str], root: &Path, waker: Waker) -> io::Result<Self> {
+        let (program, args) = command
+            .split_first()
+            .expect("Language server commands aren't empty");
+        let mut child = Command::new(program)
+            .args(args)
+            .current_dir(root)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let stdin = child.stdin.take().expect("stdin is piped");
+        let stdout = child.stdout.take().expect("stdout is piped");
+
+        let mut client = Self::new(stdout, stdin, root, waker);
+        client.child = Some(child);
+        Ok(client)
+    }
+
+    /// Talks to a server that reads what's written to `writer` and answers on `reader`, which
+    /// doesn't have to be a process (the tests use a scripted stand-in for one), and starts
+    /// initialising it.
+    pub fn new(
+        reader: impl Read + Send + 'static,
+        writer: impl Write + 'static,
+        root: &Path,
+        waker: Waker,
+    ) -> Self {
+        let (sender, incoming) = mpsc::channel();
+        thread::spawn(move || {
+            let mut reader = BufReader::new(reader);
+            loop {
+                let message = match transport::read_message(&mut reader) {
+                    Ok(Some(message)) => Ok(message),
+                    Ok(None) => Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "the server closed its output",
+                    )),
+                    Err(err) => Err(err),
+                };
+                let failed = message.is_err();
+                if sender.send(message).is_err() {
+                    break;
+                }
+                waker.wake();
+                if failed {
+                    break;
+                }
+            }
+        });
+
+        let mut client = Self {
+            writer: Box::new(writer),
+            incoming,
+            child: None,
+            next_id: 0,
+            pending: HashMap::new(),
+            queued: Vec::new(),
+            capabilities: None,
+            utf16: true,
+            exited: false,
+        };
+
+        let root_uri = Url::from_directory_path(root).ok();
+        let params = InitializeParams {
+            process_id: Some(std::process::id()),
+            workspace_folders: root_uri.map(|uri| {
+                vec![WorkspaceFolder {
+                    uri,
+                    name: root
+                        .file_name()
+                        .map_or_else(String::new, |name| name.to_string_lossy().into_owned()),
+                }]
+            }),
+            capabilities: ClientCapabilities {
+                general: Some(GeneralClientCapabilities {
+                    position_encodings: Some(vec![
+                        PositionEncodingKind::UTF8,
+                        PositionEncodingKind::UTF16,
+                    ]),
+                    ..Default::default()
+                }),
+                text_document: Some(TextDocumentClientCapabilities {
+                    synchronization: Some(TextDocumentSyncClientCapabilities {
+                        did_save: Some(true),
+                        ..Default::default()
+                    }),
+                    hover: Some(HoverClientCapabilities {
+                        content_format: Some(vec![MarkupKind::PlainText, MarkupKind::Markdown]),
+                        ..Default::default()
+                    }),
+                    completion: Some(CompletionClientCapabilities {
+                        completion_item: Some(CompletionItemCapability {
+                            snippet_support: Some(false),
+                            ..Default::default()
+                        }),
+                        ..Default::default()
+                    }),
+                    publish_diagnostics: Some(Default::default()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+            client_info: Some(ClientInfo {
+                name: env!("CARGO_PKG_NAME").to_owned(),
+                version: Some(env!("CARGO_PKG_VERSION").to_owned()),
+            }),
+            ..Default::default()
+        };
+        client.request::<Initialize>(params, Pending::Initialize);
+        client
+    }
+
+    /// Whether the server will want to hear about changes to documents at all.
+    fn sync_kind(&self) -> TextDocumentSyncKind {
+        // Until the server says otherwise, assume the most it could want
+        let Some(capabilities) = &self.capabilities else {
+            return TextDocumentSyncKind::INCREMENTAL;
+        };
+        match &capabilities.text_document_sync {
+            Some(TextDocumentSyncCapability::Kind(kind)) => *kind,
+            Some(TextDocumentSyncCapability::Options(options)) => {
+                options.change.unwrap_or(TextDocumentSyncKind::NONE)
+            }
+            None => TextDocumentSyncKind::NONE,
+        }
+    }
+
+    /// The characters that should bring up completions straight after they're typed.
+    pub fn completion_triggers(&self) -> &[String] {
+        self.capabilities
+            .as_ref()
+            .and_then(|capabilities| capabilities.completion_provider.as_ref())
+            .and_then(|provider| provider.trigger_characters.as_deref())
+            .unwrap_or_default()
+    }
+
+    pub fn did_open(&mut self, uri: Url, language_id: &str, version: i32, text: String) {
+        self.notify::<DidOpenTextDocument>(DidOpenTextDocumentParams {
+            text_document: TextDocumentItem {
+                uri,
+                language_id: language_id.to_owned(),
+                version,
+                text,
+            },
+        });
+    }
+
+    /// Tells the server about `changes` to the document at `uri`, which `document` is the
+    /// result of.
+    pub fn did_change(
+        &mut self,
+        uri: Url,
+        version: i32,
+        changes: &[TextChange],
+        document: &Document,
+    ) {
+        let content_changes = match self.sync_kind() {
+            TextDocumentSyncKind::INCREMENTAL => changes
+                .iter()
+                .map(|change| TextDocumentContentChangeEvent {
+                    range: Some(Range::new(
+                        self.position(change.start),
+                        self.position(change.old_end),
+                    )),
+                    range_length: None,
+                    text: change.text.clone(),
+                })
+                .collect(),
+            TextDocumentSyncKind::FULL => vec![TextDocumentContentChangeEvent {
+                range: None,
+                range_length: None,
+                text: document.text().to_string(),
+            }],
+            _ => return,
+        };
+        self.notify::<DidChangeTextDocument>(DidChangeTextDocumentParams {
+            text_document: VersionedTextDocumentIdentifier { uri, version },
+            content_changes,
+        });
+    }
+
+    pub fn did_close(&mut self, uri: Url) {
+        self.notify::<DidCloseTextDocument>(DidCloseTextDocumentParams {
+            text_document: TextDocumentIdentifier { uri },
+        });
+    }
+
+    pub fn did_save(&mut self, uri: Url) {
+        self.notify::<DidSaveTextDocument>(DidSaveTextDocumentParams {
+            text_document: TextDocumentIdentifier { uri },
+            text: None,
+        });
+    }
+
+    /// Asks for hover information at `byte`, which comes back as `Event::Hover`.
+    pub fn hover(&mut self, uri: Url, document: &Document, byte: usize) {
+        let params = HoverParams {
+            text_document_position_params: self.text_document_position(uri, document, byte),
+            work_done_progress_params: Default::default(),
+        };
+        self.request::<HoverRequest>(params, Pending::Hover);
+    }
+
+    /// Asks for completions at `byte`, which come back as `Event::Completion`.
+    pub fn completion(&mut self, uri: Url, document: &Document, byte: usize) {
+        let params = CompletionParams {
+            text_document_position: self.text_document_position(uri, document, byte),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+            context: None,
+        };
+        self.request::<Completion>(params, Pending::Completion);
+    }
+
+    fn text_document_position(
+        &self,
+        uri: Url,
+        document: &Document,
+        byte: usize,
+    ) -> TextDocumentPositionParams {
+        TextDocumentPositionParams {
+            text_document: TextDocumentIdentifier { uri },
+            position: self.position(document.line_column(byte)),
+        }
+    }
+
+    fn position(&self, line_column: LineColumn) -> Position {
+        let column = if self.utf16 {
+            line_column.utf16
+        } else {
+            line_column.byte
+        };
+        Position::new(line_column.line as u32, column as u32)
+    }
+
+    /// The byte offset in `document` of a position the server sent.
+    pub fn offset(&self, document: &Document, position: Position) -> usize {
+        document.byte_of_column(
+            position.line as usize,
+            position.character as usize,
+            self.utf16,
+        )
+    }
+
+    /// Handles everything the server has sent since the last call, answering its requests and
+    /// returning what the UI needs to know about.
+    pub fn poll(&mut self) -> Vec<Event> {
+        let mut events = Vec::new();
+        while !self.exited {
+            let message = match self.incoming.try_recv() {
+                Ok(Ok(message)) => message,
+                Ok(Err(err)) => {
+                    self.exited = true;
+                    events.push(Event::Exited(err.to_string()));
+                    break;
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.exited = true;
+                    events.push(Event::Exited("the connection closed".to_owned()));
+                    break;
+                }
+            };
+
+            let method = message.get("method").and_then(Value::as_str);
+            match (method, message.get("id")) {
+                (Some(method), Some(id)) => self.answer(id.clone(), method, &message["params"]),
+                (Some(method), None) => {
+                    events.extend(Self::notification(method, message["params"].clone()))
+                }
+                (None, Some(id)) => {
+                    let Some(pending) = id.as_i64().and_then(|id| self.pending.remove(&id)) else {
+                        continue;
+                    };
+                    if let Some(error) = message.get("error") {
+                        let text = error["message"].as_str().unwrap_or("unknown error");
+                        events.push(Event::Message(MessageType::ERROR, text.to_owned()));
+                        continue;
+                    }
+                    events.extend(self.response(pending, message["result"].clone()));
+                }
+                (None, None) => (),
+            }
+        }
+        events
+    }
+
+    fn response(&mut self, pending: Pending, result: Value) -> Option<Event> {
+        match pending {
+            Pending::Initialize => {
+                let result: InitializeResult = match serde_json::from_value(result) {
+                    Ok(result) => result,
+                    Err(err) => {
+                        self.exited = true;
+                        return Some(Event::Exited(format!("invalid initialize result: {err}")));
+                    }
+                };
+                self.utf16 =
+                    result.capabilities.position_encoding != Some(PositionEncodingKind::UTF8);
+                self.capabilities = Some(result.capabilities);
+                self.send(notification::<Initialized>(InitializedParams {}));
+                for message in mem::take(&mut self.queued) {
+                    self.send(message);
+                }
+                None
+            }
+            Pending::Hover => Some(Event::Hover(serde_json::from_value(result).ok().flatten())),
+            Pending::Completion => {
+                let items = match serde_json::from_value(result) {
+                    Ok(Some(CompletionResponse::Array(items))) => items,
+                    Ok(Some(CompletionResponse::List(list))) => list.items,
+                    _ => Vec::new(),
+                };
+                Some(Event::Completion(items))
+            }
+            Pending::Shutdown => None,
+        }
+    }
+
+    fn notification(method: &str, params: Value) -> Option<Event> {
+        match method {
+            PublishDiagnostics::METHOD => {
+                serde_json::from_value(params).ok().map(Event::Diagnostics)
+            }
+            ShowMessage::METHOD => {
+                let params: ShowMessageParams = serde_json::from_value(params).ok()?;
+                Some(Event::Message(params.typ, params.message))
+            }
+            _ => None,
+        }
+    }
+
+    /// Answers a request from the server. We don't have any configuration to give, and
+    /// registrations and progress reports are accepted and then ignored.
+    fn answer(&mut self, id: Value, method: &str, params: &Value) {
+        let response = match method {
+            "workspace/configuration" => {
+                let items = serde_json::from_value::<ConfigurationParams>(params.clone())
+                    .map_or(0, |params| params.items.len());
+                json!({ "jsonrpc": "2.0", "id": id, "result": vec![Value::Null; items] })
+            }
+            "client/registerCapability"
+            | "client/unregisterCapability"
+            | "window/workDoneProgress/create" => {
+                json!({ "jsonrpc": "2.0", "id": id, "result": null })
+            }
+            _ => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": { "code": -32601, "message": format!("{method} isn't supported") },
+            }),
+        };
+        self.send(response);
+    }
+
+    fn request<R: Request>(&mut self, params: R::Params, pending: Pending) {
+        // Only the answer to the latest hover or completion request is wanted, so earlier ones
+        // still waiting are cancelled, and whatever comes back for them is dropped
+        if matches!(pending, Pending::Hover | Pending::Completion) {
+            let kind = mem::discriminant(&pending);
+            let earlier: Vec<_> = (self.pending.iter())
+                .filter(|(_, earlier)| mem::discriminant(*earlier) == kind)
+                .map(|(&id, _)| id)
+                .collect();
+            for id in earlier {
+                self.pending.remove(&id);
+                self.notify::<Cancel>(CancelParams {
+                    id: NumberOrString::Number(id as i32),
+                });
+            }
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        let initialize = matches!(pending, Pending::Initialize);
+        self.pending.insert(id, pending);
+        let message = json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": R::METHOD,
+            "params": params,
+        });
+        if self.capabilities.is_none() && !initialize {
+            self.queued.push(message);
+        } else {
+            self.send(message);
+        }
+    }
+
+    fn notify<N: Notification>(&mut self, params: N::Params) {
+        let message = notification::<N>(params);
+        if self.capabilities.is_none() {
+            self.queued.push(message);
+        } else {
+            self.send(message);
+        }
+    }
+
+    fn send(&mut self, message: Value) {
+        if self.exited {
+            return;
+        }
+        // A server that can't be written to has gone, which the reader thread will notice too
+        if transport::write_message(&mut self.writer, &message).is_err() {
+            self.exited = true;
+        }
+    }
+}
+
+fn notification<N: Notification>(params: N::Params) -> Value {
+    json!({ "jsonrpc": "2.0", "method": N::METHOD, "params": params })
+}
+
+impl Drop for Client {
+    /// Asks the server to shut down and exit, without waiting around for it to do so.
+    fn drop(&mut self) {
+        if self.capabilities.is_some() {
+            self.request::<Shutdown>((), Pending::Shutdown);
+            self.send(notification::<Exit>(()));
+        }
+        if let Some(mut child) = self.child.take() {
+            thread::spawn(move || child.wait());
+        }
+    }
+}
+
+/// The `file://` URL language servers know a file by.
+pub fn file_url(path: &Path) -> Option<Url> {
+    Url::from_file_path(std::path::absolute(path).ok()?).ok()
+}
@@ -0,0 +1,434 @@
+use std::io::{self, BufReader, PipeReader, PipeWriter};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use lsp_types::{DiagnosticSeverity, HoverContents, MarkedString, MarkupContent, Position, Url};
+use serde_json::{json, Value};
+
+use super::{transport, Client, Event};
+use crate::document::Document;
+use crate::Waker;
+
+/// How long to wait for the client to pick up a message before giving up on it.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A scripted stand-in for a language server, on the other end of a pair of pipes from the
+/// client: tests read what the client sent and write what a server would have answered.
+struct StandIn {
+    reader: BufReader<PipeReader>,
+    writer: PipeWriter,
+}
+
+impl StandIn {
+    fn receive(&mut self) -> Value {
+        transport::read_message(&mut self.reader)
+            .unwrap()
+            .expect("the client closed its output")
+    }
+
+    /// Reads the next message, checking that it's a request or notification for `method`, and
+    /// returns it.
+    fn expect(&mut self, method: &str) -> Value {
+        let message = self.receive();
+        assert_eq!(message["method"], method, "in {message}");
+        message
+    }
+
+    fn send(&mut self, message: Value) {
+        transport::write_message(&mut self.writer, &message).unwrap();
+    }
+
+    fn respond(&mut self, request: &Value, result: Value) {
+        self.send(json!({ "jsonrpc": "2.0", "id": request["id"], "result": result }));
+    }
+
+    /// Answers `initialize` with `capabilities`, leaving the client to pick it up.
+    fn initialize(&mut self, capabilities: Value) {
+        let request = self.expect("initialize");
+        self.respond(&request, json!({ "capabilities": capabilities }));
+    }
+}
+
+/// A client talking to a stand-in, not yet initialised.
+fn connect() -> (Client, StandIn) {
+    let (client_reader, server_writer) = io::pipe().unwrap();
+    let (server_reader, client_writer) = io::pipe().unwrap();
+    let client = Client::new(
+        client_reader,
+        client_writer,
+        Path::new("/project"),
+        Waker::none(),
+    );
+    let server = StandIn {
+        reader: BufReader::new(server_reader),
+        writer: server_writer,
+    };
+    (client, server)
+}
+
+/// A client that the stand-in has answered `initialize` with `capabilities`, and that has said
+/// it's initialised.
+fn initialized(capabilities: Value) -> (Client, StandIn) {
+    let (mut client, mut server) = connect();
+    server.initialize(capabilities);
+    poll_until(&mut client, |client| client.capabilities.is_some());
+    server.expect("initialized");
+    (client, server)
+}
+
+/// Polls `client` until `done`, returning the events it came up with along the way.
+fn poll_until(client: &mut Client, done: impl Fn(&Client) -> bool) -> Vec<Event> {
+    let start = Instant::now();
+    let mut events = Vec::new();
+    while !done(client) {
+        assert!(start.elapsed() < TIMEOUT, "the client never got there");
+        events.extend(client.poll());
+        thread::sleep(Duration::from_millis(1));
+    }
+    events
+}
+
+/// Polls `client` until it has anything to show for it.
+fn next_events(client: &mut Client) -> Vec<Event> {
+    let start = Instant::now();
+    loop {
+        let events = client.poll();
+        if !events.is_empty() {
+            return events;
+        }
+        assert!(start.elapsed() < TIMEOUT, "the client never got anything");
+        thread::sleep(Duration::from_millis(1));
+    }
+}
+
+fn document(text: &str) -> Document {
+    let mut document = Document::default();
+    document.replace(0..0, text);
+    document.take_changes();
+    document
+}
+
+fn uri() -> Url {
+    Url::parse("file:///project/src/main.rs").unwrap()
+}
+
+#[test]
+fn initialize_handshake() {
+    let (mut client, mut server) = connect();
+    let request = server.expect("initialize");
+    assert_eq!(request["id"], 0);
+    let params = &request["params"];
+    assert_eq!(params["processId"], std::process::id());
+    assert_eq!(
+        params["workspaceFolders"],
+        json!([{ "uri": "file:///project/", "name": "project" }])
+    );
+    assert_eq!(
+        params["capabilities"]["general"]["positionEncodings"],
+        json!(["utf-8", "utf-16"])
+    );
+
+    // Anything sent before the server has answered waits for it
+    client.did_open(uri(), "rust", 0, "fn main() {}\n".to_owned());
+    server.respond(
+        &request,
+        json!({ "capabilities": { "textDocumentSync": 2, "positionEncoding": "utf-8" } }),
+    );
+    let events = poll_until(&mut client, |client| client.capabilities.is_some());
+    assert!(events.is_empty());
+    assert!(!client.utf16);
+
+    server.expect("initialized");
+    let open = server.expect("textDocument/didOpen");
+    assert_eq!(
+        open["params"]["textDocument"],
+        json!({
+            "uri": "file:///project/src/main.rs",
+            "languageId": "rust",
+            "version": 0,
+            "text": "fn main() {}\n",
+        })
+    );
+}
+
+#[test]
+fn server_requests_are_answered() {
+    let (mut client, mut server) = initialized(json!({}));
+    server.send(json!({
+        "jsonrpc": "2.0",
+        "id": "config",
+        "method": "workspace/configuration",
+        "params": { "items": [{ "section": "rust-analyzer" }, {}] },
+    }));
+    server.send(json!({ "jsonrpc": "2.0", "id": 7, "method": "workspace/applyEdit" }));
+    // The answers are sent while polling, which has nothing to show for them
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_millis(100) {
+        assert!(client.poll().is_empty());
+    }
+    assert_eq!(
+        server.receive(),
+        json!({ "jsonrpc": "2.0", "id": "config", "result": [null, null] })
+    );
+    let refused = server.receive();
+    assert_eq!(refused["id"], 7);
+    assert_eq!(refused["error"]["code"], -32601);
+}
+
+#[test]
+fn incremental_changes_count_utf16() {
+    let (mut client, mut server) = initialized(json!({ "textDocumentSync": 2 }));
+    assert!(client.utf16);
+    let mut document = document("héllo 😀 world\nsecond\n");
+    // `é` is one UTF-16 code unit and two bytes, and `😀` two code units and four bytes
+    document.replace(12..17, "there");
+    document.replace(18..24, "");
+    let changes = document.take_changes();
+    client.did_change(uri(), 3, &changes, &document);
+
+    let change = server.expect("textDocument/didChange");
+    assert_eq!(
+        change["params"],
+        json!({
+            "textDocument": { "uri": "file:///project/src/main.rs", "version": 3 },
+            "contentChanges": [
+                {
+                    "range": {
+                        "start": { "line": 0, "character": 9 },
+                        "end": { "line": 0, "character": 14 },
+                    },
+                    "text": "there",
+                },
+                {
+                    "range": {
+                        "start": { "line": 1, "character": 0 },
+                        "end": { "line": 1, "character": 6 },
+                    },
+                    "text": "",
+                },
+            ],
+        })
+    );
+}
+
+#[test]
+fn incremental_changes_count_bytes_when_agreed() {
+    let (mut client, mut server) =
+        initialized(json!({ "textDocumentSync": 2, "positionEncoding": "utf-8" }));
+    let mut document = document("héllo 😀 world\n");
+    document.replace(12..17, "there");
+    let changes = document.take_changes();
+    client.did_change(uri(), 1, &changes, &document);
+
+    let change = server.expect("textDocument/didChange");
+    assert_eq!(
+        change["params"]["contentChanges"][0]["range"],
+        json!({ "start": { "line": 0, "character": 12 }, "end": { "line": 0, "character": 17 } })
+    );
+}
+
+#[test]
+fn full_changes_send_the_whole_text() {
+    let (mut client, mut server) = initialized(json!({ "textDocumentSync": 1 }));
+    let mut document = document("one\n");
+    document.replace(0..3, "two");
+    let changes = document.take_changes();
+    client.did_change(uri(), 1, &changes, &document);
+
+    let change = server.expect("textDocument/didChange");
+    assert_eq!(
+        change["params"]["contentChanges"],
+        json!([{ "text": "two\n" }])
+    );
+}
+
+#[test]
+fn diagnostics_map_to_offsets() {
+    let (mut client, mut server) = initialized(json!({}));
+    let document = document("let 😀 = x;\nlet y = 1;\n");
+    server.send(json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": {
+            "uri": "file:///project/src/main.rs",
+            "diagnostics": [
+                {
+                    "range": {
+                        "start": { "line": 0, "character": 7 },
+                        "end": { "line": 0, "character": 8 },
+                    },
+                    "severity": 1,
+                    "message": "cannot find value `x`",
+                },
+                {
+                    // Past the end of the line, which is clamped to it
+                    "range": {
+                        "start": { "line": 1, "character": 4 },
+                        "end": { "line": 1, "character": 40 },
+                    },
+                    "severity": 2,
+                    "message": "unused variable",
+                },
+            ],
+        },
+    }));
+
+    let mut events = next_events(&mut client);
+    let Some(Event::Diagnostics(params)) = events.pop() else {
+        panic!("expected diagnostics");
+    };
+    assert_eq!(params.uri, uri());
+    let diagnostics: Vec<_> = (params.diagnostics.iter())
+        .map(|diagnostic| {
+            let start = client.offset(&document, diagnostic.range.start);
+            let end = client.offset(&document, diagnostic.range.end);
+            (start..end, diagnostic.severity, diagnostic.message.as_str())
+        })
+        .collect();
+    assert_eq!(
+        diagnostics,
+        [
+            (
+                9..10,
+                Some(DiagnosticSeverity::ERROR),
+                "cannot find value `x`"
+            ),
+            (18..24, Some(DiagnosticSeverity::WARNING), "unused variable"),
+        ]
+    );
+    // Lines past the last are the end of the document
+    assert_eq!(client.offset(&document, Position::new(9, 0)), 25);
+}
+
+#[test]
+fn hover() {
+    let (mut client, mut server) = initialized(json!({ "hoverProvider": true }));
+    let document = document("let 😀 = x;\n");
+    client.hover(uri(), &document, 11);
+
+    let request = server.expect("textDocument/hover");
+    assert_eq!(
+        request["params"],
+        json!({
+            "textDocument": { "uri": "file:///project/src/main.rs" },
+            "position": { "line": 0, "character": 9 },
+        })
+    );
+    server.respond(
+        &request,
+        json!({ "contents": { "kind": "markdown", "value": "```rust\nlet x: i32\n```" } }),
+    );
+    let mut events = next_events(&mut client);
+    let Some(Event::Hover(Some(hover))) = events.pop() else {
+        panic!("expected hover information");
+    };
+    assert_eq!(
+        hover.contents,
+        HoverContents::Markup(MarkupContent {
+            kind: lsp_types::MarkupKind::Markdown,
+            value: "```rust\nlet x: i32\n```".to_owned(),
+        })
+    );
+
+    // Nothing to say is an answer too
+    client.hover(uri(), &document, 0);
+    let request = server.expect("textDocument/hover");
+    server.respond(&request, Value::Null);
+    let mut events = next_events(&mut client);
+    assert!(matches!(events.pop(), Some(Event::Hover(None))));
+}
+
+#[test]
+fn only_the_latest_answer_counts() {
+    let (mut client, mut server) = initialized(json!({ "hoverProvider": true }));
+    let document = document("let x = y;\n");
+    client.hover(uri(), &document, 4);
+    client.hover(uri(), &document, 8);
+    let first = server.expect("textDocument/hover");
+    let cancel = server.expect("$/cancelRequest");
+    assert_eq!(cancel["params"], json!({ "id": first["id"] }));
+    let second = server.expect("textDocument/hover");
+
+    // The answer to the first request comes late, after the second was sent
+    server.respond(&first, json!({ "contents": "x" }));
+    server.respond(&second, json!({ "contents": "y" }));
+    let events = poll_until(&mut client, |client| client.pending.is_empty());
+    let [Event::Hover(Some(hover))] = &events[..] else {
+        panic!("expected one hover");
+    };
+    assert_eq!(
+        hover.contents,
+        HoverContents::Scalar(MarkedString::String("y".to_owned()))
+    );
+}
+
+#[test]
+fn completion() {
+    let (mut client, mut server) = initialized(json!({
+        "completionProvider": { "triggerCharacters": [".", ":"] },
+    }));
+    assert_eq!(client.completion_triggers(), [".", ":"]);
+    let document = document("self.\n");
+    client.completion(uri(), &document, 5);
+
+    let request = server.expect("textDocument/completion");
+    assert_eq!(
+        request["params"]["position"],
+        json!({ "line": 0, "character": 5 })
+    );
+    server.respond(
+        &request,
+        json!({
+            "isIncomplete": false,
+            "items": [{ "label": "len" }, { "label": "push", "insertText": "push()" }],
+        }),
+    );
+    let mut events = next_events(&mut client);
+    let Some(Event::Completion(items)) = events.pop() else {
+        panic!("expected completions");
+    };
+    let labels: Vec<_> = items.iter().map(|item| item.label.as_str()).collect();
+    assert_eq!(labels, ["len", "push"]);
+    assert_eq!(items[1].insert_text.as_deref(), Some("push()"));
+
+    // An error comes back as a message to show
+    client.completion(uri(), &document, 5);
+    let request = server.expect("textDocument/completion");
+    server.send(json!({
+        "jsonrpc": "2.0",
+        "id": request["id"],
+        "error": { "code": -32603, "message": "it broke" },
+    }));
+    let mut events = next_events(&mut client);
+    assert!(matches!(
+        events.pop(),
+        Some(Event::Message(_, message)) if message == "it broke"
+    ));
+}
+
+#[test]
+fn server_going_away() {
+    let (mut client, server) = initialized(json!({}));
+    drop(server);
+    let mut events = next_events(&mut client);
+    assert!(matches!(events.pop(), Some(Event::Exited(_))));
+    assert!(client.poll().is_empty());
+}
+
+#[test]
+fn closing_documents() {
+    let (mut client, mut server) = initialized(json!({ "textDocumentSync": 2 }));
+    client.did_close(uri());
+    let close = server.expect("textDocument/didClose");
+    assert_eq!(
+        close["params"],
+        json!({ "textDocument": { "uri": "file:///project/src/main.rs" } })
+    );
+
+    // Dropping the client is shutting the server down
+    drop(client);
+    let shutdown = server.expect("shutdown");
+    assert_eq!(shutdown["params"], Value::Null);
+    server.expect("exit");
+}
@@ -1,26 +1,118 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+mod config;
+mod document;
+mod fuzzy;
+mod headless;
 mod layout;
+mod lsp;
+mod pattern;
+mod replay;
 mod winit_app;
 
 use image::ImageFormat;
 use softbuffer::{Context, Surface};
+use std::env;
+use std::ffi::OsString;
+use std::fs;
 use std::num::NonZeroU32;
+use std::path::PathBuf;
+use std::process;
 use std::rc::Rc;
 use std::slice;
+use std::sync::Arc;
 use tiny_skia::{Paint, PixmapMut, Rect};
 use winit::dpi::PhysicalPosition;
 use winit::event::{
     DeviceEvent, ElementState, Event, KeyEvent, MouseButton, MouseScrollDelta, WindowEvent,
 };
-use winit::event_loop::{ActiveEventLoop, ControlFlow, EventLoop};
-use winit::keyboard::{Key, ModifiersState};
+use winit::event_loop::{ActiveEventLoop, ControlFlow, EventLoop, EventLoopProxy};
+use winit::keyboard::ModifiersState;
 use winit::window::{Icon, Window};
 
+use config::{Config, PanelSizes};
 use layout::{Interactive, RootLayout};
 
 static EXALTED_ICON_PNG: &[u8] = include_bytes!("../exalted.png");
 
+const USAGE: &str = "\
+Usage: exalted [--config CONFIG] [FILE]
+       exalted [--replay SCRIPT] [--snapshot PNG [--update]] [--size WIDTHxHEIGHT]
+               [--scale FACTOR] [--config CONFIG] [FILE]
+
+Options:
+  --config CONFIG        Read settings from CONFIG instead of exalted/config.exl in the XDG config
+                         directory, which isn't read at all without a window
+  --replay SCRIPT        Open FILE without a window and replay the input in SCRIPT against it,
+                         failing if any of its checks do
+  --snapshot PNG         Render FILE without a window (after any replay) and compare it against
+                         the image at PNG, writing the image instead if it doesn't exist yet
+  --update               Overwrite the snapshot instead of comparing against it
+  --size WIDTHxHEIGHT    The size of the window to pretend to have [default: 800x600]
+  --scale FACTOR         The scale factor to pretend to have [default: 1]";
+
+struct Args {
+    path: Option<PathBuf>,
+    config: Option<PathBuf>,
+    replay: Option<PathBuf>,
+    snapshot: Option<PathBuf>,
+    update: bool,
+    viewport: headless::Viewport,
+}
+
+impl Args {
+    fn parse(mut args: impl Iterator<Item = OsString>) -> Result<Self, String> {
+        let mut parsed = Self {
+            path: None,
+            config: None,
+            replay: None,
+            snapshot: None,
+            update: false,
+            viewport: headless::Viewport::default(),
+        };
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| {
+                args.next()
+                    .and_then(|value| value.into_string().ok())
+                    .ok_or_else(|| format!("{name} needs a value"))
+            };
+            match arg.to_str() {
+                Some("--config") => parsed.config = Some(PathBuf::from(value("--config")?)),
+                Some("--replay") => parsed.replay = Some(PathBuf::from(value("--replay")?)),
+                Some("--snapshot") => parsed.snapshot = Some(PathBuf::from(value("--snapshot")?)),
+                Some("--size") => {
+                    let size = value("--size")?;
+                    let (width, height) = size
+                        .split_once('x')
+                        .and_then(|(width, height)| {
+                            Some((width.parse().ok()?, height.parse().ok()?))
+                        })
+                        .filter(|&(width, height)| width > 0 && height > 0)
+                        .ok_or_else(|| format!("Invalid size: {size}"))?;
+                    parsed.viewport.width = width;
+                    parsed.viewport.height = height;
+                }
+                Some("--scale") => {
+                    let scale = value("--scale")?;
+                    parsed.viewport.scale_factor = scale
+                        .parse()
+                        .ok()
+                        .filter(|&scale: &f64| scale > 0.0)
+                        .ok_or_else(|| format!("Invalid scale factor: {scale}"))?;
+                }
+                Some("--update") => parsed.update = true,
+                Some("-h" | "--help") => return Err(String::new()),
+                Some(flag) if flag.starts_with("--") => {
+                    return Err(format!("Unknown option: {flag}"))
+                }
+                _ if parsed.path.is_none() => parsed.path = Some(PathBuf::from(arg)),
+                _ => return Err("Only one file can be opened".to_owned()),
+            }
+        }
+        Ok(parsed)
+    }
+}
+
 struct WindowState {
     window: Rc<Window>,
     surface: Surface<Rc<Window>, Rc<Window>>,
@@ -29,6 +121,7 @@ struct WindowState {
 }
 
 // TODO: Maybe factor out mouse_pos_{x,y} into separate struct for easier mapping per-node
+#[derive(Clone, Copy)]
 struct InputState {
     mouse_left_state: ElementState,
     mouse_pos_x: f64,
@@ -47,13 +140,110 @@ impl Default for InputState {
     }
 }
 
+/// Wakes the event loop up from another thread when something has happened in the background
+/// (like a language server sending a message), so that the UI can pick it up.
+#[derive(Clone)]
+struct Waker(Option<Arc<dyn Fn() + Send + Sync>>);
+
+impl Waker {
+    /// For when nothing is waiting to be woken, like when rendering headlessly.
+    fn none() -> Self {
+        Self(None)
+    }
+
+    fn new(proxy: EventLoopProxy<()>) -> Self {
+        // The event loop only goes away when the app is exiting anyway
+        Self(Some(Arc::new(move || {
+            let _ = proxy.send_event(());
+        })))
+    }
+
+    /// Whether there's nothing to wake, and so nothing that would pick up what happens in the
+    /// background unless it's waited for.
+    fn is_none(&self) -> bool {
+        self.0.is_none()
+    }
+
+    fn wake(&self) {
+        if let Some(wake) = &self.0 {
+            wake();
+        }
+    }
+}
+
 fn main() {
+    let args = match Args::parse(env::args_os().skip(1)) {
+        Ok(args) => args,
+        Err(err) if err.is_empty() => {
+            println!("{USAGE}");
+            return;
+        }
+        Err(err) => {
+            eprintln!("{err}\n\n{USAGE}");
+            process::exit(2);
+        }
+    };
+    if args.replay.is_some() || args.snapshot.is_some() {
+        process::exit(run_headless(args));
+    }
+
     let event_loop = EventLoop::new().unwrap();
-    let app = winit_app::WinitAppBuilder::with_init(init_state).with_event_handler(event_loop_fn);
+    let waker = Waker::new(event_loop.create_proxy());
+    // Snapshots and replays shouldn't depend on whoever's running them, so only windows get this
+    let config = args.config.or_else(Config::default_path);
+    let app = winit_app::WinitAppBuilder::with_init(move |elwt| {
+        init_state(elwt, args.path.clone(), config.clone(), waker.clone())
+    })
+    .with_event_handler(event_loop_fn);
     winit_app::run_app(event_loop, app);
 }
 
-fn init_state(elwt: &ActiveEventLoop) -> WindowState {
+/// Replays input against and/or renders a snapshot of the file in `args` without a window,
+/// returning the exit code.
+fn run_headless(args: Args) -> i32 {
+    let viewport = args.viewport;
+    let mut layout = RootLayout::new(viewport.scale_factor, Waker::none());
+    if let Some(config) = &args.config {
+        layout.load_config(config, false);
+    }
+    if let Some(path) = args.path {
+        layout.open(path);
+    }
+
+    if let Some(script) = &args.replay {
+        let script = match fs::read_to_string(script) {
+            Ok(script) => script,
+            Err(err) => {
+                eprintln!("Could not read {}: {err}", script.display());
+                return 2;
+            }
+        };
+        if let Err(err) = replay::run(&mut layout, &script, viewport) {
+            eprintln!("Replay failed at {err}");
+            return 1;
+        }
+    }
+
+    if let Some(png) = &args.snapshot {
+        let image = headless::render(&mut layout, viewport);
+        match headless::check(&image, png, args.update) {
+            Ok(true) => println!("Wrote {}", png.display()),
+            Ok(false) => (),
+            Err(err) => {
+                eprintln!("Snapshot failed: {err}");
+                return 1;
+            }
+        }
+    }
+    0
+}
+
+fn init_state(
+    elwt: &ActiveEventLoop,
+    path: Option<PathBuf>,
+    config: Option<PathBuf>,
+    waker: Waker,
+) -> WindowState {
     let icon = load_png_icon(EXALTED_ICON_PNG);
     let window = winit_app::make_window(elwt, |w| {
         w.with_title("Exalted").with_window_icon(Some(icon.clone()))
@@ -61,7 +251,17 @@ fn init_state(elwt: &ActiveEventLoop) -> WindowState {
 
     let context = Context::new(window.clone()).unwrap();
     let surface = Surface::new(&context, window.clone()).unwrap();
-    let layout = RootLayout::new(window.scale_factor());
+    let mut layout = RootLayout::new(window.scale_factor(), waker);
+    if let Some(config) = config {
+        layout.load_config(&config, true);
+    }
+    // Like the config, snapshots and replays leave these alone
+    if let Some(panels) = PanelSizes::default_path() {
+        layout.remember_panels(panels);
+    }
+    if let Some(path) = path {
+        layout.open(path);
+    }
     let input = InputState::default();
 
     WindowState {
@@ -127,14 +327,7 @@ fn event_loop_fn(
                 }
                 WindowEvent::ModifiersChanged(mods) => input.modifier_state = mods.state(),
                 WindowEvent::KeyboardInput { event, .. } => {
-                    if match event {
-                        KeyEvent {
-                            logical_key: Key::Character(key),
-                            ..
-                        } if input.modifier_state.control_key() && key == "q" => {
-                            elwt.exit();
-                            false
-                        }
+                    let redraw = match event {
                         KeyEvent {
                             state: ElementState::Pressed,
                             // Code was written a while ago so not sure why this was here
@@ -143,7 +336,10 @@ fn event_loop_fn(
                             ..
                         } => layout.handle_keyboard_input(input, logical_key),
                         _ => false,
-                    } {
+                    };
+                    if layout.quitting() {
+                        elwt.exit();
+                    } else if redraw {
                         window.request_redraw()
                     }
                 }
@@ -176,6 +372,7 @@ fn event_loop_fn(
                 WindowEvent::CloseRequested => elwt.exit(),
                 _ => (),
             },
+            Event::UserEvent(()) if layout.poll() => window.request_redraw(),
             Event::DeviceEvent {
                 event: DeviceEvent::Motion { axis, value },
                 ..
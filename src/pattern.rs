@@ -0,0 +1,73 @@
+use std::ops::Range;
+
+use regex::{Regex, RegexBuilder};
+
+/// What's being searched for, by the search bar in a buffer or across the whole project.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Pattern {
+    pub query: String,
+    /// Whether `query` is a regex rather than text to find as it is.
+    pub regex: bool,
+    pub case_sensitive: bool,
+}
+
+impl Pattern {
+    pub fn build(&self) -> Result<Regex, regex::Error> {
+        let pattern = match self.regex {
+            true => self.query.clone(),
+            false => regex::escape(&self.query),
+        };
+        RegexBuilder::new(&pattern)
+            .case_insensitive(!self.case_sensitive)
+            .multi_line(true)
+            .build()
+    }
+
+    /// How the query is matched, like ` (regex, match case)`, or nothing for the default of plain
+    /// text in any case.
+    pub fn flags(&self) -> String {
+        let mut flags = Vec::new();
+        if self.regex {
+            flags.push("regex");
+        }
+        if self.case_sensitive {
+            flags.push("match case");
+        }
+        match flags.is_empty() {
+            true => String::new(),
+            false => format!(" ({})", flags.join(", ")),
+        }
+    }
+
+    /// The matches in `text` with what each should be replaced by, which for a regex can refer to
+    /// its capture groups like `$1`.
+    pub fn replacements(
+        &self,
+        regex: &Regex,
+        text: &str,
+        matches: &[Range<usize>],
+        replacement: &str,
+    ) -> Vec<(Range<usize>, String)> {
+        matches
+            .iter()
+            .map(|found| {
+                let mut expanded = String::new();
+                match regex.captures_at(text, found.start) {
+                    Some(captures) if self.regex => captures.expand(replacement, &mut expanded),
+                    _ => expanded.push_str(replacement),
+                }
+                (found.clone(), expanded)
+            })
+            .collect()
+    }
+}
+
+/// Everything `regex` matches in `text`, in order. Empty matches are left out, since they can't be
+/// selected and would match everywhere besides.
+pub fn find_all(regex: &Regex, text: &str) -> Vec<Range<usize>> {
+    regex
+        .find_iter(text)
+        .map(|found| found.range())
+        .filter(|found| !found.is_empty())
+        .collect()
+}
@@ -0,0 +1,371 @@
+//! Replaying scripted input against the layout, for testing the editor end to end without a
+//! window. A script has one step per line:
+//!
+//! ```text
+//! # Types the rest of the line, one key per character
+//! type fn main() {}
+//! keys esc x C-s S-left    # presses keys, written like in the keymap
+//! click 320 40 C           # clicks (here with Ctrl held) at a point in the window
+//! press 320 40             # presses the left button,
+//! move 400 40              # drags with it,
+//! release                  # and lets go of it
+//! scroll -3                # scrolls by lines, negative being up
+//! # Checks the text, which is the rest of the line with \n, \t and \\ escaped
+//! text fn main() {}\n
+//! cursor 1:4               # checks the primary cursor's line and column, counted from 1
+//! selections 1:1-1:3 *2:1-2:5   # checks every selection as anchor-head, * marking the primary
+//! mode normal              # checks the mode
+//! size 100x60              # renders the window at another size from then on
+//! ```
+//!
+//! Comments start with `#`, except in `type` and `text`, which take the rest of the line as it
+//! is. Columns count characters. The layout is rendered after every step, as the window would be,
+//! since that's what lays out the text that mouse input is matched against.
+//! Anything going on in the background that input depends on, like the file picker finding files,
+//! is waited for before every step.
+
+use std::fmt;
+
+use winit::event::{ElementState, MouseButton};
+use winit::keyboard::{Key, ModifiersState, NamedKey, SmolStr};
+
+use crate::headless::{self, Viewport};
+use crate::layout::{Interactive, KeyPress, RootLayout};
+use crate::InputState;
+
+/// A step that didn't parse, or an expectation that didn't hold.
+#[derive(Debug)]
+pub struct ReplayError {
+    /// Counted from 1.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+enum Step {
+    Keys(Vec<(Key<SmolStr>, ModifiersState)>),
+    Press(f64, f64, ModifiersState),
+    Move(f64, f64),
+    Release,
+    Click(f64, f64, ModifiersState),
+    Scroll(f32),
+    Text(String),
+    Cursor(Position),
+    Selections(Vec<(Position, Position, bool)>),
+    Mode(String),
+    Size(u32, u32),
+}
+
+/// A line and column, both counted from 1, the column in characters.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Position {
+    line: usize,
+    column: usize,
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// Runs `script` against `layout`, stopping at the first step that fails.
+pub fn run(
+    layout: &mut RootLayout,
+    script: &str,
+    mut viewport: Viewport,
+) -> Result<(), ReplayError> {
+    let mut input = InputState::default();
+    headless::render(layout, viewport);
+    for (i, line) in script.lines().enumerate() {
+        let error = |message: String| ReplayError {
+            line: i + 1,
+            message,
+        };
+        let Some(step) = parse(line).map_err(error)? else {
+            continue;
+        };
+        layout.finish_background_work();
+        execute(layout, &mut input, &mut viewport, step).map_err(error)?;
+        headless::render(layout, viewport);
+    }
+    Ok(())
+}
+
+fn parse(line: &str) -> Result<Option<Step>, String> {
+    let line = line.trim_start();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+    let (command, rest) = line.split_once(' ').unwrap_or((line, ""));
+    match command {
+        "type" => {
+            let keys = rest
+                .chars()
+                .map(|c| (typed_key(c), ModifiersState::empty()));
+            return Ok(Some(Step::Keys(keys.collect())));
+        }
+        "text" => return Ok(Some(Step::Text(unescape(rest)?))),
+        _ => (),
+    }
+    let rest = rest.split_once('#').map_or(rest, |(rest, _)| rest);
+    let args: Vec<_> = rest.split_whitespace().collect();
+
+    let step = match command {
+        "keys" => Step::Keys(
+            args.iter()
+                .map(|key| {
+                    KeyPress::parse(key)
+                        .map(|key| key.event())
+                        .ok_or_else(|| format!("unknown key {key}"))
+                })
+                .collect::<Result<_, _>>()?,
+        ),
+        "press" | "click" => {
+            let (x, y, modifiers) = match args[..] {
+                [x, y] => (x, y, ModifiersState::empty()),
+                [x, y, modifiers] => (x, y, parse_modifiers(modifiers)?),
+                _ => return Err(format!("{command} needs a point and maybe modifiers")),
+            };
+            let (x, y) = (parse_number(x)?, parse_number(y)?);
+            if command == "press" {
+                Step::Press(x, y, modifiers)
+            } else {
+                Step::Click(x, y, modifiers)
+            }
+        }
+        "move" => match args[..] {
+            [x, y] => Step::Move(parse_number(x)?, parse_number(y)?),
+            _ => return Err("move needs a point".to_owned()),
+        },
+        "release" => Step::Release,
+        "scroll" => match args[..] {
+            [lines] => Step::Scroll(parse_number(lines)? as f32),
+            _ => return Err("scroll needs a number of lines".to_owned()),
+        },
+        "cursor" => match args[..] {
+            [position] => Step::Cursor(parse_position(position)?),
+            _ => return Err("cursor needs a position".to_owned()),
+        },
+        "selections" => Step::Selections(
+            args.iter()
+                .map(|range| {
+                    let (primary, range) = match range.strip_prefix('*') {
+                        Some(range) => (true, range),
+                        None => (false, *range),
+                    };
+                    let (anchor, head) = range
+                        .split_once('-')
+                        .ok_or_else(|| format!("{range} isn't a range"))?;
+                    Ok((parse_position(anchor)?, parse_position(head)?, primary))
+                })
+                .collect::<Result<_, String>>()?,
+        ),
+        "mode" => match args[..] {
+            [mode] => Step::Mode(mode.to_owned()),
+            _ => return Err("mode needs a mode".to_owned()),
+        },
+        "size" => match args[..] {
+            [size] => {
+                let (width, height) = size
+                    .split_once('x')
+                    .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)))
+                    .filter(|&(width, height)| width > 0 && height > 0)
+                    .ok_or_else(|| format!("{size} isn't a size like 800x600"))?;
+                Step::Size(width, height)
+            }
+            _ => return Err("size needs a size".to_owned()),
+        },
+        _ => return Err(format!("unknown step {command}")),
+    };
+    Ok(Some(step))
+}
+
+/// The key that types `c`, which is a named key for whitespace.
+fn typed_key(c: char) -> Key<SmolStr> {
+    match c {
+        ' ' => Key::Named(NamedKey::Space),
+        '\t' => Key::Named(NamedKey::Tab),
+        c => Key::Character(c.to_string().into()),
+    }
+}
+
+/// Parses modifiers written like a key's prefix in the keymap, but on their own: `C`, `C-S`.
+fn parse_modifiers(modifiers: &str) -> Result<ModifiersState, String> {
+    modifiers
+        .split('-')
+        .map(|modifier| match modifier {
+            "C" => Ok(ModifiersState::CONTROL),
+            "A" => Ok(ModifiersState::ALT),
+            "S" => Ok(ModifiersState::SHIFT),
+            _ => Err(format!("unknown modifier {modifier}")),
+        })
+        .collect()
+}
+
+fn parse_number(number: &str) -> Result<f64, String> {
+    number
+        .parse()
+        .map_err(|_| format!("{number} isn't a number"))
+}
+
+fn parse_position(position: &str) -> Result<Position, String> {
+    position
+        .split_once(':')
+        .and_then(|(line, column)| {
+            Some(Position {
+                line: line.parse().ok().filter(|&line| line > 0)?,
+                column: column.parse().ok().filter(|&column| column > 0)?,
+            })
+        })
+        .ok_or_else(|| format!("{position} isn't a line:column position"))
+}
+
+fn unescape(text: &str) -> Result<String, String> {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('t') => unescaped.push('\t'),
+            Some('\\') => unescaped.push('\\'),
+            other => return Err(format!("unknown escape \\{}", other.unwrap_or(' '))),
+        }
+    }
+    Ok(unescaped)
+}
+
+fn execute(
+    layout: &mut RootLayout,
+    input: &mut InputState,
+    viewport: &mut Viewport,
+    step: Step,
+) -> Result<(), String> {
+    match step {
+        Step::Keys(keys) => {
+            for (key, modifiers) in keys {
+                input.modifier_state = modifiers;
+                layout.handle_keyboard_input(input, key);
+            }
+            input.modifier_state = ModifiersState::empty();
+        }
+        Step::Press(x, y, modifiers) => press(layout, input, x, y, modifiers),
+        Step::Move(x, y) => {
+            input.mouse_pos_x = x;
+            input.mouse_pos_y = y;
+            layout.handle_cursor_moved(input);
+        }
+        Step::Release => release(layout, input),
+        Step::Click(x, y, modifiers) => {
+            press(layout, input, x, y, modifiers);
+            release(layout, input);
+        }
+        // The same as a mouse wheel's line delta in the window
+        Step::Scroll(lines) => layout.handle_scroll(input, -lines * 90.0),
+        Step::Text(expected) => {
+            let text = layout.editor_state().text;
+            if text != expected {
+                return Err(format!("expected text {expected:?}, found {text:?}"));
+            }
+        }
+        Step::Cursor(expected) => {
+            let state = layout.editor_state();
+            let cursor = position(&state.text, state.selection.primary().head);
+            if cursor != expected {
+                return Err(format!("expected the cursor at {expected}, found {cursor}"));
+            }
+        }
+        Step::Selections(expected) => {
+            let state = layout.editor_state();
+            let found: Vec<_> = state
+                .selection
+                .ranges()
+                .iter()
+                .enumerate()
+                .map(|(i, range)| {
+                    (
+                        position(&state.text, range.anchor),
+                        position(&state.text, range.head),
+                        i == state.selection.primary_index(),
+                    )
+                })
+                .collect();
+            // Leaving out the * means not caring which one is primary
+            let any_primary = expected.iter().all(|&(_, _, primary)| !primary);
+            let matches = found.len() == expected.len()
+                && found.iter().zip(&expected).all(|(found, expected)| {
+                    found.0 == expected.0
+                        && found.1 == expected.1
+                        && (any_primary || found.2 == expected.2)
+                });
+            if !matches {
+                return Err(format!(
+                    "expected selections {}, found {}",
+                    format_selections(&expected),
+                    format_selections(&found)
+                ));
+            }
+        }
+        Step::Mode(expected) => {
+            let mode = layout.editor_state().mode;
+            if mode != expected {
+                return Err(format!("expected {expected} mode, found {mode} mode"));
+            }
+        }
+        Step::Size(width, height) => {
+            viewport.width = width;
+            viewport.height = height;
+        }
+    }
+    Ok(())
+}
+
+fn press(
+    layout: &mut RootLayout,
+    input: &mut InputState,
+    x: f64,
+    y: f64,
+    modifiers: ModifiersState,
+) {
+    input.mouse_pos_x = x;
+    input.mouse_pos_y = y;
+    input.modifier_state = modifiers;
+    input.mouse_left_state = ElementState::Pressed;
+    layout.handle_mouse_input(input, MouseButton::Left, ElementState::Pressed);
+    input.modifier_state = ModifiersState::empty();
+}
+
+fn release(layout: &mut RootLayout, input: &mut InputState) {
+    input.mouse_left_state = ElementState::Released;
+    layout.handle_mouse_input(input, MouseButton::Left, ElementState::Released);
+}
+
+fn position(text: &str, byte: usize) -> Position {
+    let before = &text[..byte];
+    let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+    Position {
+        line: before.matches('\n').count() + 1,
+        column: before[line_start..].chars().count() + 1,
+    }
+}
+
+fn format_selections(selections: &[(Position, Position, bool)]) -> String {
+    selections
+        .iter()
+        .map(|(anchor, head, primary)| {
+            format!("{}{anchor}-{head}", if *primary { "*" } else { "" })
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
@@ -112,6 +112,12 @@ where
         (self.event)(state, Event::WindowEvent { window_id, event }, event_loop);
     }
 
+    fn user_event(&mut self, event_loop: &ActiveEventLoop, event: ()) {
+        if let Some(state) = self.state.as_mut() {
+            (self.event)(state, Event::UserEvent(event), event_loop);
+        }
+    }
+
     fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
         if let Some(state) = self.state.as_mut() {
             (self.event)(state, Event::AboutToWait, event_loop);